@@ -1,16 +1,22 @@
 //! Endpoints for push notifications.
 use std::{error::Error, fmt};
 
+use http::StatusCode;
+pub use ruma_common::push::RuleKind;
 use ruma_common::{
     push::{
-        Action, ConditionalPushRule, ConditionalPushRuleInit, PatternedPushRule,
-        PatternedPushRuleInit, PushCondition, PusherData, SimplePushRule, SimplePushRuleInit,
+        Action, ConditionalPushRule, ConditionalPushRuleInit, InsertPushRuleError,
+        PatternedPushRule, PatternedPushRuleInit, PushCondition, PusherData, RemovePushRuleError,
+        RuleNotFoundError, SimplePushRule, SimplePushRuleInit,
     },
     serde::StringEnum,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::PrivOwnedStr;
+use crate::{
+    error::{ErrorBody, ErrorKind},
+    PrivOwnedStr,
+};
 
 pub mod delete_pushrule;
 pub mod get_notifications;
@@ -164,29 +170,33 @@ impl TryFrom<PushRule> for ConditionalPushRule {
     }
 }
 
-/// The kinds of push rules that are available.
-#[doc = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/doc/string_enum.md"))]
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, StringEnum)]
-#[ruma_enum(rename_all = "snake_case")]
-#[non_exhaustive]
-pub enum RuleKind {
-    /// User-configured rules that override all other kinds.
-    Override,
-
-    /// Lowest priority user-defined rules.
-    Underride,
+impl From<InsertPushRuleError> for crate::Error {
+    fn from(error: InsertPushRuleError) -> Self {
+        let kind = match error {
+            InsertPushRuleError::UnknownRuleId => ErrorKind::Unknown,
+            _ => ErrorKind::InvalidParam,
+        };
 
-    /// Sender-specific rules.
-    Sender,
+        ErrorBody { kind, message: error.to_string() }.into_error(StatusCode::BAD_REQUEST)
+    }
+}
 
-    /// Room-specific rules.
-    Room,
+impl From<RuleNotFoundError> for crate::Error {
+    fn from(error: RuleNotFoundError) -> Self {
+        ErrorBody { kind: ErrorKind::NotFound, message: error.to_string() }
+            .into_error(StatusCode::NOT_FOUND)
+    }
+}
 
-    /// Content-specific rules.
-    Content,
+impl From<RemovePushRuleError> for crate::Error {
+    fn from(error: RemovePushRuleError) -> Self {
+        let (kind, status_code) = match error {
+            RemovePushRuleError::NotFound => (ErrorKind::NotFound, StatusCode::NOT_FOUND),
+            _ => (ErrorKind::InvalidParam, StatusCode::BAD_REQUEST),
+        };
 
-    #[doc(hidden)]
-    _Custom(PrivOwnedStr),
+        ErrorBody { kind, message: error.to_string() }.into_error(status_code)
+    }
 }
 
 /// Which kind a pusher is.
@@ -1,6 +1,13 @@
 //! `GET /_matrix/client/*/sync`
 
-use js_int::UInt;
+use std::collections::BTreeMap;
+
+use js_int::{uint, UInt};
+use ruma_common::{
+    push::{Action, PushConditionRoomCtx, Ruleset},
+    serde::Raw,
+    EventId, OwnedEventId,
+};
 use serde::{self, Deserialize, Serialize};
 
 pub mod v3;
@@ -32,3 +39,240 @@ impl UnreadNotificationsCount {
         self.highlight_count.is_none() && self.notification_count.is_none()
     }
 }
+
+/// The unread notifications counts of a room, in its main timeline and in its threads.
+///
+/// This is meant to be used by homeservers to compute the `unread_notifications` of a room in a
+/// sync response.
+#[derive(Clone, Debug)]
+#[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
+pub struct UnreadNotificationsCounts {
+    /// The counts of the events in the main timeline of the room, i.e. not in a thread.
+    pub main_timeline: UnreadNotificationsCount,
+
+    /// The counts of the events in the threads of the room, by thread root event ID.
+    ///
+    /// Only the threads with unread notifications are present.
+    pub threads: BTreeMap<OwnedEventId, UnreadNotificationsCount>,
+}
+
+impl UnreadNotificationsCounts {
+    /// Computes the unread notifications counts of a room.
+    ///
+    /// # Arguments
+    ///
+    /// * `events` - The events of the timeline of the room, in chronological order.
+    /// * `ruleset` - The push rules of the user.
+    /// * `context` - The context of the room and the user.
+    /// * `read_receipt` - The ID of the event of the user's read receipt, if any. This event and
+    ///   the ones before it are read. If it is not found in `events`, all the events are unread.
+    ///
+    /// An unread event is counted as a notification if its push actions contain
+    /// [`Action::Notify`] or [`Action::Coalesce`], and as a highlight if they also contain a
+    /// `highlight` tweak. An event belongs to a thread if it has an `m.thread` relation.
+    pub fn compute<'a, T: 'a>(
+        events: impl IntoIterator<Item = &'a Raw<T>>,
+        ruleset: &Ruleset,
+        context: &PushConditionRoomCtx,
+        read_receipt: Option<&EventId>,
+    ) -> Self {
+        let events: Vec<_> = events
+            .into_iter()
+            .map(|event| (event, event.deserialize_as::<EventInfo>().unwrap_or_default()))
+            .collect();
+
+        let first_unread = read_receipt
+            .and_then(|read_receipt| {
+                events.iter().position(|(_, info)| info.event_id.as_deref() == Some(read_receipt))
+            })
+            .map_or(0, |index| index + 1);
+
+        let mut counts =
+            Self { main_timeline: UnreadNotificationsCount::zero(), threads: BTreeMap::new() };
+
+        for (event, info) in &events[first_unread..] {
+            let actions = ruleset.get_actions(*event, context);
+            if !actions.iter().any(Action::should_notify) {
+                continue;
+            }
+
+            let count = match info.content.thread_root() {
+                Some(thread_root) => counts
+                    .threads
+                    .entry(thread_root.to_owned())
+                    .or_insert_with(UnreadNotificationsCount::zero),
+                None => &mut counts.main_timeline,
+            };
+            count.add_notification(actions.iter().any(Action::is_highlight));
+        }
+
+        counts
+    }
+
+    /// The counts of all the events of the room, including the events in threads.
+    ///
+    /// These are the counts to use for clients that didn't opt into threaded notifications.
+    pub fn total(&self) -> UnreadNotificationsCount {
+        let mut total = self.main_timeline.clone();
+
+        for count in self.threads.values() {
+            total.highlight_count = add_counts(total.highlight_count, count.highlight_count);
+            total.notification_count =
+                add_counts(total.notification_count, count.notification_count);
+        }
+
+        total
+    }
+}
+
+impl UnreadNotificationsCount {
+    /// Creates an `UnreadNotificationsCount` with both counts set to zero.
+    fn zero() -> Self {
+        Self { highlight_count: Some(uint!(0)), notification_count: Some(uint!(0)) }
+    }
+
+    /// Adds a notification to the counts.
+    fn add_notification(&mut self, is_highlight: bool) {
+        self.notification_count = add_counts(self.notification_count, Some(uint!(1)));
+        if is_highlight {
+            self.highlight_count = add_counts(self.highlight_count, Some(uint!(1)));
+        }
+    }
+}
+
+/// Adds the given counts, treating a missing count as zero unless both are missing.
+fn add_counts(a: Option<UInt>, b: Option<UInt>) -> Option<UInt> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.saturating_add(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The fields of an event that are needed to compute the unread notifications counts.
+#[derive(Default, Deserialize)]
+struct EventInfo {
+    event_id: Option<OwnedEventId>,
+
+    #[serde(default)]
+    content: ContentInfo,
+}
+
+#[derive(Default, Deserialize)]
+struct ContentInfo {
+    #[serde(rename = "m.relates_to")]
+    relates_to: Option<RelationInfo>,
+}
+
+impl ContentInfo {
+    /// The ID of the root of the thread of the event, if any.
+    fn thread_root(&self) -> Option<&EventId> {
+        let relates_to = self.relates_to.as_ref()?;
+
+        match relates_to.rel_type.as_deref()? {
+            "m.thread" | "io.element.thread" => relates_to.event_id.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RelationInfo {
+    rel_type: Option<String>,
+    event_id: Option<OwnedEventId>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use js_int::uint;
+    use ruma_common::{
+        event_id,
+        power_levels::NotificationPowerLevels,
+        push::{PushConditionRoomCtx, Ruleset},
+        room_id,
+        serde::Raw,
+        user_id,
+    };
+    use serde_json::{json, Value as JsonValue};
+
+    use super::UnreadNotificationsCounts;
+
+    fn message(
+        event_id: &str,
+        sender: &str,
+        body: &str,
+        thread_root: Option<&str>,
+    ) -> Raw<JsonValue> {
+        let mut content = json!({ "msgtype": "m.text", "body": body });
+        if let Some(thread_root) = thread_root {
+            content["m.relates_to"] = json!({ "rel_type": "m.thread", "event_id": thread_root });
+        }
+
+        Raw::new(&json!({
+            "type": "m.room.message",
+            "event_id": event_id,
+            "sender": sender,
+            "origin_server_ts": 1_000_000,
+            "content": content,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn compute_unread_notifications_counts() {
+        let user_id = user_id!("@alice:example.org");
+        let ruleset = Ruleset::server_default(user_id);
+        let context = PushConditionRoomCtx {
+            room_id: room_id!("!room:example.org").to_owned(),
+            member_count: uint!(3),
+            user_id: user_id.to_owned(),
+            user_display_name: "Alice".into(),
+            users_power_levels: BTreeMap::new(),
+            default_power_level: 0.into(),
+            notification_power_levels: NotificationPowerLevels::new(),
+        };
+
+        let events = [
+            message("$root", "@bob:example.org", "Hello", None),
+            message("$read", "@bob:example.org", "Hello again", None),
+            message("$highlight", "@bob:example.org", "Are you there, Alice?", None),
+            message("$own", "@alice:example.org", "Hello", None),
+            message("$thread1", "@bob:example.org", "Hi", Some("$root")),
+            message("$thread2", "@bob:example.org", "Hi alice", Some("$root")),
+        ];
+
+        let counts = UnreadNotificationsCounts::compute(
+            &events,
+            &ruleset,
+            &context,
+            Some(event_id!("$read")),
+        );
+        assert_eq!(counts.main_timeline.notification_count, Some(uint!(1)));
+        assert_eq!(counts.main_timeline.highlight_count, Some(uint!(1)));
+        assert_eq!(counts.threads.len(), 1);
+        let thread = &counts.threads[event_id!("$root")];
+        assert_eq!(thread.notification_count, Some(uint!(2)));
+        assert_eq!(thread.highlight_count, Some(uint!(1)));
+
+        let total = counts.total();
+        assert_eq!(total.notification_count, Some(uint!(3)));
+        assert_eq!(total.highlight_count, Some(uint!(2)));
+
+        // Without read receipt, all events are unread.
+        let counts = UnreadNotificationsCounts::compute(&events, &ruleset, &context, None);
+        assert_eq!(counts.main_timeline.notification_count, Some(uint!(3)));
+        assert_eq!(counts.main_timeline.highlight_count, Some(uint!(1)));
+        assert_eq!(counts.total().notification_count, Some(uint!(5)));
+
+        // Nothing is unread after the last event.
+        let counts = UnreadNotificationsCounts::compute(
+            &events,
+            &ruleset,
+            &context,
+            Some(event_id!("$thread2")),
+        );
+        assert_eq!(counts.main_timeline.notification_count, Some(uint!(0)));
+        assert!(counts.threads.is_empty());
+    }
+}
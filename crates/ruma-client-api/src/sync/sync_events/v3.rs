@@ -6,6 +6,8 @@ use std::{collections::BTreeMap, time::Duration};
 
 use super::UnreadNotificationsCount;
 use js_int::UInt;
+#[cfg(feature = "unstable-msc3773")]
+use ruma_common::OwnedEventId;
 use ruma_common::{
     api::ruma_api,
     events::{
@@ -247,6 +249,20 @@ pub struct JoinedRoom {
     #[serde(default, skip_serializing_if = "UnreadNotificationsCount::is_empty")]
     pub unread_notifications: UnreadNotificationsCount,
 
+    /// Counts of unread notifications for threads in this room, by thread root event ID.
+    ///
+    /// This uses the unstable prefix in [MSC3773].
+    ///
+    /// [MSC3773]: https://github.com/matrix-org/matrix-spec-proposals/pull/3773
+    #[cfg(feature = "unstable-msc3773")]
+    #[serde(
+        rename = "org.matrix.msc3773.unread_thread_notifications",
+        alias = "unread_thread_notifications",
+        default,
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub unread_thread_notifications: BTreeMap<OwnedEventId, UnreadNotificationsCount>,
+
     /// The timeline of messages and state changes in the room.
     #[serde(default, skip_serializing_if = "Timeline::is_empty")]
     pub timeline: Timeline,
@@ -295,6 +311,9 @@ impl JoinedRoom {
             && self.account_data.is_empty()
             && self.ephemeral.is_empty();
 
+        #[cfg(feature = "unstable-msc3773")]
+        let is_empty = is_empty && self.unread_thread_notifications.is_empty();
+
         #[cfg(not(feature = "unstable-msc2654"))]
         return is_empty;
 
@@ -628,6 +647,34 @@ mod tests {
         let timeline_default_deserialized = from_json_value::<Timeline>(json!({})).unwrap();
         assert!(!timeline_default_deserialized.limited);
     }
+
+    #[cfg(feature = "unstable-msc3773")]
+    #[test]
+    fn unread_thread_notifications_serde() {
+        use js_int::uint;
+        use ruma_common::event_id;
+
+        use super::{JoinedRoom, UnreadNotificationsCount};
+
+        let mut joined_room = JoinedRoom::new();
+        joined_room.unread_thread_notifications.insert(
+            event_id!("$root").to_owned(),
+            assign!(UnreadNotificationsCount::new(), { notification_count: Some(uint!(2)) }),
+        );
+        let json = json!({
+            "org.matrix.msc3773.unread_thread_notifications": {
+                "$root": { "notification_count": 2 },
+            },
+        });
+        assert_eq!(to_json_value(&joined_room).unwrap(), json);
+
+        let joined_room = from_json_value::<JoinedRoom>(json).unwrap();
+        assert!(!joined_room.is_empty());
+        assert_eq!(
+            joined_room.unread_thread_notifications[event_id!("$root")].notification_count,
+            Some(uint!(2))
+        );
+    }
 }
 
 #[cfg(all(test, feature = "client"))]
@@ -48,8 +48,9 @@ ruma_api! {
         #[ruma_api(query)]
         pub timeout: Option<Duration>,
 
-        /// The lists of rooms we're interested in.
-        pub lists: &'a [SyncRequestList],
+        /// The lists of rooms we're interested in, by name.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub lists: BTreeMap<String, SyncRequestList>,
 
         /// Specific rooms and event types that we want to receive events from.
         #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
@@ -73,9 +74,9 @@ ruma_api! {
         /// The token to supply in the `pos` param of the next `/sync` request.
         pub pos: String,
 
-        /// Updates to the sliding room list.
-        #[serde(default, skip_serializing_if = "Vec::is_empty")]
-        pub lists: Vec<SyncList>,
+        /// Updates to the sliding room lists, by name.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub lists: BTreeMap<String, SyncList>,
 
         /// The updates on rooms.
         #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
@@ -1,30 +1,58 @@
 use std::{
-    sync::{Arc, Mutex},
-    time::Duration,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
 };
 
 use assign::assign;
 use async_stream::try_stream;
+use bytes::BufMut;
 use futures_core::stream::Stream;
+use futures_timer::Delay;
+use futures_util::{
+    future::{self, Either},
+    lock::Mutex as AsyncMutex,
+};
+use http::header::{HeaderValue, AUTHORIZATION};
+#[cfg(feature = "unstable-msc2965")]
+use ruma_client_api::discovery::discover_homeserver::AuthenticationServerInfo;
 use ruma_client_api::{
     account::register::{self, RegistrationKind},
-    session::login::{self, v3::LoginInfo},
+    session::{
+        login::{self, v3::LoginInfo},
+        refresh_token,
+    },
     sync::sync_events,
     uiaa::UserIdentifier,
 };
 use ruma_common::{
-    api::{MatrixVersion, OutgoingRequest, SendAccessToken},
+    api::{error::IntoHttpError, MatrixVersion, OutgoingRequest, SendAccessToken},
     presence::PresenceState,
     DeviceId, UserId,
 };
+use serde::Deserialize;
+use tracing::{debug, Instrument};
 
 use crate::{
-    add_user_id_to_query, send_customized_request, Error, HttpClient, ResponseError, ResponseResult,
+    add_user_id_to_query, deserialize_response,
+    middleware::{MiddlewareChain, Outcome},
+    send_span, serialize_request, Error, HttpClient, ResponseError, ResponseResult,
 };
 
 mod builder;
+mod discovery;
+mod session;
+mod uiaa;
+
+use self::session::SessionCallback;
+pub use self::{
+    builder::ClientBuilder,
+    discovery::DiscoveryError,
+    session::Session,
+    uiaa::{UiaaHandler, UiaaStage},
+};
 
-pub use self::builder::ClientBuilder;
+/// The time before the expiration of the access token when it is refreshed.
+const REFRESH_MARGIN: Duration = Duration::from_secs(30);
 
 /// A client for the Matrix client-server API.
 #[derive(Clone, Debug)]
@@ -39,11 +67,41 @@ struct ClientData<C> {
     /// The underlying HTTP client.
     http_client: C,
 
-    /// The access token, if logged in.
-    access_token: Mutex<Option<String>>,
+    /// The credentials used to authenticate the requests.
+    credentials: Mutex<Credentials>,
+
+    /// The callback to call when the session changes.
+    on_session_change: Option<SessionCallback>,
+
+    /// The lock held while refreshing the access token, since a refresh token can only be used
+    /// once.
+    refresh_lock: AsyncMutex<()>,
+
+    /// The middleware called for every attempt to send a request.
+    middleware: MiddlewareChain,
 
     /// The (known) Matrix versions the homeserver supports.
     supported_matrix_versions: Vec<MatrixVersion>,
+
+    /// The URL of the identity server, if it was discovered.
+    identity_server_url: Option<String>,
+
+    /// Information about the authentication server, if it was discovered.
+    #[cfg(feature = "unstable-msc2965")]
+    authentication: Option<AuthenticationServerInfo>,
+}
+
+/// The credentials of a [`Client`].
+#[derive(Debug)]
+enum Credentials {
+    /// The client is not authenticated.
+    None,
+
+    /// The client only has an access token, for example as an application service.
+    AccessToken(String),
+
+    /// The client is logged in as a user.
+    Session(Session),
 }
 
 impl Client<()> {
@@ -56,9 +114,97 @@ impl Client<()> {
 impl<C> Client<C> {
     /// Get a copy of the current `access_token`, if any.
     ///
-    /// Useful for serializing and persisting the session to be restored later.
+    /// This is the access token of the current session, if the client is logged in as a user.
     pub fn access_token(&self) -> Option<String> {
-        self.0.access_token.lock().expect("session mutex was poisoned").clone()
+        match &*self.lock_credentials() {
+            Credentials::None => None,
+            Credentials::AccessToken(access_token) => Some(access_token.clone()),
+            Credentials::Session(session) => Some(session.access_token.clone()),
+        }
+    }
+
+    /// Get a copy of the current session, if any.
+    ///
+    /// Useful for serializing and persisting the session to be restored later with
+    /// [`ClientBuilder::session()`].
+    pub fn session(&self) -> Option<Session> {
+        match &*self.lock_credentials() {
+            Credentials::Session(session) => Some(session.clone()),
+            _ => None,
+        }
+    }
+
+    fn lock_credentials(&self) -> MutexGuard<'_, Credentials> {
+        self.0.credentials.lock().expect("credentials mutex was poisoned")
+    }
+
+    /// Set the current session, and call the session callback.
+    fn set_session(&self, session: Session) {
+        *self.lock_credentials() = Credentials::Session(session.clone());
+
+        if let Some(on_session_change) = &self.0.on_session_change {
+            (on_session_change.0)(&session);
+        }
+    }
+
+    /// Whether the access token of the current session can be refreshed.
+    fn can_refresh_access_token(&self) -> bool {
+        self.session().map_or(false, |session| session.refresh_token.is_some())
+    }
+
+    /// Whether the access token of the current session expires soon and can be refreshed.
+    fn access_token_expires_soon(&self) -> bool {
+        match &*self.lock_credentials() {
+            Credentials::Session(session) if session.refresh_token.is_some() => session
+                .access_token_expires_at
+                .and_then(|expires_at| expires_at.to_system_time())
+                .map_or(false, |expires_at| expires_at <= SystemTime::now() + REFRESH_MARGIN),
+            _ => false,
+        }
+    }
+
+    /// Set the current session from the response of a registration.
+    ///
+    /// Returns the new session, if the response contains one.
+    fn set_registration_session(&self, response: &register::v3::Response) -> Option<Session> {
+        match (&response.access_token, &response.device_id) {
+            (Some(access_token), Some(device_id)) => {
+                let mut session = Session::new(
+                    self.0.homeserver_url.clone(),
+                    response.user_id.clone(),
+                    device_id.clone(),
+                    access_token.clone(),
+                );
+                session.refresh_token = response.refresh_token.clone();
+                session.set_expires_in(response.expires_in);
+                session.supported_matrix_versions = self.0.supported_matrix_versions.clone();
+
+                self.set_session(session.clone());
+                Some(session)
+            }
+            (access_token, _) => {
+                *self.lock_credentials() =
+                    access_token.clone().map_or(Credentials::None, Credentials::AccessToken);
+                None
+            }
+        }
+    }
+
+    /// Get the URL of the homeserver.
+    pub fn homeserver_url(&self) -> &str {
+        &self.0.homeserver_url
+    }
+
+    /// Get the URL of the identity server, if it was discovered when building the client.
+    pub fn identity_server_url(&self) -> Option<&str> {
+        self.0.identity_server_url.as_deref()
+    }
+
+    /// Get the information about the authentication server, if it was discovered when building
+    /// the client.
+    #[cfg(feature = "unstable-msc2965")]
+    pub fn authentication_server_info(&self) -> Option<&AuthenticationServerInfo> {
+        self.0.authentication.as_ref()
     }
 }
 
@@ -78,21 +224,182 @@ impl<C: HttpClient> Client<C> {
         R: OutgoingRequest,
         F: FnOnce(&mut http::Request<C::RequestBody>) -> Result<(), ResponseError<C, R>>,
     {
+        self.send_request_with_body(request, |_| Ok(()), customize).await
+    }
+
+    /// Makes a request to a Matrix API endpoint, after modifying its serialized body with
+    /// `map_body` and the HTTP request with `customize`.
+    async fn send_request_with_body<R, B, F>(
+        &self,
+        request: R,
+        map_body: B,
+        customize: F,
+    ) -> ResponseResult<C, R>
+    where
+        R: OutgoingRequest,
+        B: FnOnce(&mut Vec<u8>) -> Result<(), IntoHttpError>,
+        F: FnOnce(&mut http::Request<C::RequestBody>) -> Result<(), ResponseError<C, R>>,
+    {
+        if self.access_token_expires_soon() {
+            // If this fails, the request is retried after refreshing the access token again.
+            if self.refresh_access_token().await.is_err() {
+                debug!("Failed to refresh the access token before it expires");
+            }
+        }
+
         let access_token = self.access_token();
         let send_access_token = match access_token.as_deref() {
             Some(at) => SendAccessToken::IfRequired(at),
             None => SendAccessToken::None,
         };
 
-        send_customized_request(
-            &self.0.http_client,
+        let (parts, mut body) = serialize_request::<Vec<u8>, C, R>(
             &self.0.homeserver_url,
             send_access_token,
             &self.0.supported_matrix_versions,
             request,
-            customize,
-        )
-        .await
+        )?
+        .into_parts();
+        map_body(&mut body)?;
+
+        let mut http_req = http::Request::from_parts(parts, request_body::<C>(&body));
+        customize(&mut http_req)?;
+
+        // Keep the serialized request, to send it again when it is retried.
+        let (parts, _) = http_req.into_parts();
+        let mut http_req = http::Request::from_parts(parts, body);
+        let mut http_res = self.send_with_middleware::<R>(&http_req).await?;
+
+        // Retry once with a new access token if the access token expired.
+        if http_req.headers().contains_key(AUTHORIZATION)
+            && is_soft_logout(&http_res)
+            && self.can_refresh_access_token()
+            && self.refresh_access_token_after(access_token.as_deref()).await.is_ok()
+        {
+            let access_token = self.access_token().expect("access token was refreshed");
+            let authorization = HeaderValue::from_str(&format!("Bearer {access_token}"))
+                .map_err(IntoHttpError::from)?;
+            http_req.headers_mut().insert(AUTHORIZATION, authorization);
+
+            http_res = self.send_with_middleware::<R>(&http_req).await?;
+        }
+
+        deserialize_response::<C, R>(http_res)
+    }
+
+    /// Send the given HTTP request for the request type `R` through the middleware, retrying it
+    /// as long as the middleware asks for it.
+    async fn send_with_middleware<R: OutgoingRequest>(
+        &self,
+        http_req: &http::Request<Vec<u8>>,
+    ) -> Result<http::Response<C::ResponseBody>, ResponseError<C, R>> {
+        let mut attempts = self.0.middleware.attempts();
+
+        loop {
+            let mut attempt_req = copy_request(http_req);
+            let timeout = self.0.middleware.before_send(&mut attempt_req, &attempts);
+
+            let result = self.send_http_request::<R>(copy_request(&attempt_req), timeout).await;
+            let (retry_after, result) = match result {
+                Ok(http_res) => {
+                    let (parts, body) = http_res.into_parts();
+                    let response = http::Response::from_parts(parts, body.as_ref());
+                    let retry_after = self.0.middleware.after_receive(
+                        &attempt_req,
+                        &Outcome::Response(&response),
+                        &mut attempts,
+                    );
+                    let (parts, _) = response.into_parts();
+                    (retry_after, Ok(http::Response::from_parts(parts, body)))
+                }
+                Err(error) => {
+                    let outcome = match error {
+                        Error::Timeout => Outcome::Timeout,
+                        _ => Outcome::Error,
+                    };
+                    let retry_after =
+                        self.0.middleware.after_receive(&attempt_req, &outcome, &mut attempts);
+                    (retry_after, Err(error))
+                }
+            };
+
+            match retry_after {
+                Some(delay) => {
+                    debug!("Retrying request after {} milliseconds", delay.as_millis());
+                    Delay::new(delay).await;
+                }
+                None => return result,
+            }
+        }
+    }
+
+    /// Send the given HTTP request for the request type `R`, with the given timeout.
+    async fn send_http_request<R: OutgoingRequest>(
+        &self,
+        http_req: http::Request<C::RequestBody>,
+        timeout: Option<Duration>,
+    ) -> Result<http::Response<C::ResponseBody>, ResponseError<C, R>> {
+        let send_span = send_span::<C, R>(&self.0.homeserver_url);
+        let send = self.0.http_client.send_http_request(http_req).instrument(send_span);
+        match timeout {
+            Some(timeout) => match future::select(send, Delay::new(timeout)).await {
+                Either::Left((result, _)) => result.map_err(Error::Response),
+                Either::Right(_) => Err(Error::Timeout),
+            },
+            None => send.await.map_err(Error::Response),
+        }
+    }
+
+    /// Refresh the access token of the current session with its refresh token.
+    ///
+    /// This is done automatically when the access token expires soon, or when a request fails
+    /// because the access token expired.
+    ///
+    /// Returns [`Error::AuthenticationRequired`] if the current session doesn't have a refresh
+    /// token.
+    pub async fn refresh_access_token(
+        &self,
+    ) -> Result<(), Error<C::Error, ruma_client_api::Error>> {
+        let access_token = self.access_token();
+        self.refresh_access_token_after(access_token.as_deref()).await
+    }
+
+    /// Refresh the access token of the current session, unless it was already refreshed since the
+    /// given access token was used.
+    ///
+    /// Only one refresh happens at a time, concurrent calls wait for it and then use the new
+    /// access token.
+    async fn refresh_access_token_after(
+        &self,
+        used_access_token: Option<&str>,
+    ) -> Result<(), Error<C::Error, ruma_client_api::Error>> {
+        let _refresh_guard = self.0.refresh_lock.lock().await;
+
+        let mut session = self.session().ok_or(Error::AuthenticationRequired)?;
+        if used_access_token != Some(session.access_token.as_str()) {
+            return Ok(());
+        }
+
+        let refresh_token = session.refresh_token.clone().ok_or(Error::AuthenticationRequired)?;
+        let http_req = serialize_request::<Vec<u8>, C, refresh_token::v3::Request<'_>>(
+            &self.0.homeserver_url,
+            SendAccessToken::None,
+            &self.0.supported_matrix_versions,
+            refresh_token::v3::Request::new(&refresh_token),
+        )?;
+        let http_res =
+            self.send_with_middleware::<refresh_token::v3::Request<'_>>(&http_req).await?;
+        let response = deserialize_response::<C, refresh_token::v3::Request<'_>>(http_res)?;
+
+        session.access_token = response.access_token;
+        if let Some(refresh_token) = response.refresh_token {
+            session.refresh_token = Some(refresh_token);
+        }
+        session.set_expires_in(response.expires_in_ms);
+
+        self.set_session(session);
+
+        Ok(())
     }
 
     /// Makes a request to a Matrix API endpoint as a virtual user.
@@ -109,49 +416,67 @@ impl<C: HttpClient> Client<C> {
 
     /// Log in with a username and password.
     ///
-    /// In contrast to [`send_request`][Self::send_request], this method stores the access token
-    /// returned by the endpoint in this client, in addition to returning it.
+    /// In contrast to [`send_request`][Self::send_request], this method stores the session
+    /// returned by the endpoint in this client, in addition to returning it. The session can also
+    /// be obtained later with [`session()`][Self::session].
     pub async fn log_in(
         &self,
         user: &str,
         password: &str,
         device_id: Option<&DeviceId>,
         initial_device_display_name: Option<&str>,
-    ) -> Result<login::v3::Response, Error<C::Error, ruma_client_api::Error>> {
+    ) -> Result<Session, Error<C::Error, ruma_client_api::Error>> {
         let response = self
             .send_request(assign!(login::v3::Request::new(
                 LoginInfo::Password(login::v3::Password::new(UserIdentifier::UserIdOrLocalpart(user), password))), {
                 device_id,
                 initial_device_display_name,
+                refresh_token: true,
                 }
             ))
             .await?;
 
-        *self.0.access_token.lock().unwrap() = Some(response.access_token.clone());
+        let mut session = Session::new(
+            self.0.homeserver_url.clone(),
+            response.user_id,
+            response.device_id,
+            response.access_token,
+        );
+        session.refresh_token = response.refresh_token;
+        session.set_expires_in(response.expires_in);
+        session.supported_matrix_versions = self.0.supported_matrix_versions.clone();
 
-        Ok(response)
+        self.set_session(session.clone());
+
+        Ok(session)
     }
 
     /// Register as a guest.
     ///
-    /// In contrast to [`send_request`][Self::send_request], this method stores the access token
-    /// returned by the endpoint in this client, in addition to returning it.
+    /// In contrast to [`send_request`][Self::send_request], this method stores the session
+    /// returned by the endpoint in this client, in addition to returning it. The session can be
+    /// obtained with [`session()`][Self::session].
     pub async fn register_guest(
         &self,
     ) -> Result<register::v3::Response, Error<C::Error, ruma_client_api::uiaa::UiaaResponse>> {
         let response = self
-            .send_request(assign!(register::v3::Request::new(), { kind: RegistrationKind::Guest }))
+            .send_request(assign!(register::v3::Request::new(), {
+                kind: RegistrationKind::Guest,
+                refresh_token: true,
+            }))
             .await?;
 
-        *self.0.access_token.lock().unwrap() = response.access_token.clone();
+        self.set_registration_session(&response);
 
         Ok(response)
     }
 
     /// Register as a new user on this server.
     ///
-    /// In contrast to [`send_request`][Self::send_request], this method stores the access token
-    /// returned by the endpoint in this client, in addition to returning it.
+    /// In contrast to [`send_request`][Self::send_request], this method stores the session
+    /// returned by the endpoint in this client, in addition to returning it. The session can also
+    /// be obtained later with [`session()`][Self::session]. Returns `None` if the homeserver
+    /// didn't log in the new user.
     ///
     /// The username is the local part of the returned user_id. If it is omitted from this request,
     /// the server will generate one.
@@ -159,16 +484,16 @@ impl<C: HttpClient> Client<C> {
         &self,
         username: Option<&str>,
         password: &str,
-    ) -> Result<register::v3::Response, Error<C::Error, ruma_client_api::uiaa::UiaaResponse>> {
+    ) -> Result<Option<Session>, Error<C::Error, ruma_client_api::uiaa::UiaaResponse>> {
         let response = self
             .send_request(assign!(register::v3::Request::new(), {
-                username, password: Some(password)
+                username,
+                password: Some(password),
+                refresh_token: true,
             }))
             .await?;
 
-        *self.0.access_token.lock().unwrap() = response.access_token.clone();
-
-        Ok(response)
+        Ok(self.set_registration_session(&response))
     }
 
     /// Convenience method that represents repeated calls to the sync_events endpoint as a stream.
@@ -224,3 +549,231 @@ impl<C: HttpClient> Client<C> {
         }
     }
 }
+
+/// Convert the given body to the request body type of the HTTP client `C`.
+fn request_body<C: HttpClient>(body: &[u8]) -> C::RequestBody {
+    let mut request_body = C::RequestBody::default();
+    request_body.put_slice(body);
+    request_body
+}
+
+/// Copy the given request, converting its body to the given type.
+fn copy_request<B: Default + BufMut>(http_req: &http::Request<Vec<u8>>) -> http::Request<B> {
+    let mut body = B::default();
+    body.put_slice(http_req.body());
+
+    let mut copy = http::Request::new(body);
+    *copy.method_mut() = http_req.method().clone();
+    *copy.uri_mut() = http_req.uri().clone();
+    *copy.version_mut() = http_req.version();
+    *copy.headers_mut() = http_req.headers().clone();
+    copy
+}
+
+/// Whether the given response is an `M_UNKNOWN_TOKEN` error with `soft_logout` set.
+fn is_soft_logout<T: AsRef<[u8]>>(http_res: &http::Response<T>) -> bool {
+    #[derive(Deserialize)]
+    struct ErrorBody<'a> {
+        errcode: &'a str,
+        #[serde(default)]
+        soft_logout: bool,
+    }
+
+    http_res.status() == http::StatusCode::UNAUTHORIZED
+        && serde_json::from_slice::<ErrorBody<'_>>(http_res.body().as_ref())
+            .map_or(false, |error| error.errcode == "M_UNKNOWN_TOKEN" && error.soft_logout)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use http::header::AUTHORIZATION;
+    use ruma_client_api::{account::whoami, sync::sync_events};
+    use ruma_common::{api::MatrixVersion, device_id, user_id, MilliSecondsSinceUnixEpoch};
+
+    use super::{Client, Session};
+    use crate::{
+        middleware::{RateLimitRetry, Timeout},
+        Error, HttpClient,
+    };
+
+    /// An HTTP client that expires the access token `old`, and refreshes it to `new`.
+    #[derive(Clone, Default)]
+    struct MockHttpClient {
+        refreshes: Arc<Mutex<u32>>,
+        whoamis: Arc<Mutex<u32>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for MockHttpClient {
+        type RequestBody = Vec<u8>;
+        type ResponseBody = Vec<u8>;
+        type Error = &'static str;
+
+        async fn send_http_request(
+            &self,
+            req: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Self::Error> {
+            // Let concurrent requests run in the meantime.
+            tokio::task::yield_now().await;
+
+            let (status, body) = match req.uri().path() {
+                "/_matrix/client/v3/refresh" => {
+                    *self.refreshes.lock().unwrap() += 1;
+                    (200, r#"{ "access_token": "new", "expires_in_ms": 60000 }"#)
+                }
+                "/_matrix/client/v3/account/whoami" => {
+                    *self.whoamis.lock().unwrap() += 1;
+                    match req.headers().get(AUTHORIZATION).and_then(|h| h.to_str().ok()) {
+                        Some("Bearer new") => (200, r#"{ "user_id": "@alice:example.org" }"#),
+                        _ => (
+                            401,
+                            r#"{
+                                "errcode": "M_UNKNOWN_TOKEN",
+                                "error": "Expired access token",
+                                "soft_logout": true
+                            }"#,
+                        ),
+                    }
+                }
+                _ => return Err("unknown endpoint"),
+            };
+
+            Ok(http::Response::builder().status(status).body(body.as_bytes().to_owned()).unwrap())
+        }
+    }
+
+    /// An HTTP client that rate-limits the first request, and never responds to `/sync`.
+    #[derive(Clone, Default)]
+    struct RateLimitedHttpClient {
+        requests: Arc<Mutex<u32>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for RateLimitedHttpClient {
+        type RequestBody = Vec<u8>;
+        type ResponseBody = Vec<u8>;
+        type Error = &'static str;
+
+        async fn send_http_request(
+            &self,
+            req: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Self::Error> {
+            if req.uri().path() == "/_matrix/client/v3/sync" {
+                futures_util::future::pending::<()>().await;
+            }
+
+            let requests = {
+                let mut requests = self.requests.lock().unwrap();
+                *requests += 1;
+                *requests
+            };
+            let (status, body) = if requests == 1 {
+                (429, r#"{ "errcode": "M_LIMIT_EXCEEDED", "error": "", "retry_after_ms": 10 }"#)
+            } else {
+                (200, r#"{ "user_id": "@alice:example.org" }"#)
+            };
+
+            Ok(http::Response::builder().status(status).body(body.as_bytes().to_owned()).unwrap())
+        }
+    }
+
+    fn session() -> Session {
+        let mut session = Session::new(
+            "https://matrix.example.org".to_owned(),
+            user_id!("@alice:example.org").to_owned(),
+            device_id!("ABCDEF").to_owned(),
+            "old".to_owned(),
+        );
+        session.refresh_token = Some("refresh".to_owned());
+        session.supported_matrix_versions = vec![MatrixVersion::V1_3];
+        session
+    }
+
+    #[tokio::test]
+    async fn refresh_after_soft_logout() {
+        let http_client = MockHttpClient::default();
+        let changed_sessions = Arc::new(Mutex::new(Vec::new()));
+        let changed = changed_sessions.clone();
+
+        let client = Client::builder()
+            .session(session())
+            .on_session_change(move |session| {
+                changed.lock().unwrap().push(session.access_token.clone());
+            })
+            .http_client(http_client.clone())
+            .await
+            .unwrap();
+
+        let response = client.send_request(whoami::v3::Request::new()).await.unwrap();
+        assert_eq!(response.user_id, "@alice:example.org");
+        assert_eq!(*http_client.refreshes.lock().unwrap(), 1);
+        assert_eq!(*http_client.whoamis.lock().unwrap(), 2);
+        assert_eq!(*changed_sessions.lock().unwrap(), ["new"]);
+
+        let session = client.session().unwrap();
+        assert_eq!(session.access_token, "new");
+        assert_eq!(session.refresh_token.as_deref(), Some("refresh"));
+        assert!(session.access_token_expires_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn concurrent_refresh() {
+        let http_client = MockHttpClient::default();
+        let client =
+            Client::builder().session(session()).http_client(http_client.clone()).await.unwrap();
+
+        let (first, second) = futures_util::future::join(
+            client.send_request(whoami::v3::Request::new()),
+            client.send_request(whoami::v3::Request::new()),
+        )
+        .await;
+        first.unwrap();
+        second.unwrap();
+
+        // The refresh token is only used once.
+        assert_eq!(*http_client.refreshes.lock().unwrap(), 1);
+        assert_eq!(*http_client.whoamis.lock().unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn refresh_before_expiry() {
+        let http_client = MockHttpClient::default();
+        let mut session = session();
+        session.access_token_expires_at = MilliSecondsSinceUnixEpoch::from_system_time(
+            std::time::SystemTime::now() + Duration::from_secs(5),
+        );
+
+        let client =
+            Client::builder().session(session).http_client(http_client.clone()).await.unwrap();
+
+        client.send_request(whoami::v3::Request::new()).await.unwrap();
+        assert_eq!(*http_client.refreshes.lock().unwrap(), 1);
+        assert_eq!(*http_client.whoamis.lock().unwrap(), 1);
+        assert_eq!(client.access_token().as_deref(), Some("new"));
+    }
+
+    #[tokio::test]
+    async fn middleware() {
+        let http_client = RateLimitedHttpClient::default();
+        let client = Client::builder()
+            .session(session())
+            .middleware(RateLimitRetry::new())
+            .middleware(Timeout::new(Duration::from_millis(10)))
+            .http_client(http_client.clone())
+            .await
+            .unwrap();
+
+        let response = client.send_request(whoami::v3::Request::new()).await.unwrap();
+        assert_eq!(response.user_id, "@alice:example.org");
+        assert_eq!(*http_client.requests.lock().unwrap(), 2);
+
+        let error = client.send_request(sync_events::v3::Request::new()).await.unwrap_err();
+        assert!(matches!(error, Error::Timeout));
+    }
+}
@@ -1,38 +1,114 @@
 use std::sync::{Arc, Mutex};
 
 use ruma_client_api::discovery::get_supported_versions;
-use ruma_common::api::{MatrixVersion, SendAccessToken};
+use ruma_common::{
+    api::{MatrixVersion, SendAccessToken},
+    OwnedServerName, ServerName,
+};
 
-use super::{Client, ClientData};
-use crate::{DefaultConstructibleHttpClient, Error, HttpClient, HttpClientExt};
+use super::{
+    discovery::{discover_homeserver, DiscoveredHomeserver},
+    session::SessionCallback,
+    Client, ClientData, Credentials, Session,
+};
+use crate::{
+    middleware::{Middleware, MiddlewareChain},
+    DefaultConstructibleHttpClient, Error, HttpClient, HttpClientExt,
+};
 
 /// A [`Client`] builder.
 ///
 /// This type can be used to construct a `Client` through a few method calls.
 pub struct ClientBuilder {
     homeserver_url: Option<String>,
+    server_name: Option<OwnedServerName>,
     access_token: Option<String>,
+    session: Option<Session>,
+    on_session_change: Option<SessionCallback>,
+    middleware: MiddlewareChain,
     supported_matrix_versions: Option<Vec<MatrixVersion>>,
 }
 
 impl ClientBuilder {
     pub(super) fn new() -> Self {
-        Self { homeserver_url: None, access_token: None, supported_matrix_versions: None }
+        Self {
+            homeserver_url: None,
+            server_name: None,
+            access_token: None,
+            session: None,
+            on_session_change: None,
+            middleware: MiddlewareChain::default(),
+            supported_matrix_versions: None,
+        }
     }
 
     /// Set the homeserver URL.
     ///
-    /// The homeserver URL must be set before calling [`build()`][Self::build] or
-    /// [`http_client()`][Self::http_client].
+    /// The homeserver URL or the server name must be set before calling [`build()`][Self::build]
+    /// or [`http_client()`][Self::http_client].
     pub fn homeserver_url(self, url: String) -> Self {
         Self { homeserver_url: Some(url), ..self }
     }
 
+    /// Set the server name, to discover the homeserver URL.
+    ///
+    /// If the homeserver URL is not set, the [`build()`][Self::build] or
+    /// [`http_client()`][Self::http_client] method will discover it with the
+    /// `.well-known/matrix/client` file of the server name, and validate it with a
+    /// [`get_supported_versions`] request. Failures are returned as [`Error::Discovery`].
+    ///
+    /// If the homeserver URL is also set, with [`homeserver_url()`][Self::homeserver_url] or
+    /// [`session()`][Self::session], it takes precedence and the server name is ignored.
+    pub fn server_name(self, server_name: &ServerName) -> Self {
+        Self { server_name: Some(server_name.to_owned()), ..self }
+    }
+
     /// Set the access token.
+    ///
+    /// If a session is restored with [`session()`][Self::session], this replaces the access token
+    /// of the session.
     pub fn access_token(self, access_token: Option<String>) -> Self {
         Self { access_token, ..self }
     }
 
+    /// Restore a session.
+    ///
+    /// This sets the homeserver URL, the access token and the supported Matrix versions, if they
+    /// are known, from the session.
+    pub fn session(self, session: Session) -> Self {
+        let supported_matrix_versions = if session.supported_matrix_versions.is_empty() {
+            self.supported_matrix_versions
+        } else {
+            Some(session.supported_matrix_versions.clone())
+        };
+
+        Self {
+            homeserver_url: Some(session.homeserver_url.clone()),
+            access_token: Some(session.access_token.clone()),
+            supported_matrix_versions,
+            session: Some(session),
+            ..self
+        }
+    }
+
+    /// Set a callback to call when the session changes.
+    ///
+    /// The session changes when logging in or registering, and when the access token is
+    /// refreshed. This can be used to persist the latest session.
+    pub fn on_session_change(self, callback: impl Fn(&Session) + Send + Sync + 'static) -> Self {
+        Self { on_session_change: Some(SessionCallback(Box::new(callback))), ..self }
+    }
+
+    /// Add middleware to the client.
+    ///
+    /// The middleware is called for every attempt to send a request, after the middleware that
+    /// was added before. See the [`middleware`](crate::middleware) module for the built-in
+    /// policies.
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
     /// Set the supported Matrix versions.
     ///
     /// This method generally *shouldn't* be called. The [`build()`][Self::build] or
@@ -67,29 +143,51 @@ impl ClientBuilder {
     where
         C: HttpClient,
     {
-        let homeserver_url = self
-            .homeserver_url
-            .expect("homeserver URL has to be set prior to calling .build() or .http_client()");
-
-        let supported_matrix_versions = match self.supported_matrix_versions {
-            Some(versions) => versions,
-            None => http_client
-                .send_matrix_request(
-                    &homeserver_url,
-                    SendAccessToken::None,
-                    &[MatrixVersion::V1_0],
-                    get_supported_versions::Request::new(),
-                )
-                .await?
-                .known_versions()
-                .collect(),
+        let homeserver = match (self.homeserver_url, self.server_name) {
+            (Some(url), _) => DiscoveredHomeserver::new(url),
+            (None, Some(server_name)) => discover_homeserver(&http_client, &server_name).await?,
+            (None, None) => panic!(
+                "homeserver URL or server name has to be set prior to calling .build() or \
+                 .http_client()"
+            ),
+        };
+
+        let supported_matrix_versions =
+            match self.supported_matrix_versions.or(homeserver.supported_matrix_versions) {
+                Some(versions) => versions,
+                None => http_client
+                    .send_matrix_request(
+                        &homeserver.homeserver_url,
+                        SendAccessToken::None,
+                        &[MatrixVersion::V1_0],
+                        get_supported_versions::Request::new(),
+                    )
+                    .await?
+                    .known_versions()
+                    .collect(),
+            };
+
+        let credentials = match (self.session, self.access_token) {
+            (Some(session), access_token) => Credentials::Session(Session {
+                access_token: access_token.unwrap_or(session.access_token),
+                supported_matrix_versions: supported_matrix_versions.clone(),
+                ..session
+            }),
+            (None, Some(access_token)) => Credentials::AccessToken(access_token),
+            (None, None) => Credentials::None,
         };
 
         Ok(Client(Arc::new(ClientData {
-            homeserver_url,
+            homeserver_url: homeserver.homeserver_url,
             http_client,
-            access_token: Mutex::new(self.access_token),
+            credentials: Mutex::new(credentials),
+            on_session_change: self.on_session_change,
+            refresh_lock: Default::default(),
+            middleware: self.middleware,
             supported_matrix_versions,
+            identity_server_url: homeserver.identity_server_url,
+            #[cfg(feature = "unstable-msc2965")]
+            authentication: homeserver.authentication,
         })))
     }
 }
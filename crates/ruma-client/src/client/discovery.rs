@@ -0,0 +1,323 @@
+//! Discovery of the homeserver of a server name with `.well-known/matrix/client`.
+
+use std::fmt::{self, Display, Formatter};
+
+#[cfg(feature = "unstable-msc2965")]
+use ruma_client_api::discovery::discover_homeserver::AuthenticationServerInfo;
+use ruma_client_api::discovery::{discover_homeserver, get_supported_versions};
+use ruma_common::{
+    api::{IncomingResponse, MatrixVersion, OutgoingRequest, SendAccessToken},
+    ServerName,
+};
+use tracing::debug;
+
+use crate::{HttpClient, HttpClientExt};
+
+/// An error that can occur during the discovery of the homeserver of a server name.
+///
+/// The [spec] distinguishes between errors for which the user should be prompted for the
+/// homeserver URL (`FAIL_PROMPT`), and errors for which the user should only be informed of the
+/// failure (`FAIL_ERROR`), see [`should_prompt()`][Self::should_prompt].
+///
+/// [spec]: https://spec.matrix.org/v1.4/client-server-api/#well-known-uri
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DiscoveryError {
+    /// The `.well-known` response of the server name could not be fetched or is invalid.
+    InvalidWellKnown,
+
+    /// The server name doesn't have a `.well-known` response, and it is not the name of a
+    /// homeserver either.
+    NoHomeserver,
+
+    /// The base URL of the homeserver is not a valid URL.
+    InvalidHomeserverUrl,
+
+    /// The base URL of the homeserver doesn't point to a homeserver.
+    NotAHomeserver,
+
+    /// The base URL of the identity server is not a valid URL or doesn't point to an identity
+    /// server.
+    InvalidIdentityServer,
+}
+
+impl DiscoveryError {
+    /// Whether the user should be prompted for the homeserver URL.
+    ///
+    /// This is `FAIL_PROMPT` in the spec, otherwise this is `FAIL_ERROR`.
+    pub fn should_prompt(&self) -> bool {
+        matches!(self, Self::InvalidWellKnown | Self::NoHomeserver)
+    }
+}
+
+impl Display for DiscoveryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidWellKnown => write!(f, "Invalid .well-known response"),
+            Self::NoHomeserver => write!(f, "No homeserver found for the server name"),
+            Self::InvalidHomeserverUrl => write!(f, "Invalid homeserver URL"),
+            Self::NotAHomeserver => {
+                write!(f, "The homeserver URL doesn't point to a homeserver")
+            }
+            Self::InvalidIdentityServer => write!(f, "Invalid identity server"),
+        }
+    }
+}
+
+impl std::error::Error for DiscoveryError {}
+
+/// Information about a homeserver, that might have been discovered.
+pub(super) struct DiscoveredHomeserver {
+    /// The base URL of the homeserver.
+    pub homeserver_url: String,
+
+    /// The Matrix versions supported by the homeserver, if they are known.
+    pub supported_matrix_versions: Option<Vec<MatrixVersion>>,
+
+    /// The validated base URL of the identity server, if any.
+    pub identity_server_url: Option<String>,
+
+    /// Information about the authentication server, if any.
+    #[cfg(feature = "unstable-msc2965")]
+    pub authentication: Option<AuthenticationServerInfo>,
+}
+
+impl DiscoveredHomeserver {
+    /// Creates a `DiscoveredHomeserver` with only the given homeserver URL.
+    pub fn new(homeserver_url: String) -> Self {
+        Self {
+            homeserver_url,
+            supported_matrix_versions: None,
+            identity_server_url: None,
+            #[cfg(feature = "unstable-msc2965")]
+            authentication: None,
+        }
+    }
+}
+
+/// Discover the homeserver of the given server name.
+pub(super) async fn discover_homeserver<C: HttpClient>(
+    http_client: &C,
+    server_name: &ServerName,
+) -> Result<DiscoveredHomeserver, DiscoveryError> {
+    let server_url = format!("https://{server_name}");
+
+    let well_known = match get_well_known(http_client, &server_url).await? {
+        Some(well_known) => well_known,
+        // The server name doesn't use `.well-known`, which means that the user should provide
+        // the homeserver URL. Try the server name as the homeserver URL first.
+        None => {
+            let supported_matrix_versions = supported_matrix_versions(http_client, &server_url)
+                .await
+                .ok_or(DiscoveryError::NoHomeserver)?;
+
+            return Ok(DiscoveredHomeserver {
+                supported_matrix_versions: Some(supported_matrix_versions),
+                ..DiscoveredHomeserver::new(server_url)
+            });
+        }
+    };
+
+    let homeserver_url = validate_url(&well_known.homeserver.base_url)
+        .ok_or(DiscoveryError::InvalidHomeserverUrl)?;
+
+    let supported_matrix_versions = supported_matrix_versions(http_client, &homeserver_url)
+        .await
+        .ok_or(DiscoveryError::NotAHomeserver)?;
+
+    let identity_server_url = match &well_known.identity_server {
+        Some(identity_server) => {
+            let url = validate_url(&identity_server.base_url)
+                .ok_or(DiscoveryError::InvalidIdentityServer)?;
+            validate_identity_server(http_client, &url).await?;
+            Some(url)
+        }
+        None => None,
+    };
+
+    Ok(DiscoveredHomeserver {
+        homeserver_url,
+        supported_matrix_versions: Some(supported_matrix_versions),
+        identity_server_url,
+        #[cfg(feature = "unstable-msc2965")]
+        authentication: well_known.authentication,
+    })
+}
+
+/// Get the Matrix versions supported by the homeserver at the given URL.
+///
+/// Returns `None` if the request fails.
+async fn supported_matrix_versions<C: HttpClient>(
+    http_client: &C,
+    homeserver_url: &str,
+) -> Option<Vec<MatrixVersion>> {
+    let response = http_client
+        .send_matrix_request(
+            homeserver_url,
+            SendAccessToken::None,
+            &[MatrixVersion::V1_0],
+            get_supported_versions::Request::new(),
+        )
+        .await
+        .ok()?;
+
+    Some(response.known_versions().collect())
+}
+
+/// Get the `.well-known` response at the given URL.
+///
+/// Returns `Ok(None)` if the server doesn't have one.
+async fn get_well_known<C: HttpClient>(
+    http_client: &C,
+    server_url: &str,
+) -> Result<Option<discover_homeserver::Response>, DiscoveryError> {
+    let request = discover_homeserver::Request::new()
+        .try_into_http_request(server_url, SendAccessToken::None, &[MatrixVersion::V1_0])
+        .map_err(|_| DiscoveryError::InvalidWellKnown)?;
+
+    let response = http_client.send_http_request(request).await.map_err(|_| {
+        debug!("Failed to get the .well-known response of {server_url}");
+        DiscoveryError::InvalidWellKnown
+    })?;
+
+    if response.status() == http::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    discover_homeserver::Response::try_from_http_response(response)
+        .map(Some)
+        .map_err(|_| DiscoveryError::InvalidWellKnown)
+}
+
+/// Check that the given identity server URL points to an identity server.
+async fn validate_identity_server<C: HttpClient>(
+    http_client: &C,
+    url: &str,
+) -> Result<(), DiscoveryError> {
+    let request = http::Request::get(format!("{url}/_matrix/identity/v2"))
+        .body(C::RequestBody::default())
+        .map_err(|_| DiscoveryError::InvalidIdentityServer)?;
+
+    match http_client.send_http_request(request).await {
+        Ok(response) if response.status().is_success() => Ok(()),
+        _ => Err(DiscoveryError::InvalidIdentityServer),
+    }
+}
+
+/// Check that the given base URL is a valid HTTP(S) URL, and strip its trailing slash.
+fn validate_url(url: &str) -> Option<String> {
+    let url = url.trim_end_matches('/');
+    let uri: http::Uri = url.parse().ok()?;
+
+    let valid_scheme = matches!(uri.scheme_str(), Some("http" | "https"));
+    (valid_scheme && uri.authority().is_some()).then(|| url.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use ruma_common::server_name;
+
+    use super::{discover_homeserver, validate_url, DiscoveryError};
+    use crate::HttpClient;
+
+    /// An HTTP client that responds to requests for known URLs.
+    struct MockHttpClient(Vec<(&'static str, u16, &'static str)>);
+
+    #[async_trait]
+    impl HttpClient for MockHttpClient {
+        type RequestBody = Vec<u8>;
+        type ResponseBody = Vec<u8>;
+        type Error = &'static str;
+
+        async fn send_http_request(
+            &self,
+            req: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Self::Error> {
+            let url = req.uri().to_string();
+            let (_, status, body) =
+                self.0.iter().find(|(known, _, _)| *known == url).ok_or("connection refused")?;
+
+            Ok(http::Response::builder().status(*status).body(body.as_bytes().to_owned()).unwrap())
+        }
+    }
+
+    const VERSIONS: &str = r#"{ "versions": ["v1.1", "v1.2"] }"#;
+
+    #[tokio::test]
+    async fn well_known() {
+        let http_client = MockHttpClient(vec![
+            (
+                "https://example.org/.well-known/matrix/client",
+                200,
+                r#"{
+                    "m.homeserver": { "base_url": "https://matrix.example.org/" },
+                    "m.identity_server": { "base_url": "https://id.example.org" }
+                }"#,
+            ),
+            ("https://matrix.example.org/_matrix/client/versions", 200, VERSIONS),
+            ("https://id.example.org/_matrix/identity/v2", 200, "{}"),
+        ]);
+
+        let discovered =
+            discover_homeserver(&http_client, server_name!("example.org")).await.unwrap();
+        assert_eq!(discovered.homeserver_url, "https://matrix.example.org");
+        assert_eq!(discovered.supported_matrix_versions.unwrap().len(), 2);
+        assert_eq!(discovered.identity_server_url.as_deref(), Some("https://id.example.org"));
+    }
+
+    #[tokio::test]
+    async fn no_well_known() {
+        let http_client = MockHttpClient(vec![
+            ("https://example.org/.well-known/matrix/client", 404, ""),
+            ("https://example.org/_matrix/client/versions", 200, VERSIONS),
+        ]);
+
+        let discovered =
+            discover_homeserver(&http_client, server_name!("example.org")).await.unwrap();
+        assert_eq!(discovered.homeserver_url, "https://example.org");
+        assert_eq!(discovered.identity_server_url, None);
+    }
+
+    #[tokio::test]
+    async fn no_well_known_nor_homeserver() {
+        let http_client =
+            MockHttpClient(vec![("https://example.org/.well-known/matrix/client", 404, "")]);
+
+        let error =
+            discover_homeserver(&http_client, server_name!("example.org")).await.err().unwrap();
+        assert!(matches!(error, DiscoveryError::NoHomeserver));
+        assert!(error.should_prompt());
+    }
+
+    #[tokio::test]
+    async fn failures() {
+        let error = discover_homeserver(&MockHttpClient(Vec::new()), server_name!("example.org"))
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(error, DiscoveryError::InvalidWellKnown));
+        assert!(error.should_prompt());
+
+        let http_client = MockHttpClient(vec![(
+            "https://example.org/.well-known/matrix/client",
+            200,
+            r#"{ "m.homeserver": { "base_url": "https://matrix.example.org" } }"#,
+        )]);
+        let error =
+            discover_homeserver(&http_client, server_name!("example.org")).await.err().unwrap();
+        assert!(matches!(error, DiscoveryError::NotAHomeserver));
+        assert!(!error.should_prompt());
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(validate_url("https://example.org/").as_deref(), Some("https://example.org"));
+        assert_eq!(
+            validate_url("http://localhost:8008/matrix").as_deref(),
+            Some("http://localhost:8008/matrix")
+        );
+        assert_eq!(validate_url("example.org"), None);
+        assert_eq!(validate_url("ftp://example.org"), None);
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use ruma_common::{api::MatrixVersion, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedUserId};
+use serde::{Deserialize, Serialize};
+
+/// The session of a logged-in user.
+///
+/// The session is set by [`Client::log_in()`], [`Client::register_guest()`] and
+/// [`Client::register_user()`], and can be obtained with [`Client::session()`]. It can be
+/// serialized to be persisted, and restored later with [`ClientBuilder::session()`].
+///
+/// [`Client::log_in()`]: crate::Client::log_in
+/// [`Client::register_guest()`]: crate::Client::register_guest
+/// [`Client::register_user()`]: crate::Client::register_user
+/// [`Client::session()`]: crate::Client::session
+/// [`ClientBuilder::session()`]: crate::ClientBuilder::session
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[non_exhaustive]
+pub struct Session {
+    /// The URL of the homeserver.
+    pub homeserver_url: String,
+
+    /// The ID of the logged-in user.
+    pub user_id: OwnedUserId,
+
+    /// The ID of the device of the session.
+    pub device_id: OwnedDeviceId,
+
+    /// The access token of the session.
+    pub access_token: String,
+
+    /// The refresh token of the session, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+
+    /// The time when the access token expires, if it expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_token_expires_at: Option<MilliSecondsSinceUnixEpoch>,
+
+    /// The (known) Matrix versions the homeserver supports.
+    ///
+    /// If this is empty, the supported versions are requested again when restoring the session.
+    #[serde(default, with = "matrix_versions")]
+    pub supported_matrix_versions: Vec<MatrixVersion>,
+}
+
+impl Session {
+    /// Creates a new `Session` with the given homeserver URL, user ID, device ID and access token.
+    pub fn new(
+        homeserver_url: String,
+        user_id: OwnedUserId,
+        device_id: OwnedDeviceId,
+        access_token: String,
+    ) -> Self {
+        Self {
+            homeserver_url,
+            user_id,
+            device_id,
+            access_token,
+            refresh_token: None,
+            access_token_expires_at: None,
+            supported_matrix_versions: Vec::new(),
+        }
+    }
+
+    /// Set the time when the access token expires from the duration of its validity.
+    pub(crate) fn set_expires_in(&mut self, expires_in: Option<Duration>) {
+        self.access_token_expires_at = expires_in.and_then(|expires_in| {
+            MilliSecondsSinceUnixEpoch::from_system_time(SystemTime::now() + expires_in)
+        });
+    }
+}
+
+/// A callback called when the session of a client changes.
+pub(crate) struct SessionCallback(pub Box<dyn Fn(&Session) + Send + Sync>);
+
+impl fmt::Debug for SessionCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionCallback").finish_non_exhaustive()
+    }
+}
+
+/// (De)serialize the Matrix versions as strings, ignoring unknown versions.
+mod matrix_versions {
+    use ruma_common::api::MatrixVersion;
+    use serde::{ser::SerializeSeq, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        versions: &[MatrixVersion],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(versions.len()))?;
+        for version in versions {
+            seq.serialize_element(&version.to_string())?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<MatrixVersion>, D::Error> {
+        let versions = Vec::<String>::deserialize(deserializer)?;
+        Ok(versions.iter().filter_map(|version| version.parse().ok()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma_common::{api::MatrixVersion, device_id, user_id};
+    use serde_json::{from_value as from_json_value, json, to_value as to_json_value};
+
+    use super::Session;
+    use crate::{http_client::Dummy, Client};
+
+    #[test]
+    fn serde_roundtrip() {
+        let mut session = Session::new(
+            "https://matrix.example.org".to_owned(),
+            user_id!("@alice:example.org").to_owned(),
+            device_id!("ABCDEF").to_owned(),
+            "access_token".to_owned(),
+        );
+        session.refresh_token = Some("refresh_token".to_owned());
+        session.supported_matrix_versions = vec![MatrixVersion::V1_1, MatrixVersion::V1_2];
+
+        let json = json!({
+            "homeserver_url": "https://matrix.example.org",
+            "user_id": "@alice:example.org",
+            "device_id": "ABCDEF",
+            "access_token": "access_token",
+            "refresh_token": "refresh_token",
+            "supported_matrix_versions": ["v1.1", "v1.2"],
+        });
+        assert_eq!(to_json_value(&session).unwrap(), json);
+
+        let mut json = json;
+        json["supported_matrix_versions"] = json!(["v1.2", "v99.0"]);
+        let session: Session = from_json_value(json).unwrap();
+        assert_eq!(session.user_id, "@alice:example.org");
+        assert_eq!(session.refresh_token.as_deref(), Some("refresh_token"));
+        assert_eq!(session.access_token_expires_at, None);
+        assert_eq!(session.supported_matrix_versions, [MatrixVersion::V1_2]);
+    }
+
+    #[tokio::test]
+    async fn restore() {
+        let mut session = Session::new(
+            "https://matrix.example.org".to_owned(),
+            user_id!("@alice:example.org").to_owned(),
+            device_id!("ABCDEF").to_owned(),
+            "access_token".to_owned(),
+        );
+        session.supported_matrix_versions = vec![MatrixVersion::V1_2];
+
+        let client = Client::builder().session(session).http_client(Dummy).await.unwrap();
+
+        assert_eq!(client.homeserver_url(), "https://matrix.example.org");
+        assert_eq!(client.access_token().as_deref(), Some("access_token"));
+        let session = client.session().unwrap();
+        assert_eq!(session.device_id, "ABCDEF");
+        assert_eq!(session.supported_matrix_versions, [MatrixVersion::V1_2]);
+    }
+
+    #[tokio::test]
+    async fn restore_with_access_token() {
+        let session = Session::new(
+            "https://matrix.example.org".to_owned(),
+            user_id!("@alice:example.org").to_owned(),
+            device_id!("ABCDEF").to_owned(),
+            "access_token".to_owned(),
+        );
+
+        let client = Client::builder()
+            .session(session)
+            .access_token(Some("other_access_token".to_owned()))
+            .supported_matrix_versions(vec![MatrixVersion::V1_2])
+            .http_client(Dummy)
+            .await
+            .unwrap();
+
+        assert_eq!(client.access_token().as_deref(), Some("other_access_token"));
+        assert_eq!(client.session().unwrap().access_token, "other_access_token");
+    }
+}
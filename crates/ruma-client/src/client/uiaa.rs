@@ -0,0 +1,342 @@
+//! Driver for the User-Interactive Authentication API.
+
+use async_trait::async_trait;
+use ruma_client_api::uiaa::{AuthData, AuthFlow, AuthType, Dummy, UiaaInfo, UiaaResponse};
+use ruma_common::{
+    api::{
+        error::{FromHttpResponseError, IntoHttpError, ServerError},
+        OutgoingRequest,
+    },
+    serde::JsonObject,
+};
+use serde_json::Value as JsonValue;
+
+use super::Client;
+use crate::{Error, HttpClient, ResponseResult};
+
+/// A handler for the stages of the User-Interactive Authentication API.
+///
+/// Used with [`Client::send_uiaa_request()`] to complete the stages that require input from the
+/// user. The `m.login.dummy` stage is completed automatically.
+#[async_trait]
+pub trait UiaaHandler: Send {
+    /// Whether this handler can complete stages of the given type.
+    ///
+    /// Only the flows whose stages are all supported are selected.
+    fn supports(&self, auth_type: &AuthType) -> bool;
+
+    /// Complete the given stage.
+    ///
+    /// The returned authentication data is sent with the request, the session is set
+    /// automatically. If a previous attempt to complete this stage failed, the error is available
+    /// in the [`auth_error`](UiaaInfo::auth_error) of the [`UiaaStage::info`].
+    ///
+    /// Returns `None` to abort the authentication, in which case the last response of the
+    /// homeserver is returned as an error.
+    async fn complete_stage<'a>(&'a mut self, stage: UiaaStage<'_>) -> Option<AuthData<'a>>;
+}
+
+/// A stage of the User-Interactive Authentication API to complete.
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct UiaaStage<'a> {
+    /// The type of the stage.
+    pub auth_type: &'a AuthType,
+
+    /// The last response of the homeserver.
+    pub info: &'a UiaaInfo,
+
+    /// The URL of the homeserver.
+    homeserver_url: &'a str,
+}
+
+impl UiaaStage<'_> {
+    /// The parameters of the homeserver for this stage, if any.
+    pub fn params(&self) -> Option<JsonValue> {
+        let mut params = serde_json::from_str::<JsonObject>(self.info.params.get()).ok()?;
+        params.remove(self.auth_type.as_str())
+    }
+
+    /// The URL of the fallback web page to complete this stage, if the homeserver provided a
+    /// session.
+    ///
+    /// Once the user completed the stage on this page, it can be acknowledged with
+    /// [`AuthData::fallback_acknowledgement()`].
+    pub fn fallback_url(&self) -> Option<String> {
+        let session = self.info.session.as_deref()?;
+        Some(format!(
+            "{}/_matrix/client/v3/auth/{}/fallback/web?session={session}",
+            self.homeserver_url,
+            self.auth_type.as_str()
+        ))
+    }
+}
+
+impl<C: HttpClient> Client<C> {
+    /// Makes a request to a Matrix API endpoint that uses the User-Interactive Authentication API.
+    ///
+    /// The request is sent with the `auth` that it contains, usually `None`. As long as the
+    /// homeserver responds that more authentication is needed, this selects the first flow whose
+    /// stages can be completed, lets the `handler` complete the next stage of the flow, and sends
+    /// the request again with the authentication data.
+    ///
+    /// Returns the last response of the homeserver as an error if no flow can be completed, or if
+    /// the `handler` aborts the authentication.
+    pub async fn send_uiaa_request<R, H>(&self, request: R, handler: &mut H) -> ResponseResult<C, R>
+    where
+        R: OutgoingRequest<EndpointError = UiaaResponse> + Clone,
+        H: UiaaHandler,
+    {
+        let mut auth: Option<JsonObject> = None;
+
+        loop {
+            let result = match auth.take() {
+                Some(auth) => {
+                    self.send_request_with_body(
+                        request.clone(),
+                        move |body| set_auth(body, auth),
+                        keep_request,
+                    )
+                    .await
+                }
+                None => self.send_request(request.clone()).await,
+            };
+
+            let info = match &result {
+                Err(Error::FromHttpResponse(FromHttpResponseError::Server(
+                    ServerError::Known(UiaaResponse::AuthResponse(info)),
+                ))) => info,
+                _ => return result,
+            };
+
+            let auth_type = match next_stage(info, handler) {
+                Some(auth_type) => auth_type,
+                None => return result,
+            };
+
+            let auth_data = if *auth_type == AuthType::Dummy {
+                // The dummy stage can't fail, don't retry it forever.
+                if info.auth_error.is_some() {
+                    return result;
+                }
+
+                serialize_auth_data(&AuthData::Dummy(Dummy::new()), info)
+            } else {
+                let stage = UiaaStage { auth_type, info, homeserver_url: self.homeserver_url() };
+                match handler.complete_stage(stage).await {
+                    Some(auth_data) => serialize_auth_data(&auth_data, info),
+                    None => return result,
+                }
+            };
+
+            auth = Some(auth_data.map_err(IntoHttpError::from)?);
+        }
+    }
+}
+
+/// The next stage to complete, in the first flow that can be completed by the given handler.
+fn next_stage<'a, H: UiaaHandler>(info: &'a UiaaInfo, handler: &H) -> Option<&'a AuthType> {
+    let can_complete = |flow: &AuthFlow| {
+        flow.stages.starts_with(&info.completed)
+            && flow.stages[info.completed.len()..]
+                .iter()
+                .all(|auth_type| *auth_type == AuthType::Dummy || handler.supports(auth_type))
+    };
+
+    info.flows.iter().find(|flow| can_complete(flow))?.stages.get(info.completed.len())
+}
+
+/// Serialize the given authentication data, with the session of the given UIAA response.
+fn serialize_auth_data(
+    auth_data: &AuthData<'_>,
+    info: &UiaaInfo,
+) -> serde_json::Result<JsonObject> {
+    let mut auth = match serde_json::to_value(auth_data)? {
+        JsonValue::Object(auth) => auth,
+        _ => unreachable!("authentication data always serializes to an object"),
+    };
+
+    if let Some(session) = &info.session {
+        auth.insert("session".to_owned(), session.clone().into());
+    }
+
+    Ok(auth)
+}
+
+/// Keep the given HTTP request as it is.
+fn keep_request<B, E>(_: &mut http::Request<B>) -> Result<(), E> {
+    Ok(())
+}
+
+/// Set the `auth` field of the given serialized request body.
+fn set_auth(body: &mut Vec<u8>, auth: JsonObject) -> Result<(), IntoHttpError> {
+    let mut object = if body.is_empty() {
+        JsonObject::new()
+    } else {
+        serde_json::from_slice::<JsonObject>(body)?
+    };
+    object.insert("auth".to_owned(), auth.into());
+
+    *body = serde_json::to_vec(&object)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use ruma_client_api::{
+        account::change_password,
+        uiaa::{AuthData, AuthType, Password, UiaaResponse},
+    };
+    use ruma_common::{
+        api::{
+            error::{FromHttpResponseError, ServerError},
+            MatrixVersion,
+        },
+        user_id, OwnedUserId,
+    };
+    use serde_json::{from_slice as from_json_slice, json, Value as JsonValue};
+
+    use super::{UiaaHandler, UiaaStage};
+    use crate::{Client, Error, HttpClient};
+
+    /// An HTTP client that requires the password and dummy stages, and records the `auth` of the
+    /// requests.
+    #[derive(Clone, Default)]
+    struct MockHttpClient {
+        auths: Arc<Mutex<Vec<JsonValue>>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for MockHttpClient {
+        type RequestBody = Vec<u8>;
+        type ResponseBody = Vec<u8>;
+        type Error = &'static str;
+
+        async fn send_http_request(
+            &self,
+            req: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Self::Error> {
+            let body: JsonValue = from_json_slice(req.body()).unwrap();
+            let auth = body.get("auth").cloned().unwrap_or(JsonValue::Null);
+            self.auths.lock().unwrap().push(auth.clone());
+
+            let completed = match auth["type"].as_str() {
+                None => json!([]),
+                Some("m.login.password") if auth["password"] == "correct" => {
+                    json!(["m.login.password"])
+                }
+                Some("m.login.password") => json!([]),
+                Some("m.login.dummy") => {
+                    return Ok(http::Response::new(b"{}".to_vec()));
+                }
+                Some(_) => panic!("unexpected auth type"),
+            };
+
+            let mut info = json!({
+                "flows": [
+                    { "stages": ["m.login.email.identity"] },
+                    { "stages": ["m.login.password", "m.login.dummy"] },
+                ],
+                "params": { "m.login.password": { "hint": "secret" } },
+                "session": "xyz",
+                "completed": completed,
+            });
+            if auth["password"] == "wrong" {
+                info["errcode"] = "M_FORBIDDEN".into();
+                info["error"] = "Invalid password".into();
+            }
+
+            Ok(http::Response::builder()
+                .status(401)
+                .body(serde_json::to_vec(&info).unwrap())
+                .unwrap())
+        }
+    }
+
+    /// A handler that tries the given passwords in order.
+    struct PasswordHandler {
+        user_id: OwnedUserId,
+        passwords: Vec<&'static str>,
+        params: Vec<Option<JsonValue>>,
+    }
+
+    #[async_trait]
+    impl UiaaHandler for PasswordHandler {
+        fn supports(&self, auth_type: &AuthType) -> bool {
+            *auth_type == AuthType::Password
+        }
+
+        async fn complete_stage<'a>(&'a mut self, stage: UiaaStage<'_>) -> Option<AuthData<'a>> {
+            self.params.push(stage.params());
+            let password = self.passwords.pop()?;
+            Some(AuthData::Password(Password::new((&self.user_id).into(), password)))
+        }
+    }
+
+    async fn client(http_client: MockHttpClient) -> Client<MockHttpClient> {
+        Client::builder()
+            .homeserver_url("https://matrix.example.org".to_owned())
+            .access_token(Some("token".to_owned()))
+            .supported_matrix_versions(vec![MatrixVersion::V1_3])
+            .http_client(http_client)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn password_and_dummy() {
+        let http_client = MockHttpClient::default();
+        let client = client(http_client.clone()).await;
+        let mut handler = PasswordHandler {
+            user_id: user_id!("@alice:example.org").to_owned(),
+            passwords: vec!["correct", "wrong"],
+            params: Vec::new(),
+        };
+
+        client
+            .send_uiaa_request(change_password::v3::Request::new("new"), &mut handler)
+            .await
+            .unwrap();
+
+        let auths = http_client.auths.lock().unwrap();
+        assert_eq!(auths.len(), 4);
+        assert_eq!(auths[0], JsonValue::Null);
+        assert_eq!(auths[1]["password"], "wrong");
+        assert_eq!(auths[1]["session"], "xyz");
+        assert_eq!(auths[2]["password"], "correct");
+        assert_eq!(auths[3], json!({ "type": "m.login.dummy", "session": "xyz" }));
+        assert_eq!(
+            handler.params,
+            [Some(json!({ "hint": "secret" })), Some(json!({ "hint": "secret" }))]
+        );
+    }
+
+    #[tokio::test]
+    async fn abort() {
+        let http_client = MockHttpClient::default();
+        let client = client(http_client.clone()).await;
+        let mut handler = PasswordHandler {
+            user_id: user_id!("@alice:example.org").to_owned(),
+            passwords: vec!["wrong"],
+            params: Vec::new(),
+        };
+
+        let error = client
+            .send_uiaa_request(change_password::v3::Request::new("new"), &mut handler)
+            .await
+            .unwrap_err();
+
+        match error {
+            Error::FromHttpResponse(FromHttpResponseError::Server(ServerError::Known(
+                UiaaResponse::AuthResponse(info),
+            ))) => {
+                assert_eq!(info.auth_error.unwrap().message, "Invalid password");
+            }
+            _ => panic!("unexpected error"),
+        }
+        assert_eq!(http_client.auths.lock().unwrap().len(), 2);
+    }
+}
@@ -1,6 +1,8 @@
 //! Error conditions.
 
 use std::fmt::{self, Debug, Display, Formatter};
+#[cfg(feature = "federation-api")]
+use std::time::Duration;
 
 use ruma_common::api::error::{FromHttpResponseError, IntoHttpError};
 
@@ -22,6 +24,29 @@ pub enum Error<E, F> {
 
     /// Converting the HTTP response to one of ruma's types failed.
     FromHttpResponse(FromHttpResponseError<F>),
+
+    /// No response was received before the [`Timeout`](crate::middleware::Timeout).
+    #[cfg(feature = "client-api")]
+    Timeout,
+
+    /// The discovery of the homeserver failed.
+    #[cfg(feature = "client-api")]
+    Discovery(crate::DiscoveryError),
+
+    /// Signing the federation request failed.
+    #[cfg(feature = "federation-api")]
+    Signatures(ruma_signatures::Error),
+
+    /// The destination server of the federation request is backed off after previous failures to
+    /// reach it.
+    ///
+    /// Contains the remaining time until the destination can be retried.
+    #[cfg(feature = "federation-api")]
+    Backoff(Duration),
+
+    /// The destination server name of the federation request was resolved to no address.
+    #[cfg(feature = "federation-api")]
+    NoDestinationAddress,
 }
 
 impl<E: Display, F: Display> Display for Error<E, F> {
@@ -34,6 +59,18 @@ impl<E: Display, F: Display> Display for Error<E, F> {
             Self::Url(err) => write!(f, "Invalid URL: {err}"),
             Self::Response(err) => write!(f, "Couldn't obtain a response: {err}"),
             Self::FromHttpResponse(err) => write!(f, "HTTP response conversion failed: {err}"),
+            #[cfg(feature = "client-api")]
+            Self::Timeout => write!(f, "The request timed out"),
+            #[cfg(feature = "client-api")]
+            Self::Discovery(err) => write!(f, "Homeserver discovery failed: {err}"),
+            #[cfg(feature = "federation-api")]
+            Self::Signatures(err) => write!(f, "Signing the request failed: {err}"),
+            #[cfg(feature = "federation-api")]
+            Self::Backoff(remaining) => {
+                write!(f, "The destination is backed off for {} more seconds", remaining.as_secs())
+            }
+            #[cfg(feature = "federation-api")]
+            Self::NoDestinationAddress => write!(f, "The destination has no address to connect to"),
         }
     }
 }
@@ -64,4 +101,18 @@ impl<E, F> From<FromHttpResponseError<F>> for Error<E, F> {
     }
 }
 
+#[cfg(feature = "client-api")]
+impl<E, F> From<crate::DiscoveryError> for Error<E, F> {
+    fn from(err: crate::DiscoveryError) -> Self {
+        Error::Discovery(err)
+    }
+}
+
+#[cfg(feature = "federation-api")]
+impl<E, F> From<ruma_signatures::Error> for Error<E, F> {
+    fn from(err: ruma_signatures::Error) -> Self {
+        Error::Signatures(err)
+    }
+}
+
 impl<E: Debug + Display, F: Debug + Display> std::error::Error for Error<E, F> {}
@@ -0,0 +1,542 @@
+//! A client for the Matrix server-server API.
+
+use std::{
+    any::type_name,
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::BufMut;
+use http::header::{HeaderValue, AUTHORIZATION, HOST};
+use ruma_common::{
+    api::{
+        error::IntoHttpError, IncomingResponse, MatrixVersion, OutgoingRequest, SendAccessToken,
+    },
+    CanonicalJsonObject, OwnedServerName, ServerName,
+};
+use ruma_signatures::{sign_federation_request, Ed25519KeyPair};
+use serde_json::from_slice as from_json_slice;
+use tracing::{debug, info_span, Instrument};
+
+use crate::{
+    server_discovery::{DestinationAddress, ResolvedDestination, ResolverBackend, ServerResolver},
+    Error, HttpClient, ResponseError, ResponseResult,
+};
+
+/// The Matrix versions used to select the paths of the federation endpoints.
+const SUPPORTED_MATRIX_VERSIONS: &[MatrixVersion] = &[MatrixVersion::V1_3];
+
+/// The time a destination is backed off after the first failure to reach it.
+const MIN_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The maximum time a destination is backed off.
+const MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A client for the Matrix server-server API.
+///
+/// Requests are sent to the address of the destination server found with a [`ServerResolver`],
+/// and are authenticated with an `Authorization` header of the `X-Matrix` scheme, signed with the
+/// key pair of the origin server.
+///
+/// Destinations that can't be reached are backed off exponentially: until the backoff expires,
+/// requests to them fail immediately with [`Error::Backoff`].
+///
+/// The host of the URL of the requests is the [`tls_server_name`] of the destination, so the HTTP
+/// client validates the TLS certificate and sends SNI for the name expected by the destination
+/// server. The address to connect to is attached to the requests as a [`DestinationAddress`]
+/// extension, and the HTTP client must connect to it instead of resolving the host of the URL.
+///
+/// [`tls_server_name`]: ResolvedDestination::tls_server_name
+#[derive(Clone, Debug)]
+pub struct FederationClient<C, B>(Arc<FederationClientData<C, B>>);
+
+#[derive(Debug)]
+struct FederationClientData<C, B> {
+    /// The name of the server sending the requests.
+    origin: OwnedServerName,
+
+    /// The key pair used to sign the requests.
+    key_pair: Ed25519KeyPair,
+
+    /// The underlying HTTP client.
+    http_client: C,
+
+    /// The resolver of the destination server names.
+    resolver: ServerResolver<B>,
+
+    /// The backoff state of the destinations that couldn't be reached.
+    backoff: Mutex<BTreeMap<OwnedServerName, Backoff>>,
+}
+
+#[derive(Debug)]
+struct Backoff {
+    /// The number of consecutive failures to reach the destination.
+    failures: u32,
+
+    /// The time until which requests to the destination are not sent.
+    retry_at: Instant,
+}
+
+impl<C, B> FederationClient<C, B> {
+    /// Creates a new `FederationClient` for the given origin server.
+    pub fn new(
+        origin: OwnedServerName,
+        key_pair: Ed25519KeyPair,
+        http_client: C,
+        resolver: ServerResolver<B>,
+    ) -> Self {
+        Self(Arc::new(FederationClientData {
+            origin,
+            key_pair,
+            http_client,
+            resolver,
+            backoff: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    /// Get the name of the server sending the requests.
+    pub fn origin(&self) -> &ServerName {
+        &self.0.origin
+    }
+
+    /// Get a reference to the resolver of the destination server names.
+    pub fn resolver(&self) -> &ServerResolver<B> {
+        &self.0.resolver
+    }
+
+    /// Get the time until which requests to the given destination are backed off, if any.
+    pub fn backoff_until(&self, destination: &ServerName) -> Option<Instant> {
+        let backoff = self.0.backoff.lock().expect("backoff mutex was poisoned");
+        backoff.get(destination).map(|b| b.retry_at).filter(|retry_at| *retry_at > Instant::now())
+    }
+
+    /// Reset the backoff of the given destination.
+    ///
+    /// This is useful when the destination is known to be reachable again, for example because it
+    /// sent a request to this server.
+    pub fn reset_backoff(&self, destination: &ServerName) {
+        self.0.backoff.lock().expect("backoff mutex was poisoned").remove(destination);
+    }
+
+    pub(crate) fn record_failure(&self, destination: &ServerName) {
+        let mut backoff = self.0.backoff.lock().expect("backoff mutex was poisoned");
+        let failures = backoff.get(destination).map_or(1, |b| b.failures.saturating_add(1));
+        let duration = MIN_BACKOFF
+            .checked_mul(2_u32.saturating_pow(failures - 1))
+            .map_or(MAX_BACKOFF, |duration| duration.min(MAX_BACKOFF));
+
+        debug!("Backing off {destination} for {} seconds", duration.as_secs());
+        backoff.insert(
+            destination.to_owned(),
+            Backoff { failures, retry_at: Instant::now() + duration },
+        );
+    }
+}
+
+impl<C: HttpClient, B: ResolverBackend> FederationClient<C, B> {
+    /// Makes a request to a Matrix server-server API endpoint of the given destination server.
+    ///
+    /// The addresses of the destination are tried in order until one of them returns a response.
+    /// Responses with a server error status code count as a failure to reach the destination for
+    /// the backoff.
+    pub async fn send_request<R: OutgoingRequest>(
+        &self,
+        destination: &ServerName,
+        request: R,
+    ) -> ResponseResult<C, R> {
+        let http_res = self.send_http_request(destination, request).await?;
+
+        let res =
+            info_span!("deserialize_response", response_type = type_name::<R::IncomingResponse>())
+                .in_scope(move || R::IncomingResponse::try_from_http_response(http_res))?;
+
+        Ok(res)
+    }
+
+    /// Makes a request to the given destination server, and returns the HTTP response without
+    /// deserializing it.
+    pub(crate) async fn send_http_request<R: OutgoingRequest>(
+        &self,
+        destination: &ServerName,
+        request: R,
+    ) -> Result<http::Response<C::ResponseBody>, ResponseError<C, R>> {
+        if let Some(retry_at) = self.backoff_until(destination) {
+            return Err(Error::Backoff(retry_at.saturating_duration_since(Instant::now())));
+        }
+
+        let resolved = self.0.resolver.resolve(destination).await;
+        let http_req = info_span!("serialize_request", request_type = type_name::<R>())
+            .in_scope(|| self.signed_http_request(destination, &resolved, request))?;
+
+        let mut last_error = None;
+        for address in &resolved.addresses {
+            let authority = format!("{}:{}", address.host, address.port);
+            let send_span = info_span!(
+                "send_request",
+                request_type = type_name::<R>(),
+                http_client = type_name::<C>(),
+                destination = destination.as_str(),
+                address = authority.as_str(),
+            );
+
+            let http_req = for_address::<C, R>(&http_req, &resolved.tls_server_name, address)?;
+            let http_res =
+                match self.0.http_client.send_http_request(http_req).instrument(send_span).await {
+                    Ok(http_res) => http_res,
+                    Err(error) => {
+                        last_error = Some(error);
+                        continue;
+                    }
+                };
+
+            // The destination is reachable, but it can't handle the request right now.
+            if http_res.status().is_server_error() {
+                self.record_failure(destination);
+            } else {
+                self.reset_backoff(destination);
+            }
+
+            return Ok(http_res);
+        }
+
+        self.record_failure(destination);
+        Err(last_error.map_or(Error::NoDestinationAddress, Error::Response))
+    }
+
+    /// Serialize the given request and sign it with the `X-Matrix` scheme.
+    fn signed_http_request<R: OutgoingRequest>(
+        &self,
+        destination: &ServerName,
+        resolved: &ResolvedDestination,
+        request: R,
+    ) -> Result<http::Request<Vec<u8>>, ResponseError<C, R>> {
+        let mut http_req = request.try_into_http_request::<Vec<u8>>(
+            &format!("https://{}", resolved.host_header),
+            SendAccessToken::None,
+            SUPPORTED_MATRIX_VERSIONS,
+        )?;
+
+        let content: Option<CanonicalJsonObject> = if http_req.body().is_empty() {
+            None
+        } else {
+            Some(from_json_slice(http_req.body()).map_err(IntoHttpError::from)?)
+        };
+        let authorization = sign_federation_request(
+            &self.0.key_pair,
+            http_req.method().as_str(),
+            http_req.uri().path_and_query().map_or("/", |path_and_query| path_and_query.as_str()),
+            &self.0.origin,
+            destination,
+            content,
+        )?;
+
+        let headers = http_req.headers_mut();
+        headers.insert(AUTHORIZATION, header_value(&authorization)?);
+        headers.insert(HOST, header_value(&resolved.host_header)?);
+
+        Ok(http_req)
+    }
+}
+
+fn header_value<E, F>(value: &str) -> Result<HeaderValue, Error<E, F>> {
+    HeaderValue::from_str(value).map_err(|err| Error::Url(err.into()))
+}
+
+/// Copy the given request to send it to the given address, converting the body to the body type of
+/// the HTTP client.
+///
+/// The host of the URI is the TLS server name, and the address is attached as an extension.
+fn for_address<C: HttpClient, R: OutgoingRequest>(
+    http_req: &http::Request<Vec<u8>>,
+    tls_server_name: &str,
+    address: &DestinationAddress,
+) -> Result<http::Request<C::RequestBody>, ResponseError<C, R>> {
+    let authority = if tls_server_name.contains(':') {
+        format!("[{tls_server_name}]:{}", address.port)
+    } else {
+        format!("{tls_server_name}:{}", address.port)
+    };
+    let uri = http::Uri::builder()
+        .scheme("https")
+        .authority(authority)
+        .path_and_query(http_req.uri().path_and_query().map_or("/", |p| p.as_str()))
+        .build()
+        .map_err(Error::Url)?;
+
+    let mut body = C::RequestBody::default();
+    body.put_slice(http_req.body());
+
+    let mut builder =
+        http::Request::builder().method(http_req.method()).uri(uri).extension(address.clone());
+    for (name, value) in http_req.headers() {
+        builder = builder.header(name, value);
+    }
+
+    builder.body(body).map_err(Error::Url)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Mutex};
+
+    use async_trait::async_trait;
+    use http::header::{AUTHORIZATION, HOST};
+    use ruma_common::{
+        serde::Base64, server_name, CanonicalJsonObject, CanonicalJsonValue,
+        MilliSecondsSinceUnixEpoch, TransactionId,
+    };
+    use ruma_federation_api::transactions::send_transaction_message;
+    use ruma_signatures::{verify_json, Ed25519KeyPair};
+
+    use super::FederationClient;
+    use crate::{
+        server_discovery::{DestinationAddress, ResolverBackend, ServerResolver, SrvRecord},
+        Error, HttpClient,
+    };
+
+    struct StubBackend;
+
+    #[async_trait]
+    impl ResolverBackend for StubBackend {
+        type Error = &'static str;
+
+        async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, Self::Error> {
+            match name {
+                "_matrix-fed._tcp.remote.example" => Ok(vec![
+                    SrvRecord {
+                        priority: 0,
+                        weight: 0,
+                        port: 8448,
+                        target: "a.remote.example.".to_owned(),
+                    },
+                    SrvRecord {
+                        priority: 10,
+                        weight: 0,
+                        port: 8449,
+                        target: "b.remote.example.".to_owned(),
+                    },
+                ]),
+                _ => Ok(Vec::new()),
+            }
+        }
+
+        async fn get_well_known(
+            &self,
+            _hostname: &str,
+        ) -> Result<http::Response<Vec<u8>>, Self::Error> {
+            Err("connection refused")
+        }
+    }
+
+    #[derive(Default)]
+    struct MockHttpClient {
+        unreachable: Vec<&'static str>,
+        server_error: bool,
+        requests: Mutex<Vec<http::Request<Vec<u8>>>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for MockHttpClient {
+        type RequestBody = Vec<u8>;
+        type ResponseBody = Vec<u8>;
+        type Error = String;
+
+        async fn send_http_request(
+            &self,
+            req: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Self::Error> {
+            let address = req.extensions().get::<DestinationAddress>().unwrap();
+            let authority = format!("{}:{}", address.host, address.port);
+            self.requests.lock().unwrap().push(req);
+
+            if self.unreachable.contains(&authority.as_str()) {
+                return Err(format!("{authority} is unreachable"));
+            }
+
+            if self.server_error {
+                return Ok(http::Response::builder()
+                    .status(http::StatusCode::BAD_GATEWAY)
+                    .body(br#"{ "errcode": "M_UNKNOWN", "error": "Bad gateway" }"#.to_vec())
+                    .unwrap());
+            }
+
+            Ok(http::Response::new(br#"{ "pdus": {} }"#.to_vec()))
+        }
+    }
+
+    fn client(
+        unreachable: Vec<&'static str>,
+    ) -> (FederationClient<MockHttpClient, StubBackend>, Base64) {
+        client_with_http_client(MockHttpClient { unreachable, ..Default::default() })
+    }
+
+    fn client_with_http_client(
+        http_client: MockHttpClient,
+    ) -> (FederationClient<MockHttpClient, StubBackend>, Base64) {
+        let key_pair =
+            Ed25519KeyPair::from_der(&Ed25519KeyPair::generate().unwrap(), "1".to_owned()).unwrap();
+        let public_key = Base64::new(key_pair.public_key().to_owned());
+
+        let client = FederationClient::new(
+            server_name!("origin.example").to_owned(),
+            key_pair,
+            http_client,
+            ServerResolver::new(StubBackend),
+        );
+
+        (client, public_key)
+    }
+
+    #[tokio::test]
+    async fn signed_request_with_fallback() {
+        let (client, public_key) = client(vec!["a.remote.example:8448"]);
+
+        let request = send_transaction_message::v1::Request::new(
+            <&TransactionId>::from("txn1"),
+            client.origin(),
+            MilliSecondsSinceUnixEpoch::now(),
+        );
+        client.send_request(server_name!("remote.example"), request).await.unwrap();
+
+        let requests = client.0.http_client.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[0].extensions().get::<DestinationAddress>(),
+            Some(&DestinationAddress::new("a.remote.example", 8448))
+        );
+
+        let request = &requests[1];
+        assert_eq!(
+            request.extensions().get::<DestinationAddress>(),
+            Some(&DestinationAddress::new("b.remote.example", 8449))
+        );
+        assert_eq!(request.headers()[HOST], "remote.example");
+
+        let authorization = request.headers()[AUTHORIZATION].to_str().unwrap();
+        assert!(authorization.starts_with(
+            r#"X-Matrix origin="origin.example",destination="remote.example",key="ed25519:1",sig=""#
+        ));
+        let sig = authorization.rsplit('"').nth(1).unwrap();
+
+        let content: CanonicalJsonObject = serde_json::from_slice(request.body()).unwrap();
+        let object = CanonicalJsonObject::from([
+            ("method".to_owned(), CanonicalJsonValue::String("PUT".to_owned())),
+            (
+                "uri".to_owned(),
+                CanonicalJsonValue::String(request.uri().path_and_query().unwrap().to_string()),
+            ),
+            ("origin".to_owned(), CanonicalJsonValue::String("origin.example".to_owned())),
+            ("destination".to_owned(), CanonicalJsonValue::String("remote.example".to_owned())),
+            ("content".to_owned(), CanonicalJsonValue::Object(content)),
+            (
+                "signatures".to_owned(),
+                CanonicalJsonValue::Object(CanonicalJsonObject::from([(
+                    "origin.example".to_owned(),
+                    CanonicalJsonValue::Object(CanonicalJsonObject::from([(
+                        "ed25519:1".to_owned(),
+                        CanonicalJsonValue::String(sig.to_owned()),
+                    )])),
+                )])),
+            ),
+        ]);
+        let public_key_map = BTreeMap::from([(
+            "origin.example".to_owned(),
+            BTreeMap::from([("ed25519:1".to_owned(), public_key)]),
+        )]);
+        verify_json(&public_key_map, &object).unwrap();
+    }
+
+    #[tokio::test]
+    async fn tls_server_name_in_uri() {
+        let (client, _) = client(vec![]);
+
+        let request = send_transaction_message::v1::Request::new(
+            <&TransactionId>::from("txn1"),
+            client.origin(),
+            MilliSecondsSinceUnixEpoch::now(),
+        );
+        client.send_request(server_name!("remote.example"), request).await.unwrap();
+
+        let request = client.0.http_client.requests.lock().unwrap().pop().unwrap();
+        // The certificate is checked against the server name, not against the SRV target.
+        assert_eq!(request.uri().host(), Some("remote.example"));
+        assert_eq!(request.uri().port_u16(), Some(8448));
+        assert_eq!(
+            request.extensions().get::<DestinationAddress>(),
+            Some(&DestinationAddress::new("a.remote.example", 8448))
+        );
+    }
+
+    #[tokio::test]
+    async fn tls_server_name_in_uri_ipv6() {
+        let (client, _) = client(vec![]);
+
+        let request = send_transaction_message::v1::Request::new(
+            <&TransactionId>::from("txn1"),
+            client.origin(),
+            MilliSecondsSinceUnixEpoch::now(),
+        );
+        client.send_request(server_name!("[::1]:8000"), request).await.unwrap();
+
+        let request = client.0.http_client.requests.lock().unwrap().pop().unwrap();
+        assert_eq!(request.uri().authority().unwrap(), "[::1]:8000");
+        assert_eq!(request.headers()[HOST], "[::1]:8000");
+        assert_eq!(
+            request.extensions().get::<DestinationAddress>(),
+            Some(&DestinationAddress::new("::1", 8000))
+        );
+    }
+
+    #[tokio::test]
+    async fn backoff() {
+        let (client, _) = client(vec!["a.remote.example:8448", "b.remote.example:8449"]);
+        let destination = server_name!("remote.example");
+        let transaction_id = <&TransactionId>::from("txn1");
+        let request = || {
+            send_transaction_message::v1::Request::new(
+                transaction_id,
+                client.origin(),
+                MilliSecondsSinceUnixEpoch::now(),
+            )
+        };
+
+        assert!(matches!(
+            client.send_request(destination, request()).await,
+            Err(Error::Response(_))
+        ));
+        assert!(client.backoff_until(destination).is_some());
+
+        assert!(matches!(
+            client.send_request(destination, request()).await,
+            Err(Error::Backoff(_))
+        ));
+        assert_eq!(client.0.http_client.requests.lock().unwrap().len(), 2);
+
+        client.reset_backoff(destination);
+        assert!(matches!(
+            client.send_request(destination, request()).await,
+            Err(Error::Response(_))
+        ));
+        assert_eq!(client.0.http_client.requests.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn backoff_after_server_error() {
+        let (client, _) =
+            client_with_http_client(MockHttpClient { server_error: true, ..Default::default() });
+        let destination = server_name!("remote.example");
+        let request = send_transaction_message::v1::Request::new(
+            <&TransactionId>::from("txn1"),
+            client.origin(),
+            MilliSecondsSinceUnixEpoch::now(),
+        );
+
+        assert!(matches!(
+            client.send_request(destination, request).await,
+            Err(Error::FromHttpResponse(_))
+        ));
+        assert!(client.backoff_until(destination).is_some());
+        assert_eq!(client.0.http_client.requests.lock().unwrap().len(), 1);
+    }
+}
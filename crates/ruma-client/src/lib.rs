@@ -19,11 +19,11 @@
 //!     .build::<ruma_client::http_client::Dummy>()
 //!     .await?;
 //!
-//! let session = client
-//!     .log_in("@alice:example.com", "secret", None, None)
-//!     .await?;
+//! let session = client.log_in("@alice:example.com", "secret", None, None).await?;
+//!
+//! // You're now logged in! Write the session to a file if you want to restore it later with
+//! // `ClientBuilder::session`.
 //!
-//! // You're now logged in! Write the session to a file if you want to restore it later.
 //! // Then start using the API!
 //! # Result::<(), ruma_client::Error<_, _>>::Ok(())
 //! # };
@@ -102,23 +102,39 @@
 
 use std::{any::type_name, future::Future};
 
+use bytes::BufMut;
 use ruma_common::{
-    api::{MatrixVersion, OutgoingRequest, SendAccessToken},
+    api::{IncomingResponse, MatrixVersion, OutgoingRequest, SendAccessToken},
     UserId,
 };
-use tracing::{info_span, Instrument};
+use tracing::{info_span, Instrument, Span};
 
 #[cfg(feature = "client-api")]
 mod client;
 mod error;
+#[cfg(feature = "federation-api")]
+mod federation_client;
 pub mod http_client;
+#[cfg(feature = "client-api")]
+pub mod middleware;
+#[cfg(feature = "federation-api")]
+pub mod server_discovery;
+#[cfg(all(feature = "client-api", feature = "unstable-msc3575"))]
+pub mod sliding_sync;
+#[cfg(feature = "federation-api")]
+mod transaction_sender;
 
 #[cfg(feature = "client-api")]
-pub use self::client::{Client, ClientBuilder};
+pub use self::client::{Client, ClientBuilder, DiscoveryError, Session, UiaaHandler, UiaaStage};
 pub use self::{
     error::Error,
     http_client::{DefaultConstructibleHttpClient, HttpClient, HttpClientExt},
 };
+#[cfg(feature = "federation-api")]
+pub use self::{
+    federation_client::FederationClient,
+    transaction_sender::{TransactionError, TransactionSender},
+};
 
 /// The error type for sending the request `R` with the http client `C`.
 pub type ResponseError<C, R> =
@@ -142,22 +158,13 @@ where
     F: FnOnce(&mut http::Request<C::RequestBody>) -> Result<(), ResponseError<C, R>>,
 {
     let http_req =
-        info_span!("serialize_request", request_type = type_name::<R>()).in_scope(move || {
-            request
-                .try_into_http_request(homeserver_url, send_access_token, for_versions)
-                .map_err(ResponseError::<C, R>::from)
-                .and_then(|mut req| {
-                    customize(&mut req)?;
-                    Ok(req)
-                })
-        });
-
-    let send_span = info_span!(
-        "send_request",
-        request_type = type_name::<R>(),
-        http_client = type_name::<C>(),
-        homeserver_url,
-    );
+        serialize_request::<_, C, R>(homeserver_url, send_access_token, for_versions, request)
+            .and_then(|mut req| {
+                customize(&mut req)?;
+                Ok(req)
+            });
+
+    let send_span = send_span::<C, R>(homeserver_url);
 
     async move {
         let http_res = http_client
@@ -166,16 +173,47 @@ where
             .await
             .map_err(Error::Response)?;
 
-        let res =
-            info_span!("deserialize_response", response_type = type_name::<R::IncomingResponse>())
-                .in_scope(move || {
-                    ruma_common::api::IncomingResponse::try_from_http_response(http_res)
-                })?;
-
-        Ok(res)
+        deserialize_response::<C, R>(http_res)
     }
 }
 
+/// Serialize the given request into an HTTP request with the body type `T`.
+fn serialize_request<T, C, R>(
+    homeserver_url: &str,
+    send_access_token: SendAccessToken<'_>,
+    for_versions: &[MatrixVersion],
+    request: R,
+) -> Result<http::Request<T>, ResponseError<C, R>>
+where
+    T: Default + BufMut,
+    C: HttpClient + ?Sized,
+    R: OutgoingRequest,
+{
+    info_span!("serialize_request", request_type = type_name::<R>()).in_scope(move || {
+        Ok(request.try_into_http_request(homeserver_url, send_access_token, for_versions)?)
+    })
+}
+
+/// The span of sending the request `R` to the given homeserver with the HTTP client `C`.
+fn send_span<C: ?Sized, R>(homeserver_url: &str) -> Span {
+    info_span!(
+        "send_request",
+        request_type = type_name::<R>(),
+        http_client = type_name::<C>(),
+        homeserver_url,
+    )
+}
+
+/// Deserialize the given HTTP response into the response type of the request `R`.
+fn deserialize_response<C, R>(http_res: http::Response<C::ResponseBody>) -> ResponseResult<C, R>
+where
+    C: HttpClient + ?Sized,
+    R: OutgoingRequest,
+{
+    info_span!("deserialize_response", response_type = type_name::<R::IncomingResponse>())
+        .in_scope(move || Ok(R::IncomingResponse::try_from_http_response(http_res)?))
+}
+
 fn add_user_id_to_query<C: HttpClient + ?Sized, R: OutgoingRequest>(
     user_id: &UserId,
 ) -> impl FnOnce(&mut http::Request<C::RequestBody>) -> Result<(), ResponseError<C, R>> + '_ {
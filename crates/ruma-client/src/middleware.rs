@@ -0,0 +1,467 @@
+//! Middleware to customize how the [`Client`] sends HTTP requests.
+//!
+//! Middleware is added to a client with [`ClientBuilder::middleware()`]. Before every attempt to
+//! send a request, [`Middleware::before_send()`] is called on all the middleware in the order
+//! they were added, and after every attempt [`Middleware::after_receive()`] is called with its
+//! outcome and can ask for the request to be retried.
+//!
+//! This module also contains built-in policies:
+//!
+//! * [`RateLimitRetry`] honours the `retry_after_ms` of `M_LIMIT_EXCEEDED` errors,
+//! * [`TransientErrorRetry`] retries requests after network failures, timeouts and server errors,
+//! * [`Timeout`] limits the time to wait for a response.
+//!
+//! [`Client`]: crate::Client
+//! [`ClientBuilder::middleware()`]: crate::ClientBuilder::middleware
+
+use std::{fmt, time::Duration};
+
+use rand::Rng;
+use serde::Deserialize;
+
+/// A hook into the HTTP requests sent by a [`Client`](crate::Client).
+///
+/// Every middleware counts the attempts to send the same request on its own, so a retry policy is
+/// not limited by the retries requested by other middleware.
+pub trait Middleware: Send + Sync {
+    /// Called before every attempt to send a request.
+    ///
+    /// The request can be modified, e.g. to add headers. The changes only apply to this attempt.
+    ///
+    /// The `attempt` is the number of previous attempts to send the same request, so it is `0`
+    /// for the first attempt.
+    fn before_send(&self, request: &mut http::Request<Vec<u8>>, attempt: u32) {
+        let _ = (request, attempt);
+    }
+
+    /// Called after every attempt to send a request, with its outcome.
+    ///
+    /// The request will be retried if any middleware returns [`Action::Retry`].
+    ///
+    /// The `attempt` is the number of retries of the same request that were requested by this
+    /// middleware, so it is `0` until this middleware returns [`Action::Retry`].
+    fn after_receive(
+        &self,
+        request: &http::Request<Vec<u8>>,
+        outcome: &Outcome<'_>,
+        attempt: u32,
+    ) -> Action {
+        let _ = (request, outcome, attempt);
+        Action::Continue
+    }
+}
+
+/// The outcome of an attempt to send a request.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Outcome<'a> {
+    /// A response was received.
+    Response(&'a http::Response<&'a [u8]>),
+
+    /// The HTTP client failed to obtain a response (e.g. due to network or DNS issues).
+    Error,
+
+    /// No response was received before the [`Timeout`].
+    Timeout,
+}
+
+impl Outcome<'_> {
+    /// The HTTP status code of the response, if one was received.
+    pub fn status(&self) -> Option<http::StatusCode> {
+        match self {
+            Self::Response(response) => Some(response.status()),
+            Self::Error | Self::Timeout => None,
+        }
+    }
+}
+
+/// What to do after an attempt to send a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Action {
+    /// Return the outcome of the attempt.
+    Continue,
+
+    /// Send the request again after the given delay.
+    ///
+    /// If several middleware ask for a retry, the longest delay is used.
+    Retry {
+        /// The time to wait before sending the request again.
+        after: Duration,
+    },
+}
+
+/// Retries requests that were rate-limited by the homeserver.
+///
+/// Responses with an `M_LIMIT_EXCEEDED` error are retried after the `retry_after_ms` of the error,
+/// or after [`default_delay`](Self::default_delay) if the homeserver didn't specify it.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RateLimitRetry {
+    /// The maximum number of times a request is retried.
+    ///
+    /// Defaults to `3`.
+    pub max_retries: u32,
+
+    /// The delay to use when the homeserver doesn't specify one.
+    ///
+    /// Defaults to 5 seconds.
+    pub default_delay: Duration,
+
+    /// The maximum delay before a retry.
+    ///
+    /// If the homeserver asks to wait longer, the error is returned. Defaults to 60 seconds.
+    pub max_delay: Duration,
+}
+
+impl RateLimitRetry {
+    /// Creates a `RateLimitRetry` with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for RateLimitRetry {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            default_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Middleware for RateLimitRetry {
+    fn after_receive(
+        &self,
+        _request: &http::Request<Vec<u8>>,
+        outcome: &Outcome<'_>,
+        attempt: u32,
+    ) -> Action {
+        #[derive(Deserialize)]
+        struct LimitExceeded<'a> {
+            errcode: &'a str,
+            retry_after_ms: Option<u64>,
+        }
+
+        let response = match outcome {
+            Outcome::Response(response) if attempt < self.max_retries => response,
+            _ => return Action::Continue,
+        };
+
+        let error = match serde_json::from_slice::<LimitExceeded<'_>>(response.body()) {
+            Ok(error) if error.errcode == "M_LIMIT_EXCEEDED" => error,
+            _ => return Action::Continue,
+        };
+
+        let after = error.retry_after_ms.map_or(self.default_delay, Duration::from_millis);
+        if after > self.max_delay {
+            return Action::Continue;
+        }
+
+        Action::Retry { after }
+    }
+}
+
+/// Retries requests that failed because of a transient error.
+///
+/// Requests are retried when the HTTP client failed to obtain a response, when no response was
+/// received before the [`Timeout`], and when the homeserver responded with a `500`, `502`, `503`
+/// or `504` status code. The delay before a retry grows exponentially from
+/// [`base_delay`](Self::base_delay), with a random jitter.
+///
+/// Note that this also retries requests that are not idempotent, that might have been processed
+/// by the homeserver before the failure.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct TransientErrorRetry {
+    /// The maximum number of times a request is retried.
+    ///
+    /// Defaults to `3`.
+    pub max_retries: u32,
+
+    /// The delay before the first retry.
+    ///
+    /// Defaults to 500 milliseconds.
+    pub base_delay: Duration,
+
+    /// The maximum delay before a retry.
+    ///
+    /// Defaults to 30 seconds.
+    pub max_delay: Duration,
+}
+
+impl TransientErrorRetry {
+    /// Creates a `TransientErrorRetry` with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The delay before the retry following the given attempt, without jitter.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay.saturating_mul(2_u32.saturating_pow(attempt)).min(self.max_delay)
+    }
+}
+
+impl Default for TransientErrorRetry {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Middleware for TransientErrorRetry {
+    fn after_receive(
+        &self,
+        _request: &http::Request<Vec<u8>>,
+        outcome: &Outcome<'_>,
+        attempt: u32,
+    ) -> Action {
+        let is_transient = match outcome.status() {
+            Some(status) => matches!(status.as_u16(), 500 | 502 | 503 | 504),
+            None => true,
+        };
+
+        if !is_transient || attempt >= self.max_retries {
+            return Action::Continue;
+        }
+
+        let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+        Action::Retry { after: self.delay(attempt).mul_f64(jitter) }
+    }
+}
+
+/// Limits the time to wait for the response to every attempt to send a request.
+///
+/// For long-polling requests, like `/sync`, the `timeout` query parameter of the request is added
+/// to the duration, so the timeout only applies to the time the homeserver takes to respond after
+/// the long-poll.
+///
+/// When the timeout expires, the outcome of the attempt is [`Outcome::Timeout`], and the request
+/// fails with [`Error::Timeout`](crate::Error::Timeout) unless it is retried.
+#[derive(Clone, Copy, Debug)]
+pub struct Timeout(Duration);
+
+impl Timeout {
+    /// Creates a `Timeout` with the given duration.
+    pub fn new(duration: Duration) -> Self {
+        Self(duration)
+    }
+
+    /// The duration of this timeout.
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl Middleware for Timeout {
+    fn before_send(&self, request: &mut http::Request<Vec<u8>>, _attempt: u32) {
+        let long_poll = long_poll_timeout(request.uri()).unwrap_or_default();
+        request.extensions_mut().insert(Self(self.0.saturating_add(long_poll)));
+    }
+}
+
+/// The `timeout` query parameter of the given URI, used by long-polling endpoints.
+fn long_poll_timeout(uri: &http::Uri) -> Option<Duration> {
+    uri.query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("timeout="))
+        .and_then(|timeout| timeout.parse().ok())
+        .map(Duration::from_millis)
+}
+
+/// The middleware of a client.
+#[derive(Default)]
+pub(crate) struct MiddlewareChain(Vec<Box<dyn Middleware>>);
+
+impl MiddlewareChain {
+    /// Add the given middleware at the end of the chain.
+    pub fn push(&mut self, middleware: Box<dyn Middleware>) {
+        self.0.push(middleware);
+    }
+
+    /// The attempts to send a new request.
+    pub fn attempts(&self) -> Attempts {
+        Attempts { total: 0, retries: vec![0; self.0.len()] }
+    }
+
+    /// Call [`Middleware::before_send()`] on all the middleware.
+    ///
+    /// Returns the timeout of the request, if any.
+    pub fn before_send(
+        &self,
+        request: &mut http::Request<Vec<u8>>,
+        attempts: &Attempts,
+    ) -> Option<Duration> {
+        for middleware in &self.0 {
+            middleware.before_send(request, attempts.total);
+        }
+
+        request.extensions().get::<Timeout>().map(Timeout::duration)
+    }
+
+    /// Call [`Middleware::after_receive()`] on all the middleware, and count the attempt.
+    ///
+    /// Returns the longest delay of the retries requested by the middleware, if any.
+    pub fn after_receive(
+        &self,
+        request: &http::Request<Vec<u8>>,
+        outcome: &Outcome<'_>,
+        attempts: &mut Attempts,
+    ) -> Option<Duration> {
+        attempts.total += 1;
+
+        self.0
+            .iter()
+            .zip(&mut attempts.retries)
+            .filter_map(|(middleware, retries)| {
+                match middleware.after_receive(request, outcome, *retries) {
+                    Action::Continue => None,
+                    Action::Retry { after } => {
+                        *retries += 1;
+                        Some(after)
+                    }
+                }
+            })
+            .max()
+    }
+}
+
+/// The attempts to send a request through a [`MiddlewareChain`].
+#[derive(Debug)]
+pub(crate) struct Attempts {
+    /// The number of previous attempts.
+    total: u32,
+
+    /// The number of retries requested by every middleware, in the order of the chain.
+    retries: Vec<u32>,
+}
+
+impl fmt::Debug for MiddlewareChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MiddlewareChain").field(&self.0.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        Action, Middleware, MiddlewareChain, Outcome, RateLimitRetry, Timeout, TransientErrorRetry,
+    };
+
+    fn response(status: u16, body: &'static str) -> http::Response<&'static [u8]> {
+        http::Response::builder().status(status).body(body.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn rate_limit() {
+        let policy = RateLimitRetry::new();
+        let request = http::Request::new(Vec::new());
+
+        let limited = response(429, r#"{ "errcode": "M_LIMIT_EXCEEDED", "retry_after_ms": 2000 }"#);
+        assert_eq!(
+            policy.after_receive(&request, &Outcome::Response(&limited), 0),
+            Action::Retry { after: Duration::from_secs(2) }
+        );
+        assert_eq!(
+            policy.after_receive(&request, &Outcome::Response(&limited), 3),
+            Action::Continue
+        );
+
+        let no_delay = response(429, r#"{ "errcode": "M_LIMIT_EXCEEDED" }"#);
+        assert_eq!(
+            policy.after_receive(&request, &Outcome::Response(&no_delay), 0),
+            Action::Retry { after: Duration::from_secs(5) }
+        );
+
+        let too_long =
+            response(429, r#"{ "errcode": "M_LIMIT_EXCEEDED", "retry_after_ms": 3600000 }"#);
+        assert_eq!(
+            policy.after_receive(&request, &Outcome::Response(&too_long), 0),
+            Action::Continue
+        );
+
+        let forbidden = response(403, r#"{ "errcode": "M_FORBIDDEN" }"#);
+        assert_eq!(
+            policy.after_receive(&request, &Outcome::Response(&forbidden), 0),
+            Action::Continue
+        );
+        assert_eq!(policy.after_receive(&request, &Outcome::Error, 0), Action::Continue);
+    }
+
+    #[test]
+    fn transient_error() {
+        let policy = TransientErrorRetry::new();
+        let request = http::Request::new(Vec::new());
+
+        for (outcome, attempt) in [(Outcome::Error, 0), (Outcome::Timeout, 2)] {
+            match policy.after_receive(&request, &outcome, attempt) {
+                Action::Retry { after } => {
+                    assert!(after >= policy.delay(attempt) / 2 && after <= policy.delay(attempt));
+                }
+                Action::Continue => panic!("transient error was not retried"),
+            }
+        }
+        assert_eq!(policy.after_receive(&request, &Outcome::Error, 3), Action::Continue);
+
+        let unavailable = response(503, "");
+        assert!(matches!(
+            policy.after_receive(&request, &Outcome::Response(&unavailable), 0),
+            Action::Retry { .. }
+        ));
+        let not_found = response(404, r#"{ "errcode": "M_NOT_FOUND" }"#);
+        assert_eq!(
+            policy.after_receive(&request, &Outcome::Response(&not_found), 0),
+            Action::Continue
+        );
+
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(20), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn retries_per_middleware() {
+        let mut chain = MiddlewareChain::default();
+        chain.push(Box::new(RateLimitRetry::new()));
+        chain.push(Box::new(TransientErrorRetry::new()));
+        let request = http::Request::new(Vec::new());
+        let mut attempts = chain.attempts();
+
+        let limited = response(429, r#"{ "errcode": "M_LIMIT_EXCEEDED", "retry_after_ms": 10 }"#);
+        for _ in 0..3 {
+            assert!(chain
+                .after_receive(&request, &Outcome::Response(&limited), &mut attempts)
+                .is_some());
+        }
+        assert_eq!(
+            chain.after_receive(&request, &Outcome::Response(&limited), &mut attempts),
+            None
+        );
+
+        // The rate-limited retries don't count for the transient errors.
+        assert!(chain.after_receive(&request, &Outcome::Error, &mut attempts).is_some());
+        assert_eq!(attempts.total, 5);
+        assert_eq!(attempts.retries, [3, 1]);
+    }
+
+    #[test]
+    fn long_poll_timeout() {
+        let mut chain = MiddlewareChain::default();
+        chain.push(Box::new(Timeout::new(Duration::from_secs(10))));
+        let attempts = chain.attempts();
+
+        let mut request = http::Request::new(Vec::new());
+        assert_eq!(chain.before_send(&mut request, &attempts), Some(Duration::from_secs(10)));
+
+        let mut sync = http::Request::builder()
+            .uri("https://example.org/_matrix/client/v3/sync?since=s1&timeout=30000")
+            .body(Vec::new())
+            .unwrap();
+        assert_eq!(chain.before_send(&mut sync, &attempts), Some(Duration::from_secs(40)));
+    }
+}
@@ -0,0 +1,532 @@
+//! Resolution of the server name of a homeserver to the address used for federation requests.
+//!
+//! [`ServerResolver`] implements the [server discovery algorithm] of the Matrix Server-Server API,
+//! on top of a [`ResolverBackend`] that performs the DNS lookups and the HTTP requests.
+//!
+//! [server discovery algorithm]: https://spec.matrix.org/v1.4/server-server-api/#resolving-server-names
+
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use rand::Rng;
+use ruma_common::{api::IncomingResponse, OwnedServerName, ServerName};
+use ruma_federation_api::discovery::discover_homeserver;
+use tracing::debug;
+
+/// The default port for federation requests.
+const DEFAULT_PORT: u16 = 8448;
+
+/// The time a `.well-known` response is cached if it doesn't have caching headers.
+const WELL_KNOWN_DEFAULT_CACHE_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The maximum time a `.well-known` response is cached.
+const WELL_KNOWN_MAX_CACHE_DURATION: Duration = Duration::from_secs(48 * 60 * 60);
+
+/// The time a failure to get a `.well-known` response is cached.
+const WELL_KNOWN_ERROR_CACHE_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// A DNS `SRV` record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::exhaustive_structs)]
+pub struct SrvRecord {
+    /// The priority of the target host, lower values are preferred.
+    pub priority: u16,
+
+    /// The weight of the target host, for records with the same priority.
+    pub weight: u16,
+
+    /// The port of the target host.
+    pub port: u16,
+
+    /// The hostname of the target host.
+    pub target: String,
+}
+
+/// The DNS and HTTP operations needed to resolve server names.
+#[async_trait]
+pub trait ResolverBackend: Sync {
+    /// The error type of the operations.
+    type Error: std::fmt::Display + Send;
+
+    /// Look up the `SRV` records of the given name, like `_matrix-fed._tcp.example.org`.
+    ///
+    /// Returns an empty list if there are no records.
+    async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, Self::Error>;
+
+    /// Send a `GET` request to `https://{hostname}/.well-known/matrix/server`.
+    ///
+    /// Redirects must be followed. The TLS certificate must be valid for `hostname`.
+    async fn get_well_known(&self, hostname: &str) -> Result<http::Response<Vec<u8>>, Self::Error>;
+}
+
+/// An address to connect to for federation requests.
+///
+/// [`FederationClient`](crate::FederationClient) attaches it to the requests it sends as an
+/// extension, since the URL of the requests contains the TLS server name instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::exhaustive_structs)]
+pub struct DestinationAddress {
+    /// The hostname or IP address to connect to.
+    ///
+    /// IPv6 addresses are not enclosed in square brackets. A hostname must still be resolved to IP
+    /// addresses with `A` or `AAAA` DNS records.
+    pub host: String,
+
+    /// The port to connect to.
+    pub port: u16,
+}
+
+impl DestinationAddress {
+    /// Creates a new `DestinationAddress` with the given host and port.
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self { host: host.into(), port }
+    }
+}
+
+/// The result of the resolution of a server name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ResolvedDestination {
+    /// The addresses to connect to, in order of preference.
+    ///
+    /// Each address should be tried until a connection succeeds.
+    pub addresses: Vec<DestinationAddress>,
+
+    /// The value of the `Host` header of the requests.
+    pub host_header: String,
+
+    /// The name the TLS certificate of the server must be valid for, also used for SNI.
+    ///
+    /// IPv6 addresses are not enclosed in square brackets.
+    pub tls_server_name: String,
+}
+
+/// A resolver of server names, caching the `.well-known` responses.
+#[derive(Debug)]
+pub struct ServerResolver<B> {
+    backend: B,
+    well_known_cache: Mutex<BTreeMap<OwnedServerName, CachedWellKnown>>,
+}
+
+#[derive(Debug)]
+struct CachedWellKnown {
+    delegated: Option<OwnedServerName>,
+    expires_at: Instant,
+}
+
+impl<B: ResolverBackend> ServerResolver<B> {
+    /// Creates a new `ServerResolver` with the given backend.
+    pub fn new(backend: B) -> Self {
+        Self { backend, well_known_cache: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Get a reference to the backend of this resolver.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Resolve the given server name.
+    ///
+    /// Failures of the backend are treated as missing records, so there is always a destination
+    /// to try.
+    pub async fn resolve(&self, server_name: &ServerName) -> ResolvedDestination {
+        let hostname = server_name.host();
+
+        // 1. The hostname is an IP literal.
+        // 2. The server name has an explicit port.
+        if server_name.is_ip_literal() || server_name.port().is_some() {
+            return ResolvedDestination {
+                addresses: vec![DestinationAddress::new(
+                    strip_brackets(hostname),
+                    server_name.port().unwrap_or(DEFAULT_PORT),
+                )],
+                host_header: server_name.to_string(),
+                tls_server_name: strip_brackets(hostname).to_owned(),
+            };
+        }
+
+        // 3. The server name is delegated with `.well-known`.
+        if let Some(delegated) = self.well_known(server_name).await {
+            let delegated_hostname = delegated.host();
+
+            // 3.1. The delegated hostname is an IP literal.
+            // 3.2. The delegated server name has an explicit port.
+            if delegated.is_ip_literal() || delegated.port().is_some() {
+                return ResolvedDestination {
+                    addresses: vec![DestinationAddress::new(
+                        strip_brackets(delegated_hostname),
+                        delegated.port().unwrap_or(DEFAULT_PORT),
+                    )],
+                    host_header: delegated.to_string(),
+                    tls_server_name: strip_brackets(delegated_hostname).to_owned(),
+                };
+            }
+
+            // 3.3. and 3.4. `SRV` records of the delegated hostname.
+            // 3.5. The default port.
+            return ResolvedDestination {
+                addresses: self.srv_or_default(delegated_hostname).await,
+                host_header: delegated_hostname.to_owned(),
+                tls_server_name: delegated_hostname.to_owned(),
+            };
+        }
+
+        // 4. and 5. `SRV` records of the hostname.
+        // 6. The default port.
+        ResolvedDestination {
+            addresses: self.srv_or_default(hostname).await,
+            host_header: hostname.to_owned(),
+            tls_server_name: hostname.to_owned(),
+        }
+    }
+
+    /// Get the server name delegated to with `.well-known`, if any, using the cache.
+    async fn well_known(&self, server_name: &ServerName) -> Option<OwnedServerName> {
+        if let Some(cached) = self.well_known_cache.lock().unwrap().get(server_name) {
+            if cached.expires_at > Instant::now() {
+                return cached.delegated.clone();
+            }
+        }
+
+        let (delegated, cache_duration) =
+            match self.backend.get_well_known(server_name.host()).await {
+                Ok(response) => {
+                    let cache_duration = cache_duration(response.headers());
+
+                    match discover_homeserver::Response::try_from_http_response(response) {
+                        Ok(response) => (Some(response.server), cache_duration),
+                        Err(error) => {
+                            debug!("Invalid .well-known response of {server_name}: {error}");
+                            (None, WELL_KNOWN_ERROR_CACHE_DURATION)
+                        }
+                    }
+                }
+                Err(error) => {
+                    debug!("Failed to get the .well-known response of {server_name}: {error}");
+                    (None, WELL_KNOWN_ERROR_CACHE_DURATION)
+                }
+            };
+
+        self.well_known_cache.lock().unwrap().insert(
+            server_name.to_owned(),
+            CachedWellKnown {
+                delegated: delegated.clone(),
+                expires_at: Instant::now() + cache_duration,
+            },
+        );
+
+        delegated
+    }
+
+    /// Get the addresses from the `SRV` records of the given hostname, or the default port.
+    async fn srv_or_default(&self, hostname: &str) -> Vec<DestinationAddress> {
+        for service in ["_matrix-fed._tcp", "_matrix._tcp"] {
+            let name = format!("{service}.{hostname}");
+
+            let mut records = match self.backend.lookup_srv(&name).await {
+                Ok(records) => records,
+                Err(error) => {
+                    debug!("Failed to look up the SRV records of {name}: {error}");
+                    continue;
+                }
+            };
+
+            // A single record with the target "." means that the service is not available.
+            records.retain(|record| record.target != ".");
+
+            if !records.is_empty() {
+                return order_srv_records(records, &mut rand::thread_rng())
+                    .into_iter()
+                    .map(|record| {
+                        DestinationAddress::new(record.target.trim_end_matches('.'), record.port)
+                    })
+                    .collect();
+            }
+        }
+
+        vec![DestinationAddress::new(hostname, DEFAULT_PORT)]
+    }
+}
+
+/// Remove the square brackets around an IPv6 address.
+fn strip_brackets(host: &str) -> &str {
+    host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host)
+}
+
+/// Order `SRV` records by priority, and randomly by weight among records with the same priority,
+/// as described in [RFC 2782].
+///
+/// [RFC 2782]: https://www.rfc-editor.org/rfc/rfc2782
+fn order_srv_records(mut records: Vec<SrvRecord>, rng: &mut impl Rng) -> Vec<SrvRecord> {
+    // Records with a weight of 0 are placed first in their priority group, so they are only
+    // selected when there is no other choice.
+    records.sort_by_key(|record| (record.priority, record.weight != 0));
+
+    let mut ordered = Vec::with_capacity(records.len());
+    let mut records = records.into_iter().peekable();
+
+    while let Some(first) = records.next() {
+        let priority = first.priority;
+        let mut group = vec![first];
+        while let Some(record) = records.next_if(|record| record.priority == priority) {
+            group.push(record);
+        }
+
+        while !group.is_empty() {
+            let total_weight: u32 = group.iter().map(|record| u32::from(record.weight)).sum();
+            let target = rng.gen_range(0..=total_weight);
+
+            let mut running_sum = 0;
+            let index = group
+                .iter()
+                .position(|record| {
+                    running_sum += u32::from(record.weight);
+                    running_sum >= target
+                })
+                .expect("the running sum reaches the total weight");
+
+            ordered.push(group.remove(index));
+        }
+    }
+
+    ordered
+}
+
+/// Get the cache duration of a `.well-known` response from its `Cache-Control` header.
+fn cache_duration(headers: &http::HeaderMap) -> Duration {
+    let max_age = headers
+        .get_all(http::header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|directive| {
+            let directive = directive.trim();
+
+            if directive.eq_ignore_ascii_case("no-store")
+                || directive.eq_ignore_ascii_case("no-cache")
+            {
+                return Some(0);
+            }
+
+            let (name, value) = directive.split_once('=')?;
+            if name.trim().eq_ignore_ascii_case("max-age") {
+                value.trim().trim_matches('"').parse().ok()
+            } else {
+                None
+            }
+        });
+
+    max_age
+        .map(Duration::from_secs)
+        .unwrap_or(WELL_KNOWN_DEFAULT_CACHE_DURATION)
+        .min(WELL_KNOWN_MAX_CACHE_DURATION)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use async_trait::async_trait;
+    use ruma_common::server_name;
+
+    use super::{
+        order_srv_records, DestinationAddress, ResolvedDestination, ResolverBackend,
+        ServerResolver, SrvRecord,
+    };
+
+    #[derive(Default)]
+    struct StubBackend {
+        srv: BTreeMap<&'static str, Vec<SrvRecord>>,
+        well_known: BTreeMap<&'static str, (&'static str, Option<&'static str>)>,
+        well_known_requests: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ResolverBackend for StubBackend {
+        type Error = &'static str;
+
+        async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, Self::Error> {
+            Ok(self.srv.get(name).cloned().unwrap_or_default())
+        }
+
+        async fn get_well_known(
+            &self,
+            hostname: &str,
+        ) -> Result<http::Response<Vec<u8>>, Self::Error> {
+            self.well_known_requests.fetch_add(1, Ordering::SeqCst);
+
+            let (body, cache_control) =
+                self.well_known.get(hostname).ok_or("connection refused")?;
+            let mut response = http::Response::builder();
+            if let Some(cache_control) = cache_control {
+                response = response.header(http::header::CACHE_CONTROL, *cache_control);
+            }
+
+            Ok(response.body(body.as_bytes().to_owned()).unwrap())
+        }
+    }
+
+    fn srv(priority: u16, weight: u16, port: u16, target: &str) -> SrvRecord {
+        SrvRecord { priority, weight, port, target: target.to_owned() }
+    }
+
+    fn destination(
+        addresses: &[(&str, u16)],
+        host_header: &str,
+        tls_server_name: &str,
+    ) -> ResolvedDestination {
+        ResolvedDestination {
+            addresses: addresses
+                .iter()
+                .map(|(host, port)| DestinationAddress::new(*host, *port))
+                .collect(),
+            host_header: host_header.to_owned(),
+            tls_server_name: tls_server_name.to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn ip_literal_and_explicit_port() {
+        let resolver = ServerResolver::new(StubBackend::default());
+
+        assert_eq!(
+            resolver.resolve(server_name!("1.2.3.4")).await,
+            destination(&[("1.2.3.4", 8448)], "1.2.3.4", "1.2.3.4")
+        );
+        assert_eq!(
+            resolver.resolve(server_name!("[::1]:8000")).await,
+            destination(&[("::1", 8000)], "[::1]:8000", "::1")
+        );
+        assert_eq!(
+            resolver.resolve(server_name!("example.org:8000")).await,
+            destination(&[("example.org", 8000)], "example.org:8000", "example.org")
+        );
+        assert_eq!(resolver.backend().well_known_requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn well_known_delegation() {
+        let backend = StubBackend {
+            srv: BTreeMap::from([(
+                "_matrix-fed._tcp.matrix.example.org",
+                vec![srv(10, 0, 8449, "backup.example.org."), srv(0, 5, 8448, "main.example.org.")],
+            )]),
+            well_known: BTreeMap::from([
+                ("example.org", (r#"{ "m.server": "matrix.example.org" }"#, None)),
+                ("example.com", (r#"{ "m.server": "matrix.example.com:443" }"#, None)),
+                ("example.net", (r#"{ "m.server": "matrix.example.net" }"#, None)),
+            ]),
+            ..Default::default()
+        };
+        let resolver = ServerResolver::new(backend);
+
+        assert_eq!(
+            resolver.resolve(server_name!("example.org")).await,
+            destination(
+                &[("main.example.org", 8448), ("backup.example.org", 8449)],
+                "matrix.example.org",
+                "matrix.example.org"
+            )
+        );
+        assert_eq!(
+            resolver.resolve(server_name!("example.com")).await,
+            destination(
+                &[("matrix.example.com", 443)],
+                "matrix.example.com:443",
+                "matrix.example.com"
+            )
+        );
+        assert_eq!(
+            resolver.resolve(server_name!("example.net")).await,
+            destination(
+                &[("matrix.example.net", 8448)],
+                "matrix.example.net",
+                "matrix.example.net"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn srv_without_well_known() {
+        let backend = StubBackend {
+            srv: BTreeMap::from([
+                ("_matrix._tcp.example.org", vec![srv(0, 0, 8000, "matrix.example.org.")]),
+                ("_matrix-fed._tcp.example.com", vec![srv(0, 0, 8001, "matrix.example.com.")]),
+                ("_matrix._tcp.example.com", vec![srv(0, 0, 8000, "matrix.example.com.")]),
+            ]),
+            well_known: BTreeMap::from([("example.net", ("not json", None))]),
+            ..Default::default()
+        };
+        let resolver = ServerResolver::new(backend);
+
+        assert_eq!(
+            resolver.resolve(server_name!("example.org")).await,
+            destination(&[("matrix.example.org", 8000)], "example.org", "example.org")
+        );
+        assert_eq!(
+            resolver.resolve(server_name!("example.com")).await,
+            destination(&[("matrix.example.com", 8001)], "example.com", "example.com")
+        );
+        assert_eq!(
+            resolver.resolve(server_name!("example.net")).await,
+            destination(&[("example.net", 8448)], "example.net", "example.net")
+        );
+    }
+
+    #[test]
+    fn srv_records_order() {
+        let records = vec![
+            srv(10, 0, 8448, "zero.example.org."),
+            srv(10, 1, 8448, "light.example.org."),
+            srv(10, 3, 8448, "heavy.example.org."),
+            srv(0, 0, 8448, "main.example.org."),
+        ];
+
+        let mut heavy_first = 0;
+        for _ in 0..1000 {
+            let ordered = order_srv_records(records.clone(), &mut rand::thread_rng());
+            assert_eq!(ordered.len(), 4);
+            assert_eq!(ordered[0].target, "main.example.org.");
+
+            if ordered[1].target == "heavy.example.org." {
+                heavy_first += 1;
+            }
+        }
+
+        // The random number is between 0 and 4 included, so the heavy record should be chosen
+        // first 60% of the time.
+        assert!((500..700).contains(&heavy_first), "heavy record first {heavy_first} times");
+    }
+
+    #[tokio::test]
+    async fn well_known_cache() {
+        let backend = StubBackend {
+            well_known: BTreeMap::from([
+                ("example.org", (r#"{ "m.server": "matrix.example.org" }"#, None)),
+                ("example.com", (r#"{ "m.server": "matrix.example.com" }"#, Some("no-cache"))),
+            ]),
+            ..Default::default()
+        };
+        let resolver = ServerResolver::new(backend);
+
+        resolver.resolve(server_name!("example.org")).await;
+        resolver.resolve(server_name!("example.org")).await;
+        assert_eq!(resolver.backend().well_known_requests.load(Ordering::SeqCst), 1);
+
+        resolver.resolve(server_name!("example.com")).await;
+        resolver.resolve(server_name!("example.com")).await;
+        assert_eq!(resolver.backend().well_known_requests.load(Ordering::SeqCst), 3);
+
+        // Errors are cached too.
+        resolver.resolve(server_name!("example.net")).await;
+        resolver.resolve(server_name!("example.net")).await;
+        assert_eq!(resolver.backend().well_known_requests.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,748 @@
+//! Driver for sliding sync ([MSC3575]).
+//!
+//! [`SlidingSync`] keeps the `pos` of the last response, the state of the lists of rooms and the
+//! room subscriptions. Every response is applied to the local state of the lists, and the changes
+//! are returned as [`RoomListDiff`]s.
+//!
+//! [MSC3575]: https://github.com/matrix-org/matrix-spec-proposals/pull/3575
+
+use std::{collections::BTreeMap, time::Duration};
+
+use assign::assign;
+use async_stream::try_stream;
+use futures_core::stream::Stream;
+use js_int::UInt;
+use ruma_client_api::sync::sync_events::v4::{
+    self, RoomSubscription, SlidingOp, SlidingSyncRoom, SyncList, SyncOp, SyncRequestList,
+};
+use ruma_common::{
+    api::error::{FromHttpResponseError, ServerError},
+    OwnedRoomId,
+};
+
+use crate::{Client, Error, HttpClient};
+
+/// An entry of a list of rooms.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RoomListEntry {
+    /// The room at this position is not known yet.
+    Empty,
+
+    /// The room at this position.
+    Filled(OwnedRoomId),
+
+    /// The room that was at this position, before the position was invalidated.
+    ///
+    /// This happens when the position is not in the ranges of the list anymore.
+    Invalidated(OwnedRoomId),
+}
+
+impl RoomListEntry {
+    /// The ID of the room at this position, if it is known.
+    pub fn room_id(&self) -> Option<&OwnedRoomId> {
+        match self {
+            Self::Empty => None,
+            Self::Filled(room_id) | Self::Invalidated(room_id) => Some(room_id),
+        }
+    }
+}
+
+/// A change to a list of rooms.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RoomListDiff {
+    /// The list was resized to the given length.
+    ///
+    /// New entries are [`RoomListEntry::Empty`].
+    Resize {
+        /// The new length of the list.
+        len: usize,
+    },
+
+    /// The entry at the given index was replaced.
+    Set {
+        /// The index of the entry.
+        index: usize,
+
+        /// The new entry.
+        entry: RoomListEntry,
+    },
+
+    /// A room was inserted at the given index, shifting the following entries.
+    Insert {
+        /// The index of the room.
+        index: usize,
+
+        /// The ID of the room.
+        room_id: OwnedRoomId,
+    },
+
+    /// The entry at the given index was removed, shifting the following entries.
+    Remove {
+        /// The index of the entry.
+        index: usize,
+    },
+}
+
+/// A list of rooms of a sliding sync.
+#[derive(Clone, Debug)]
+pub struct SlidingSyncList {
+    /// The name of the list.
+    name: String,
+
+    /// The parameters of the list.
+    request: SyncRequestList,
+
+    /// The number of rooms to add to the range at every request, if the range grows.
+    batch_size: Option<UInt>,
+
+    /// The rooms of the list.
+    rooms: Vec<RoomListEntry>,
+}
+
+impl SlidingSyncList {
+    /// Creates a `SlidingSyncList` with the given name and parameters.
+    ///
+    /// The ranges of the list are the ones of the parameters, and can be changed with
+    /// [`set_ranges()`](Self::set_ranges).
+    pub fn new(name: impl Into<String>, request: SyncRequestList) -> Self {
+        Self { name: name.into(), request, batch_size: None, rooms: Vec::new() }
+    }
+
+    /// Creates a `SlidingSyncList` whose range grows until it contains all the rooms.
+    ///
+    /// The range starts with the first `batch_size` rooms, and grows by `batch_size` rooms after
+    /// every response until the end of the list is reached. The ranges of the parameters are
+    /// ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is zero.
+    pub fn growing(
+        name: impl Into<String>,
+        mut request: SyncRequestList,
+        batch_size: UInt,
+    ) -> Self {
+        assert!(batch_size > UInt::MIN, "batch_size must not be zero");
+
+        request.ranges = vec![(UInt::MIN, batch_size - UInt::from(1_u32))];
+        Self { name: name.into(), request, batch_size: Some(batch_size), rooms: Vec::new() }
+    }
+
+    /// The name of this list.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The parameters of this list, sent with every request.
+    pub fn request(&self) -> &SyncRequestList {
+        &self.request
+    }
+
+    /// Set the ranges of this list, for the next request.
+    ///
+    /// This stops the growth of the range of a [growing](Self::growing) list.
+    pub fn set_ranges(&mut self, ranges: Vec<(UInt, UInt)>) {
+        self.request.ranges = ranges;
+        self.batch_size = None;
+    }
+
+    /// The rooms of this list.
+    ///
+    /// The length of the list is the total number of rooms of the list on the homeserver.
+    pub fn rooms(&self) -> &[RoomListEntry] {
+        &self.rooms
+    }
+
+    /// Apply the given update from the homeserver, and return the changes.
+    fn apply(&mut self, list: &SyncList) -> Vec<RoomListDiff> {
+        let mut diffs = Vec::new();
+        let len = to_usize(list.count);
+
+        // Operations are relative to the new count, grow the list before applying them and
+        // shrink it after.
+        if len > self.rooms.len() {
+            self.rooms.resize(len, RoomListEntry::Empty);
+            diffs.push(RoomListDiff::Resize { len });
+        }
+
+        for op in &list.ops {
+            self.apply_op(op, &mut diffs);
+        }
+
+        if len != self.rooms.len() {
+            self.rooms.resize(len, RoomListEntry::Empty);
+            diffs.push(RoomListDiff::Resize { len });
+        }
+
+        self.grow_range(list.count);
+
+        diffs
+    }
+
+    /// Remove all the rooms of this list, and restart the growth of the range of a growing list.
+    fn reset(&mut self) -> Vec<RoomListDiff> {
+        if let Some(batch_size) = self.batch_size {
+            self.request.ranges = vec![(UInt::MIN, batch_size - UInt::from(1_u32))];
+        }
+
+        if self.rooms.is_empty() {
+            return Vec::new();
+        }
+
+        self.rooms.clear();
+        vec![RoomListDiff::Resize { len: 0 }]
+    }
+
+    /// Apply the given operation, and add the changes to `diffs`.
+    fn apply_op(&mut self, op: &SyncOp, diffs: &mut Vec<RoomListDiff>) {
+        match op.op {
+            SlidingOp::Sync => {
+                let start = match op.range {
+                    Some((start, _)) => to_usize(start),
+                    None => return,
+                };
+
+                for (index, room_id) in (start..).zip(&op.room_ids) {
+                    let entry = RoomListEntry::Filled(room_id.clone());
+                    self.set(index, entry, diffs);
+                }
+            }
+            SlidingOp::Invalidate => {
+                let (start, end) = match op.range {
+                    Some((start, end)) => (to_usize(start), to_usize(end)),
+                    None => return,
+                };
+
+                for index in start..end.saturating_add(1).min(self.rooms.len()) {
+                    if let RoomListEntry::Filled(room_id) = &self.rooms[index] {
+                        let entry = RoomListEntry::Invalidated(room_id.clone());
+                        self.set(index, entry, diffs);
+                    }
+                }
+            }
+            SlidingOp::Insert => {
+                let (index, room_id) = match (op.index, &op.room_id) {
+                    (Some(index), Some(room_id)) => (to_usize(index), room_id),
+                    _ => return,
+                };
+                let index = index.min(self.rooms.len());
+
+                self.rooms.insert(index, RoomListEntry::Filled(room_id.clone()));
+                diffs.push(RoomListDiff::Insert { index, room_id: room_id.clone() });
+            }
+            SlidingOp::Delete => {
+                let index = match op.index {
+                    Some(index) => to_usize(index),
+                    None => return,
+                };
+
+                if index < self.rooms.len() {
+                    self.rooms.remove(index);
+                    diffs.push(RoomListDiff::Remove { index });
+                }
+            }
+            #[allow(unreachable_patterns)]
+            _ => {}
+        }
+    }
+
+    /// Set the entry at the given index, growing the list if necessary.
+    fn set(&mut self, index: usize, entry: RoomListEntry, diffs: &mut Vec<RoomListDiff>) {
+        if index >= self.rooms.len() {
+            self.rooms.resize(index + 1, RoomListEntry::Empty);
+            diffs.push(RoomListDiff::Resize { len: index + 1 });
+        }
+
+        if self.rooms[index] != entry {
+            self.rooms[index] = entry.clone();
+            diffs.push(RoomListDiff::Set { index, entry });
+        }
+    }
+
+    /// Grow the range of a growing list, with the given total number of rooms.
+    fn grow_range(&mut self, count: UInt) {
+        let batch_size = match self.batch_size {
+            Some(batch_size) => batch_size,
+            None => return,
+        };
+        let last = match count.checked_sub(UInt::from(1_u32)) {
+            Some(last) => last,
+            None => return,
+        };
+
+        if let Some((_, end)) = self.request.ranges.first_mut() {
+            *end = end.saturating_add(batch_size).min(last);
+        }
+    }
+}
+
+/// The changes of a sliding sync response.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct SlidingSyncUpdate {
+    /// Whether this is the first response of a new sync.
+    ///
+    /// This is the case for the first response, and when the homeserver didn't know the previous
+    /// `pos`. The lists were reset before applying the response, so the previous state of the
+    /// lists should be discarded.
+    pub initial: bool,
+
+    /// The changes of the lists of rooms, by name.
+    ///
+    /// Lists without changes are omitted.
+    pub lists: BTreeMap<String, Vec<RoomListDiff>>,
+
+    /// The updates of the rooms in the lists and of the subscribed rooms.
+    pub rooms: BTreeMap<OwnedRoomId, SlidingSyncRoom>,
+}
+
+/// The state of a sliding sync.
+///
+/// Use [`stream()`](Self::stream) or [`sync_once()`](Self::sync_once) with a [`Client`] to send
+/// the requests, or [`apply_response()`](Self::apply_response) to apply responses that were
+/// received otherwise.
+#[derive(Clone, Debug, Default)]
+pub struct SlidingSync {
+    /// The `pos` of the last response.
+    pos: Option<String>,
+
+    /// The lists of rooms.
+    lists: Vec<SlidingSyncList>,
+
+    /// The room subscriptions.
+    room_subscriptions: BTreeMap<OwnedRoomId, RoomSubscription>,
+
+    /// The rooms to unsubscribe from with the next request.
+    unsubscribe_rooms: Vec<OwnedRoomId>,
+}
+
+impl SlidingSync {
+    /// Creates a `SlidingSync` without lists.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the given list of rooms.
+    ///
+    /// Lists are identified by their name, this replaces the list with the same name, if any.
+    pub fn add_list(&mut self, list: SlidingSyncList) {
+        match self.lists.iter_mut().find(|l| l.name == list.name) {
+            Some(existing) => *existing = list,
+            None => self.lists.push(list),
+        }
+    }
+
+    /// The list of rooms with the given name, if any.
+    pub fn list(&self, name: &str) -> Option<&SlidingSyncList> {
+        self.lists.iter().find(|list| list.name == name)
+    }
+
+    /// The list of rooms with the given name, if any.
+    pub fn list_mut(&mut self, name: &str) -> Option<&mut SlidingSyncList> {
+        self.lists.iter_mut().find(|list| list.name == name)
+    }
+
+    /// The lists of rooms.
+    pub fn lists(&self) -> &[SlidingSyncList] {
+        &self.lists
+    }
+
+    /// Subscribe to the given room, to receive its updates even if it's not in the ranges of the
+    /// lists.
+    pub fn subscribe_to_room(&mut self, room_id: OwnedRoomId, subscription: RoomSubscription) {
+        self.unsubscribe_rooms.retain(|id| *id != room_id);
+        self.room_subscriptions.insert(room_id, subscription);
+    }
+
+    /// Unsubscribe from the given room, with the next request.
+    pub fn unsubscribe_from_room(&mut self, room_id: OwnedRoomId) {
+        if self.room_subscriptions.remove(&room_id).is_some() {
+            self.unsubscribe_rooms.push(room_id);
+        }
+    }
+
+    /// The `pos` of the last response, if any.
+    pub fn pos(&self) -> Option<&str> {
+        self.pos.as_deref()
+    }
+
+    /// Apply the given response, and return the changes.
+    ///
+    /// The lists of the response are matched with the lists of this `SlidingSync` by name. If the
+    /// response starts a new sync, the lists are reset before applying it.
+    pub fn apply_response(&mut self, response: v4::Response) -> SlidingSyncUpdate {
+        let initial = response.initial || self.pos.is_none();
+
+        let lists = self
+            .lists
+            .iter_mut()
+            .filter_map(|list| {
+                let mut diffs = if initial { list.reset() } else { Vec::new() };
+                if let Some(update) = response.lists.get(&list.name) {
+                    diffs.extend(list.apply(update));
+                }
+
+                (!diffs.is_empty()).then(|| (list.name.clone(), diffs))
+            })
+            .collect();
+
+        self.pos = Some(response.pos);
+        self.unsubscribe_rooms.clear();
+
+        SlidingSyncUpdate { initial, lists, rooms: response.rooms }
+    }
+
+    /// Forget the `pos` and the rooms of the lists, to start a new sync with the next request.
+    fn reset(&mut self) {
+        self.pos = None;
+        for list in &mut self.lists {
+            list.reset();
+        }
+    }
+
+    /// Send a sliding sync request with the given client, and apply the response.
+    ///
+    /// If the homeserver doesn't know the `pos` anymore, the `pos` and the lists are reset before
+    /// returning the error, so the next request starts a new sync.
+    pub async fn sync_once<C: HttpClient>(
+        &mut self,
+        client: &Client<C>,
+        timeout: Option<Duration>,
+    ) -> Result<SlidingSyncUpdate, Error<C::Error, ruma_client_api::Error>> {
+        let lists =
+            self.lists.iter().map(|list| (list.name.clone(), list.request.clone())).collect();
+        let request = assign!(v4::Request::new(), {
+            pos: self.pos.as_deref(),
+            timeout,
+            lists,
+            room_subscriptions: self.room_subscriptions.clone(),
+            unsubscribe_rooms: &self.unsubscribe_rooms,
+        });
+
+        match client.send_request(request).await {
+            Ok(response) => Ok(self.apply_response(response)),
+            Err(error) => {
+                if is_unknown_pos(&error) {
+                    self.reset();
+                }
+
+                Err(error)
+            }
+        }
+    }
+
+    /// Send sliding sync requests with the given client, in a loop.
+    ///
+    /// The first request is sent without `timeout`, so that the homeserver responds immediately.
+    /// If the homeserver doesn't know the `pos` anymore, a new sync is started.
+    pub fn stream<'a, C: HttpClient>(
+        &'a mut self,
+        client: &'a Client<C>,
+        timeout: Option<Duration>,
+    ) -> impl Stream<Item = Result<SlidingSyncUpdate, Error<C::Error, ruma_client_api::Error>>> + 'a
+    {
+        try_stream! {
+            let mut timeout_for_request = self.pos.as_ref().and(timeout);
+
+            loop {
+                match self.sync_once(client, timeout_for_request).await {
+                    Ok(update) => {
+                        timeout_for_request = timeout;
+                        yield update;
+                    }
+                    Err(error) if is_unknown_pos(&error) => timeout_for_request = None,
+                    Err(error) => Err(error)?,
+                }
+            }
+        }
+    }
+}
+
+/// Whether the given error is an `M_UNKNOWN_POS` error, returned when the homeserver doesn't know
+/// the `pos` of the request.
+fn is_unknown_pos<E>(error: &Error<E, ruma_client_api::Error>) -> bool {
+    matches!(
+        error,
+        Error::FromHttpResponse(FromHttpResponseError::Server(ServerError::Known(error)))
+            if error.kind.as_ref() == "M_UNKNOWN_POS"
+    )
+}
+
+/// Convert the given index or count to a `usize`.
+fn to_usize(n: UInt) -> usize {
+    usize::try_from(u64::from(n)).unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use assign::assign;
+    use async_trait::async_trait;
+    use js_int::uint;
+    use ruma_client_api::sync::sync_events::v4::{self, SyncRequestList};
+    use ruma_common::{
+        api::{IncomingResponse, MatrixVersion},
+        room_id, OwnedRoomId,
+    };
+    use serde_json::{json, Value as JsonValue};
+    use tokio_stream::StreamExt;
+
+    use super::{RoomListDiff, RoomListEntry, SlidingSync, SlidingSyncList};
+    use crate::{Client, HttpClient};
+
+    fn response(json: JsonValue) -> v4::Response {
+        let http_response = http::Response::new(serde_json::to_vec(&json).unwrap());
+        v4::Response::try_from_http_response(http_response).unwrap()
+    }
+
+    fn filled(room_id: &str) -> RoomListEntry {
+        RoomListEntry::Filled(room_id.try_into().unwrap())
+    }
+
+    #[test]
+    fn list_ops() {
+        let mut sliding_sync = SlidingSync::new();
+        sliding_sync.add_list(SlidingSyncList::new(
+            "all",
+            assign!(SyncRequestList::default(), { ranges: vec![(uint!(0), uint!(2))] }),
+        ));
+
+        let update = sliding_sync.apply_response(response(json!({
+            "pos": "1",
+            "lists": {
+                "all": {
+                    "count": 5,
+                    "ops": [{
+                        "op": "SYNC",
+                        "range": [0, 2],
+                        "room_ids": ["!a:example.org", "!b:example.org", "!c:example.org"],
+                    }],
+                },
+            },
+            "rooms": {
+                "!a:example.org": { "name": "A" },
+            },
+        })));
+
+        assert_eq!(sliding_sync.pos(), Some("1"));
+        assert_eq!(update.rooms[room_id!("!a:example.org")].name.as_deref(), Some("A"));
+        assert_eq!(update.lists["all"].len(), 4);
+        assert_eq!(update.lists["all"][0], RoomListDiff::Resize { len: 5 });
+        let rooms = sliding_sync.list("all").unwrap().rooms();
+        assert_eq!(
+            rooms,
+            [
+                filled("!a:example.org"),
+                filled("!b:example.org"),
+                filled("!c:example.org"),
+                RoomListEntry::Empty,
+                RoomListEntry::Empty,
+            ]
+        );
+
+        // `!d` moves to the top, and `!c` leaves the range.
+        let update = sliding_sync.apply_response(response(json!({
+            "pos": "2",
+            "lists": {
+                "all": {
+                    "count": 5,
+                    "ops": [
+                        { "op": "DELETE", "index": 2 },
+                        { "op": "INSERT", "index": 0, "room_id": "!d:example.org" },
+                        { "op": "INVALIDATE", "range": [1, 1] },
+                    ],
+                },
+            },
+        })));
+
+        assert_eq!(
+            update.lists["all"],
+            [
+                RoomListDiff::Remove { index: 2 },
+                RoomListDiff::Insert { index: 0, room_id: room_id!("!d:example.org").to_owned() },
+                RoomListDiff::Set {
+                    index: 1,
+                    entry: RoomListEntry::Invalidated(room_id!("!a:example.org").to_owned()),
+                },
+            ]
+        );
+        let rooms = sliding_sync.list("all").unwrap().rooms();
+        assert_eq!(rooms.len(), 5);
+        assert_eq!(rooms[0], filled("!d:example.org"));
+        assert_eq!(rooms[2], filled("!b:example.org"));
+
+        // Rooms are left.
+        let update = sliding_sync.apply_response(response(json!({
+            "pos": "3",
+            "lists": { "all": { "count": 2, "ops": [{ "op": "DELETE", "index": 0 }] } },
+        })));
+
+        assert_eq!(
+            update.lists["all"],
+            [RoomListDiff::Remove { index: 0 }, RoomListDiff::Resize { len: 2 }]
+        );
+        assert_eq!(sliding_sync.list("all").unwrap().rooms()[1], filled("!b:example.org"));
+
+        // No changes.
+        let update = sliding_sync
+            .apply_response(response(json!({ "pos": "4", "lists": { "all": { "count": 2 } } })));
+        assert!(!update.initial);
+        assert!(update.lists.is_empty());
+
+        // The homeserver starts a new sync, lists that are not in the response are reset too.
+        sliding_sync.add_list(SlidingSyncList::new("other", SyncRequestList::default()));
+        sliding_sync.list_mut("other").unwrap().rooms.push(filled("!o:example.org"));
+        let update = sliding_sync.apply_response(response(json!({
+            "initial": true,
+            "pos": "5",
+            "lists": {
+                "all": {
+                    "count": 1,
+                    "ops": [{ "op": "SYNC", "range": [0, 0], "room_ids": ["!e:example.org"] }],
+                },
+            },
+        })));
+
+        assert!(update.initial);
+        assert_eq!(
+            update.lists["all"],
+            [
+                RoomListDiff::Resize { len: 0 },
+                RoomListDiff::Resize { len: 1 },
+                RoomListDiff::Set { index: 0, entry: filled("!e:example.org") },
+            ]
+        );
+        assert_eq!(update.lists["other"], [RoomListDiff::Resize { len: 0 }]);
+        assert_eq!(sliding_sync.list("all").unwrap().rooms(), [filled("!e:example.org")]);
+        assert!(sliding_sync.list("other").unwrap().rooms().is_empty());
+    }
+
+    #[test]
+    fn growing_range() {
+        let mut list = SlidingSyncList::growing("all", SyncRequestList::default(), uint!(10));
+        assert_eq!(list.request().ranges, [(uint!(0), uint!(9))]);
+
+        list.apply(
+            &response(json!({ "pos": "1", "lists": { "all": { "count": 25 } } })).lists["all"],
+        );
+        assert_eq!(list.request().ranges, [(uint!(0), uint!(19))]);
+
+        list.apply(
+            &response(json!({ "pos": "2", "lists": { "all": { "count": 25 } } })).lists["all"],
+        );
+        assert_eq!(list.request().ranges, [(uint!(0), uint!(24))]);
+
+        list.apply(
+            &response(json!({ "pos": "3", "lists": { "all": { "count": 25 } } })).lists["all"],
+        );
+        assert_eq!(list.request().ranges, [(uint!(0), uint!(24))]);
+    }
+
+    /// An HTTP client that replies with the given responses in order, and records the requests.
+    #[derive(Clone)]
+    struct MockHttpClient {
+        requests: Arc<Mutex<Vec<http::Request<Vec<u8>>>>>,
+        responses: Arc<Mutex<Vec<JsonValue>>>,
+    }
+
+    #[async_trait]
+    impl HttpClient for MockHttpClient {
+        type RequestBody = Vec<u8>;
+        type ResponseBody = Vec<u8>;
+        type Error = &'static str;
+
+        async fn send_http_request(
+            &self,
+            req: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Self::Error> {
+            self.requests.lock().unwrap().push(req);
+            let mut responses = self.responses.lock().unwrap();
+            if responses.is_empty() {
+                return Err("no more responses");
+            }
+
+            let response = responses.remove(0);
+            let status = if response.get("errcode").is_some() { 400 } else { 200 };
+            Ok(http::Response::builder()
+                .status(status)
+                .body(serde_json::to_vec(&response).unwrap())
+                .unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn stream() {
+        let http_client = MockHttpClient {
+            requests: Default::default(),
+            responses: Arc::new(Mutex::new(vec![
+                json!({
+                    "pos": "1",
+                    "lists": {
+                        "all": {
+                            "count": 1,
+                            "ops": [{
+                                "op": "SYNC",
+                                "range": [0, 0],
+                                "room_ids": ["!a:example.org"],
+                            }],
+                        },
+                    },
+                }),
+                json!({ "pos": "2", "lists": { "all": { "count": 1 } } }),
+                json!({ "errcode": "M_UNKNOWN_POS", "error": "Unknown pos" }),
+                json!({ "initial": true, "pos": "3", "lists": { "all": { "count": 0 } } }),
+            ])),
+        };
+        let client = Client::builder()
+            .homeserver_url("https://matrix.example.org".to_owned())
+            .access_token(Some("token".to_owned()))
+            .supported_matrix_versions(vec![MatrixVersion::V1_3])
+            .http_client(http_client.clone())
+            .await
+            .unwrap();
+
+        let mut sliding_sync = SlidingSync::new();
+        sliding_sync.add_list(SlidingSyncList::growing(
+            "all",
+            SyncRequestList::default(),
+            uint!(5),
+        ));
+        let room_id: OwnedRoomId = room_id!("!sub:example.org").to_owned();
+        sliding_sync.subscribe_to_room(room_id.clone(), Default::default());
+
+        let updates: Vec<_> = sliding_sync
+            .stream(&client, Some(std::time::Duration::from_secs(30)))
+            .take(4)
+            .collect()
+            .await;
+        assert_eq!(updates.len(), 4);
+        assert!(updates[0].as_ref().unwrap().initial);
+        assert_eq!(updates[0].as_ref().unwrap().lists["all"].len(), 2);
+        assert!(!updates[1].as_ref().unwrap().initial);
+        assert!(updates[1].as_ref().unwrap().lists.is_empty());
+        // The unknown `pos` starts a new sync.
+        assert!(updates[2].as_ref().unwrap().initial);
+        assert!(updates[2].as_ref().unwrap().lists.is_empty());
+        assert!(updates[3].is_err());
+
+        let requests = http_client.requests.lock().unwrap();
+        assert_eq!(requests[0].uri().query(), Some(""));
+        assert_eq!(requests[1].uri().query(), Some("pos=1&timeout=30000"));
+        assert_eq!(requests[2].uri().query(), Some("pos=2&timeout=30000"));
+        assert_eq!(requests[3].uri().query(), Some(""));
+        let body: JsonValue = serde_json::from_slice(requests[3].body()).unwrap();
+        assert_eq!(body["lists"], json!({ "all": { "ranges": [[0, 4]] } }));
+
+        let body: JsonValue = serde_json::from_slice(requests[0].body()).unwrap();
+        assert_eq!(body["lists"], json!({ "all": { "ranges": [[0, 4]] } }));
+        assert_eq!(body["room_subscriptions"], json!({ "!sub:example.org": {} }));
+
+        drop(requests);
+        sliding_sync.unsubscribe_from_room(room_id);
+        assert_eq!(sliding_sync.room_subscriptions.len(), 0);
+        assert_eq!(sliding_sync.unsubscribe_rooms, [room_id!("!sub:example.org")]);
+    }
+}
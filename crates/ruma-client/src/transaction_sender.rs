@@ -0,0 +1,439 @@
+//! Batching of PDUs and EDUs into federation transactions.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Mutex, MutexGuard},
+    time::Instant,
+};
+
+use assign::assign;
+use http::StatusCode;
+use ruma_common::{
+    api::{error::FromHttpResponseError, IncomingResponse},
+    serde::Raw,
+    MilliSecondsSinceUnixEpoch, OwnedServerName, OwnedTransactionId, ServerName, TransactionId,
+};
+use ruma_federation_api::transactions::{edu::Edu, send_transaction_message};
+use serde_json::value::RawValue as RawJsonValue;
+
+use crate::{
+    server_discovery::ResolverBackend, Error, FederationClient, HttpClient, ResponseError,
+};
+
+/// The maximum number of PDUs in a transaction.
+const MAX_PDUS_PER_TRANSACTION: usize = 50;
+
+/// The maximum number of EDUs in a transaction.
+const MAX_EDUS_PER_TRANSACTION: usize = 100;
+
+/// The error type for sending a transaction with the http client `C`.
+pub type TransactionError<C> = ResponseError<C, send_transaction_message::v1::Request<'static>>;
+
+/// A queue of PDUs and EDUs to send to other servers in transactions.
+///
+/// PDUs and EDUs are queued per destination with [`queue_pdu`][Self::queue_pdu] and
+/// [`queue_edu`][Self::queue_edu], in the order they must be sent, and are sent in transactions of
+/// at most 50 PDUs and 100 EDUs with [`send_transaction`][Self::send_transaction].
+///
+/// A transaction that could not be sent because the destination couldn't be reached or returned a
+/// server error is retried with the same transaction ID before any other PDUs and EDUs of the
+/// destination. In the meantime, the destination is backed off exponentially by the underlying
+/// [`FederationClient`]. A transaction that was rejected by the destination is dropped.
+#[derive(Debug)]
+pub struct TransactionSender<C, B> {
+    client: FederationClient<C, B>,
+    queues: Mutex<BTreeMap<OwnedServerName, DestinationQueue>>,
+}
+
+#[derive(Debug, Default)]
+struct DestinationQueue {
+    /// The PDUs that are not in a transaction yet.
+    pdus: VecDeque<Box<RawJsonValue>>,
+
+    /// The EDUs that are not in a transaction yet.
+    edus: VecDeque<Raw<Edu>>,
+
+    /// The transaction that failed to be sent, to retry before the queued PDUs and EDUs.
+    failed: Option<Transaction>,
+
+    /// Whether a transaction is currently being sent.
+    in_flight: bool,
+}
+
+impl DestinationQueue {
+    fn is_empty(&self) -> bool {
+        self.pdus.is_empty() && self.edus.is_empty() && self.failed.is_none()
+    }
+}
+
+#[derive(Debug)]
+struct Transaction {
+    id: OwnedTransactionId,
+    origin_server_ts: MilliSecondsSinceUnixEpoch,
+    pdus: Vec<Box<RawJsonValue>>,
+    edus: Vec<Raw<Edu>>,
+}
+
+impl<C, B> TransactionSender<C, B> {
+    /// Creates a new `TransactionSender` sending transactions with the given client.
+    pub fn new(client: FederationClient<C, B>) -> Self {
+        Self { client, queues: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Get a reference to the client used to send the transactions.
+    pub fn client(&self) -> &FederationClient<C, B> {
+        &self.client
+    }
+
+    /// Queue a PDU to send to the given destination.
+    pub fn queue_pdu(&self, destination: &ServerName, pdu: Box<RawJsonValue>) {
+        self.lock_queues().entry(destination.to_owned()).or_default().pdus.push_back(pdu);
+    }
+
+    /// Queue an EDU to send to the given destination.
+    pub fn queue_edu(&self, destination: &ServerName, edu: Raw<Edu>) {
+        self.lock_queues().entry(destination.to_owned()).or_default().edus.push_back(edu);
+    }
+
+    /// Get the destinations that have PDUs or EDUs to send.
+    pub fn pending_destinations(&self) -> Vec<OwnedServerName> {
+        self.lock_queues()
+            .iter()
+            .filter(|(_, queue)| !queue.is_empty())
+            .map(|(destination, _)| destination.clone())
+            .collect()
+    }
+
+    /// Get the time until which transactions to the given destination are backed off, if any.
+    pub fn retry_at(&self, destination: &ServerName) -> Option<Instant> {
+        self.client.backoff_until(destination)
+    }
+
+    fn lock_queues(&self) -> MutexGuard<'_, BTreeMap<OwnedServerName, DestinationQueue>> {
+        self.queues.lock().expect("queues mutex was poisoned")
+    }
+
+    /// Take the next transaction to send to the given destination, if any.
+    fn start_transaction(&self, destination: &ServerName) -> Option<Transaction> {
+        let mut queues = self.lock_queues();
+        let queue = queues.get_mut(destination)?;
+
+        if queue.in_flight || queue.is_empty() {
+            return None;
+        }
+
+        let transaction = queue.failed.take().unwrap_or_else(|| {
+            let pdus_len = queue.pdus.len().min(MAX_PDUS_PER_TRANSACTION);
+            let edus_len = queue.edus.len().min(MAX_EDUS_PER_TRANSACTION);
+
+            Transaction {
+                id: TransactionId::new(),
+                origin_server_ts: MilliSecondsSinceUnixEpoch::now(),
+                pdus: queue.pdus.drain(..pdus_len).collect(),
+                edus: queue.edus.drain(..edus_len).collect(),
+            }
+        });
+        queue.in_flight = true;
+
+        Some(transaction)
+    }
+
+    /// Mark the transaction to the given destination as done, keeping it for a retry if it failed.
+    fn finish_transaction(&self, destination: &ServerName, failed: Option<Transaction>) {
+        let mut queues = self.lock_queues();
+
+        if let Some(queue) = queues.get_mut(destination) {
+            queue.in_flight = false;
+            queue.failed = failed;
+
+            if queue.is_empty() {
+                queues.remove(destination);
+            }
+        }
+    }
+}
+
+impl<C: HttpClient, B: ResolverBackend> TransactionSender<C, B> {
+    /// Send the next transaction to the given destination.
+    ///
+    /// Returns `Ok(None)` if there is nothing to send to the destination, or if a transaction is
+    /// already being sent to it. Otherwise, returns the response of the destination, with the
+    /// result of the processing of each PDU of the transaction.
+    ///
+    /// If the transaction could not be sent because of a failure to reach the destination or a
+    /// server error, it is kept to be retried and the destination is backed off. Until the backoff
+    /// expires, this returns [`Error::Backoff`]. If the transaction was rejected, for example with
+    /// a `4xx` status code, it is dropped since it would be rejected again.
+    pub async fn send_transaction(
+        &self,
+        destination: &ServerName,
+    ) -> Result<Option<send_transaction_message::v1::Response>, TransactionError<C>> {
+        if let Some(retry_at) = self.retry_at(destination) {
+            return Err(Error::Backoff(retry_at.saturating_duration_since(Instant::now())));
+        }
+
+        let transaction = match self.start_transaction(destination) {
+            Some(transaction) => transaction,
+            None => return Ok(None),
+        };
+
+        // Keep the transaction for a retry if this future is dropped before it is sent.
+        let mut in_flight =
+            InFlightTransaction { sender: self, destination, transaction: Some(transaction) };
+        let transaction = in_flight.transaction.as_ref().expect("transaction was just set");
+
+        let request = assign!(send_transaction_message::v1::Request::new(
+            &transaction.id,
+            self.client.origin(),
+            transaction.origin_server_ts,
+        ), {
+            pdus: &transaction.pdus,
+            edus: &transaction.edus,
+        });
+
+        // Keep the status code of the response, the error body might not be deserializable.
+        let (status, result) = match self.client.send_http_request(destination, request).await {
+            Ok(http_res) => (
+                Some(http_res.status()),
+                send_transaction_message::v1::Response::try_from_http_response(http_res)
+                    .map_err(Error::from),
+            ),
+            Err(error) => (None, Err(error)),
+        };
+
+        match result {
+            Ok(response) => {
+                in_flight.transaction = None;
+                Ok(Some(response))
+            }
+            Err(error) => {
+                if !is_retryable(&error, status) {
+                    in_flight.transaction = None;
+                } else if self.retry_at(destination).is_none() {
+                    // Failures to reach the destination and server errors are already backed off
+                    // by the client, but not rate-limited requests.
+                    self.client.record_failure(destination);
+                }
+
+                Err(error)
+            }
+        }
+    }
+}
+
+/// Whether a transaction should be retried after the given error, with the status code of the
+/// response if one was received.
+///
+/// Only failures to reach the destination, server errors and rate-limited requests are retried,
+/// other errors would happen again with the same transaction. The status code is checked even if
+/// the body of the error is not JSON, like the error pages of reverse proxies.
+fn is_retryable<E, F>(error: &Error<E, F>, status: Option<StatusCode>) -> bool {
+    match error {
+        Error::Response(_) | Error::Backoff(_) | Error::NoDestinationAddress => true,
+        Error::FromHttpResponse(FromHttpResponseError::Server(_)) => status
+            .map_or(false, |status| {
+                status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+            }),
+        _ => false,
+    }
+}
+
+/// A transaction being sent, that is kept for a retry when dropped if it was not sent.
+struct InFlightTransaction<'a, C, B> {
+    sender: &'a TransactionSender<C, B>,
+    destination: &'a ServerName,
+    transaction: Option<Transaction>,
+}
+
+impl<C, B> Drop for InFlightTransaction<'_, C, B> {
+    fn drop(&mut self) {
+        self.sender.finish_transaction(self.destination, self.transaction.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use ruma_common::{serde::Raw, server_name};
+    use ruma_signatures::Ed25519KeyPair;
+    use serde_json::{
+        from_slice as from_json_slice, json, to_value as to_json_value,
+        value::to_raw_value as to_raw_json_value, Value as JsonValue,
+    };
+
+    use super::TransactionSender;
+    use crate::{
+        server_discovery::{ResolverBackend, ServerResolver, SrvRecord},
+        Error, FederationClient, HttpClient,
+    };
+
+    struct StubBackend;
+
+    #[async_trait]
+    impl ResolverBackend for StubBackend {
+        type Error = &'static str;
+
+        async fn lookup_srv(&self, _name: &str) -> Result<Vec<SrvRecord>, Self::Error> {
+            Ok(Vec::new())
+        }
+
+        async fn get_well_known(
+            &self,
+            _hostname: &str,
+        ) -> Result<http::Response<Vec<u8>>, Self::Error> {
+            Err("connection refused")
+        }
+    }
+
+    #[derive(Default)]
+    struct MockState {
+        /// The status code and body of the error to respond with, if any.
+        error: Mutex<Option<(u16, &'static str)>>,
+        transactions: Mutex<Vec<JsonValue>>,
+    }
+
+    struct MockHttpClient(Arc<MockState>);
+
+    #[async_trait]
+    impl HttpClient for MockHttpClient {
+        type RequestBody = Vec<u8>;
+        type ResponseBody = Vec<u8>;
+        type Error = String;
+
+        async fn send_http_request(
+            &self,
+            req: http::Request<Vec<u8>>,
+        ) -> Result<http::Response<Vec<u8>>, Self::Error> {
+            let mut transaction: JsonValue = from_json_slice(req.body()).unwrap();
+            transaction["transaction_id"] = req.uri().path().rsplit('/').next().unwrap().into();
+            self.0.transactions.lock().unwrap().push(transaction);
+
+            if let Some((status, body)) = *self.0.error.lock().unwrap() {
+                return Ok(http::Response::builder()
+                    .status(status)
+                    .body(body.as_bytes().to_vec())
+                    .unwrap());
+            }
+
+            let body = json!({ "pdus": { "$event:origin.example": {} } });
+            Ok(http::Response::new(serde_json::to_vec(&body).unwrap()))
+        }
+    }
+
+    fn sender() -> (TransactionSender<MockHttpClient, StubBackend>, Arc<MockState>) {
+        let key_pair =
+            Ed25519KeyPair::from_der(&Ed25519KeyPair::generate().unwrap(), "1".to_owned()).unwrap();
+        let state = Arc::new(MockState::default());
+
+        let sender = TransactionSender::new(FederationClient::new(
+            server_name!("origin.example").to_owned(),
+            key_pair,
+            MockHttpClient(state.clone()),
+            ServerResolver::new(StubBackend),
+        ));
+
+        (sender, state)
+    }
+
+    #[tokio::test]
+    async fn batch_limits() {
+        let (sender, state) = sender();
+        let destination = server_name!("remote.example");
+
+        for i in 0..60 {
+            sender.queue_pdu(destination, to_raw_json_value(&json!({ "depth": i })).unwrap());
+        }
+        for _ in 0..120 {
+            let edu = json!({ "edu_type": "m.typing", "content": {} });
+            sender.queue_edu(destination, Raw::from_json(to_raw_json_value(&edu).unwrap()));
+        }
+        assert_eq!(sender.pending_destinations(), [destination]);
+
+        let response = sender.send_transaction(destination).await.unwrap().unwrap();
+        assert_eq!(response.pdus.len(), 1);
+        assert_eq!(response.pdus.values().next().unwrap(), &Ok(()));
+
+        sender.send_transaction(destination).await.unwrap().unwrap();
+        assert!(sender.send_transaction(destination).await.unwrap().is_none());
+        assert!(sender.pending_destinations().is_empty());
+
+        let transactions = state.transactions.lock().unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0]["pdus"].as_array().unwrap().len(), 50);
+        assert_eq!(transactions[0]["edus"].as_array().unwrap().len(), 100);
+        assert_eq!(transactions[1]["pdus"].as_array().unwrap().len(), 10);
+        assert_eq!(transactions[1]["pdus"][0], json!({ "depth": 50 }));
+        assert_eq!(transactions[1]["edus"].as_array().unwrap().len(), 20);
+        assert_ne!(transactions[0]["transaction_id"], transactions[1]["transaction_id"]);
+    }
+
+    #[tokio::test]
+    async fn retry_failed_transaction() {
+        let (sender, state) = sender();
+        let destination = server_name!("remote.example");
+        *state.error.lock().unwrap() =
+            Some((500, r#"{ "errcode": "M_UNKNOWN", "error": "Oops" }"#));
+
+        sender.queue_pdu(destination, to_raw_json_value(&json!({ "depth": 1 })).unwrap());
+        assert!(matches!(
+            sender.send_transaction(destination).await,
+            Err(Error::FromHttpResponse(_))
+        ));
+        assert!(sender.retry_at(destination).is_some());
+        assert!(matches!(sender.send_transaction(destination).await, Err(Error::Backoff(_))));
+
+        sender.queue_pdu(destination, to_raw_json_value(&json!({ "depth": 2 })).unwrap());
+        *state.error.lock().unwrap() = None;
+        sender.client().reset_backoff(destination);
+
+        sender.send_transaction(destination).await.unwrap().unwrap();
+        sender.send_transaction(destination).await.unwrap().unwrap();
+
+        let transactions = state.transactions.lock().unwrap();
+        assert_eq!(transactions.len(), 3);
+        assert_eq!(transactions[1], transactions[0]);
+        assert_eq!(transactions[2]["pdus"], to_json_value([json!({ "depth": 2 })]).unwrap());
+    }
+
+    #[tokio::test]
+    async fn retry_rate_limited_transaction() {
+        let (sender, state) = sender();
+        let destination = server_name!("remote.example");
+        *state.error.lock().unwrap() = Some((429, "Too Many Requests"));
+
+        sender.queue_pdu(destination, to_raw_json_value(&json!({ "depth": 1 })).unwrap());
+        assert!(matches!(
+            sender.send_transaction(destination).await,
+            Err(Error::FromHttpResponse(_))
+        ));
+        assert!(sender.retry_at(destination).is_some());
+        assert_eq!(sender.pending_destinations(), [destination]);
+    }
+
+    #[tokio::test]
+    async fn drop_rejected_transaction() {
+        let errors = [
+            (403, r#"{ "errcode": "M_FORBIDDEN", "error": "Nope" }"#),
+            (401, "<html><body>Unauthorized</body></html>"),
+            (404, ""),
+        ];
+
+        for error in errors {
+            let (sender, state) = sender();
+            let destination = server_name!("remote.example");
+            *state.error.lock().unwrap() = Some(error);
+
+            sender.queue_pdu(destination, to_raw_json_value(&json!({ "depth": 1 })).unwrap());
+            assert!(matches!(
+                sender.send_transaction(destination).await,
+                Err(Error::FromHttpResponse(_))
+            ));
+            assert!(sender.retry_at(destination).is_none());
+            assert!(sender.pending_destinations().is_empty());
+            assert!(sender.send_transaction(destination).await.unwrap().is_none());
+            assert_eq!(state.transactions.lock().unwrap().len(), 1);
+        }
+    }
+}
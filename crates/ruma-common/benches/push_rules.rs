@@ -0,0 +1,85 @@
+// `cargo bench` works, but if you use `cargo bench -- --save-baseline <name>`
+// or pass any other args to it, it fails with the error
+// `cargo bench unknown option --save-baseline`.
+// To pass args to criterion, use this form
+// `cargo bench --features criterion --bench <name of the bench> -- --save-baseline <name>`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use js_int::{int, UInt};
+use ruma_common::{
+    power_levels::NotificationPowerLevels,
+    push::{PreparedPushEvent, PushConditionRoomCtx, Ruleset, RulesetCompiler},
+    room_id,
+    serde::Raw,
+    OwnedUserId, UserId,
+};
+use serde_json::{json, Value as JsonValue};
+
+const USERS: usize = 1000;
+
+fn message() -> Raw<JsonValue> {
+    Raw::new(&json!({
+        "content": {
+            "body": "Is anyone around to review my pull request?",
+            "msgtype": "m.text"
+        },
+        "event_id": "$15139375512JaHAW:localhost",
+        "origin_server_ts": 45,
+        "sender": "@example:localhost",
+        "room_id": "!room:localhost",
+        "type": "m.room.message"
+    }))
+    .unwrap()
+}
+
+fn users() -> Vec<OwnedUserId> {
+    (0..USERS).map(|i| UserId::parse(format!("@user{i}:localhost")).unwrap()).collect()
+}
+
+fn context(user_id: OwnedUserId) -> PushConditionRoomCtx {
+    PushConditionRoomCtx {
+        room_id: room_id!("!room:localhost").to_owned(),
+        member_count: UInt::new(USERS as u64).unwrap(),
+        user_display_name: user_id.localpart().to_owned(),
+        user_id,
+        users_power_levels: Default::default(),
+        default_power_level: int!(0),
+        notification_power_levels: NotificationPowerLevels::new(),
+    }
+}
+
+fn ruleset_get_actions(c: &mut Criterion) {
+    let event = message();
+    let users: Vec<_> =
+        users().into_iter().map(|u| (Ruleset::server_default(&u), context(u))).collect();
+
+    c.bench_function("`Ruleset::get_actions` for many users", |b| {
+        b.iter(|| {
+            for (ruleset, context) in &users {
+                let _ = ruleset.get_actions(&event, context);
+            }
+        });
+    });
+}
+
+fn compiled_ruleset_get_actions(c: &mut Criterion) {
+    let event = message();
+    let mut compiler = RulesetCompiler::new();
+    let users: Vec<_> = users()
+        .into_iter()
+        .map(|u| (compiler.compile(&Ruleset::server_default(&u)), context(u)))
+        .collect();
+
+    c.bench_function("`CompiledRuleset::get_actions` for many users", |b| {
+        b.iter(|| {
+            let event = PreparedPushEvent::new(&event);
+            for (ruleset, context) in &users {
+                let _ = ruleset.get_actions(&event, context);
+            }
+        });
+    });
+}
+
+criterion_group!(benches, ruleset_get_actions, compiled_ruleset_get_actions);
+
+criterion_main!(benches);
@@ -155,12 +155,10 @@ pub fn redact_in_place(
     event: &mut CanonicalJsonObject,
     version: &RoomVersionId,
 ) -> Result<(), RedactionError> {
-    // Get the content keys here even if they're only needed inside the branch below, because we
-    // can't teach rust that this is a disjoint borrow with `get_mut("content")`.
-    let allowed_content_keys: &[&str] = match event.get("type") {
-        Some(CanonicalJsonValue::String(event_type)) => {
-            allowed_content_keys_for(event_type, version)
-        }
+    // Clone the event type because we can't teach rust that this is a disjoint borrow with
+    // `get_mut("content")`.
+    let event_type = match event.get("type") {
+        Some(CanonicalJsonValue::String(event_type)) => event_type.clone(),
         Some(_) => return Err(RedactionError::not_of_type("type", JsonType::String)),
         None => return Err(RedactionError::field_missing_from_object("type")),
     };
@@ -171,12 +169,12 @@ pub fn redact_in_place(
             _ => return Err(RedactionError::not_of_type("content", JsonType::Object)),
         };
 
-        object_retain_keys(content, allowed_content_keys);
+        redact_content_in_place(content, version, event_type);
     }
 
     let mut old_event = mem::take(event);
 
-    for &key in ALLOWED_KEYS {
+    for &key in allowed_keys_for(version) {
         if let Some(value) = old_event.remove(key) {
             event.insert(key.to_owned(), value);
         }
@@ -193,7 +191,25 @@ pub fn redact_content_in_place(
     version: &RoomVersionId,
     event_type: impl AsRef<str>,
 ) {
-    object_retain_keys(object, allowed_content_keys_for(event_type.as_ref(), version));
+    match allowed_content_keys_for(event_type.as_ref(), version) {
+        #[cfg(feature = "unstable-msc3820")]
+        AllowedKeys::All => {}
+        AllowedKeys::Some(keys) => object_retain_keys(object, keys),
+    }
+
+    // Only the `signed` key of `third_party_invite` is kept since room version 11.
+    #[cfg(feature = "unstable-msc3820")]
+    if event_type.as_ref() == "m.room.member" && *version == RoomVersionId::V11 {
+        match object.get_mut("third_party_invite") {
+            Some(CanonicalJsonValue::Object(third_party_invite)) => {
+                object_retain_keys(third_party_invite, &["signed"]);
+            }
+            Some(_) => {
+                object.remove("third_party_invite");
+            }
+            None => {}
+        }
+    }
 }
 
 fn object_retain_keys(object: &mut CanonicalJsonObject, keys: &[&str]) {
@@ -225,29 +241,90 @@ static ALLOWED_KEYS: &[&str] = &[
     "membership",
 ];
 
-fn allowed_content_keys_for(event_type: &str, version: &RoomVersionId) -> &'static [&'static str] {
-    match event_type {
+/// The fields that are allowed to remain in an event during redaction since room version 11.
+///
+/// `origin`, `membership` and `prev_state` are not kept anymore.
+#[cfg(feature = "unstable-msc3820")]
+static ALLOWED_KEYS_V11: &[&str] = &[
+    "event_id",
+    "type",
+    "room_id",
+    "sender",
+    "state_key",
+    "content",
+    "hashes",
+    "signatures",
+    "depth",
+    "prev_events",
+    "auth_events",
+    "origin_server_ts",
+];
+
+fn allowed_keys_for(version: &RoomVersionId) -> &'static [&'static str] {
+    match version {
+        #[cfg(feature = "unstable-msc3820")]
+        RoomVersionId::V11 => ALLOWED_KEYS_V11,
+        _ => ALLOWED_KEYS,
+    }
+}
+
+/// The keys of the content of an event that are allowed to remain during redaction.
+enum AllowedKeys {
+    /// All the keys are kept.
+    #[cfg(feature = "unstable-msc3820")]
+    All,
+
+    /// Only the given keys are kept.
+    Some(&'static [&'static str]),
+}
+
+fn allowed_content_keys_for(event_type: &str, version: &RoomVersionId) -> AllowedKeys {
+    let keys: &[&str] = match event_type {
         "m.room.member" => match version {
             RoomVersionId::V9 | RoomVersionId::V10 => {
                 &["membership", "join_authorised_via_users_server"]
             }
+            #[cfg(feature = "unstable-msc3820")]
+            RoomVersionId::V11 => {
+                &["membership", "join_authorised_via_users_server", "third_party_invite"]
+            }
             _ => &["membership"],
         },
-        "m.room.create" => &["creator"],
+        "m.room.create" => match version {
+            #[cfg(feature = "unstable-msc3820")]
+            RoomVersionId::V11 => return AllowedKeys::All,
+            _ => &["creator"],
+        },
         "m.room.join_rules" => match version {
             RoomVersionId::V8 | RoomVersionId::V9 | RoomVersionId::V10 => &["join_rule", "allow"],
+            #[cfg(feature = "unstable-msc3820")]
+            RoomVersionId::V11 => &["join_rule", "allow"],
             _ => &["join_rule"],
         },
-        "m.room.power_levels" => &[
-            "ban",
-            "events",
-            "events_default",
-            "kick",
-            "redact",
-            "state_default",
-            "users",
-            "users_default",
-        ],
+        "m.room.power_levels" => match version {
+            #[cfg(feature = "unstable-msc3820")]
+            RoomVersionId::V11 => &[
+                "ban",
+                "events",
+                "events_default",
+                "invite",
+                "kick",
+                "redact",
+                "state_default",
+                "users",
+                "users_default",
+            ],
+            _ => &[
+                "ban",
+                "events",
+                "events_default",
+                "kick",
+                "redact",
+                "state_default",
+                "users",
+                "users_default",
+            ],
+        },
         "m.room.aliases" => match version {
             RoomVersionId::V1
             | RoomVersionId::V2
@@ -263,8 +340,12 @@ fn allowed_content_keys_for(event_type: &str, version: &RoomVersionId) -> &'stat
             &["allow", "deny", "allow_ip_literals"]
         }
         "m.room.history_visibility" => &["history_visibility"],
+        #[cfg(feature = "unstable-msc3820")]
+        "m.room.redaction" if *version == RoomVersionId::V11 => &["redacts"],
         _ => &[],
-    }
+    };
+
+    AllowedKeys::Some(keys)
 }
 
 #[cfg(test)]
@@ -364,4 +445,82 @@ mod tests {
 
         assert_eq!(to_canonical_value(t).unwrap(), CanonicalJsonValue::Object(expected));
     }
+
+    #[cfg(feature = "unstable-msc3820")]
+    #[test]
+    fn redact_v11() {
+        use super::redact;
+        use crate::RoomVersionId;
+
+        let create: CanonicalJsonValue = json!({
+            "content": {
+                "m.federate": false,
+                "room_version": "11",
+            },
+            "origin": "example.com",
+            "origin_server_ts": 1,
+            "room_id": "!room:example.com",
+            "sender": "@carl:example.com",
+            "state_key": "",
+            "type": "m.room.create",
+        })
+        .try_into()
+        .unwrap();
+        let create = match create {
+            CanonicalJsonValue::Object(create) => create,
+            _ => unreachable!(),
+        };
+
+        // The whole content is kept, but not the `origin`.
+        let redacted = redact(&create, &RoomVersionId::V11).unwrap();
+        assert_eq!(redacted.get("content"), create.get("content"));
+        assert_eq!(redacted.get("origin"), None);
+
+        let member: CanonicalJsonValue = json!({
+            "content": {
+                "displayname": "Carl",
+                "membership": "invite",
+                "third_party_invite": {
+                    "display_name": "carl",
+                    "signed": {
+                        "mxid": "@carl:example.com",
+                        "signatures": {},
+                        "token": "abc",
+                    },
+                },
+            },
+            "origin_server_ts": 1,
+            "room_id": "!room:example.com",
+            "sender": "@alice:example.com",
+            "state_key": "@carl:example.com",
+            "type": "m.room.member",
+        })
+        .try_into()
+        .unwrap();
+        let member = match member {
+            CanonicalJsonValue::Object(member) => member,
+            _ => unreachable!(),
+        };
+
+        // Only the `signed` key of the third-party invite is kept.
+        let redacted = redact(&member, &RoomVersionId::V11).unwrap();
+        let expected: CanonicalJsonValue = json!({
+            "membership": "invite",
+            "third_party_invite": {
+                "signed": {
+                    "mxid": "@carl:example.com",
+                    "signatures": {},
+                    "token": "abc",
+                },
+            },
+        })
+        .try_into()
+        .unwrap();
+        assert_eq!(redacted.get("content"), Some(&expected));
+
+        // Before room version 11, the third-party invite is not kept.
+        let redacted = redact(&member, &RoomVersionId::V10).unwrap();
+        let expected: CanonicalJsonValue = json!({ "membership": "invite" }).try_into().unwrap();
+        assert_eq!(redacted.get("content"), Some(&expected));
+    }
 }
@@ -102,9 +102,16 @@
 //! );
 //! ```
 
+#[cfg(feature = "unstable-msc3952")]
+use std::collections::BTreeSet;
+
+#[cfg(feature = "unstable-msc3952")]
+use serde::Serialize;
 use serde::{de::IgnoredAny, Deserialize, Serializer};
 
 use self::room::redaction::SyncRoomRedactionEvent;
+#[cfg(feature = "unstable-msc3952")]
+use crate::OwnedUserId;
 use crate::{EventEncryptionAlgorithm, RoomVersionId};
 
 // Needs to be public for trybuild tests
@@ -203,6 +210,42 @@ pub trait RedactContent {
     fn redact(self, version: &RoomVersionId) -> Self::Redacted;
 }
 
+/// The users and the room mentioned by an event.
+///
+/// This is [MSC3952].
+///
+/// [MSC3952]: https://github.com/matrix-org/matrix-spec-proposals/pull/3952
+#[cfg(feature = "unstable-msc3952")]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
+pub struct Mentions {
+    /// The IDs of the mentioned users.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub user_ids: BTreeSet<OwnedUserId>,
+
+    /// Whether the whole room is mentioned.
+    #[serde(default, skip_serializing_if = "crate::serde::is_default")]
+    pub room: bool,
+}
+
+#[cfg(feature = "unstable-msc3952")]
+impl Mentions {
+    /// Creates an empty `Mentions`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a `Mentions` with the given user IDs.
+    pub fn with_user_ids(user_ids: impl IntoIterator<Item = OwnedUserId>) -> Self {
+        Self { user_ids: user_ids.into_iter().collect(), ..Default::default() }
+    }
+
+    /// Creates a `Mentions` for a room mention.
+    pub fn with_room_mention() -> Self {
+        Self { room: true, ..Default::default() }
+    }
+}
+
 /// Helper struct to determine the event kind from a `serde_json::value::RawValue`.
 #[doc(hidden)]
 #[derive(Deserialize)]
@@ -114,6 +114,8 @@ impl From<AudioEventContent> for RoomMessageEventContent {
                 message, file, audio,
             )),
             relates_to,
+            #[cfg(feature = "unstable-msc3952")]
+            mentions: None,
         }
     }
 }
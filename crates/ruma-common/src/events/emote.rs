@@ -75,6 +75,11 @@ impl From<EmoteEventContent> for RoomMessageEventContent {
     fn from(content: EmoteEventContent) -> Self {
         let EmoteEventContent { message, relates_to, .. } = content;
 
-        Self { msgtype: MessageType::Emote(message.into()), relates_to }
+        Self {
+            msgtype: MessageType::Emote(message.into()),
+            relates_to,
+            #[cfg(feature = "unstable-msc3952")]
+            mentions: None,
+        }
     }
 }
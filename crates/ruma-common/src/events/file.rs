@@ -127,6 +127,8 @@ impl From<FileEventContent> for RoomMessageEventContent {
                 message, file,
             )),
             relates_to,
+            #[cfg(feature = "unstable-msc3952")]
+            mentions: None,
         }
     }
 }
@@ -133,6 +133,8 @@ impl From<ImageEventContent> for RoomMessageEventContent {
                 message, file, image, thumbnail, caption,
             )),
             relates_to,
+            #[cfg(feature = "unstable-msc3952")]
+            mentions: None,
         }
     }
 }
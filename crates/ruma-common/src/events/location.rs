@@ -95,6 +95,8 @@ impl From<LocationEventContent> for RoomMessageEventContent {
                 message, location, asset, ts,
             )),
             relates_to,
+            #[cfg(feature = "unstable-msc3952")]
+            mentions: None,
         }
     }
 }
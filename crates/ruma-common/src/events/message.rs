@@ -137,7 +137,12 @@ impl From<MessageEventContent> for RoomMessageEventContent {
     fn from(content: MessageEventContent) -> Self {
         let MessageEventContent { message, relates_to, .. } = content;
 
-        Self { msgtype: MessageType::Text(message.into()), relates_to }
+        Self {
+            msgtype: MessageType::Text(message.into()),
+            relates_to,
+            #[cfg(feature = "unstable-msc3952")]
+            mentions: None,
+        }
     }
 }
 
@@ -75,6 +75,11 @@ impl From<NoticeEventContent> for RoomMessageEventContent {
     fn from(content: NoticeEventContent) -> Self {
         let NoticeEventContent { message, relates_to, .. } = content;
 
-        Self { msgtype: MessageType::Notice(message.into()), relates_to }
+        Self {
+            msgtype: MessageType::Notice(message.into()),
+            relates_to,
+            #[cfg(feature = "unstable-msc3952")]
+            mentions: None,
+        }
     }
 }
@@ -63,7 +63,6 @@ pub struct RoomV1Pdu {
 
     /// Event IDs for the most recent events in the room that the homeserver was
     /// aware of when it created this event.
-    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub prev_events: Vec<(OwnedEventId, EventHash)>,
 
     /// The maximum depth of the `prev_events`, plus one.
@@ -71,7 +70,6 @@ pub struct RoomV1Pdu {
 
     /// Event IDs for the authorization events that would allow this event to be
     /// in the room.
-    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub auth_events: Vec<(OwnedEventId, EventHash)>,
 
     /// For redaction events, the ID of the event being redacted.
@@ -4,7 +4,15 @@
 
 use ruma_macros::EventContent;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "unstable-msc3820")]
+use serde_json::value::RawValue as RawJsonValue;
 
+#[cfg(feature = "unstable-msc3820")]
+use crate::events::{
+    EventContent, EventKind, HasDeserializeFields, RedactContent, RedactedEventContent,
+    RedactedStateEventContent, StateEventContent, StateEventType, StateUnsigned,
+    StaticEventContent,
+};
 use crate::{
     events::EmptyStateKey, room::RoomType, OwnedEventId, OwnedRoomId, OwnedUserId, RoomVersionId,
 };
@@ -17,13 +25,24 @@ use crate::{
 #[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
 #[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
 #[ruma_event(type = "m.room.create", kind = State, state_key_type = EmptyStateKey)]
+#[cfg_attr(feature = "unstable-msc3820", ruma_event(custom_redacted))]
 pub struct RoomCreateEventContent {
     /// The `user_id` of the room creator.
     ///
     /// This is set by the homeserver.
+    #[cfg(not(feature = "unstable-msc3820"))]
     #[ruma_event(skip_redaction)]
     pub creator: OwnedUserId,
 
+    /// The `user_id` of the room creator.
+    ///
+    /// This is set by the homeserver. It was removed in room version 11, where the creator is the
+    /// sender of the event.
+    #[cfg(feature = "unstable-msc3820")]
+    #[ruma_event(skip_redaction)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub creator: Option<OwnedUserId>,
+
     /// Whether or not this room's data should be transferred to other homeservers.
     #[serde(
         rename = "m.federate",
@@ -53,13 +72,145 @@ impl RoomCreateEventContent {
     /// Creates a new `RoomCreateEventContent` with the given creator.
     pub fn new(creator: OwnedUserId) -> Self {
         Self {
+            #[cfg(not(feature = "unstable-msc3820"))]
             creator,
+            #[cfg(feature = "unstable-msc3820")]
+            creator: Some(creator),
             federate: true,
             room_version: default_room_version_id(),
             predecessor: None,
             room_type: None,
         }
     }
+
+    /// Creates a new `RoomCreateEventContent` for a room of version 11 or later.
+    ///
+    /// The `creator` field is not set because it was removed in room version 11.
+    #[cfg(feature = "unstable-msc3820")]
+    pub fn new_v11() -> Self {
+        Self {
+            creator: None,
+            federate: true,
+            room_version: RoomVersionId::V11,
+            predecessor: None,
+            room_type: None,
+        }
+    }
+}
+
+#[cfg(feature = "unstable-msc3820")]
+impl RedactContent for RoomCreateEventContent {
+    type Redacted = RedactedRoomCreateEventContent;
+
+    fn redact(self, version: &RoomVersionId) -> RedactedRoomCreateEventContent {
+        match version {
+            // The whole content is kept since room version 11.
+            RoomVersionId::V11 => RedactedRoomCreateEventContent {
+                creator: self.creator,
+                federate: Some(self.federate),
+                room_version: Some(self.room_version),
+                predecessor: self.predecessor,
+                room_type: self.room_type,
+            },
+            _ => RedactedRoomCreateEventContent::new(self.creator),
+        }
+    }
+}
+
+/// A create event that has been redacted.
+#[cfg(feature = "unstable-msc3820")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
+pub struct RedactedRoomCreateEventContent {
+    /// The `user_id` of the room creator.
+    ///
+    /// This is set by the homeserver. It was removed in room version 11, where the creator is the
+    /// sender of the event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub creator: Option<OwnedUserId>,
+
+    /// Whether or not this room's data should be transferred to other homeservers.
+    ///
+    /// This is redacted in room versions 10 and below.
+    #[serde(rename = "m.federate", skip_serializing_if = "Option::is_none")]
+    pub federate: Option<bool>,
+
+    /// The version of the room.
+    ///
+    /// This is redacted in room versions 10 and below.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room_version: Option<RoomVersionId>,
+
+    /// A reference to the room this room replaces, if the previous room was upgraded.
+    ///
+    /// This is redacted in room versions 10 and below.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub predecessor: Option<PreviousRoom>,
+
+    /// The room type.
+    ///
+    /// This is redacted in room versions 10 and below.
+    #[serde(skip_serializing_if = "Option::is_none", rename = "type")]
+    pub room_type: Option<RoomType>,
+}
+
+#[cfg(feature = "unstable-msc3820")]
+impl RedactedRoomCreateEventContent {
+    /// Create a `RedactedRoomCreateEventContent` with the given creator.
+    ///
+    /// This is only valid for room version 10 and below.
+    pub fn new(creator: Option<OwnedUserId>) -> Self {
+        Self { creator, federate: None, room_version: None, predecessor: None, room_type: None }
+    }
+}
+
+#[cfg(feature = "unstable-msc3820")]
+impl EventContent for RedactedRoomCreateEventContent {
+    type EventType = StateEventType;
+
+    fn event_type(&self) -> StateEventType {
+        StateEventType::RoomCreate
+    }
+
+    fn from_parts(event_type: &str, content: &RawJsonValue) -> serde_json::Result<Self> {
+        if event_type != "m.room.create" {
+            return Err(::serde::de::Error::custom(format!(
+                "expected event type `m.room.create`, found `{}`",
+                event_type
+            )));
+        }
+
+        serde_json::from_str(content.get())
+    }
+}
+
+#[cfg(feature = "unstable-msc3820")]
+impl StaticEventContent for RedactedRoomCreateEventContent {
+    const KIND: EventKind = EventKind::State { redacted: true };
+    const TYPE: &'static str = "m.room.create";
+}
+
+#[cfg(feature = "unstable-msc3820")]
+impl StateEventContent for RedactedRoomCreateEventContent {
+    type StateKey = EmptyStateKey;
+    // FIXME: Not actually used
+    type Unsigned = StateUnsigned<Self>;
+}
+
+#[cfg(feature = "unstable-msc3820")]
+impl RedactedStateEventContent for RedactedRoomCreateEventContent {}
+
+// Since this redacted event has fields we leave the default `empty` method
+// that will error if called.
+#[cfg(feature = "unstable-msc3820")]
+impl RedactedEventContent for RedactedRoomCreateEventContent {
+    fn has_serialize_fields(&self) -> bool {
+        true
+    }
+
+    fn has_deserialize_fields() -> HasDeserializeFields {
+        HasDeserializeFields::True
+    }
 }
 
 /// A reference to an old room replaced during a room version upgrade.
@@ -96,11 +247,10 @@ mod tests {
     #[test]
     fn serialization() {
         let content = RoomCreateEventContent {
-            creator: user_id!("@carl:example.com").to_owned(),
             federate: false,
             room_version: RoomVersionId::V4,
-            predecessor: None,
             room_type: None,
+            ..RoomCreateEventContent::new(user_id!("@carl:example.com").to_owned())
         };
 
         let json = json!({
@@ -115,11 +265,10 @@ mod tests {
     #[test]
     fn space_serialization() {
         let content = RoomCreateEventContent {
-            creator: user_id!("@carl:example.com").to_owned(),
             federate: false,
             room_version: RoomVersionId::V4,
-            predecessor: None,
             room_type: Some(RoomType::Space),
+            ..RoomCreateEventContent::new(user_id!("@carl:example.com").to_owned())
         };
 
         let json = json!({
@@ -141,7 +290,10 @@ mod tests {
         });
 
         let content = from_json_value::<RoomCreateEventContent>(json).unwrap();
+        #[cfg(not(feature = "unstable-msc3820"))]
         assert_eq!(content.creator, "@carl:example.com");
+        #[cfg(feature = "unstable-msc3820")]
+        assert_eq!(content.creator.unwrap(), "@carl:example.com");
         assert!(content.federate);
         assert_eq!(content.room_version, RoomVersionId::V4);
         assert_matches!(content.predecessor, None);
@@ -158,10 +310,39 @@ mod tests {
         });
 
         let content = from_json_value::<RoomCreateEventContent>(json).unwrap();
+        #[cfg(not(feature = "unstable-msc3820"))]
         assert_eq!(content.creator, "@carl:example.com");
+        #[cfg(feature = "unstable-msc3820")]
+        assert_eq!(content.creator.unwrap(), "@carl:example.com");
         assert!(content.federate);
         assert_eq!(content.room_version, RoomVersionId::V4);
         assert_matches!(content.predecessor, None);
         assert_eq!(content.room_type, Some(RoomType::Space));
     }
+
+    #[cfg(feature = "unstable-msc3820")]
+    #[test]
+    fn v11_serialization() {
+        let content = RoomCreateEventContent::new_v11();
+
+        let json = json!({
+            "room_version": "11"
+        });
+
+        assert_eq!(to_json_value(&content).unwrap(), json);
+    }
+
+    #[cfg(feature = "unstable-msc3820")]
+    #[test]
+    fn v11_deserialization() {
+        let json = json!({
+            "m.federate": true,
+            "room_version": "11"
+        });
+
+        let content = from_json_value::<RoomCreateEventContent>(json).unwrap();
+        assert_eq!(content.creator, None);
+        assert!(content.federate);
+        assert_eq!(content.room_version, RoomVersionId::V11);
+    }
 }
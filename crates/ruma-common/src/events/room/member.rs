@@ -148,11 +148,20 @@ impl RoomMemberEventContent {
 impl RedactContent for RoomMemberEventContent {
     type Redacted = RedactedRoomMemberEventContent;
 
-    fn redact(self, _version: &RoomVersionId) -> RedactedRoomMemberEventContent {
+    fn redact(self, version: &RoomVersionId) -> RedactedRoomMemberEventContent {
         RedactedRoomMemberEventContent {
             membership: self.membership,
-            join_authorized_via_users_server: match _version {
+            join_authorized_via_users_server: match version {
                 RoomVersionId::V9 | RoomVersionId::V10 => self.join_authorized_via_users_server,
+                #[cfg(feature = "unstable-msc3820")]
+                RoomVersionId::V11 => self.join_authorized_via_users_server,
+                _ => None,
+            },
+            #[cfg(feature = "unstable-msc3820")]
+            third_party_invite: match version {
+                RoomVersionId::V11 => self
+                    .third_party_invite
+                    .map(|invite| RedactedThirdPartyInvite::new(invite.signed)),
                 _ => None,
             },
         }
@@ -172,12 +181,24 @@ pub struct RedactedRoomMemberEventContent {
     /// joins when the join rule is restricted.
     #[serde(rename = "join_authorised_via_users_server")]
     pub join_authorized_via_users_server: Option<OwnedUserId>,
+
+    /// The signed content of the third party invitation, if any.
+    ///
+    /// This is redacted in room versions 10 and below.
+    #[cfg(feature = "unstable-msc3820")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub third_party_invite: Option<RedactedThirdPartyInvite>,
 }
 
 impl RedactedRoomMemberEventContent {
     /// Create a `RedactedRoomMemberEventContent` with the given membership.
     pub fn new(membership: MembershipState) -> Self {
-        Self { membership, join_authorized_via_users_server: None }
+        Self {
+            membership,
+            join_authorized_via_users_server: None,
+            #[cfg(feature = "unstable-msc3820")]
+            third_party_invite: None,
+        }
     }
 
     /// Obtain the details about this event that are required to calculate a membership change.
@@ -294,6 +315,25 @@ impl ThirdPartyInvite {
     }
 }
 
+/// A third party invitation of a redacted member event.
+#[cfg(feature = "unstable-msc3820")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
+pub struct RedactedThirdPartyInvite {
+    /// A block of content which has been signed, which servers can use to verify the event.
+    ///
+    /// Clients should ignore this.
+    pub signed: SignedContent,
+}
+
+#[cfg(feature = "unstable-msc3820")]
+impl RedactedThirdPartyInvite {
+    /// Creates a new `RedactedThirdPartyInvite` with the given signed content.
+    pub fn new(signed: SignedContent) -> Self {
+        Self { signed }
+    }
+}
+
 /// A block of content which has been signed, which servers can use to verify a third party
 /// invitation.
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -8,6 +8,8 @@ use ruma_macros::EventContent;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 
+#[cfg(feature = "unstable-msc3952")]
+use crate::events::Mentions;
 use crate::{
     serde::{JsonObject, StringEnum},
     OwnedEventId, PrivOwnedStr,
@@ -63,12 +65,27 @@ pub struct RoomMessageEventContent {
     /// [rich replies]: https://spec.matrix.org/v1.2/client-server-api/#rich-replies
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
     pub relates_to: Option<Relation>,
+
+    /// The [mentions] of this event.
+    ///
+    /// This is [MSC3952].
+    ///
+    /// [mentions]: https://github.com/matrix-org/matrix-spec-proposals/pull/3952
+    /// [MSC3952]: https://github.com/matrix-org/matrix-spec-proposals/pull/3952
+    #[cfg(feature = "unstable-msc3952")]
+    #[serde(rename = "m.mentions", skip_serializing_if = "Option::is_none")]
+    pub mentions: Option<Mentions>,
 }
 
 impl RoomMessageEventContent {
     /// Create a `RoomMessageEventContent` with the given `MessageType`.
     pub fn new(msgtype: MessageType) -> Self {
-        Self { msgtype, relates_to: None }
+        Self {
+            msgtype,
+            relates_to: None,
+            #[cfg(feature = "unstable-msc3952")]
+            mentions: None,
+        }
     }
 
     /// A constructor to create a plain text message.
@@ -259,7 +276,12 @@ impl RoomMessageEventContent {
             }
         };
 
-        Self { msgtype, relates_to: Some(relates_to) }
+        Self {
+            msgtype,
+            relates_to: Some(relates_to),
+            #[cfg(feature = "unstable-msc3952")]
+            mentions: None,
+        }
     }
 
     /// Create a new message for a thread that is optionally a reply.
@@ -317,9 +339,20 @@ impl RoomMessageEventContent {
                 in_reply_to: InReplyTo { event_id: previous_message.event_id.clone() },
                 is_falling_back: is_reply == ReplyInThread::No,
             })),
+            #[cfg(feature = "unstable-msc3952")]
+            mentions: None,
         }
     }
 
+    /// Set the [mentions] of this message.
+    ///
+    /// [mentions]: https://github.com/matrix-org/matrix-spec-proposals/pull/3952
+    #[cfg(feature = "unstable-msc3952")]
+    pub fn set_mentions(mut self, mentions: Mentions) -> Self {
+        self.mentions = Some(mentions);
+        self
+    }
+
     /// Returns a reference to the `msgtype` string.
     ///
     /// If you want to access the message type-specific data rather than the message type itself,
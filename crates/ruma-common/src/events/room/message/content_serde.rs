@@ -36,6 +36,8 @@ use crate::events::room::MediaSource;
 use crate::events::video::VideoContent;
 #[cfg(feature = "unstable-msc3245")]
 use crate::events::voice::VoiceContent;
+#[cfg(feature = "unstable-msc3952")]
+use crate::events::Mentions;
 use crate::serde::from_raw_json_value;
 #[cfg(feature = "unstable-msc3488")]
 use crate::MilliSecondsSinceUnixEpoch;
@@ -50,10 +52,26 @@ impl<'de> Deserialize<'de> for RoomMessageEventContent {
         let relates_to =
             Option::<Relation>::deserialize(&mut deserializer).map_err(de::Error::custom)?;
 
-        Ok(Self { msgtype: from_raw_json_value(&json)?, relates_to })
+        #[cfg(feature = "unstable-msc3952")]
+        let MentionsDeHelper { mentions } = from_raw_json_value(&json)?;
+
+        Ok(Self {
+            msgtype: from_raw_json_value(&json)?,
+            relates_to,
+            #[cfg(feature = "unstable-msc3952")]
+            mentions,
+        })
     }
 }
 
+/// Helper struct to deserialize the mentions of a `RoomMessageEventContent`.
+#[cfg(feature = "unstable-msc3952")]
+#[derive(Deserialize)]
+struct MentionsDeHelper {
+    #[serde(rename = "m.mentions")]
+    mentions: Option<Mentions>,
+}
+
 /// Helper struct to determine the msgtype from a `serde_json::value::RawValue`
 #[derive(Debug, Deserialize)]
 struct MessageTypeDeHelper {
@@ -7,12 +7,22 @@ use std::{cmp::max, collections::BTreeMap};
 use js_int::{int, Int};
 use ruma_macros::EventContent;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "unstable-msc3820")]
+use serde_json::value::RawValue as RawJsonValue;
 
 use crate::{
     events::{EmptyStateKey, MessageLikeEventType, RoomEventType, StateEventType},
     power_levels::{default_power_level, NotificationPowerLevels},
     OwnedUserId, UserId,
 };
+#[cfg(feature = "unstable-msc3820")]
+use crate::{
+    events::{
+        EventContent, EventKind, HasDeserializeFields, RedactContent, RedactedEventContent,
+        RedactedStateEventContent, StateEventContent, StateUnsigned, StaticEventContent,
+    },
+    RoomVersionId,
+};
 
 /// The content of an `m.room.power_levels` event.
 ///
@@ -20,6 +30,7 @@ use crate::{
 #[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
 #[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
 #[ruma_event(type = "m.room.power_levels", kind = State, state_key_type = EmptyStateKey)]
+#[cfg_attr(feature = "unstable-msc3820", ruma_event(custom_redacted))]
 pub struct RoomPowerLevelsEventContent {
     /// The level required to ban a user.
     #[serde(
@@ -138,6 +149,161 @@ impl Default for RoomPowerLevelsEventContent {
     }
 }
 
+#[cfg(feature = "unstable-msc3820")]
+impl RedactContent for RoomPowerLevelsEventContent {
+    type Redacted = RedactedRoomPowerLevelsEventContent;
+
+    fn redact(self, version: &RoomVersionId) -> RedactedRoomPowerLevelsEventContent {
+        RedactedRoomPowerLevelsEventContent {
+            ban: self.ban,
+            events: self.events,
+            events_default: self.events_default,
+            invite: match version {
+                RoomVersionId::V11 => self.invite,
+                _ => int!(0),
+            },
+            kick: self.kick,
+            redact: self.redact,
+            state_default: self.state_default,
+            users: self.users,
+            users_default: self.users_default,
+        }
+    }
+}
+
+/// A power levels event that has been redacted.
+#[cfg(feature = "unstable-msc3820")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
+pub struct RedactedRoomPowerLevelsEventContent {
+    /// The level required to ban a user.
+    #[serde(
+        default = "default_power_level",
+        skip_serializing_if = "is_default_power_level",
+        deserialize_with = "crate::serde::deserialize_v1_powerlevel"
+    )]
+    pub ban: Int,
+
+    /// The level required to send specific event types.
+    ///
+    /// This is a mapping from event type to power level required.
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "crate::serde::btreemap_deserialize_v1_powerlevel_values"
+    )]
+    pub events: BTreeMap<RoomEventType, Int>,
+
+    /// The default level required to send message events.
+    #[serde(
+        default,
+        skip_serializing_if = "crate::serde::is_default",
+        deserialize_with = "crate::serde::deserialize_v1_powerlevel"
+    )]
+    pub events_default: Int,
+
+    /// The level required to invite a user.
+    ///
+    /// This is redacted in room versions 10 and below.
+    #[serde(
+        default,
+        skip_serializing_if = "crate::serde::is_default",
+        deserialize_with = "crate::serde::deserialize_v1_powerlevel"
+    )]
+    pub invite: Int,
+
+    /// The level required to kick a user.
+    #[serde(
+        default = "default_power_level",
+        skip_serializing_if = "is_default_power_level",
+        deserialize_with = "crate::serde::deserialize_v1_powerlevel"
+    )]
+    pub kick: Int,
+
+    /// The level required to redact an event.
+    #[serde(
+        default = "default_power_level",
+        skip_serializing_if = "is_default_power_level",
+        deserialize_with = "crate::serde::deserialize_v1_powerlevel"
+    )]
+    pub redact: Int,
+
+    /// The default level required to send state events.
+    #[serde(
+        default = "default_power_level",
+        skip_serializing_if = "is_default_power_level",
+        deserialize_with = "crate::serde::deserialize_v1_powerlevel"
+    )]
+    pub state_default: Int,
+
+    /// The power levels for specific users.
+    ///
+    /// This is a mapping from `user_id` to power level for that user.
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "crate::serde::btreemap_deserialize_v1_powerlevel_values"
+    )]
+    pub users: BTreeMap<OwnedUserId, Int>,
+
+    /// The default power level for every user in the room.
+    #[serde(
+        default,
+        skip_serializing_if = "crate::serde::is_default",
+        deserialize_with = "crate::serde::deserialize_v1_powerlevel"
+    )]
+    pub users_default: Int,
+}
+
+#[cfg(feature = "unstable-msc3820")]
+impl EventContent for RedactedRoomPowerLevelsEventContent {
+    type EventType = StateEventType;
+
+    fn event_type(&self) -> StateEventType {
+        StateEventType::RoomPowerLevels
+    }
+
+    fn from_parts(event_type: &str, content: &RawJsonValue) -> serde_json::Result<Self> {
+        if event_type != "m.room.power_levels" {
+            return Err(::serde::de::Error::custom(format!(
+                "expected event type `m.room.power_levels`, found `{}`",
+                event_type
+            )));
+        }
+
+        serde_json::from_str(content.get())
+    }
+}
+
+#[cfg(feature = "unstable-msc3820")]
+impl StaticEventContent for RedactedRoomPowerLevelsEventContent {
+    const KIND: EventKind = EventKind::State { redacted: true };
+    const TYPE: &'static str = "m.room.power_levels";
+}
+
+#[cfg(feature = "unstable-msc3820")]
+impl StateEventContent for RedactedRoomPowerLevelsEventContent {
+    type StateKey = EmptyStateKey;
+    // FIXME: Not actually used
+    type Unsigned = StateUnsigned<Self>;
+}
+
+#[cfg(feature = "unstable-msc3820")]
+impl RedactedStateEventContent for RedactedRoomPowerLevelsEventContent {}
+
+// Since this redacted event has fields we leave the default `empty` method
+// that will error if called.
+#[cfg(feature = "unstable-msc3820")]
+impl RedactedEventContent for RedactedRoomPowerLevelsEventContent {
+    fn has_serialize_fields(&self) -> bool {
+        true
+    }
+
+    fn has_deserialize_fields() -> HasDeserializeFields {
+        HasDeserializeFields::True
+    }
+}
+
 /// Used with `#[serde(skip_serializing_if)]` to omit default power levels.
 #[allow(clippy::trivially_copy_pass_by_ref)]
 fn is_default_power_level(l: &Int) -> bool {
@@ -284,7 +450,10 @@ impl From<RedactedRoomPowerLevelsEventContent> for RoomPowerLevels {
             ban: c.ban,
             events: c.events,
             events_default: c.events_default,
+            #[cfg(not(feature = "unstable-msc3820"))]
             invite: int!(0),
+            #[cfg(feature = "unstable-msc3820")]
+            invite: c.invite,
             kick: c.kick,
             redact: c.redact,
             state_default: c.state_default,
@@ -12,7 +12,8 @@ use crate::{
         RedactedUnsigned, RedactionDeHelper,
     },
     serde::from_raw_json_value,
-    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId,
+    RoomVersionId, UserId,
 };
 
 /// A possibly-redacted redaction event.
@@ -47,8 +48,17 @@ pub struct OriginalRoomRedactionEvent {
     pub content: RoomRedactionEventContent,
 
     /// The ID of the event that was redacted.
+    #[cfg(not(feature = "unstable-msc3820"))]
     pub redacts: OwnedEventId,
 
+    /// The ID of the event that was redacted.
+    ///
+    /// This field is required in room versions prior to 11. Starting from room version 11, it is
+    /// in the `content` of the event. Use [`OriginalRoomRedactionEvent::redacts()`] to get the ID
+    /// for any room version.
+    #[cfg(feature = "unstable-msc3820")]
+    pub redacts: Option<OwnedEventId>,
+
     /// The globally unique event identifier for the user who sent the event.
     pub event_id: OwnedEventId,
 
@@ -65,14 +75,21 @@ pub struct OriginalRoomRedactionEvent {
     pub unsigned: MessageLikeUnsigned,
 }
 
+impl OriginalRoomRedactionEvent {
+    /// Returns the ID of the event that this event redacts, according to the given room version.
+    ///
+    /// In room versions prior to 11, this is the `redacts` field at the top level of the event.
+    /// Starting from room version 11, this is the `redacts` field of the content.
+    #[cfg(feature = "unstable-msc3820")]
+    pub fn redacts(&self, room_version: &RoomVersionId) -> Option<&EventId> {
+        redacts(room_version, self.redacts.as_deref(), &self.content)
+    }
+}
+
 impl Redact for OriginalRoomRedactionEvent {
     type Redacted = RedactedRoomRedactionEvent;
 
-    fn redact(
-        self,
-        redaction: SyncRoomRedactionEvent,
-        version: &crate::RoomVersionId,
-    ) -> Self::Redacted {
+    fn redact(self, redaction: SyncRoomRedactionEvent, version: &RoomVersionId) -> Self::Redacted {
         RedactedRoomRedactionEvent {
             content: self.content.redact(version),
             event_id: self.event_id,
@@ -115,8 +132,17 @@ pub struct OriginalSyncRoomRedactionEvent {
     pub content: RoomRedactionEventContent,
 
     /// The ID of the event that was redacted.
+    #[cfg(not(feature = "unstable-msc3820"))]
     pub redacts: OwnedEventId,
 
+    /// The ID of the event that was redacted.
+    ///
+    /// This field is required in room versions prior to 11. Starting from room version 11, it is
+    /// in the `content` of the event. Use [`OriginalSyncRoomRedactionEvent::redacts()`] to get the
+    /// ID for any room version.
+    #[cfg(feature = "unstable-msc3820")]
+    pub redacts: Option<OwnedEventId>,
+
     /// The globally unique event identifier for the user who sent the event.
     pub event_id: OwnedEventId,
 
@@ -130,14 +156,21 @@ pub struct OriginalSyncRoomRedactionEvent {
     pub unsigned: MessageLikeUnsigned,
 }
 
+impl OriginalSyncRoomRedactionEvent {
+    /// Returns the ID of the event that this event redacts, according to the given room version.
+    ///
+    /// In room versions prior to 11, this is the `redacts` field at the top level of the event.
+    /// Starting from room version 11, this is the `redacts` field of the content.
+    #[cfg(feature = "unstable-msc3820")]
+    pub fn redacts(&self, room_version: &RoomVersionId) -> Option<&EventId> {
+        redacts(room_version, self.redacts.as_deref(), &self.content)
+    }
+}
+
 impl Redact for OriginalSyncRoomRedactionEvent {
     type Redacted = RedactedSyncRoomRedactionEvent;
 
-    fn redact(
-        self,
-        redaction: SyncRoomRedactionEvent,
-        version: &crate::RoomVersionId,
-    ) -> Self::Redacted {
+    fn redact(self, redaction: SyncRoomRedactionEvent, version: &RoomVersionId) -> Self::Redacted {
         RedactedSyncRoomRedactionEvent {
             content: self.content.redact(version),
             event_id: self.event_id,
@@ -173,6 +206,15 @@ pub struct RedactedSyncRoomRedactionEvent {
 #[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
 #[ruma_event(type = "m.room.redaction", kind = MessageLike)]
 pub struct RoomRedactionEventContent {
+    /// The ID of the event that was redacted.
+    ///
+    /// This field is required starting from room version 11, where it replaces the `redacts`
+    /// field at the top level of the event.
+    #[cfg(feature = "unstable-msc3820")]
+    #[ruma_event(skip_redaction)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redacts: Option<OwnedEventId>,
+
     /// The reason for the redaction, if any.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reason: Option<String>,
@@ -184,9 +226,40 @@ impl RoomRedactionEventContent {
         Self::default()
     }
 
+    /// Creates a new `RoomRedactionEventContent` for a room of version 11 or later, with the ID of
+    /// the event to redact.
+    #[cfg(feature = "unstable-msc3820")]
+    pub fn new_v11(redacts: OwnedEventId) -> Self {
+        Self { redacts: Some(redacts), ..Default::default() }
+    }
+
     /// Creates a new `RoomRedactionEventContent` with the given reason.
     pub fn with_reason(reason: String) -> Self {
-        Self { reason: Some(reason) }
+        Self { reason: Some(reason), ..Default::default() }
+    }
+}
+
+/// The ID of the event redacted by an event, according to the given room version.
+#[cfg(feature = "unstable-msc3820")]
+fn redacts<'a>(
+    room_version: &RoomVersionId,
+    redacts: Option<&'a EventId>,
+    content: &'a RoomRedactionEventContent,
+) -> Option<&'a EventId> {
+    match room_version {
+        RoomVersionId::V1
+        | RoomVersionId::V2
+        | RoomVersionId::V3
+        | RoomVersionId::V4
+        | RoomVersionId::V5
+        | RoomVersionId::V6
+        | RoomVersionId::V7
+        | RoomVersionId::V8
+        | RoomVersionId::V9
+        | RoomVersionId::V10 => redacts,
+        RoomVersionId::V11 => content.redacts.as_deref(),
+        // Unknown room versions could use either of the fields.
+        _ => redacts.or(content.redacts.as_deref()),
     }
 }
 
@@ -243,7 +316,7 @@ impl RoomRedactionEvent {
 impl Redact for RoomRedactionEvent {
     type Redacted = Self;
 
-    fn redact(self, redaction: SyncRoomRedactionEvent, version: &crate::RoomVersionId) -> Self {
+    fn redact(self, redaction: SyncRoomRedactionEvent, version: &RoomVersionId) -> Self {
         match self {
             Self::Original(ev) => Self::Redacted(ev.redact(redaction, version)),
             Self::Redacted(ev) => Self::Redacted(ev),
@@ -329,7 +402,7 @@ impl From<RoomRedactionEvent> for SyncRoomRedactionEvent {
 impl Redact for SyncRoomRedactionEvent {
     type Redacted = Self;
 
-    fn redact(self, redaction: SyncRoomRedactionEvent, version: &crate::RoomVersionId) -> Self {
+    fn redact(self, redaction: SyncRoomRedactionEvent, version: &RoomVersionId) -> Self {
         match self {
             Self::Original(ev) => Self::Redacted(ev.redact(redaction, version)),
             Self::Redacted(ev) => Self::Redacted(ev),
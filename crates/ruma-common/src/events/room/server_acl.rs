@@ -2,6 +2,8 @@
 //!
 //! [`m.room.server_acl`]: https://spec.matrix.org/v1.2/client-server-api/#mroomserver_acl
 
+use std::collections::BTreeSet;
+
 use ruma_macros::EventContent;
 use serde::{Deserialize, Serialize};
 use wildmatch::WildMatch;
@@ -60,6 +62,70 @@ impl RoomServerAclEventContent {
         self.deny.iter().all(|d| !WildMatch::new(d).matches(host))
             && self.allow.iter().any(|a| WildMatch::new(a).matches(host))
     }
+
+    /// Compile the ACL rules, to check many server names efficiently.
+    pub fn compile(&self) -> CompiledRoomServerAcl {
+        CompiledRoomServerAcl {
+            allow_ip_literals: self.allow_ip_literals,
+            allow: ServerNamePatterns::new(&self.allow),
+            deny: ServerNamePatterns::new(&self.deny),
+        }
+    }
+}
+
+/// The rules of a [`RoomServerAclEventContent`], compiled to check many server names efficiently.
+///
+/// Patterns without wildcards are looked up directly, so checking a server name doesn't get slower
+/// with the number of such patterns.
+#[derive(Clone, Debug)]
+pub struct CompiledRoomServerAcl {
+    allow_ip_literals: bool,
+    allow: ServerNamePatterns,
+    deny: ServerNamePatterns,
+}
+
+impl CompiledRoomServerAcl {
+    /// Returns true if and only if the server is allowed by the ACL rules.
+    ///
+    /// This gives the same result as [`RoomServerAclEventContent::is_allowed()`].
+    pub fn is_allowed(&self, server_name: &ServerName) -> bool {
+        if !self.allow_ip_literals && server_name.is_ip_literal() {
+            return false;
+        }
+
+        let host = server_name.host();
+
+        !self.deny.matches(host) && self.allow.matches(host)
+    }
+}
+
+impl From<&RoomServerAclEventContent> for CompiledRoomServerAcl {
+    fn from(content: &RoomServerAclEventContent) -> Self {
+        content.compile()
+    }
+}
+
+/// A list of server name patterns, split by whether they contain wildcards.
+#[derive(Clone, Debug)]
+struct ServerNamePatterns {
+    literals: BTreeSet<String>,
+    wildcards: Vec<WildMatch>,
+}
+
+impl ServerNamePatterns {
+    fn new(patterns: &[String]) -> Self {
+        let (wildcards, literals): (Vec<_>, _) =
+            patterns.iter().partition(|pattern| pattern.contains(['*', '?']));
+
+        Self {
+            literals: literals.into_iter().cloned().collect(),
+            wildcards: wildcards.into_iter().map(|pattern| WildMatch::new(pattern)).collect(),
+        }
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        self.literals.contains(host) || self.wildcards.iter().any(|pattern| pattern.matches(host))
+    }
 }
 
 #[cfg(test)]
@@ -173,4 +239,35 @@ mod tests {
         assert!(!acl_event.is_allowed(server_name!("[2001:db8:1234::2]")));
         assert!(acl_event.is_allowed(server_name!("[2001:db8:1234::1]")));
     }
+
+    #[test]
+    fn compiled_acl() {
+        let acl_event = RoomServerAclEventContent {
+            allow_ip_literals: false,
+            allow: vec!["*.example.org".to_owned(), "example.com".to_owned()],
+            deny: (0..1000)
+                .map(|i| format!("evil{i}.example.org"))
+                .chain(["?.example.org".to_owned()])
+                .collect(),
+        };
+        let compiled = acl_event.compile();
+
+        for server_name in [
+            server_name!("matrix.example.org"),
+            server_name!("example.com"),
+            server_name!("example.com:8448"),
+            server_name!("evil12.example.org"),
+            server_name!("evil1000.example.org"),
+            server_name!("a.example.org"),
+            server_name!("matrix.example.com"),
+            server_name!("1.1.1.1"),
+        ] {
+            assert_eq!(compiled.is_allowed(server_name), acl_event.is_allowed(server_name));
+        }
+
+        assert!(compiled.is_allowed(server_name!("matrix.example.org")));
+        assert!(compiled.is_allowed(server_name!("evil1000.example.org")));
+        assert!(!compiled.is_allowed(server_name!("evil12.example.org")));
+        assert!(!compiled.is_allowed(server_name!("a.example.org")));
+    }
 }
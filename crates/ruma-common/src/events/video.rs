@@ -142,6 +142,8 @@ impl From<VideoEventContent> for RoomMessageEventContent {
                 message, file, video, thumbnail, caption,
             )),
             relates_to,
+            #[cfg(feature = "unstable-msc3952")]
+            mentions: None,
         }
     }
 }
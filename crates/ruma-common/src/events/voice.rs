@@ -112,6 +112,8 @@ impl From<VoiceEventContent> for RoomMessageEventContent {
                 message, file, audio, voice,
             )),
             relates_to,
+            #[cfg(feature = "unstable-msc3952")]
+            mentions: None,
         }
     }
 }
@@ -56,6 +56,10 @@ pub enum RoomVersionId {
     /// A version 10 room.
     V10,
 
+    /// A version 11 room.
+    #[cfg(feature = "unstable-msc3820")]
+    V11,
+
     #[doc(hidden)]
     _Custom(CustomRoomVersion),
 }
@@ -76,6 +80,8 @@ impl RoomVersionId {
             Self::V8 => "8",
             Self::V9 => "9",
             Self::V10 => "10",
+            #[cfg(feature = "unstable-msc3820")]
+            Self::V11 => "11",
             Self::_Custom(version) => version.as_str(),
         }
     }
@@ -99,6 +105,8 @@ impl From<RoomVersionId> for String {
             RoomVersionId::V8 => "8".to_owned(),
             RoomVersionId::V9 => "9".to_owned(),
             RoomVersionId::V10 => "10".to_owned(),
+            #[cfg(feature = "unstable-msc3820")]
+            RoomVersionId::V11 => "11".to_owned(),
             RoomVersionId::_Custom(version) => version.into(),
         }
     }
@@ -166,6 +174,8 @@ where
         "8" => RoomVersionId::V8,
         "9" => RoomVersionId::V9,
         "10" => RoomVersionId::V10,
+        #[cfg(feature = "unstable-msc3820")]
+        "11" => RoomVersionId::V11,
         custom => {
             ruma_identifiers_validation::room_version_id::validate(custom)?;
             RoomVersionId::_Custom(CustomRoomVersion(room_version_id.into()))
@@ -302,6 +312,15 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "unstable-msc3820")]
+    #[test]
+    fn valid_version_11_room_version_id() {
+        assert_eq!(
+            RoomVersionId::try_from("11").expect("Failed to create RoomVersionId.").as_ref(),
+            "11"
+        );
+    }
+
     #[test]
     fn valid_custom_room_version_id() {
         assert_eq!(
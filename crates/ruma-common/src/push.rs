@@ -14,7 +14,12 @@
 //! - sender rules
 //! - underride rules
 
-use std::hash::{Hash, Hasher};
+use std::{
+    error::Error as StdError,
+    fmt,
+    hash::{Hash, Hasher},
+    mem,
+};
 
 use indexmap::{Equivalent, IndexSet};
 use serde::{Deserialize, Serialize};
@@ -28,14 +33,17 @@ use crate::{
 };
 
 mod action;
+mod compiled;
 mod condition;
 mod iter;
 mod predefined;
 
 pub use self::{
     action::{Action, Tweak},
+    compiled::{CompiledRuleset, PreparedPushEvent, RulesetCompiler},
     condition::{
-        ComparisonOperator, FlattenedJson, PushCondition, PushConditionRoomCtx, RoomMemberCountIs,
+        ComparisonOperator, FlattenedJson, FlattenedJsonValue, PushCondition, PushConditionRoomCtx,
+        RoomMemberCountIs, ScalarJsonValue,
     },
     iter::{AnyPushRule, AnyPushRuleRef, RulesetIntoIter, RulesetIter},
 };
@@ -130,6 +138,352 @@ impl Ruleset {
     pub fn get_actions<T>(&self, event: &Raw<T>, context: &PushConditionRoomCtx) -> &[Action] {
         self.get_match(event, context).map(|rule| rule.actions()).unwrap_or(&[])
     }
+
+    /// Compile this ruleset, to evaluate many events efficiently.
+    ///
+    /// To compile the rulesets of many users, use a [`RulesetCompiler`] to share the compiled
+    /// patterns between them.
+    pub fn compile(&self) -> CompiledRuleset {
+        RulesetCompiler::new().compile(self)
+    }
+
+    /// Get the rule of the given kind with the given ID, if any.
+    pub fn get(&self, kind: RuleKind, rule_id: impl AsRef<str>) -> Option<AnyPushRuleRef<'_>> {
+        let rule_id = rule_id.as_ref();
+
+        match kind {
+            RuleKind::Override => self.override_.get(rule_id).map(AnyPushRuleRef::Override),
+            RuleKind::Underride => self.underride.get(rule_id).map(AnyPushRuleRef::Underride),
+            RuleKind::Sender => self.sender.get(rule_id).map(AnyPushRuleRef::Sender),
+            RuleKind::Room => self.room.get(rule_id).map(AnyPushRuleRef::Room),
+            RuleKind::Content => self.content.get(rule_id).map(AnyPushRuleRef::Content),
+            RuleKind::_Custom(_) => None,
+        }
+    }
+
+    /// Inserts a user-defined rule in the rule set, like the homeserver does when a client sets a
+    /// push rule.
+    ///
+    /// If a rule of the same kind with the same ID exists, it is replaced, and keeps its `enabled`
+    /// flag. Otherwise the new rule is enabled. In both cases, the `default` flag of the rule is
+    /// set to `false`.
+    ///
+    /// If `after` is set, the rule becomes the next-less important rule relative to the rule with
+    /// this ID. If `before` is set, the rule becomes the next-most important rule relative to the
+    /// rule with this ID. If neither is set, a new rule becomes the most important user-defined
+    /// rule of its kind, and an existing rule keeps its position.
+    ///
+    /// Returns an error if the rule ID is reserved or invalid, or if the rule can't be placed
+    /// according to `after` and `before`. The rule set is not modified in this case.
+    pub fn insert(
+        &mut self,
+        mut rule: AnyPushRule,
+        after: Option<&str>,
+        before: Option<&str>,
+    ) -> Result<(), InsertPushRuleError> {
+        let rule_id = rule.rule_id();
+        if rule_id.starts_with('.') {
+            return Err(InsertPushRuleError::ServerDefaultRuleId);
+        }
+        if rule_id.is_empty() || rule_id.contains(['/', '\\']) {
+            return Err(InsertPushRuleError::InvalidRuleId);
+        }
+        if after.into_iter().chain(before).any(|rule_id| rule_id.starts_with('.')) {
+            return Err(InsertPushRuleError::RelativeToServerDefaultRule);
+        }
+
+        let enabled = self.get(rule.kind(), rule_id).map_or(true, |rule| rule.enabled());
+        match &mut rule {
+            AnyPushRule::Override(rule) | AnyPushRule::Underride(rule) => {
+                rule.default = false;
+                rule.enabled = enabled;
+            }
+            AnyPushRule::Content(rule) => {
+                rule.default = false;
+                rule.enabled = enabled;
+            }
+            AnyPushRule::Room(rule) | AnyPushRule::Sender(rule) => {
+                rule.default = false;
+                rule.enabled = enabled;
+            }
+        }
+
+        match rule {
+            AnyPushRule::Override(rule) => insert_rule(&mut self.override_, rule, after, before),
+            AnyPushRule::Underride(rule) => insert_rule(&mut self.underride, rule, after, before),
+            AnyPushRule::Content(rule) => insert_rule(&mut self.content, rule, after, before),
+            AnyPushRule::Room(rule) => insert_rule(&mut self.room, rule, after, before),
+            AnyPushRule::Sender(rule) => insert_rule(&mut self.sender, rule, after, before),
+        }
+    }
+
+    /// Set whether the rule of the given kind with the given ID is enabled.
+    ///
+    /// Returns an error if the rule can't be found.
+    pub fn set_enabled(
+        &mut self,
+        kind: RuleKind,
+        rule_id: impl AsRef<str>,
+        enabled: bool,
+    ) -> Result<(), RuleNotFoundError> {
+        let rule_id = rule_id.as_ref();
+
+        match kind {
+            RuleKind::Override => {
+                update_rule(&mut self.override_, rule_id, |r| r.enabled = enabled)
+            }
+            RuleKind::Underride => {
+                update_rule(&mut self.underride, rule_id, |r| r.enabled = enabled)
+            }
+            RuleKind::Sender => update_rule(&mut self.sender, rule_id, |r| r.enabled = enabled),
+            RuleKind::Room => update_rule(&mut self.room, rule_id, |r| r.enabled = enabled),
+            RuleKind::Content => update_rule(&mut self.content, rule_id, |r| r.enabled = enabled),
+            RuleKind::_Custom(_) => Err(RuleNotFoundError),
+        }
+    }
+
+    /// Set the actions of the rule of the given kind with the given ID.
+    ///
+    /// Returns an error if the rule can't be found.
+    pub fn set_actions(
+        &mut self,
+        kind: RuleKind,
+        rule_id: impl AsRef<str>,
+        actions: Vec<Action>,
+    ) -> Result<(), RuleNotFoundError> {
+        let rule_id = rule_id.as_ref();
+
+        match kind {
+            RuleKind::Override => {
+                update_rule(&mut self.override_, rule_id, |r| r.actions = actions)
+            }
+            RuleKind::Underride => {
+                update_rule(&mut self.underride, rule_id, |r| r.actions = actions)
+            }
+            RuleKind::Sender => update_rule(&mut self.sender, rule_id, |r| r.actions = actions),
+            RuleKind::Room => update_rule(&mut self.room, rule_id, |r| r.actions = actions),
+            RuleKind::Content => update_rule(&mut self.content, rule_id, |r| r.actions = actions),
+            RuleKind::_Custom(_) => Err(RuleNotFoundError),
+        }
+    }
+
+    /// Update this ruleset with the given new server-default rules.
+    ///
+    /// This is meant to be used by homeservers to update the rules stored for a user when the
+    /// server-default rules change, for example when new predefined rules are added to the
+    /// specification. `new_server_default` is usually built with [`Ruleset::server_default()`].
+    ///
+    /// For each kind of rules:
+    ///
+    /// * The server-default rules are replaced by the ones of `new_server_default`, in the same
+    ///   order, so new rules are added and obsolete rules are removed.
+    /// * The `enabled` and `actions` fields of the server-default rules that were already present
+    ///   are preserved, to keep the customizations of the user.
+    /// * The user-defined rules are preserved, in the same order, with a higher priority than the
+    ///   server-default rules except `.m.rule.master`.
+    pub fn update_with_server_default(&mut self, new_server_default: Ruleset) {
+        let Ruleset { content, override_, room, sender, underride } = new_server_default;
+
+        update_rules_with_server_default(&mut self.override_, override_);
+        update_rules_with_server_default(&mut self.content, content);
+        update_rules_with_server_default(&mut self.room, room);
+        update_rules_with_server_default(&mut self.sender, sender);
+        update_rules_with_server_default(&mut self.underride, underride);
+    }
+
+    /// Removes the user-defined rule of the given kind with the given ID.
+    ///
+    /// The priority of the other rules is preserved.
+    ///
+    /// Returns an error if the rule can't be found, or if it is a server-default rule.
+    pub fn remove(
+        &mut self,
+        kind: RuleKind,
+        rule_id: impl AsRef<str>,
+    ) -> Result<(), RemovePushRuleError> {
+        let rule_id = rule_id.as_ref();
+
+        if self.get(kind.clone(), rule_id).is_none() {
+            return Err(RemovePushRuleError::NotFound);
+        }
+        if rule_id.starts_with('.') {
+            return Err(RemovePushRuleError::ServerDefault);
+        }
+
+        match kind {
+            RuleKind::Override => self.override_.shift_remove(rule_id),
+            RuleKind::Underride => self.underride.shift_remove(rule_id),
+            RuleKind::Sender => self.sender.shift_remove(rule_id),
+            RuleKind::Room => self.room.shift_remove(rule_id),
+            RuleKind::Content => self.content.shift_remove(rule_id),
+            RuleKind::_Custom(_) => unreachable!("custom rule kinds can't be found"),
+        };
+
+        Ok(())
+    }
+}
+
+/// Insert the given rule in the given set, or replace the rule with the same ID.
+///
+/// The rule is placed right after `after`, or right before `before`. If neither is set, a new rule
+/// becomes the most important user-defined rule and an existing rule keeps its position.
+fn insert_rule<T>(
+    set: &mut IndexSet<T>,
+    rule: T,
+    after: Option<&str>,
+    before: Option<&str>,
+) -> Result<(), InsertPushRuleError>
+where
+    T: PushRuleFields,
+    str: Equivalent<T>,
+{
+    let current = set.get_index_of(&rule);
+
+    // The position of the rule with the given ID, once the rule to insert is removed.
+    let position_of = |rule_id: &str| {
+        let index = set.get_index_of(rule_id).ok_or(InsertPushRuleError::UnknownRuleId)?;
+
+        match current {
+            // A rule can't be placed relative to itself.
+            Some(current) if current == index => Err(InsertPushRuleError::UnknownRuleId),
+            Some(current) if current < index => Ok(index - 1),
+            _ => Ok(index),
+        }
+    };
+
+    let position = match (after.map(position_of).transpose()?, before.map(position_of).transpose()?)
+    {
+        (Some(after), Some(before)) if before <= after => {
+            return Err(InsertPushRuleError::BeforeHigherThanAfter);
+        }
+        (_, Some(before)) => before,
+        (Some(after), None) => after + 1,
+        (None, None) if current.is_some() => {
+            set.replace(rule);
+            return Ok(());
+        }
+        (None, None) => first_user_rule_position(set),
+    };
+
+    if current.is_some() {
+        set.shift_remove(&rule);
+    }
+    let (index, _) = set.insert_full(rule);
+    set.move_index(index, position);
+
+    Ok(())
+}
+
+/// Update the rule with the given ID in the given set.
+fn update_rule<T>(
+    set: &mut IndexSet<T>,
+    rule_id: &str,
+    update: impl FnOnce(&mut T),
+) -> Result<(), RuleNotFoundError>
+where
+    T: Clone + Hash + Eq,
+    str: Equivalent<T>,
+{
+    let mut rule = set.get(rule_id).ok_or(RuleNotFoundError)?.clone();
+    update(&mut rule);
+    set.replace(rule);
+
+    Ok(())
+}
+
+/// Update the given set of rules with the given new server-default rules.
+fn update_rules_with_server_default<T>(rules: &mut IndexSet<T>, mut new_server_default: IndexSet<T>)
+where
+    T: PushRuleFields,
+    str: Equivalent<T>,
+{
+    let mut user_rules = Vec::new();
+
+    for rule in mem::take(rules) {
+        if !rule.is_server_default() {
+            user_rules.push(rule);
+        } else if let Some(new_rule) = new_server_default.get(rule.rule_id()) {
+            let mut new_rule = new_rule.clone();
+            new_rule.copy_customizations(&rule);
+            new_server_default.replace(new_rule);
+        }
+    }
+
+    *rules = new_server_default;
+
+    let mut position = first_user_rule_position(rules);
+    for rule in user_rules {
+        let (index, inserted) = rules.insert_full(rule);
+
+        // Server-default rules take precedence over user-defined rules with the same ID.
+        if inserted {
+            rules.move_index(index, position);
+            position += 1;
+        }
+    }
+}
+
+/// The position of the most important user-defined rule in the given set.
+fn first_user_rule_position<T: PushRuleFields>(rules: &IndexSet<T>) -> usize {
+    // `.m.rule.master` always has the highest priority.
+    rules.first().map_or(0, |first| usize::from(first.rule_id() == ".m.rule.master"))
+}
+
+/// The fields that are common to all the push rule types.
+trait PushRuleFields: Clone + Hash + Eq {
+    /// The ID of the rule.
+    fn rule_id(&self) -> &str;
+
+    /// Whether this is a server-default rule.
+    fn is_server_default(&self) -> bool;
+
+    /// Copy the fields that can be customized by the user from the given rule.
+    fn copy_customizations(&mut self, from: &Self);
+}
+
+impl PushRuleFields for SimplePushRule {
+    fn rule_id(&self) -> &str {
+        &self.rule_id
+    }
+
+    fn is_server_default(&self) -> bool {
+        self.default
+    }
+
+    fn copy_customizations(&mut self, from: &Self) {
+        self.enabled = from.enabled;
+        self.actions = from.actions.clone();
+    }
+}
+
+impl PushRuleFields for ConditionalPushRule {
+    fn rule_id(&self) -> &str {
+        &self.rule_id
+    }
+
+    fn is_server_default(&self) -> bool {
+        self.default
+    }
+
+    fn copy_customizations(&mut self, from: &Self) {
+        self.enabled = from.enabled;
+        self.actions = from.actions.clone();
+    }
+}
+
+impl PushRuleFields for PatternedPushRule {
+    fn rule_id(&self) -> &str {
+        &self.rule_id
+    }
+
+    fn is_server_default(&self) -> bool {
+        self.default
+    }
+
+    fn copy_customizations(&mut self, from: &Self) {
+        self.enabled = from.enabled;
+        self.actions = from.actions.clone();
+    }
 }
 
 /// A push rule is a single rule that states under what conditions an event should be passed onto a
@@ -463,6 +817,106 @@ pub enum PushFormat {
     _Custom(PrivOwnedStr),
 }
 
+/// The kinds of push rules that are available.
+#[doc = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/doc/string_enum.md"))]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, StringEnum)]
+#[ruma_enum(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum RuleKind {
+    /// User-configured rules that override all other kinds.
+    Override,
+
+    /// Lowest priority user-defined rules.
+    Underride,
+
+    /// Sender-specific rules.
+    Sender,
+
+    /// Room-specific rules.
+    Room,
+
+    /// Content-specific rules.
+    Content,
+
+    #[doc(hidden)]
+    _Custom(PrivOwnedStr),
+}
+
+/// An error that happens when a push rule cannot be inserted in a [`Ruleset`].
+#[derive(Debug)]
+#[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
+pub enum InsertPushRuleError {
+    /// The rule ID starts with a dot (`.`), which is reserved for server-default rules.
+    ServerDefaultRuleId,
+
+    /// The rule ID is empty or contains a slash (`/`) or a backslash (`\\`).
+    InvalidRuleId,
+
+    /// The rule should be placed relative to a server-default rule, which is forbidden.
+    RelativeToServerDefaultRule,
+
+    /// The rule of the `after` or `before` ID could not be found.
+    UnknownRuleId,
+
+    /// The rule of the `before` ID has a higher priority than the rule of the `after` ID.
+    BeforeHigherThanAfter,
+}
+
+impl fmt::Display for InsertPushRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ServerDefaultRuleId => {
+                write!(f, "Rule IDs starting with a dot are reserved for server-default rules")
+            }
+            Self::InvalidRuleId => write!(f, "Invalid rule ID"),
+            Self::RelativeToServerDefaultRule => {
+                write!(f, "Can't place a rule relative to a server-default rule")
+            }
+            Self::UnknownRuleId => write!(f, "The before or after rule could not be found"),
+            Self::BeforeHigherThanAfter => {
+                write!(f, "The before rule has a higher priority than the after rule")
+            }
+        }
+    }
+}
+
+impl StdError for InsertPushRuleError {}
+
+/// An error that happens when a push rule cannot be found in a [`Ruleset`].
+#[derive(Debug)]
+#[allow(clippy::exhaustive_structs)]
+pub struct RuleNotFoundError;
+
+impl fmt::Display for RuleNotFoundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The push rule could not be found")
+    }
+}
+
+impl StdError for RuleNotFoundError {}
+
+/// An error that happens when a push rule cannot be removed from a [`Ruleset`].
+#[derive(Debug)]
+#[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
+pub enum RemovePushRuleError {
+    /// The rule is a server-default rule, which can't be removed.
+    ServerDefault,
+
+    /// The rule could not be found.
+    NotFound,
+}
+
+impl fmt::Display for RemovePushRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ServerDefault => write!(f, "Server-default push rules can't be removed"),
+            Self::NotFound => write!(f, "The push rule could not be found"),
+        }
+    }
+}
+
+impl StdError for RemovePushRuleError {}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
@@ -477,7 +931,8 @@ mod tests {
     use super::{
         action::{Action, Tweak},
         condition::{PushCondition, PushConditionRoomCtx, RoomMemberCountIs},
-        AnyPushRule, ConditionalPushRule, PatternedPushRule, Ruleset, SimplePushRule,
+        AnyPushRule, AnyPushRuleRef, ConditionalPushRule, InsertPushRuleError, PatternedPushRule,
+        RemovePushRuleError, RuleKind, RuleNotFoundError, Ruleset, SimplePushRule,
     };
     use crate::{power_levels::NotificationPowerLevels, room_id, serde::Raw, user_id};
 
@@ -540,6 +995,215 @@ mod tests {
         assert_matches!(rule, None);
     }
 
+    fn user_override_rule(rule_id: &str) -> AnyPushRule {
+        AnyPushRule::Override(ConditionalPushRule {
+            conditions: vec![],
+            actions: vec![Action::Notify],
+            rule_id: rule_id.into(),
+            enabled: false,
+            default: true,
+        })
+    }
+
+    fn override_rule_ids(set: &Ruleset) -> Vec<&str> {
+        set.override_.iter().map(|rule| rule.rule_id.as_str()).collect()
+    }
+
+    #[test]
+    fn get_by_kind_and_rule_id() {
+        let set = example_ruleset();
+
+        let rule = set.get(RuleKind::Override, ".m.rule.call").unwrap();
+        assert_eq!(rule.rule_id(), ".m.rule.call");
+        assert_eq!(rule.kind(), RuleKind::Override);
+
+        assert_matches!(set.get(RuleKind::Underride, ".m.rule.call"), None);
+        assert_matches!(set.get(RuleKind::Override, ".m.rule.doesntexist"), None);
+    }
+
+    #[test]
+    fn insert_user_rules() {
+        let mut set = Ruleset::server_default(user_id!("@jolly_jumper:server.name"));
+
+        // New rules are placed after `.m.rule.master`, are enabled and are not server-default.
+        set.insert(user_override_rule("first"), None, None).unwrap();
+        set.insert(user_override_rule("second"), None, None).unwrap();
+        assert_eq!(&override_rule_ids(&set)[..3], [".m.rule.master", "second", "first"]);
+        let rule = set.get(RuleKind::Override, "first").unwrap();
+        assert!(rule.enabled());
+        assert_matches!(rule, AnyPushRuleRef::Override(ConditionalPushRule { default: false, .. }));
+
+        set.insert(user_override_rule("third"), Some("first"), None).unwrap();
+        assert_eq!(&override_rule_ids(&set)[..4], [".m.rule.master", "second", "first", "third"]);
+
+        set.insert(user_override_rule("fourth"), None, Some("second")).unwrap();
+        assert_eq!(
+            &override_rule_ids(&set)[..5],
+            [".m.rule.master", "fourth", "second", "first", "third"]
+        );
+
+        // Moving an existing rule keeps its `enabled` flag.
+        set.set_enabled(RuleKind::Override, "third", false).unwrap();
+        set.insert(user_override_rule("third"), Some("fourth"), Some("second")).unwrap();
+        assert_eq!(
+            &override_rule_ids(&set)[..5],
+            [".m.rule.master", "fourth", "third", "second", "first"]
+        );
+        assert!(!set.get(RuleKind::Override, "third").unwrap().enabled());
+
+        // Replacing an existing rule without a position keeps its position.
+        set.insert(user_override_rule("fourth"), None, None).unwrap();
+        assert_eq!(
+            &override_rule_ids(&set)[..5],
+            [".m.rule.master", "fourth", "third", "second", "first"]
+        );
+
+        // Other kinds don't have a special rule at the top.
+        set.insert(
+            AnyPushRule::Room(SimplePushRule {
+                actions: vec![],
+                default: false,
+                enabled: true,
+                rule_id: "!dm:server.name".into(),
+            }),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(set.room.first().unwrap().rule_id, "!dm:server.name");
+    }
+
+    #[test]
+    fn insert_invalid_user_rules() {
+        let mut set = Ruleset::server_default(user_id!("@jolly_jumper:server.name"));
+        set.insert(user_override_rule("first"), None, None).unwrap();
+        set.insert(user_override_rule("second"), Some("first"), None).unwrap();
+        let rule_ids =
+            override_rule_ids(&set).into_iter().map(ToOwned::to_owned).collect::<Vec<_>>();
+
+        assert_matches!(
+            set.insert(user_override_rule(".m.rule.mine"), None, None),
+            Err(InsertPushRuleError::ServerDefaultRuleId)
+        );
+        assert_matches!(
+            set.insert(user_override_rule("my/rule"), None, None),
+            Err(InsertPushRuleError::InvalidRuleId)
+        );
+        assert_matches!(
+            set.insert(user_override_rule("third"), Some(".m.rule.master"), None),
+            Err(InsertPushRuleError::RelativeToServerDefaultRule)
+        );
+        assert_matches!(
+            set.insert(user_override_rule("third"), None, Some("unknown")),
+            Err(InsertPushRuleError::UnknownRuleId)
+        );
+        assert_matches!(
+            set.insert(user_override_rule("first"), Some("first"), None),
+            Err(InsertPushRuleError::UnknownRuleId)
+        );
+        assert_matches!(
+            set.insert(user_override_rule("third"), Some("second"), Some("first")),
+            Err(InsertPushRuleError::BeforeHigherThanAfter)
+        );
+
+        // The rule set was not modified.
+        assert_eq!(override_rule_ids(&set), rule_ids);
+    }
+
+    #[test]
+    fn update_rules() {
+        let mut set = Ruleset::server_default(user_id!("@jolly_jumper:server.name"));
+        let rule_ids =
+            override_rule_ids(&set).into_iter().map(ToOwned::to_owned).collect::<Vec<_>>();
+
+        set.set_enabled(RuleKind::Override, ".m.rule.master", true).unwrap();
+        assert!(set.get(RuleKind::Override, ".m.rule.master").unwrap().enabled());
+
+        set.set_actions(RuleKind::Override, ".m.rule.suppress_notices", vec![Action::Notify])
+            .unwrap();
+        assert_matches!(
+            set.get(RuleKind::Override, ".m.rule.suppress_notices").unwrap().actions(),
+            [Action::Notify]
+        );
+
+        // The priority of the rules is preserved.
+        assert_eq!(override_rule_ids(&set), rule_ids);
+
+        assert_matches!(
+            set.set_enabled(RuleKind::Content, ".m.rule.master", true),
+            Err(RuleNotFoundError)
+        );
+        assert_matches!(set.set_actions(RuleKind::Room, "unknown", vec![]), Err(RuleNotFoundError));
+    }
+
+    #[test]
+    fn update_with_server_default() {
+        let user_id = user_id!("@jolly_jumper:server.name");
+        let new_server_default = Ruleset::server_default(user_id);
+
+        let mut set = Ruleset::server_default(user_id);
+        // An older version of the server-default rules.
+        set.override_.shift_remove(".m.rule.suppress_notices");
+        set.underride.insert(ConditionalPushRule {
+            conditions: vec![],
+            actions: vec![Action::Notify],
+            rule_id: ".m.rule.obsolete".into(),
+            enabled: true,
+            default: true,
+        });
+        // The customizations of the user.
+        set.set_enabled(RuleKind::Override, ".m.rule.master", true).unwrap();
+        set.set_actions(RuleKind::Content, ".m.rule.contains_user_name", vec![]).unwrap();
+        set.insert(user_override_rule("first"), None, None).unwrap();
+        set.insert(user_override_rule("second"), Some("first"), None).unwrap();
+        set.insert(
+            AnyPushRule::Underride(ConditionalPushRule {
+                conditions: vec![],
+                actions: vec![],
+                rule_id: "last".into(),
+                enabled: true,
+                default: false,
+            }),
+            None,
+            None,
+        )
+        .unwrap();
+
+        set.update_with_server_default(new_server_default.clone());
+
+        assert_eq!(set.override_.len(), new_server_default.override_.len() + 2);
+        assert_eq!(&override_rule_ids(&set)[..3], [".m.rule.master", "first", "second"]);
+        assert!(set.get(RuleKind::Override, ".m.rule.master").unwrap().enabled());
+        assert!(set.get(RuleKind::Override, ".m.rule.suppress_notices").is_some());
+
+        let rule = set.get(RuleKind::Content, ".m.rule.contains_user_name").unwrap();
+        assert!(rule.actions().is_empty());
+
+        assert_eq!(set.underride.len(), new_server_default.underride.len() + 1);
+        assert_eq!(set.underride.first().unwrap().rule_id, "last");
+        assert_matches!(set.get(RuleKind::Underride, ".m.rule.obsolete"), None);
+    }
+
+    #[test]
+    fn remove_rules() {
+        let mut set = Ruleset::server_default(user_id!("@jolly_jumper:server.name"));
+        set.insert(user_override_rule("first"), None, None).unwrap();
+        set.insert(user_override_rule("second"), Some("first"), None).unwrap();
+
+        set.remove(RuleKind::Override, "first").unwrap();
+        assert_eq!(&override_rule_ids(&set)[..2], [".m.rule.master", "second"]);
+        assert_matches!(set.get(RuleKind::Override, "first"), None);
+
+        assert_matches!(
+            set.remove(RuleKind::Override, "first"),
+            Err(RemovePushRuleError::NotFound)
+        );
+        assert_matches!(
+            set.remove(RuleKind::Override, ".m.rule.master"),
+            Err(RemovePushRuleError::ServerDefault)
+        );
+    }
+
     #[test]
     fn iter() {
         let mut set = example_ruleset();
@@ -1061,6 +1725,81 @@ mod tests {
         assert_matches!(set.get_actions(&empty, context_one_to_one), []);
     }
 
+    #[cfg(feature = "unstable-msc3952")]
+    #[test]
+    fn intentional_mentions_apply() {
+        let set = Ruleset::server_default(user_id!("@jolly_jumper:server.name"));
+
+        let context = &PushConditionRoomCtx {
+            room_id: room_id!("!far_west:server.name").to_owned(),
+            member_count: uint!(100),
+            user_id: user_id!("@jolly_jumper:server.name").to_owned(),
+            user_display_name: "Jolly Jumper".into(),
+            users_power_levels: BTreeMap::new(),
+            default_power_level: int!(50),
+            notification_power_levels: NotificationPowerLevels { room: int!(50) },
+        };
+
+        let get_rule_id = |json: JsonValue| {
+            let event = Raw::new(&json).unwrap();
+            set.get_match(&event, context).map(|rule| rule.rule_id().to_owned())
+        };
+
+        let user_mention = get_rule_id(json!({
+            "type": "m.room.message",
+            "sender": "@rantanplan:server.name",
+            "content": {
+                "body": "Hey!",
+                "m.mentions": {
+                    "user_ids": ["@jolly_jumper:server.name"]
+                }
+            }
+        }));
+        assert_eq!(user_mention.as_deref(), Some(".m.rule.is_user_mention"));
+
+        let room_mention = get_rule_id(json!({
+            "type": "m.room.message",
+            "sender": "@rantanplan:server.name",
+            "content": {
+                "body": "Hey everyone!",
+                "m.mentions": {
+                    "room": true
+                }
+            }
+        }));
+        assert_eq!(room_mention.as_deref(), Some(".m.rule.is_room_mention"));
+
+        // The legacy mention rules don't apply to events with mentions.
+        let legacy_user_mention = get_rule_id(json!({
+            "type": "m.room.message",
+            "sender": "@rantanplan:server.name",
+            "content": {
+                "body": "Hi Jolly Jumper, aka jolly_jumper!",
+                "m.mentions": {}
+            }
+        }));
+        assert_eq!(legacy_user_mention.as_deref(), Some(".m.rule.message"));
+
+        let legacy_room_mention = get_rule_id(json!({
+            "type": "m.room.message",
+            "sender": "@rantanplan:server.name",
+            "content": {
+                "body": "@room Attention please!",
+                "m.mentions": {}
+            }
+        }));
+        assert_eq!(legacy_room_mention.as_deref(), Some(".m.rule.message"));
+
+        let legacy_without_mentions = get_rule_id(json!({
+            "type": "m.room.message",
+            "sender": "@rantanplan:server.name",
+            "content": {
+                "body": "@room Attention please!"
+            }
+        }));
+        assert_eq!(legacy_without_mentions.as_deref(), Some(".m.rule.roomnotif"));
+    }
+
     #[test]
     fn custom_ruleset_applies() {
         let context_one_to_one = &PushConditionRoomCtx {
@@ -24,6 +24,18 @@ pub enum Action {
     SetTweak(Tweak),
 }
 
+impl Action {
+    /// Whether this action is an `Action::SetTweak(Tweak::Highlight(true))`.
+    pub fn is_highlight(&self) -> bool {
+        matches!(self, Action::SetTweak(Tweak::Highlight(true)))
+    }
+
+    /// Whether this action should trigger a notification.
+    pub fn should_notify(&self) -> bool {
+        matches!(self, Action::Notify | Action::Coalesce)
+    }
+}
+
 /// The `set_tweak` action.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
@@ -0,0 +1,600 @@
+//! Push rules compiled to evaluate many events efficiently.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use regex::Regex;
+use wildmatch::WildMatch;
+
+use super::{
+    condition::{word_pattern_regex, StrExt},
+    Action, AnyPushRuleRef, ConditionalPushRule, FlattenedJson, PushCondition,
+    PushConditionRoomCtx, Ruleset, SimplePushRule,
+};
+use crate::serde::Raw;
+
+/// An event prepared to be evaluated against many [`CompiledRuleset`]s.
+///
+/// The event is only flattened and lowercased once, instead of once per ruleset.
+#[derive(Clone, Debug)]
+pub struct PreparedPushEvent {
+    /// The flattened JSON of the event.
+    event: FlattenedJson,
+
+    /// The lowercased values of the flattened JSON.
+    lowercase: BTreeMap<String, String>,
+}
+
+impl PreparedPushEvent {
+    /// Prepare the given event.
+    pub fn new<T>(event: &Raw<T>) -> Self {
+        Self::from_flattened(FlattenedJson::from_raw(event))
+    }
+
+    /// Prepare the given flattened event.
+    pub fn from_flattened(event: FlattenedJson) -> Self {
+        let lowercase =
+            event.iter_str().map(|(path, value)| (path.to_owned(), value.to_lowercase())).collect();
+        Self { event, lowercase }
+    }
+
+    /// The flattened JSON of the event.
+    pub fn flattened(&self) -> &FlattenedJson {
+        &self.event
+    }
+
+    /// The lowercased value associated with the given `path`.
+    fn get_lowercase(&self, path: &str) -> Option<&str> {
+        self.lowercase.get(path).map(|s| s.as_str())
+    }
+}
+
+impl From<FlattenedJson> for PreparedPushEvent {
+    fn from(event: FlattenedJson) -> Self {
+        Self::from_flattened(event)
+    }
+}
+
+/// A compiler for push rulesets, that shares the compiled patterns between rulesets.
+///
+/// Most users only have the server-default rules and a few rules of their own, so compiling the
+/// rulesets of many users with the same compiler compiles each of the default patterns only once.
+#[derive(Clone, Debug, Default)]
+pub struct RulesetCompiler {
+    matchers: BTreeMap<(String, bool), Arc<Matcher>>,
+}
+
+impl RulesetCompiler {
+    /// Creates a new `RulesetCompiler`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compile the given ruleset.
+    pub fn compile(&mut self, ruleset: &Ruleset) -> CompiledRuleset {
+        CompiledRuleset {
+            override_: self.compile_conditional(&ruleset.override_),
+            content: ruleset
+                .content
+                .iter()
+                .enumerate()
+                .filter(|(_, rule)| rule.enabled)
+                .map(|(index, rule)| CompiledContentRule {
+                    index,
+                    matcher: self.matcher(&rule.pattern, true),
+                    #[cfg(feature = "unstable-msc3952")]
+                    is_legacy_mention: AnyPushRuleRef::Content(rule).is_legacy_mention_rule(),
+                })
+                .collect(),
+            room: self.compile_simple(&ruleset.room),
+            sender: self.compile_simple(&ruleset.sender),
+            underride: self.compile_conditional(&ruleset.underride),
+            ruleset: ruleset.clone(),
+        }
+    }
+
+    /// Get the matcher of the given pattern.
+    fn matcher(&mut self, pattern: &str, match_words: bool) -> Arc<Matcher> {
+        let pattern = pattern.to_lowercase();
+        self.matchers
+            .entry((pattern, match_words))
+            .or_insert_with_key(|(pattern, match_words)| {
+                Arc::new(Matcher::new(pattern.clone(), *match_words))
+            })
+            .clone()
+    }
+
+    fn compile_conditional<'a>(
+        &mut self,
+        rules: impl IntoIterator<Item = &'a ConditionalPushRule>,
+    ) -> ConditionalRules {
+        let mut compiled = ConditionalRules::default();
+
+        for (index, rule) in rules.into_iter().enumerate().filter(|(_, rule)| rule.enabled) {
+            let position = compiled.rules.len();
+            let conditions =
+                rule.conditions.iter().map(|condition| self.compile_condition(condition)).collect();
+            compiled.rules.push(CompiledConditionalRule {
+                index,
+                conditions,
+                #[cfg(feature = "unstable-msc3952")]
+                is_legacy_mention: AnyPushRuleRef::Override(rule).is_legacy_mention_rule(),
+            });
+
+            // Rules that need an exact event type are only checked against events of that type.
+            let event_type = rule.conditions.iter().find_map(|condition| match condition {
+                PushCondition::EventMatch { key, pattern }
+                    if key == "type" && !has_wildcards(pattern) =>
+                {
+                    Some(pattern.to_lowercase())
+                }
+                _ => None,
+            });
+
+            match event_type {
+                Some(event_type) => compiled.by_type.entry(event_type).or_default().push(position),
+                None => compiled.any_type.push(position),
+            }
+        }
+
+        compiled
+    }
+
+    fn compile_condition(&mut self, condition: &PushCondition) -> CompiledCondition {
+        match condition {
+            PushCondition::EventMatch { key, pattern } => CompiledCondition::EventMatch {
+                key: key.clone(),
+                matcher: self.matcher(pattern, key == "content.body"),
+            },
+            PushCondition::ContainsDisplayName => CompiledCondition::ContainsDisplayName,
+            condition => CompiledCondition::Other(condition.clone()),
+        }
+    }
+
+    fn compile_simple<'a>(
+        &mut self,
+        rules: impl IntoIterator<Item = &'a SimplePushRule>,
+    ) -> SimpleRules {
+        let mut compiled = SimpleRules::default();
+
+        for (index, rule) in rules.into_iter().enumerate().filter(|(_, rule)| rule.enabled) {
+            if has_wildcards(&rule.rule_id) {
+                compiled.globs.push((index, self.matcher(&rule.rule_id, false)));
+            } else {
+                compiled.exact.entry(rule.rule_id.to_lowercase()).or_insert(index);
+            }
+        }
+
+        compiled
+    }
+}
+
+/// A push ruleset compiled to evaluate many events efficiently.
+///
+/// The patterns of the rules are compiled once, and the rules are indexed by the room ID, sender
+/// or event type they match, so only the rules that can apply to an event are evaluated.
+///
+/// To compile many rulesets, use a [`RulesetCompiler`].
+#[derive(Clone, Debug)]
+pub struct CompiledRuleset {
+    ruleset: Ruleset,
+    override_: ConditionalRules,
+    content: Vec<CompiledContentRule>,
+    room: SimpleRules,
+    sender: SimpleRules,
+    underride: ConditionalRules,
+}
+
+impl CompiledRuleset {
+    /// The ruleset that was compiled.
+    pub fn ruleset(&self) -> &Ruleset {
+        &self.ruleset
+    }
+
+    /// Get the first push rule that applies to this event, if any.
+    ///
+    /// This gives the same result as [`Ruleset::get_match()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The prepared room message event.
+    /// * `context` - The context of the message and room at the time of the event.
+    pub fn get_match(
+        &self,
+        event: &PreparedPushEvent,
+        context: &PushConditionRoomCtx,
+    ) -> Option<AnyPushRuleRef<'_>> {
+        if event.event.get("sender").map_or(false, |sender| sender == context.user_id) {
+            // no need to look at the rules if the event was by the user themselves
+            return None;
+        }
+
+        if let Some(index) = self.override_.find(event, context) {
+            return self.ruleset.override_.get_index(index).map(AnyPushRuleRef::Override);
+        }
+
+        if let Some(body) = event.get_lowercase("content.body") {
+            let rule = self.content.iter().find(|rule| {
+                #[cfg(feature = "unstable-msc3952")]
+                if rule.is_legacy_mention && event.event.has_mentions() {
+                    return false;
+                }
+
+                rule.matcher.matches(body)
+            });
+
+            if let Some(rule) = rule {
+                return self.ruleset.content.get_index(rule.index).map(AnyPushRuleRef::Content);
+            }
+        }
+
+        let room_id = context.room_id.as_str().to_lowercase();
+        if let Some(index) = self.room.find(&room_id) {
+            return self.ruleset.room.get_index(index).map(AnyPushRuleRef::Room);
+        }
+
+        if let Some(index) =
+            event.get_lowercase("sender").and_then(|sender| self.sender.find(sender))
+        {
+            return self.ruleset.sender.get_index(index).map(AnyPushRuleRef::Sender);
+        }
+
+        self.underride
+            .find(event, context)
+            .and_then(|index| self.ruleset.underride.get_index(index))
+            .map(AnyPushRuleRef::Underride)
+    }
+
+    /// Get the push actions that apply to this event.
+    ///
+    /// Returns an empty slice if no push rule applies.
+    ///
+    /// This gives the same result as [`Ruleset::get_actions()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The prepared room message event.
+    /// * `context` - The context of the message and room at the time of the event.
+    pub fn get_actions(
+        &self,
+        event: &PreparedPushEvent,
+        context: &PushConditionRoomCtx,
+    ) -> &[Action] {
+        self.get_match(event, context).map(|rule| rule.actions()).unwrap_or(&[])
+    }
+}
+
+impl From<&Ruleset> for CompiledRuleset {
+    fn from(ruleset: &Ruleset) -> Self {
+        ruleset.compile()
+    }
+}
+
+/// A compiled glob pattern, with its value already lowercased.
+#[derive(Debug)]
+enum Matcher {
+    /// A pattern matched against the whole value.
+    Glob(WildMatch),
+
+    /// A pattern without wildcards matched against the words of the value.
+    Word(String),
+
+    /// A pattern with wildcards matched against the words of the value.
+    WordRegex {
+        pattern: String,
+
+        /// The regex of the pattern, or `None` if it is invalid.
+        regex: Option<Regex>,
+    },
+}
+
+impl Matcher {
+    fn new(pattern: String, match_words: bool) -> Self {
+        if !match_words {
+            Self::Glob(WildMatch::new(&pattern))
+        } else if has_wildcards(&pattern) {
+            Self::WordRegex { regex: word_pattern_regex(&pattern), pattern }
+        } else {
+            Self::Word(pattern)
+        }
+    }
+
+    /// Whether the given lowercased value matches this pattern.
+    ///
+    /// This gives the same result as `StrExt::matches_pattern()`.
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Self::Glob(glob) => glob.matches(value),
+            Self::Word(pattern) => value.matches_word(pattern),
+            Self::WordRegex { pattern, regex } => {
+                value == pattern || regex.as_ref().map_or(false, |regex| regex.is_match(value))
+            }
+        }
+    }
+}
+
+/// A compiled push condition.
+#[derive(Clone, Debug)]
+enum CompiledCondition {
+    EventMatch { key: String, matcher: Arc<Matcher> },
+    ContainsDisplayName,
+    Other(PushCondition),
+}
+
+impl CompiledCondition {
+    fn applies(&self, event: &PreparedPushEvent, context: &PushConditionRoomCtx) -> bool {
+        match self {
+            Self::EventMatch { key, matcher } => {
+                if key == "room_id" {
+                    matcher.matches(&context.room_id.as_str().to_lowercase())
+                } else {
+                    event.get_lowercase(key).map_or(false, |value| matcher.matches(value))
+                }
+            }
+            Self::ContainsDisplayName => event
+                .get_lowercase("content.body")
+                .map_or(false, |body| body.matches_word(&context.user_display_name.to_lowercase())),
+            Self::Other(condition) => condition.applies(&event.event, context),
+        }
+    }
+}
+
+/// A compiled content rule.
+#[derive(Clone, Debug)]
+struct CompiledContentRule {
+    /// The index of the rule in the ruleset.
+    index: usize,
+    matcher: Arc<Matcher>,
+
+    /// Whether this is a legacy mention rule.
+    #[cfg(feature = "unstable-msc3952")]
+    is_legacy_mention: bool,
+}
+
+/// A compiled override or underride rule.
+#[derive(Clone, Debug)]
+struct CompiledConditionalRule {
+    /// The index of the rule in the ruleset.
+    index: usize,
+    conditions: Vec<CompiledCondition>,
+
+    /// Whether this is a legacy mention rule.
+    #[cfg(feature = "unstable-msc3952")]
+    is_legacy_mention: bool,
+}
+
+/// The enabled override or underride rules of a ruleset, indexed by event type.
+#[derive(Clone, Debug, Default)]
+struct ConditionalRules {
+    rules: Vec<CompiledConditionalRule>,
+
+    /// The positions in `rules` of the rules that only apply to a lowercased event type.
+    by_type: BTreeMap<String, Vec<usize>>,
+
+    /// The positions in `rules` of the rules that can apply to any event type.
+    any_type: Vec<usize>,
+}
+
+impl ConditionalRules {
+    /// Get the index in the ruleset of the first rule that applies to the event, if any.
+    fn find(&self, event: &PreparedPushEvent, context: &PushConditionRoomCtx) -> Option<usize> {
+        let typed = event
+            .get_lowercase("type")
+            .and_then(|event_type| self.by_type.get(event_type))
+            .map_or(&[][..], |positions| positions.as_slice());
+
+        // Merge the two sorted lists of candidates to keep the priority of the rules.
+        let mut typed = typed.iter().peekable();
+        let mut any_type = self.any_type.iter().peekable();
+
+        loop {
+            let position = match (typed.peek(), any_type.peek()) {
+                (Some(a), Some(b)) if a < b => typed.next(),
+                (Some(_), Some(_)) | (None, Some(_)) => any_type.next(),
+                (Some(_), None) => typed.next(),
+                (None, None) => return None,
+            };
+
+            let rule = &self.rules[*position?];
+
+            #[cfg(feature = "unstable-msc3952")]
+            if rule.is_legacy_mention && event.event.has_mentions() {
+                continue;
+            }
+
+            if rule.conditions.iter().all(|condition| condition.applies(event, context)) {
+                return Some(rule.index);
+            }
+        }
+    }
+}
+
+/// The enabled room or sender rules of a ruleset.
+#[derive(Clone, Debug, Default)]
+struct SimpleRules {
+    /// The index in the ruleset of the first rule for each lowercased ID without wildcards.
+    exact: BTreeMap<String, usize>,
+
+    /// The index in the ruleset and the matcher of the rules with wildcards.
+    globs: Vec<(usize, Arc<Matcher>)>,
+}
+
+impl SimpleRules {
+    /// Get the index in the ruleset of the first rule that matches the lowercased value, if any.
+    fn find(&self, value: &str) -> Option<usize> {
+        let exact = self.exact.get(value).copied();
+        let glob = self.globs.iter().find(|(_, matcher)| matcher.matches(value)).map(|(i, _)| *i);
+
+        match (exact, glob) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// Whether the given pattern contains wildcards.
+fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+#[cfg(test)]
+mod tests {
+    use js_int::{int, uint};
+    use serde_json::{json, Value as JsonValue};
+
+    use super::{PreparedPushEvent, RulesetCompiler};
+    use crate::{
+        power_levels::NotificationPowerLevels,
+        push::{
+            Action, AnyPushRule, ConditionalPushRuleInit, PatternedPushRuleInit, PushCondition,
+            PushConditionRoomCtx, Ruleset, SimplePushRuleInit,
+        },
+        room_id,
+        serde::Raw,
+        user_id,
+    };
+
+    fn context() -> PushConditionRoomCtx {
+        PushConditionRoomCtx {
+            room_id: room_id!("!dm:server.name").to_owned(),
+            member_count: uint!(2),
+            user_id: user_id!("@jj:server.name").to_owned(),
+            user_display_name: "Jolly Jumper".into(),
+            users_power_levels: [(user_id!("@rantanplan:server.name").to_owned(), int!(50))].into(),
+            default_power_level: int!(0),
+            notification_power_levels: NotificationPowerLevels { room: int!(50) },
+        }
+    }
+
+    fn events() -> Vec<Raw<JsonValue>> {
+        [
+            json!({
+                "type": "m.room.message",
+                "sender": "@rantanplan:server.name",
+                "content": { "msgtype": "m.text", "body": "Hi Jolly Jumper!" },
+            }),
+            json!({
+                "type": "m.room.message",
+                "sender": "@rantanplan:server.name",
+                "content": { "msgtype": "m.notice", "body": "@room: maintenance" },
+            }),
+            json!({
+                "type": "m.room.message",
+                "sender": "@lucky:server.name",
+                "content": { "msgtype": "m.text", "body": "Cheese or ham?" },
+            }),
+            json!({
+                "type": "M.Room.Message",
+                "sender": "@lucky:server.name",
+                "content": { "msgtype": "m.text", "body": "I want more CHEESE!" },
+            }),
+            json!({
+                "type": "m.room.message",
+                "sender": "@rantanplan:server.name",
+                "content": {
+                    "body": "Hi Jolly Jumper!",
+                    "m.mentions": { "user_ids": ["@jj:server.name"] },
+                },
+            }),
+            json!({
+                "type": "m.room.message",
+                "sender": "@rantanplan:server.name",
+                "content": { "body": "@room: maintenance", "m.mentions": { "room": true } },
+            }),
+            json!({
+                "type": "m.room.member",
+                "sender": "@lucky:server.name",
+                "state_key": "@jj:server.name",
+                "content": { "membership": "invite" },
+            }),
+            json!({
+                "type": "m.call.invite",
+                "sender": "@lucky:server.name",
+                "content": {},
+            }),
+            json!({
+                "type": "m.room.encrypted",
+                "sender": "@jj:server.name",
+                "content": {},
+            }),
+        ]
+        .into_iter()
+        .map(|event| Raw::new(&event).unwrap())
+        .collect()
+    }
+
+    fn user_ruleset() -> Ruleset {
+        let mut ruleset = Ruleset::server_default(user_id!("@jj:server.name"));
+        ruleset.content.insert(
+            PatternedPushRuleInit {
+                actions: vec![Action::Notify],
+                default: false,
+                enabled: true,
+                rule_id: "cheese".into(),
+                pattern: "chee*".into(),
+            }
+            .into(),
+        );
+        ruleset.sender.insert(
+            SimplePushRuleInit {
+                actions: vec![],
+                default: false,
+                enabled: true,
+                rule_id: "@Lucky:server.name".into(),
+            }
+            .into(),
+        );
+        ruleset.add(AnyPushRule::Override(
+            ConditionalPushRuleInit {
+                actions: vec![],
+                default: false,
+                enabled: true,
+                rule_id: "notices".into(),
+                conditions: vec![PushCondition::EventMatch {
+                    key: "content.msgtype".into(),
+                    pattern: "m.notice".into(),
+                }],
+            }
+            .into(),
+        ));
+        ruleset
+    }
+
+    #[test]
+    fn same_match_as_ruleset() {
+        let mut compiler = RulesetCompiler::new();
+        let context = context();
+
+        for ruleset in [Ruleset::server_default(user_id!("@jj:server.name")), user_ruleset()] {
+            let compiled = compiler.compile(&ruleset);
+
+            for event in events() {
+                let prepared = PreparedPushEvent::new(&event);
+                assert_eq!(
+                    compiled.get_match(&prepared, &context).map(|rule| rule.rule_id()),
+                    ruleset.get_match(&event, &context).map(|rule| rule.rule_id()),
+                    "{}",
+                    event.json().get()
+                );
+                assert_eq!(
+                    format!("{:?}", compiled.get_actions(&prepared, &context)),
+                    format!("{:?}", ruleset.get_actions(&event, &context))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn shared_patterns() {
+        let mut compiler = RulesetCompiler::new();
+        compiler.compile(&Ruleset::server_default(user_id!("@jj:server.name")));
+        let count = compiler.matchers.len();
+
+        // Only the patterns with the user ID and the localpart are new.
+        compiler.compile(&Ruleset::server_default(user_id!("@lucky:server.name")));
+        assert_eq!(compiler.matchers.len(), count + 2);
+
+        // Only the `chee*` pattern is new.
+        compiler.compile(&user_ruleset());
+        assert_eq!(compiler.matchers.len(), count + 3);
+    }
+}
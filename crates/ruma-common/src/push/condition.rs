@@ -3,15 +3,17 @@ use std::{collections::BTreeMap, ops::RangeBounds, str::FromStr};
 use js_int::{Int, UInt};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serde_json::{to_value as to_json_value, value::Value as JsonValue};
-use tracing::{instrument, warn};
 use wildmatch::WildMatch;
 
-use crate::{power_levels::NotificationPowerLevels, serde::Raw, OwnedRoomId, OwnedUserId, UserId};
+use crate::{power_levels::NotificationPowerLevels, OwnedRoomId, OwnedUserId, UserId};
 
+mod flattened_json;
 mod room_member_count_is;
 
-pub use room_member_count_is::{ComparisonOperator, RoomMemberCountIs};
+pub use self::{
+    flattened_json::{FlattenedJson, FlattenedJsonValue, ScalarJsonValue},
+    room_member_count_is::{ComparisonOperator, RoomMemberCountIs},
+};
 
 /// A condition that must apply for an associated push rule's action to be taken.
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -49,6 +51,34 @@ pub enum PushCondition {
         /// `content`.
         key: String,
     },
+
+    /// Exact value match on a property of the event.
+    ///
+    /// This is [MSC3758].
+    ///
+    /// [MSC3758]: https://github.com/matrix-org/matrix-spec-proposals/pull/3758
+    #[cfg(feature = "unstable-msc3758")]
+    EventPropertyIs {
+        /// The dot-separated path to the property of the event to match.
+        key: String,
+
+        /// The value to match against.
+        value: ScalarJsonValue,
+    },
+
+    /// Exact value match on a value in an array property of the event.
+    ///
+    /// This is [MSC3966].
+    ///
+    /// [MSC3966]: https://github.com/matrix-org/matrix-spec-proposals/pull/3966
+    #[cfg(feature = "unstable-msc3966")]
+    EventPropertyContains {
+        /// The dot-separated path to the array property of the event to match.
+        key: String,
+
+        /// The value to match against.
+        value: ScalarJsonValue,
+    },
 }
 
 pub(super) fn check_event_match(
@@ -110,6 +140,15 @@ impl PushCondition {
                     None => false,
                 }
             }
+            #[cfg(feature = "unstable-msc3758")]
+            Self::EventPropertyIs { key, value } => {
+                event.get_value(key).map_or(false, |property| property == value)
+            }
+            #[cfg(feature = "unstable-msc3966")]
+            Self::EventPropertyContains { key, value } => event
+                .get_value(key)
+                .and_then(FlattenedJsonValue::as_array)
+                .map_or(false, |array| array.contains(value)),
         }
     }
 }
@@ -141,7 +180,7 @@ pub struct PushConditionRoomCtx {
 }
 
 /// Additional functions for character matching.
-trait CharExt {
+pub(super) trait CharExt {
     /// Whether or not this char can be part of a word.
     fn is_word_char(&self) -> bool;
 }
@@ -153,7 +192,7 @@ impl CharExt for char {
 }
 
 /// Additional functions for string matching.
-trait StrExt {
+pub(super) trait StrExt {
     /// Get the length of the char at `index`. The byte index must correspond to
     /// the start of a char boundary.
     fn char_len(&self, index: usize) -> usize;
@@ -243,39 +282,7 @@ impl StrExt for str {
         let has_wildcards = pattern.contains(|c| matches!(c, '?' | '*'));
 
         if has_wildcards {
-            let mut chunks: Vec<String> = vec![];
-            let mut prev_wildcard = false;
-            let mut chunk_start = 0;
-
-            for (i, c) in pattern.char_indices() {
-                if matches!(c, '?' | '*') && !prev_wildcard {
-                    if i != 0 {
-                        chunks.push(regex::escape(&pattern[chunk_start..i]));
-                        chunk_start = i;
-                    }
-
-                    prev_wildcard = true;
-                } else if prev_wildcard {
-                    let chunk = &pattern[chunk_start..i];
-                    chunks.push(chunk.wildcards_to_regex());
-
-                    chunk_start = i;
-                    prev_wildcard = false;
-                }
-            }
-
-            let len = pattern.len();
-            if !prev_wildcard {
-                chunks.push(regex::escape(&pattern[chunk_start..len]));
-            } else if prev_wildcard {
-                let chunk = &pattern[chunk_start..len];
-                chunks.push(chunk.wildcards_to_regex());
-            }
-
-            // The word characters in ASCII compatible mode (with the `-u` flag) match the
-            // definition in the spec: any character not in the set `[A-Za-z0-9_]`.
-            let regex = format!(r"(?-u:^|\W|\b){}(?-u:\b|\W|$)", chunks.concat());
-            Regex::new(&regex).ok().filter(|re| re.is_match(self)).is_some()
+            word_pattern_regex(pattern).filter(|re| re.is_match(self)).is_some()
         } else {
             match self.find(pattern) {
                 Some(start) => {
@@ -329,44 +336,44 @@ impl StrExt for str {
     }
 }
 
-/// The flattened representation of a JSON object.
-#[derive(Clone, Debug)]
-pub struct FlattenedJson {
-    /// The internal map containing the flattened JSON as a pair path, value.
-    map: BTreeMap<String, String>,
-}
+/// Translate the given glob pattern with wildcards to a regex that matches it with word
+/// boundaries.
+///
+/// Returns `None` if the regex is invalid.
+pub(super) fn word_pattern_regex(pattern: &str) -> Option<Regex> {
+    let mut chunks: Vec<String> = vec![];
+    let mut prev_wildcard = false;
+    let mut chunk_start = 0;
+
+    for (i, c) in pattern.char_indices() {
+        if matches!(c, '?' | '*') && !prev_wildcard {
+            if i != 0 {
+                chunks.push(regex::escape(&pattern[chunk_start..i]));
+                chunk_start = i;
+            }
 
-impl FlattenedJson {
-    /// Create a `FlattenedJson` from `Raw`.
-    pub fn from_raw<T>(raw: &Raw<T>) -> Self {
-        let mut s = Self { map: BTreeMap::new() };
-        s.flatten_value(to_json_value(raw).unwrap(), "".into());
-        s
-    }
+            prev_wildcard = true;
+        } else if prev_wildcard {
+            let chunk = &pattern[chunk_start..i];
+            chunks.push(chunk.wildcards_to_regex());
 
-    /// Flatten and insert the `value` at `path`.
-    #[instrument(skip(self, value))]
-    fn flatten_value(&mut self, value: JsonValue, path: String) {
-        match value {
-            JsonValue::Object(fields) => {
-                for (key, value) in fields {
-                    let path = if path.is_empty() { key } else { format!("{path}.{key}") };
-                    self.flatten_value(value, path);
-                }
-            }
-            JsonValue::String(s) => {
-                if self.map.insert(path.clone(), s).is_some() {
-                    warn!("Duplicate path in flattened JSON: {path}");
-                }
-            }
-            JsonValue::Number(_) | JsonValue::Bool(_) | JsonValue::Array(_) | JsonValue::Null => {}
+            chunk_start = i;
+            prev_wildcard = false;
         }
     }
 
-    /// Value associated with the given `path`.
-    pub fn get(&self, path: &str) -> Option<&str> {
-        self.map.get(path).map(|s| s.as_str())
+    let len = pattern.len();
+    if !prev_wildcard {
+        chunks.push(regex::escape(&pattern[chunk_start..len]));
+    } else if prev_wildcard {
+        let chunk = &pattern[chunk_start..len];
+        chunks.push(chunk.wildcards_to_regex());
     }
+
+    // The word characters in ASCII compatible mode (with the `-u` flag) match the
+    // definition in the spec: any character not in the set `[A-Za-z0-9_]`.
+    let regex = format!(r"(?-u:^|\W|\b){}(?-u:\b|\W|$)", chunks.concat());
+    Regex::new(&regex).ok()
 }
 
 #[cfg(test)]
@@ -375,11 +382,12 @@ mod tests {
 
     use assert_matches::assert_matches;
     use js_int::{int, uint};
-    use maplit::btreemap;
     use serde_json::{
         from_value as from_json_value, json, to_value as to_json_value, Value as JsonValue,
     };
 
+    #[cfg(any(feature = "unstable-msc3758", feature = "unstable-msc3966"))]
+    use super::ScalarJsonValue;
     use super::{FlattenedJson, PushCondition, PushConditionRoomCtx, RoomMemberCountIs, StrExt};
     use crate::{power_levels::NotificationPowerLevels, room_id, serde::Raw, user_id};
 
@@ -658,46 +666,100 @@ mod tests {
         assert!(sender_notification_permission.applies(&second_event, &context));
     }
 
+    #[cfg(feature = "unstable-msc3758")]
     #[test]
-    fn flattened_json_values() {
-        let raw = serde_json::from_str::<Raw<JsonValue>>(
+    fn event_property_is_serde() {
+        let condition = PushCondition::EventPropertyIs {
+            key: "content.m.relates_to.is_falling_back".into(),
+            value: false.into(),
+        };
+        let json_data = json!({
+            "kind": "event_property_is",
+            "key": "content.m.relates_to.is_falling_back",
+            "value": false
+        });
+        assert_eq!(to_json_value(&condition).unwrap(), json_data);
+
+        let (key, value) = assert_matches!(
+            from_json_value::<PushCondition>(json_data).unwrap(),
+            PushCondition::EventPropertyIs { key, value } => (key, value)
+        );
+        assert_eq!(key, "content.m.relates_to.is_falling_back");
+        assert_eq!(value, ScalarJsonValue::Bool(false));
+    }
+
+    #[cfg(feature = "unstable-msc3758")]
+    #[test]
+    fn event_property_is_applies() {
+        let context = PushConditionRoomCtx {
+            room_id: room_id!("!room:server.name").to_owned(),
+            member_count: uint!(3),
+            user_id: user_id!("@gorilla:server.name").to_owned(),
+            user_display_name: "Groovy Gorilla".into(),
+            users_power_levels: BTreeMap::new(),
+            default_power_level: int!(50),
+            notification_power_levels: NotificationPowerLevels { room: int!(50) },
+        };
+        let event_raw = serde_json::from_str::<Raw<JsonValue>>(
             r#"{
-                "string": "Hello World",
-                "number": 10,
-                "array": [1, 2],
-                "boolean": true,
-                "null": null
+                "sender": "@worthy_whale:server.name",
+                "content": {
+                    "body": "Hello",
+                    "count": 2,
+                    "falling_back": false,
+                    "nothing": null
+                }
             }"#,
         )
         .unwrap();
+        let event = FlattenedJson::from_raw(&event_raw);
 
-        let flattened = FlattenedJson::from_raw(&raw);
-        assert_eq!(flattened.map, btreemap! { "string".into() => "Hello World".into() });
+        let is = |key: &str, value: ScalarJsonValue| {
+            PushCondition::EventPropertyIs { key: key.into(), value }.applies(&event, &context)
+        };
+
+        assert!(is("content.body", "Hello".into()));
+        assert!(!is("content.body", "hello".into()));
+        assert!(is("content.count", int!(2).into()));
+        assert!(!is("content.count", "2".into()));
+        assert!(is("content.falling_back", false.into()));
+        assert!(is("content.nothing", ScalarJsonValue::Null));
+        assert!(!is("content.missing", ScalarJsonValue::Null));
     }
 
+    #[cfg(feature = "unstable-msc3966")]
     #[test]
-    fn flattened_json_nested() {
-        let raw = serde_json::from_str::<Raw<JsonValue>>(
+    fn event_property_contains_applies() {
+        let context = PushConditionRoomCtx {
+            room_id: room_id!("!room:server.name").to_owned(),
+            member_count: uint!(3),
+            user_id: user_id!("@gorilla:server.name").to_owned(),
+            user_display_name: "Groovy Gorilla".into(),
+            users_power_levels: BTreeMap::new(),
+            default_power_level: int!(50),
+            notification_power_levels: NotificationPowerLevels { room: int!(50) },
+        };
+        let event_raw = serde_json::from_str::<Raw<JsonValue>>(
             r#"{
-                "desc": "Level 0",
-                "up": {
-                    "desc": "Level 1",
-                    "up": {
-                        "desc": "Level 2"
-                    }
+                "sender": "@worthy_whale:server.name",
+                "content": {
+                    "labels": ["cats", 7, true],
+                    "label": "cats"
                 }
             }"#,
         )
         .unwrap();
+        let event = FlattenedJson::from_raw(&event_raw);
 
-        let flattened = FlattenedJson::from_raw(&raw);
-        assert_eq!(
-            flattened.map,
-            btreemap! {
-                "desc".into() => "Level 0".into(),
-                "up.desc".into() => "Level 1".into(),
-                "up.up.desc".into() => "Level 2".into(),
-            },
-        );
+        let contains = |key: &str, value: ScalarJsonValue| {
+            PushCondition::EventPropertyContains { key: key.into(), value }
+                .applies(&event, &context)
+        };
+
+        assert!(contains("content.labels", "cats".into()));
+        assert!(contains("content.labels", int!(7).into()));
+        assert!(contains("content.labels", true.into()));
+        assert!(!contains("content.labels", "dogs".into()));
+        assert!(!contains("content.label", "cats".into()));
     }
 }
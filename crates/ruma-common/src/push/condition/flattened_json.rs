@@ -0,0 +1,352 @@
+use std::collections::BTreeMap;
+
+use js_int::Int;
+use serde::{de, Deserialize, Serialize};
+use serde_json::{to_value as to_json_value, value::Value as JsonValue};
+use tracing::{instrument, warn};
+
+use crate::serde::Raw;
+
+/// The flattened representation of a JSON object.
+#[derive(Clone, Debug)]
+pub struct FlattenedJson {
+    /// The internal map containing the flattened JSON as a pair path, value.
+    map: BTreeMap<String, FlattenedJsonValue>,
+
+    /// Whether the content of the event has an `m.mentions` object.
+    #[cfg(feature = "unstable-msc3952")]
+    has_mentions: bool,
+}
+
+impl FlattenedJson {
+    /// Create a `FlattenedJson` from `Raw`.
+    pub fn from_raw<T>(raw: &Raw<T>) -> Self {
+        let value = to_json_value(raw).unwrap();
+
+        let mut s = Self {
+            map: BTreeMap::new(),
+            #[cfg(feature = "unstable-msc3952")]
+            has_mentions: value
+                .get("content")
+                .and_then(|content| content.get("m.mentions"))
+                .map_or(false, JsonValue::is_object),
+        };
+        s.flatten_value(value, "".into());
+        s
+    }
+
+    /// Flatten and insert the `value` at `path`.
+    #[instrument(skip(self, value))]
+    fn flatten_value(&mut self, value: JsonValue, path: String) {
+        match value {
+            JsonValue::Object(fields) => {
+                for (key, value) in fields {
+                    let key = escape_key(key);
+                    let path = if path.is_empty() { key } else { format!("{path}.{key}") };
+                    self.flatten_value(value, path);
+                }
+            }
+            value => {
+                if let Some(value) = FlattenedJsonValue::from_json_value(value) {
+                    if self.map.insert(path.clone(), value).is_some() {
+                        warn!("Duplicate path in flattened JSON: {path}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// String value associated with the given `path`.
+    ///
+    /// Returns `None` if there is no value at this path, or if it is not a string.
+    pub fn get(&self, path: &str) -> Option<&str> {
+        self.map.get(path).and_then(FlattenedJsonValue::as_str)
+    }
+
+    /// Value associated with the given `path`.
+    pub fn get_value(&self, path: &str) -> Option<&FlattenedJsonValue> {
+        self.map.get(path)
+    }
+
+    /// Whether the content of the event has an `m.mentions` object.
+    ///
+    /// The legacy mention rules don't apply to such events.
+    #[cfg(feature = "unstable-msc3952")]
+    pub fn has_mentions(&self) -> bool {
+        self.has_mentions
+    }
+
+    /// Iterate over the paths and their associated string values.
+    pub(in crate::push) fn iter_str(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.map.iter().filter_map(|(path, value)| Some((path.as_str(), value.as_str()?)))
+    }
+}
+
+/// Escape the dots and backslashes in the given key, as defined in [MSC3873].
+///
+/// [MSC3873]: https://github.com/matrix-org/matrix-spec-proposals/pull/3873
+#[cfg(feature = "unstable-msc3873")]
+fn escape_key(key: String) -> String {
+    if key.contains(['.', '\\']) {
+        key.replace('\\', r"\\").replace('.', r"\.")
+    } else {
+        key
+    }
+}
+
+#[cfg(not(feature = "unstable-msc3873"))]
+fn escape_key(key: String) -> String {
+    key
+}
+
+/// A value in a [`FlattenedJson`].
+///
+/// Objects are flattened, and floating-point numbers and integers that are out of the range of
+/// [`Int`] are ignored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::exhaustive_enums)]
+pub enum FlattenedJsonValue {
+    /// A string.
+    String(String),
+
+    /// An integer.
+    Integer(Int),
+
+    /// A boolean.
+    Bool(bool),
+
+    /// `null`.
+    Null,
+
+    /// An array, with only its scalar values.
+    Array(Vec<ScalarJsonValue>),
+}
+
+impl FlattenedJsonValue {
+    /// Convert the given JSON value, unless it is an object.
+    fn from_json_value(value: JsonValue) -> Option<Self> {
+        Some(match value {
+            JsonValue::Array(values) => Self::Array(
+                values.into_iter().filter_map(ScalarJsonValue::from_json_value).collect(),
+            ),
+            JsonValue::Object(_) => return None,
+            value => ScalarJsonValue::from_json_value(value)?.into(),
+        })
+    }
+
+    /// The string of this value, if it is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The values of this array, if it is an array.
+    pub fn as_array(&self) -> Option<&[ScalarJsonValue]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl From<ScalarJsonValue> for FlattenedJsonValue {
+    fn from(value: ScalarJsonValue) -> Self {
+        match value {
+            ScalarJsonValue::String(s) => Self::String(s),
+            ScalarJsonValue::Integer(i) => Self::Integer(i),
+            ScalarJsonValue::Bool(b) => Self::Bool(b),
+            ScalarJsonValue::Null => Self::Null,
+        }
+    }
+}
+
+impl PartialEq<ScalarJsonValue> for FlattenedJsonValue {
+    fn eq(&self, other: &ScalarJsonValue) -> bool {
+        match (self, other) {
+            (Self::String(a), ScalarJsonValue::String(b)) => a == b,
+            (Self::Integer(a), ScalarJsonValue::Integer(b)) => a == b,
+            (Self::Bool(a), ScalarJsonValue::Bool(b)) => a == b,
+            (Self::Null, ScalarJsonValue::Null) => true,
+            _ => false,
+        }
+    }
+}
+
+/// A scalar JSON value, that can be compared to the values of a [`FlattenedJson`].
+///
+/// Floating-point numbers and integers that are out of the range of [`Int`] are not allowed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::exhaustive_enums)]
+pub enum ScalarJsonValue {
+    /// A string.
+    String(String),
+
+    /// An integer.
+    Integer(Int),
+
+    /// A boolean.
+    Bool(bool),
+
+    /// `null`.
+    Null,
+}
+
+impl ScalarJsonValue {
+    /// Convert the given JSON value, if it is a valid scalar value.
+    fn from_json_value(value: JsonValue) -> Option<Self> {
+        match value {
+            JsonValue::String(s) => Some(Self::String(s)),
+            JsonValue::Number(n) => n.as_i64().and_then(Int::new).map(Self::Integer),
+            JsonValue::Bool(b) => Some(Self::Bool(b)),
+            JsonValue::Null => Some(Self::Null),
+            JsonValue::Array(_) | JsonValue::Object(_) => None,
+        }
+    }
+}
+
+impl Serialize for ScalarJsonValue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Self::String(s) => serializer.serialize_str(s),
+            Self::Integer(i) => i.serialize(serializer),
+            Self::Bool(b) => serializer.serialize_bool(*b),
+            Self::Null => serializer.serialize_unit(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ScalarJsonValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let value = JsonValue::deserialize(deserializer)?;
+        Self::from_json_value(value)
+            .ok_or_else(|| de::Error::custom("expected a string, an integer, a boolean or null"))
+    }
+}
+
+impl From<String> for ScalarJsonValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<&str> for ScalarJsonValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_owned())
+    }
+}
+
+impl From<Int> for ScalarJsonValue {
+    fn from(value: Int) -> Self {
+        Self::Integer(value)
+    }
+}
+
+impl From<bool> for ScalarJsonValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use js_int::int;
+    use maplit::btreemap;
+    use serde_json::{
+        from_value as from_json_value, json, to_value as to_json_value, Value as JsonValue,
+    };
+
+    use super::{FlattenedJson, FlattenedJsonValue, ScalarJsonValue};
+    use crate::serde::Raw;
+
+    #[test]
+    fn flattened_json_values() {
+        let raw = serde_json::from_str::<Raw<JsonValue>>(
+            r#"{
+                "string": "Hello World",
+                "number": 10,
+                "float": 1.5,
+                "array": [1, "two", [3], { "four": 4 }],
+                "boolean": true,
+                "null": null
+            }"#,
+        )
+        .unwrap();
+
+        let flattened = FlattenedJson::from_raw(&raw);
+        assert_eq!(
+            flattened.map,
+            btreemap! {
+                "string".into() => FlattenedJsonValue::String("Hello World".into()),
+                "number".into() => FlattenedJsonValue::Integer(int!(10)),
+                "array".into() => FlattenedJsonValue::Array(vec![int!(1).into(), "two".into()]),
+                "boolean".into() => FlattenedJsonValue::Bool(true),
+                "null".into() => FlattenedJsonValue::Null,
+            }
+        );
+        assert_eq!(flattened.get("string"), Some("Hello World"));
+        assert_eq!(flattened.get("number"), None);
+    }
+
+    #[test]
+    fn flattened_json_nested() {
+        let raw = serde_json::from_str::<Raw<JsonValue>>(
+            r#"{
+                "desc": "Level 0",
+                "up": {
+                    "desc": "Level 1",
+                    "up": {
+                        "desc": "Level 2"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let flattened = FlattenedJson::from_raw(&raw);
+        assert_eq!(
+            flattened.map,
+            btreemap! {
+                "desc".into() => FlattenedJsonValue::String("Level 0".into()),
+                "up.desc".into() => FlattenedJsonValue::String("Level 1".into()),
+                "up.up.desc".into() => FlattenedJsonValue::String("Level 2".into()),
+            },
+        );
+    }
+
+    #[cfg(feature = "unstable-msc3873")]
+    #[test]
+    fn flattened_json_escaped_keys() {
+        let raw = serde_json::from_str::<Raw<JsonValue>>(
+            r#"{
+                "m.foo": { "b\\ar": "abc" }
+            }"#,
+        )
+        .unwrap();
+
+        let flattened = FlattenedJson::from_raw(&raw);
+        assert_eq!(flattened.get(r"m\.foo.b\\ar"), Some("abc"));
+    }
+
+    #[test]
+    fn scalar_json_value_serde() {
+        assert_eq!(to_json_value(ScalarJsonValue::from("foo")).unwrap(), json!("foo"));
+        assert_eq!(to_json_value(ScalarJsonValue::from(int!(-5))).unwrap(), json!(-5));
+        assert_eq!(to_json_value(ScalarJsonValue::Null).unwrap(), json!(null));
+
+        assert_eq!(
+            from_json_value::<ScalarJsonValue>(json!(true)).unwrap(),
+            ScalarJsonValue::Bool(true)
+        );
+        assert_eq!(from_json_value::<ScalarJsonValue>(json!(null)).unwrap(), ScalarJsonValue::Null);
+        from_json_value::<ScalarJsonValue>(json!(1.5)).unwrap_err();
+        from_json_value::<ScalarJsonValue>(json!(["foo"])).unwrap_err();
+    }
+}
@@ -2,7 +2,7 @@ use indexmap::set::{IntoIter as IndexSetIntoIter, Iter as IndexSetIter};
 
 use super::{
     condition, Action, ConditionalPushRule, FlattenedJson, PatternedPushRule, PushConditionRoomCtx,
-    Ruleset, SimplePushRule,
+    RuleKind, Ruleset, SimplePushRule,
 };
 
 /// The kinds of push rules that are available.
@@ -52,6 +52,11 @@ impl AnyPushRule {
         self.as_ref().rule_id()
     }
 
+    /// Get the kind of the push rule.
+    pub fn kind(&self) -> RuleKind {
+        self.as_ref().kind()
+    }
+
     /// Check if the push rule applies to the event.
     ///
     /// # Arguments
@@ -178,6 +183,17 @@ impl<'a> AnyPushRuleRef<'a> {
         }
     }
 
+    /// Get the kind of the push rule.
+    pub fn kind(self) -> RuleKind {
+        match self {
+            Self::Override(_) => RuleKind::Override,
+            Self::Content(_) => RuleKind::Content,
+            Self::Room(_) => RuleKind::Room,
+            Self::Sender(_) => RuleKind::Sender,
+            Self::Underride(_) => RuleKind::Underride,
+        }
+    }
+
     /// Check if the push rule applies to the event.
     ///
     /// # Arguments
@@ -189,6 +205,11 @@ impl<'a> AnyPushRuleRef<'a> {
             return false;
         }
 
+        #[cfg(feature = "unstable-msc3952")]
+        if event.has_mentions() && self.is_legacy_mention_rule() {
+            return false;
+        }
+
         match self {
             Self::Override(rule) => rule.applies(event, context),
             Self::Underride(rule) => rule.applies(event, context),
@@ -203,6 +224,16 @@ impl<'a> AnyPushRuleRef<'a> {
             }
         }
     }
+
+    /// Whether this is one of the legacy mention rules, that don't apply to events with an
+    /// `m.mentions` property.
+    #[cfg(feature = "unstable-msc3952")]
+    pub(super) fn is_legacy_mention_rule(self) -> bool {
+        matches!(
+            self.rule_id(),
+            ".m.rule.contains_display_name" | ".m.rule.contains_user_name" | ".m.rule.roomnotif"
+        )
+    }
 }
 
 /// Iterator type for `Ruleset`
@@ -24,7 +24,11 @@ impl Ruleset {
                 ConditionalPushRule::suppress_notices(),
                 ConditionalPushRule::invite_for_me(user_id),
                 ConditionalPushRule::member_event(),
+                #[cfg(feature = "unstable-msc3952")]
+                ConditionalPushRule::is_user_mention(user_id),
                 ConditionalPushRule::contains_display_name(),
+                #[cfg(feature = "unstable-msc3952")]
+                ConditionalPushRule::is_room_mention(),
                 ConditionalPushRule::tombstone(),
                 #[cfg(feature = "unstable-msc3786")]
                 ConditionalPushRule::server_acl(),
@@ -112,6 +116,26 @@ impl ConditionalPushRule {
         }
     }
 
+    /// Matches any message which contains the user's Matrix ID in the list of `user_ids` under
+    /// the `m.mentions` property.
+    #[cfg(feature = "unstable-msc3952")]
+    pub fn is_user_mention(user_id: &UserId) -> Self {
+        Self {
+            actions: vec![
+                Notify,
+                SetTweak(Tweak::Sound("default".into())),
+                SetTweak(Tweak::Highlight(true)),
+            ],
+            default: true,
+            enabled: true,
+            rule_id: ".m.rule.is_user_mention".into(),
+            conditions: vec![EventPropertyContains {
+                key: r"content.m\.mentions.user_ids".into(),
+                value: user_id.as_str().into(),
+            }],
+        }
+    }
+
     /// Matches any message whose content is unencrypted and contains the user's current display
     /// name in the room in which it was sent.
     pub fn contains_display_name() -> Self {
@@ -128,6 +152,22 @@ impl ConditionalPushRule {
         }
     }
 
+    /// Matches any message whose `room` property under the `m.mentions` property is `true`, sent
+    /// by a user with the permission to notify the whole room.
+    #[cfg(feature = "unstable-msc3952")]
+    pub fn is_room_mention() -> Self {
+        Self {
+            actions: vec![Notify, SetTweak(Tweak::Highlight(true))],
+            default: true,
+            enabled: true,
+            rule_id: ".m.rule.is_room_mention".into(),
+            conditions: vec![
+                EventPropertyIs { key: r"content.m\.mentions.room".into(), value: true.into() },
+                SenderNotificationPermission { key: "room".into() },
+            ],
+        }
+    }
+
     /// Matches any state event whose type is `m.room.tombstone`. This
     /// is intended to notify users of a room when it is upgraded,
     /// similar to what an `@room` notification would accomplish.
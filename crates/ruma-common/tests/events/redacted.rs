@@ -28,7 +28,10 @@ fn unsigned() -> RedactedUnsigned {
     unsigned.redacted_because =
         Some(Box::new(SyncRoomRedactionEvent::Original(OriginalSyncRoomRedactionEvent {
             content: RoomRedactionEventContent::with_reason("redacted because".into()),
+            #[cfg(not(feature = "unstable-msc3820"))]
             redacts: event_id!("$h29iv0s8:example.com").to_owned(),
+            #[cfg(feature = "unstable-msc3820")]
+            redacts: Some(event_id!("$h29iv0s8:example.com").to_owned()),
             event_id: event_id!("$h29iv0s8:example.com").to_owned(),
             origin_server_ts: MilliSecondsSinceUnixEpoch(uint!(1)),
             sender: user_id!("@carl:example.com").to_owned(),
@@ -163,7 +166,10 @@ fn redacted_deserialize_any_room_sync() {
     unsigned.redacted_because =
         Some(Box::new(SyncRoomRedactionEvent::Original(OriginalSyncRoomRedactionEvent {
             content: RoomRedactionEventContent::with_reason("redacted because".into()),
+            #[cfg(not(feature = "unstable-msc3820"))]
             redacts: event_id!("$h29iv0s8:example.com").to_owned(),
+            #[cfg(feature = "unstable-msc3820")]
+            redacts: Some(event_id!("$h29iv0s8:example.com").to_owned()),
             event_id: event_id!("$h29iv0s8:example.com").to_owned(),
             origin_server_ts: MilliSecondsSinceUnixEpoch(uint!(1)),
             sender: user_id!("@carl:example.com").to_owned(),
@@ -211,7 +217,10 @@ fn redacted_state_event_deserialize() {
     );
     assert_eq!(redacted.event_id, "$h29iv0s8:example.com");
     assert!(redacted.unsigned.redacted_because.is_some());
+    #[cfg(not(feature = "unstable-msc3820"))]
     assert_eq!(redacted.content.creator, "@carl:example.com");
+    #[cfg(feature = "unstable-msc3820")]
+    assert_eq!(redacted.content.creator.unwrap(), "@carl:example.com");
 }
 
 #[test]
@@ -249,7 +258,10 @@ fn redact_method_properly_redacts() {
 
     let redaction = OriginalSyncRoomRedactionEvent {
         content: RoomRedactionEventContent::with_reason("redacted because".into()),
+        #[cfg(not(feature = "unstable-msc3820"))]
         redacts: event_id!("$143273582443PhrSn:example.com").to_owned(),
+        #[cfg(feature = "unstable-msc3820")]
+        redacts: Some(event_id!("$143273582443PhrSn:example.com").to_owned()),
         event_id: event_id!("$h29iv0s8:example.com").to_owned(),
         origin_server_ts: MilliSecondsSinceUnixEpoch(uint!(1)),
         sender: user_id!("@carl:example.com").to_owned(),
@@ -307,5 +319,80 @@ fn redact_state_content() {
             ..
         } => creator
     );
+    #[cfg(not(feature = "unstable-msc3820"))]
     assert_eq!(creator, "@carl:example.com");
+    #[cfg(feature = "unstable-msc3820")]
+    assert_eq!(creator.unwrap(), "@carl:example.com");
+}
+
+#[cfg(all(feature = "canonical-json", feature = "unstable-msc3820"))]
+#[test]
+fn redact_state_content_like_canonical_json() {
+    use ruma_common::{
+        canonical_json::redact_content_in_place,
+        events::room::{member::RoomMemberEventContent, power_levels::RoomPowerLevelsEventContent},
+        CanonicalJsonObject,
+    };
+    use serde::Serialize;
+    use serde_json::Value as JsonValue;
+
+    fn assert_same_redaction<C>(event_type: &str, content: JsonValue)
+    where
+        C: EventContent + RedactContent,
+        C::Redacted: Serialize,
+    {
+        for version in [RoomVersionId::V10, RoomVersionId::V11] {
+            let raw_json = to_raw_json_value(&content).unwrap();
+            let typed = C::from_parts(event_type, &raw_json).unwrap().redact(&version);
+
+            let mut object: CanonicalJsonObject = from_json_value(content.clone()).unwrap();
+            redact_content_in_place(&mut object, &version, event_type);
+
+            assert_eq!(
+                to_json_value(typed).unwrap(),
+                to_json_value(object).unwrap(),
+                "{event_type} in room version {version}"
+            );
+        }
+    }
+
+    assert_same_redaction::<RoomCreateEventContent>(
+        "m.room.create",
+        json!({
+            "creator": "@carl:example.com",
+            "m.federate": false,
+            "predecessor": {
+                "event_id": "$last:example.com",
+                "room_id": "!old:example.com",
+            },
+            "room_version": "11",
+            "type": "m.space",
+        }),
+    );
+    assert_same_redaction::<RoomPowerLevelsEventContent>(
+        "m.room.power_levels",
+        json!({
+            "events": { "m.room.name": 75 },
+            "invite": 25,
+            "kick": 40,
+            "notifications": { "room": 20 },
+            "users": { "@carl:example.com": 100 },
+        }),
+    );
+    assert_same_redaction::<RoomMemberEventContent>(
+        "m.room.member",
+        json!({
+            "displayname": "Carl",
+            "join_authorised_via_users_server": "@alice:example.com",
+            "membership": "invite",
+            "third_party_invite": {
+                "display_name": "carl",
+                "signed": {
+                    "mxid": "@carl:example.com",
+                    "signatures": { "example.com": { "ed25519:1": "signature" } },
+                    "token": "abc",
+                },
+            },
+        }),
+    );
 }
@@ -1,5 +1,7 @@
 use assert_matches::assert_matches;
 use js_int::uint;
+#[cfg(feature = "unstable-msc3820")]
+use ruma_common::RoomVersionId;
 use ruma_common::{
     event_id,
     events::{
@@ -34,7 +36,10 @@ fn redaction() -> JsonValue {
 fn serialize_redaction() {
     let aliases_event = OriginalRoomRedactionEvent {
         content: RoomRedactionEventContent::with_reason("being a turd".into()),
+        #[cfg(not(feature = "unstable-msc3820"))]
         redacts: event_id!("$nomore:example.com").to_owned(),
+        #[cfg(feature = "unstable-msc3820")]
+        redacts: Some(event_id!("$nomore:example.com").to_owned()),
         event_id: event_id!("$h29iv0s8:example.com").to_owned(),
         origin_server_ts: MilliSecondsSinceUnixEpoch(uint!(1)),
         room_id: room_id!("!roomid:room.com").to_owned(),
@@ -58,9 +63,65 @@ fn deserialize_redaction() {
     );
     assert_eq!(ev.content.reason.as_deref(), Some("being a turd"));
     assert_eq!(ev.event_id, "$h29iv0s8:example.com");
+    #[cfg(not(feature = "unstable-msc3820"))]
     assert_eq!(ev.redacts, "$nomore:example.com");
+    #[cfg(feature = "unstable-msc3820")]
+    assert_eq!(ev.redacts(&RoomVersionId::V1).unwrap(), "$nomore:example.com");
     assert_eq!(ev.origin_server_ts, MilliSecondsSinceUnixEpoch(uint!(1)));
     assert_eq!(ev.room_id, "!roomid:room.com");
     assert_eq!(ev.sender, "@carl:example.com");
     assert!(ev.unsigned.is_empty());
 }
+
+#[cfg(feature = "unstable-msc3820")]
+#[test]
+fn serialize_v11_redaction_content() {
+    let content = RoomRedactionEventContent::new_v11(event_id!("$nomore:example.com").to_owned());
+
+    assert_eq!(to_json_value(&content).unwrap(), json!({ "redacts": "$nomore:example.com" }));
+}
+
+#[cfg(feature = "unstable-msc3820")]
+#[test]
+fn deserialize_v11_redaction() {
+    let json_data = json!({
+        "content": {
+            "redacts": "$nomore:example.com",
+            "reason": "being a turd"
+        },
+        "event_id": "$h29iv0s8:example.com",
+        "sender": "@carl:example.com",
+        "origin_server_ts": 1,
+        "room_id": "!roomid:room.com",
+        "type": "m.room.redaction"
+    });
+
+    let ev = assert_matches!(
+        from_json_value::<AnyMessageLikeEvent>(json_data),
+        Ok(AnyMessageLikeEvent::RoomRedaction(RoomRedactionEvent::Original(ev))) => ev
+    );
+    assert_eq!(ev.redacts, None);
+    assert_eq!(ev.redacts(&RoomVersionId::V11).unwrap(), "$nomore:example.com");
+    assert_eq!(ev.redacts(&RoomVersionId::V10), None);
+}
+
+#[cfg(feature = "unstable-msc3820")]
+#[test]
+fn redacts_custom_room_version() {
+    let custom_version = RoomVersionId::try_from("org.example.custom").unwrap();
+
+    let ev = assert_matches!(
+        from_json_value::<AnyMessageLikeEvent>(redaction()),
+        Ok(AnyMessageLikeEvent::RoomRedaction(RoomRedactionEvent::Original(ev))) => ev
+    );
+    assert_eq!(ev.redacts(&custom_version).unwrap(), "$nomore:example.com");
+
+    let mut json_data = redaction();
+    let redacts = json_data.as_object_mut().unwrap().remove("redacts").unwrap();
+    json_data["content"]["redacts"] = redacts;
+    let ev = assert_matches!(
+        from_json_value::<AnyMessageLikeEvent>(json_data),
+        Ok(AnyMessageLikeEvent::RoomRedaction(RoomRedactionEvent::Original(ev))) => ev
+    );
+    assert_eq!(ev.redacts(&custom_version).unwrap(), "$nomore:example.com");
+}
@@ -579,3 +579,61 @@ fn reply_sanitize() {
         "
     );
 }
+
+#[test]
+#[cfg(all(feature = "unstable-msc3952", not(feature = "unstable-msc1767")))]
+fn mentions_serialization() {
+    use ruma_common::events::Mentions;
+
+    let content = RoomMessageEventContent::text_plain("@alice: Hello!")
+        .set_mentions(Mentions::with_user_ids([user_id!("@alice:example.org").to_owned()]));
+
+    assert_eq!(
+        to_json_value(&content).unwrap(),
+        json!({
+            "body": "@alice: Hello!",
+            "msgtype": "m.text",
+            "m.mentions": {
+                "user_ids": ["@alice:example.org"],
+            },
+        })
+    );
+
+    let content = RoomMessageEventContent::text_plain("@room: Hello!")
+        .set_mentions(Mentions::with_room_mention());
+    assert_eq!(
+        to_json_value(&content).unwrap(),
+        json!({
+            "body": "@room: Hello!",
+            "msgtype": "m.text",
+            "m.mentions": {
+                "room": true,
+            },
+        })
+    );
+}
+
+#[test]
+#[cfg(feature = "unstable-msc3952")]
+fn mentions_deserialization() {
+    let json_data = json!({
+        "body": "@alice: Hello!",
+        "msgtype": "m.text",
+        "m.mentions": {
+            "user_ids": ["@alice:example.org"],
+        },
+    });
+
+    let content = from_json_value::<RoomMessageEventContent>(json_data).unwrap();
+    let mentions = content.mentions.unwrap();
+    assert_eq!(mentions.user_ids.len(), 1);
+    assert!(mentions.user_ids.contains(user_id!("@alice:example.org")));
+    assert!(!mentions.room);
+
+    let content = from_json_value::<RoomMessageEventContent>(json!({
+        "body": "Hello!",
+        "msgtype": "m.text",
+    }))
+    .unwrap();
+    assert!(content.mentions.is_none());
+}
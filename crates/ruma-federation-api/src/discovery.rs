@@ -14,6 +14,8 @@ pub mod get_server_keys;
 pub mod get_server_version;
 #[cfg(feature = "unstable-msc3723")]
 pub mod get_server_versions;
+#[cfg(feature = "signatures")]
+pub mod verification;
 
 /// Public key of the homeserver for verifying digital signatures.
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -0,0 +1,374 @@
+//! Verification of the server keys returned by homeservers and notary servers.
+//!
+//! The keys returned by [`get_server_keys`] must be signed by the homeserver itself. The keys
+//! returned by [`get_remote_server_keys`] and [`get_remote_server_keys_batch`] must additionally be
+//! signed by the notary server that was queried.
+//!
+//! [`get_server_keys`]: super::get_server_keys
+//! [`get_remote_server_keys`]: super::get_remote_server_keys
+//! [`get_remote_server_keys_batch`]: super::get_remote_server_keys_batch
+
+use ruma_common::{
+    serde::Raw, CanonicalJsonObject, MilliSecondsSinceUnixEpoch, OwnedServerName, ServerName,
+};
+use ruma_signatures::{verify_json_signed_by, PublicKeySet};
+use thiserror::Error;
+
+use super::{get_remote_server_keys, get_remote_server_keys_batch, ServerSigningKeys};
+
+/// An error encountered when verifying server keys.
+#[derive(Debug, Error)]
+#[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
+pub enum ServerKeysError {
+    /// The server keys could not be deserialized.
+    #[error("invalid server keys: {0}")]
+    Deserialization(#[source] serde_json::Error),
+
+    /// The server keys are for another server than the one that was queried.
+    #[error("received the keys of {0}, which was not queried")]
+    UnexpectedServer(OwnedServerName),
+
+    /// The server keys are not signed by the server with one of its verify keys.
+    #[error("invalid signature of the server: {0}")]
+    ServerSignature(#[source] ruma_signatures::Error),
+
+    /// The server keys are not signed by the notary server with one of its keys.
+    #[error("invalid signature of the notary server: {0}")]
+    NotarySignature(#[source] ruma_signatures::Error),
+
+    /// The server keys are not valid until the requested time.
+    #[error(
+        "keys are valid until {valid_until_ts:?}, before the minimum of {minimum_valid_until_ts:?}"
+    )]
+    Stale {
+        /// The time until which the keys are valid.
+        valid_until_ts: MilliSecondsSinceUnixEpoch,
+
+        /// The time until which the keys were requested to be valid.
+        minimum_valid_until_ts: MilliSecondsSinceUnixEpoch,
+    },
+}
+
+/// Verify the keys returned by a homeserver for itself.
+///
+/// Checks that the keys are the ones of `server_name`, that they are signed by the server with
+/// one of its `verify_keys`, and that they are valid until at least `minimum_valid_until_ts`.
+pub fn verify_server_keys(
+    server_keys: &Raw<ServerSigningKeys>,
+    server_name: &ServerName,
+    minimum_valid_until_ts: MilliSecondsSinceUnixEpoch,
+) -> Result<ServerSigningKeys, ServerKeysError> {
+    let (keys, object) = deserialize(server_keys)?;
+
+    if keys.server_name != server_name {
+        return Err(ServerKeysError::UnexpectedServer(keys.server_name));
+    }
+
+    verify_server_signature(&keys, &object)?;
+    check_validity(keys, minimum_valid_until_ts)
+}
+
+/// Verify the keys of a homeserver returned by a notary server.
+///
+/// Checks that the keys are signed by the server with one of its `verify_keys`, that they are
+/// signed by the notary server with one of the `notary_keys`, and that they are valid until at
+/// least `minimum_valid_until_ts`.
+///
+/// The name of the server is not checked, use [`verify_server_keys`] to check the keys of a
+/// known server or the methods of the responses of the notary endpoints to check whole responses.
+pub fn verify_notary_server_keys(
+    server_keys: &Raw<ServerSigningKeys>,
+    notary_name: &ServerName,
+    notary_keys: &PublicKeySet,
+    minimum_valid_until_ts: MilliSecondsSinceUnixEpoch,
+) -> Result<ServerSigningKeys, ServerKeysError> {
+    let (keys, object) = deserialize(server_keys)?;
+    verify_notary_signatures(keys, &object, notary_name, notary_keys, minimum_valid_until_ts)
+}
+
+impl get_remote_server_keys::v2::Response {
+    /// Verify the keys in this response, returned by the given notary server for the given
+    /// request.
+    ///
+    /// The keys are checked with [`verify_notary_server_keys`], and must be the keys of the server
+    /// that was queried. The results are returned in the same order as the keys in the response,
+    /// the verified keys can be obtained by filtering out the errors.
+    pub fn verify(
+        &self,
+        request: &get_remote_server_keys::v2::Request<'_>,
+        notary_name: &ServerName,
+        notary_keys: &PublicKeySet,
+    ) -> Vec<Result<ServerSigningKeys, ServerKeysError>> {
+        self.server_keys
+            .iter()
+            .map(|server_keys| {
+                let keys = verify_notary_server_keys(
+                    server_keys,
+                    notary_name,
+                    notary_keys,
+                    request.minimum_valid_until_ts,
+                )?;
+
+                if keys.server_name != request.server_name {
+                    return Err(ServerKeysError::UnexpectedServer(keys.server_name));
+                }
+
+                Ok(keys)
+            })
+            .collect()
+    }
+}
+
+impl get_remote_server_keys_batch::v2::Response {
+    /// Verify the keys in this response, returned by the given notary server for the given
+    /// request.
+    ///
+    /// The keys are checked with [`verify_notary_server_keys`], and must be the keys of one of the
+    /// servers that were queried. The `minimum_valid_until_ts` of a server is the latest of the
+    /// ones in its query criteria, or `now` if none is set.
+    ///
+    /// The results are returned in the same order as the keys in the response, the verified keys
+    /// can be obtained by filtering out the errors.
+    pub fn verify(
+        &self,
+        request: &get_remote_server_keys_batch::v2::Request,
+        notary_name: &ServerName,
+        notary_keys: &PublicKeySet,
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Vec<Result<ServerSigningKeys, ServerKeysError>> {
+        self.server_keys
+            .iter()
+            .map(|server_keys| {
+                let (keys, object) = deserialize(server_keys)?;
+                let criteria = request
+                    .server_keys
+                    .get(&keys.server_name)
+                    .ok_or_else(|| ServerKeysError::UnexpectedServer(keys.server_name.clone()))?;
+                let minimum_valid_until_ts = criteria
+                    .values()
+                    .filter_map(|criteria| criteria.minimum_valid_until_ts)
+                    .max()
+                    .unwrap_or(now);
+
+                verify_notary_signatures(
+                    keys,
+                    &object,
+                    notary_name,
+                    notary_keys,
+                    minimum_valid_until_ts,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Deserialize the server keys, both as the typed struct and as canonical JSON.
+fn deserialize(
+    server_keys: &Raw<ServerSigningKeys>,
+) -> Result<(ServerSigningKeys, CanonicalJsonObject), ServerKeysError> {
+    let keys = server_keys.deserialize().map_err(ServerKeysError::Deserialization)?;
+    let object = server_keys.deserialize_as().map_err(ServerKeysError::Deserialization)?;
+
+    Ok((keys, object))
+}
+
+/// Verify that the server keys are signed by the server and the notary server, and that they are
+/// not stale.
+fn verify_notary_signatures(
+    keys: ServerSigningKeys,
+    object: &CanonicalJsonObject,
+    notary_name: &ServerName,
+    notary_keys: &PublicKeySet,
+    minimum_valid_until_ts: MilliSecondsSinceUnixEpoch,
+) -> Result<ServerSigningKeys, ServerKeysError> {
+    verify_server_signature(&keys, object)?;
+    verify_json_signed_by(notary_name, notary_keys, object)
+        .map_err(ServerKeysError::NotarySignature)?;
+    check_validity(keys, minimum_valid_until_ts)
+}
+
+/// Verify that the server keys are signed by the server with one of its verify keys.
+fn verify_server_signature(
+    keys: &ServerSigningKeys,
+    object: &CanonicalJsonObject,
+) -> Result<(), ServerKeysError> {
+    let verify_keys = keys
+        .verify_keys
+        .iter()
+        .map(|(key_id, verify_key)| (key_id.to_string(), verify_key.key.clone()))
+        .collect();
+
+    verify_json_signed_by(&keys.server_name, &verify_keys, object)
+        .map_err(ServerKeysError::ServerSignature)
+}
+
+/// Check that the keys are valid until at least the given time.
+fn check_validity(
+    keys: ServerSigningKeys,
+    minimum_valid_until_ts: MilliSecondsSinceUnixEpoch,
+) -> Result<ServerSigningKeys, ServerKeysError> {
+    if keys.valid_until_ts < minimum_valid_until_ts {
+        return Err(ServerKeysError::Stale {
+            valid_until_ts: keys.valid_until_ts,
+            minimum_valid_until_ts,
+        });
+    }
+
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use assert_matches::assert_matches;
+    use ruma_common::{
+        serde::{Base64, Raw},
+        server_name, server_signing_key_id, CanonicalJsonObject, MilliSecondsSinceUnixEpoch,
+    };
+    use ruma_signatures::{sign_json, Ed25519KeyPair, PublicKeySet};
+    use serde_json::{
+        from_value as from_json_value, json, value::to_raw_value as to_raw_json_value,
+    };
+
+    use super::{verify_notary_server_keys, verify_server_keys, ServerKeysError};
+    use crate::discovery::{get_remote_server_keys_batch, ServerSigningKeys};
+
+    fn generate_key_pair() -> Ed25519KeyPair {
+        Ed25519KeyPair::from_der(&Ed25519KeyPair::generate().unwrap(), "1".to_owned()).unwrap()
+    }
+
+    fn public_keys(key_pair: &Ed25519KeyPair) -> PublicKeySet {
+        [("ed25519:1".to_owned(), Base64::new(key_pair.public_key().to_owned()))].into()
+    }
+
+    fn ts(ms: u32) -> MilliSecondsSinceUnixEpoch {
+        MilliSecondsSinceUnixEpoch(ms.into())
+    }
+
+    fn server_keys(server_name: &str, key_pair: &Ed25519KeyPair) -> CanonicalJsonObject {
+        let key: Base64 = Base64::new(key_pair.public_key().to_owned());
+        let mut server_keys: CanonicalJsonObject = from_json_value(json!({
+            "server_name": server_name,
+            "verify_keys": {
+                "ed25519:1": { "key": key.encode() },
+            },
+            "old_verify_keys": {},
+            "valid_until_ts": 2000,
+        }))
+        .unwrap();
+
+        sign_json(server_name, key_pair, &mut server_keys).unwrap();
+        server_keys
+    }
+
+    fn to_raw(object: &CanonicalJsonObject) -> Raw<ServerSigningKeys> {
+        Raw::from_json(to_raw_json_value(object).unwrap())
+    }
+
+    #[test]
+    fn verify_self_signed_keys() {
+        let key_pair = generate_key_pair();
+        let keys = to_raw(&server_keys("origin", &key_pair));
+
+        let verified = verify_server_keys(&keys, server_name!("origin"), ts(1000)).unwrap();
+        assert_eq!(verified.server_name, "origin");
+
+        assert_matches!(
+            verify_server_keys(&keys, server_name!("other"), ts(1000)),
+            Err(ServerKeysError::UnexpectedServer(server_name)) if server_name == "origin"
+        );
+        assert_matches!(
+            verify_server_keys(&keys, server_name!("origin"), ts(3000)),
+            Err(ServerKeysError::Stale { .. })
+        );
+
+        let mut keys = server_keys("origin", &key_pair);
+        keys.remove("signatures");
+        sign_json("origin", &generate_key_pair(), &mut keys).unwrap();
+        assert_matches!(
+            verify_server_keys(&to_raw(&keys), server_name!("origin"), ts(1000)),
+            Err(ServerKeysError::ServerSignature(_))
+        );
+    }
+
+    #[test]
+    fn verify_notary_signed_keys() {
+        let key_pair = generate_key_pair();
+        let notary_key_pair = generate_key_pair();
+
+        let keys = server_keys("origin", &key_pair);
+        assert_matches!(
+            verify_notary_server_keys(
+                &to_raw(&keys),
+                server_name!("notary"),
+                &public_keys(&notary_key_pair),
+                ts(1000),
+            ),
+            Err(ServerKeysError::NotarySignature(_))
+        );
+
+        let mut keys = keys;
+        sign_json("notary", &notary_key_pair, &mut keys).unwrap();
+        let verified = verify_notary_server_keys(
+            &to_raw(&keys),
+            server_name!("notary"),
+            &public_keys(&notary_key_pair),
+            ts(1000),
+        )
+        .unwrap();
+        assert_eq!(verified.server_name, "origin");
+
+        assert_matches!(
+            verify_notary_server_keys(
+                &to_raw(&keys),
+                server_name!("notary"),
+                &public_keys(&key_pair),
+                ts(1000),
+            ),
+            Err(ServerKeysError::NotarySignature(_))
+        );
+    }
+
+    #[test]
+    fn verify_batch_response() {
+        let notary_key_pair = generate_key_pair();
+        let signed_by_notary = |server_name| {
+            let mut keys = server_keys(server_name, &generate_key_pair());
+            sign_json("notary", &notary_key_pair, &mut keys).unwrap();
+            to_raw(&keys)
+        };
+
+        let mut criteria = get_remote_server_keys_batch::v2::QueryCriteria::new();
+        criteria.minimum_valid_until_ts = Some(ts(3000));
+        let request = get_remote_server_keys_batch::v2::Request::new(BTreeMap::from([
+            (
+                server_name!("origin").to_owned(),
+                BTreeMap::from([(
+                    server_signing_key_id!("ed25519:1").to_owned(),
+                    Default::default(),
+                )]),
+            ),
+            (
+                server_name!("stale").to_owned(),
+                BTreeMap::from([(server_signing_key_id!("ed25519:1").to_owned(), criteria)]),
+            ),
+        ]));
+        let response = get_remote_server_keys_batch::v2::Response::new(vec![
+            signed_by_notary("origin"),
+            signed_by_notary("stale"),
+            signed_by_notary("unknown"),
+        ]);
+
+        let results = response.verify(
+            &request,
+            server_name!("notary"),
+            &public_keys(&notary_key_pair),
+            ts(1000),
+        );
+        assert_eq!(results.len(), 3);
+        assert_matches!(&results[0], Ok(keys) if keys.server_name == "origin");
+        assert_matches!(&results[1], Err(ServerKeysError::Stale { .. }));
+        assert_matches!(&results[2], Err(ServerKeysError::UnexpectedServer(_)));
+    }
+}
@@ -0,0 +1,318 @@
+//! Enforcement of the server ACLs of rooms on federation requests ([spec]).
+//!
+//! [spec]: https://spec.matrix.org/v1.4/server-server-api/#server-access-control-lists-acls
+
+use std::collections::BTreeMap;
+
+use ruma_common::{
+    events::room::server_acl::{CompiledRoomServerAcl, RoomServerAclEventContent},
+    serde::Raw,
+    OwnedRoomId, RoomId, ServerName,
+};
+use serde::Deserialize;
+use serde_json::value::RawValue as RawJsonValue;
+
+use crate::{
+    authorization::get_event_authorization,
+    backfill::get_backfill,
+    event::{get_missing_events, get_room_state, get_room_state_ids},
+    knock::{create_knock_event_template, send_knock},
+    membership::{
+        create_invite, create_join_event, create_leave_event, prepare_join_event,
+        prepare_leave_event,
+    },
+    transactions::{
+        edu::{Edu, ReceiptContent},
+        send_transaction_message,
+    },
+};
+
+/// A federation request that is subject to the server ACL of a room.
+///
+/// The server ACL of the room must be checked against the origin of the request before it is
+/// processed, and the request must be rejected with an `M_FORBIDDEN` error if the origin is not
+/// allowed.
+///
+/// Requests that don't implement this trait are not subject to server ACLs, except for
+/// transactions whose PDUs and EDUs are checked individually, see
+/// [`ServerAcls::filter_transaction()`].
+pub trait ServerAclRequest {
+    /// The ID of the room whose server ACL applies to this request.
+    fn acl_room_id(&self) -> &RoomId;
+}
+
+macro_rules! impl_server_acl_request {
+    ($($endpoint:ident::$version:ident),* $(,)?) => {
+        $(
+            impl ServerAclRequest for $endpoint::$version::Request<'_> {
+                fn acl_room_id(&self) -> &RoomId {
+                    self.room_id
+                }
+            }
+
+            impl ServerAclRequest for $endpoint::$version::IncomingRequest {
+                fn acl_room_id(&self) -> &RoomId {
+                    &self.room_id
+                }
+            }
+        )*
+    };
+}
+
+impl_server_acl_request!(
+    prepare_join_event::v1,
+    create_join_event::v1,
+    create_join_event::v2,
+    prepare_leave_event::v1,
+    create_leave_event::v1,
+    create_leave_event::v2,
+    create_invite::v1,
+    create_invite::v2,
+    create_knock_event_template::v1,
+    send_knock::v1,
+    get_backfill::v1,
+    get_missing_events::v1,
+    get_room_state::v1,
+    get_room_state_ids::v1,
+    get_event_authorization::v1,
+);
+
+/// The current server ACLs of rooms.
+///
+/// Rooms without a server ACL allow all servers.
+#[derive(Clone, Debug, Default)]
+pub struct ServerAcls {
+    rooms: BTreeMap<OwnedRoomId, CompiledRoomServerAcl>,
+}
+
+impl ServerAcls {
+    /// Creates an empty `ServerAcls`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the current server ACL of the given room.
+    pub fn set_room_acl(&mut self, room_id: OwnedRoomId, content: &RoomServerAclEventContent) {
+        self.rooms.insert(room_id, content.compile());
+    }
+
+    /// Remove the server ACL of the given room.
+    pub fn remove_room_acl(&mut self, room_id: &RoomId) {
+        self.rooms.remove(room_id);
+    }
+
+    /// Whether the given server is allowed in the given room.
+    pub fn is_allowed(&self, room_id: &RoomId, server_name: &ServerName) -> bool {
+        self.rooms.get(room_id).map_or(true, |acl| acl.is_allowed(server_name))
+    }
+
+    /// Whether the given request from the given origin is allowed by the server ACL of its room.
+    pub fn is_request_allowed<R: ServerAclRequest>(
+        &self,
+        request: &R,
+        origin: &ServerName,
+    ) -> bool {
+        self.is_allowed(request.acl_room_id(), origin)
+    }
+
+    /// Whether the given PDU from the given origin is allowed by the server ACL of its room.
+    ///
+    /// PDUs without a valid `room_id` are allowed, they must be rejected by the validation of the
+    /// PDU.
+    pub fn is_pdu_allowed(&self, pdu: &RawJsonValue, origin: &ServerName) -> bool {
+        #[derive(Deserialize)]
+        struct ExtractRoomId {
+            room_id: OwnedRoomId,
+        }
+
+        serde_json::from_str::<ExtractRoomId>(pdu.get())
+            .map_or(true, |pdu| self.is_allowed(&pdu.room_id, origin))
+    }
+
+    /// Whether the given EDU from the given origin is allowed by the server ACLs of its rooms.
+    ///
+    /// EDUs that are not sent in a room are always allowed. Receipt EDUs are allowed if the origin
+    /// is allowed in at least one of their rooms, the receipts of the other rooms must be removed
+    /// with [`ServerAcls::filter_receipts()`].
+    pub fn is_edu_allowed(&self, edu: &Edu, origin: &ServerName) -> bool {
+        match edu {
+            Edu::Typing(typing) => self.is_allowed(&typing.room_id, origin),
+            Edu::Receipt(receipt) => {
+                receipt.receipts.keys().any(|room_id| self.is_allowed(room_id, origin))
+            }
+            _ => true,
+        }
+    }
+
+    /// Remove the receipts of the rooms where the given origin is not allowed from the given
+    /// receipt EDU content.
+    ///
+    /// Returns whether receipts were removed.
+    pub fn filter_receipts(&self, receipt: &mut ReceiptContent, origin: &ServerName) -> bool {
+        let len = receipt.receipts.len();
+        receipt.receipts.retain(|room_id, _| self.is_allowed(room_id, origin));
+        receipt.receipts.len() != len
+    }
+
+    /// Remove the PDUs and EDUs that are not allowed by the server ACLs of their rooms from the
+    /// given transaction.
+    ///
+    /// The origin of the transaction must have been checked against the origin of the request.
+    /// EDUs that fail to deserialize are kept. Receipt EDUs only keep the receipts of the rooms
+    /// where the origin is allowed.
+    ///
+    /// Returns the removed PDUs.
+    pub fn filter_transaction(
+        &self,
+        transaction: &mut send_transaction_message::v1::IncomingRequest,
+    ) -> Vec<Box<RawJsonValue>> {
+        let origin = &transaction.origin;
+
+        let (allowed, denied) = std::mem::take(&mut transaction.pdus)
+            .into_iter()
+            .partition(|pdu| self.is_pdu_allowed(pdu, origin));
+        transaction.pdus = allowed;
+
+        transaction.edus = std::mem::take(&mut transaction.edus)
+            .into_iter()
+            .filter_map(|raw_edu| {
+                let mut edu = match raw_edu.deserialize() {
+                    Ok(edu) => edu,
+                    Err(_) => return Some(raw_edu),
+                };
+
+                if !self.is_edu_allowed(&edu, origin) {
+                    return None;
+                }
+
+                if let Edu::Receipt(receipt) = &mut edu {
+                    if self.filter_receipts(receipt, origin) {
+                        return Some(Raw::new(&edu).expect("EDU serialization to succeed"));
+                    }
+                }
+
+                Some(raw_edu)
+            })
+            .collect();
+
+        denied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma_common::{
+        events::room::server_acl::RoomServerAclEventContent, room_id, serde::Raw, server_name,
+        MilliSecondsSinceUnixEpoch,
+    };
+    use serde_json::{json, value::to_raw_value as to_raw_json_value};
+
+    use super::{ServerAclRequest, ServerAcls};
+    use crate::{
+        backfill::get_backfill,
+        transactions::{edu::Edu, send_transaction_message},
+    };
+
+    fn acls() -> ServerAcls {
+        let mut acls = ServerAcls::new();
+        acls.set_room_acl(
+            room_id!("!acl:example.org").to_owned(),
+            &RoomServerAclEventContent::new(
+                false,
+                vec!["*".to_owned()],
+                vec!["evil.example.org".to_owned()],
+            ),
+        );
+        acls
+    }
+
+    #[test]
+    fn request() {
+        let acls = acls();
+        let room_id = room_id!("!acl:example.org");
+        let request = get_backfill::v1::Request::new(room_id, &[], 10_u32.into());
+
+        assert_eq!(request.acl_room_id(), room_id);
+        assert!(acls.is_request_allowed(&request, server_name!("good.example.org")));
+        assert!(!acls.is_request_allowed(&request, server_name!("evil.example.org")));
+        assert!(!acls.is_request_allowed(&request, server_name!("1.1.1.1")));
+
+        let request =
+            get_backfill::v1::Request::new(room_id!("!open:example.org"), &[], 10_u32.into());
+        assert!(acls.is_request_allowed(&request, server_name!("evil.example.org")));
+    }
+
+    #[test]
+    fn transaction() {
+        let acls = acls();
+        let edu = |room_id: &str| {
+            Raw::<Edu>::from_json(
+                to_raw_json_value(&json!({
+                    "edu_type": "m.typing",
+                    "content": {
+                        "room_id": room_id,
+                        "user_id": "@alice:evil.example.org",
+                        "typing": true,
+                    },
+                }))
+                .unwrap(),
+            )
+        };
+
+        let receipt = |room_ids: &[&str]| {
+            let receipts: serde_json::Map<_, _> = room_ids
+                .iter()
+                .map(|room_id| {
+                    let receipt = json!({
+                        "m.read": {
+                            "@alice:evil.example.org": {
+                                "data": { "ts": 1 },
+                                "event_ids": ["$event:evil.example.org"],
+                            },
+                        },
+                    });
+                    ((*room_id).to_owned(), receipt)
+                })
+                .collect();
+
+            Raw::<Edu>::from_json(
+                to_raw_json_value(&json!({ "edu_type": "m.receipt", "content": receipts }))
+                    .unwrap(),
+            )
+        };
+
+        let mut transaction = send_transaction_message::v1::IncomingRequest {
+            transaction_id: "txn".into(),
+            origin: server_name!("evil.example.org").to_owned(),
+            origin_server_ts: MilliSecondsSinceUnixEpoch::now(),
+            pdus: vec![
+                to_raw_json_value(&json!({ "room_id": "!acl:example.org" })).unwrap(),
+                to_raw_json_value(&json!({ "room_id": "!open:example.org" })).unwrap(),
+                to_raw_json_value(&json!({})).unwrap(),
+            ],
+            edus: vec![
+                edu("!acl:example.org"),
+                edu("!open:example.org"),
+                receipt(&["!acl:example.org"]),
+                receipt(&["!acl:example.org", "!open:example.org"]),
+            ],
+        };
+
+        let denied = acls.filter_transaction(&mut transaction);
+
+        assert_eq!(denied.len(), 1);
+        assert_eq!(denied[0].get(), r#"{"room_id":"!acl:example.org"}"#);
+        assert_eq!(transaction.pdus.len(), 2);
+        assert_eq!(transaction.edus.len(), 2);
+        match transaction.edus[0].deserialize().unwrap() {
+            Edu::Typing(typing) => assert_eq!(typing.room_id, "!open:example.org"),
+            _ => panic!("unexpected EDU type"),
+        }
+        match transaction.edus[1].deserialize().unwrap() {
+            Edu::Receipt(receipt) => {
+                assert_eq!(receipt.receipts.keys().collect::<Vec<_>>(), ["!open:example.org"]);
+            }
+            _ => panic!("unexpected EDU type"),
+        }
+    }
+}
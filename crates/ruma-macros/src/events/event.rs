@@ -92,19 +92,16 @@ fn expand_serialize_event(
                 }
             } else {
                 let name_s = name.to_string();
-                match &field.ty {
-                    syn::Type::Path(syn::TypePath { path: syn::Path { segments, .. }, .. })
-                        if segments.last().unwrap().ident == "Option" =>
-                    {
-                        quote! {
-                            if let Some(content) = self.#name.as_ref() {
-                                state.serialize_field(#name_s, content)?;
-                            }
+                if is_option(&field.ty) {
+                    quote! {
+                        if let Some(content) = self.#name.as_ref() {
+                            state.serialize_field(#name_s, content)?;
                         }
                     }
-                    _ => quote! {
+                } else {
+                    quote! {
                         state.serialize_field(#name_s, &self.#name)?;
-                    },
+                    }
                 }
             }
         })
@@ -247,6 +244,10 @@ fn expand_deserialize_event(
                         #serde::de::IntoDeserializer::<A::Error>::into_deserializer(state_key),
                     )?;
                 }
+            } else if is_option(&field.ty) {
+                quote! {
+                    let #name = #name.flatten();
+                }
             } else {
                 quote! {
                     let #name = #name.ok_or_else(|| {
@@ -502,3 +503,12 @@ fn expand_eq_ord_event(input: &DeriveInput) -> TokenStream {
         }
     }
 }
+
+/// Whether the given type is an `Option`.
+fn is_option(ty: &syn::Type) -> bool {
+    matches!(
+        ty,
+        syn::Type::Path(syn::TypePath { path: syn::Path { segments, .. }, .. })
+            if segments.last().unwrap().ident == "Option"
+    )
+}
@@ -1,8 +1,15 @@
 //! Common types for implementing federation authorization.
 
 use headers::{authorization::Credentials, HeaderValue};
-
-use ruma_common::{OwnedServerName, OwnedServerSigningKeyId};
+use http::header::AUTHORIZATION;
+use ruma_common::{
+    CanonicalJsonObject, CanonicalJsonValue, OwnedServerName, OwnedServerSigningKeyId, ServerName,
+};
+use ruma_signatures::{
+    federation_request_json, sign_federation_request, verify_json, KeyPair, PublicKeyMap,
+};
+use serde_json::from_slice as from_json_slice;
+use thiserror::Error;
 use tracing::debug;
 use yap::{IntoTokens, TokenLocation, Tokens};
 
@@ -134,10 +141,22 @@ fn parse_xmatrix_field<'a>(tokens: &mut impl Tokens<Item = &'a u8>) -> Option<(S
     })
 }
 
+/// Whether the given `Authorization` header value uses the `X-Matrix` scheme.
+///
+/// The scheme is compared case-insensitively, like all HTTP authentication schemes.
+fn has_xmatrix_scheme(value: &[u8]) -> bool {
+    value.get(..XMatrix::SCHEME.len()).map_or(false, |scheme| {
+        scheme.eq_ignore_ascii_case(XMatrix::SCHEME.as_bytes())
+            && value.get(XMatrix::SCHEME.len()).map_or(true, |c| *c == b' ')
+    })
+}
+
+/// Parse the parameters of an `Authorization` header value with the `X-Matrix` scheme, after the
+/// scheme.
 fn parse_xmatrix<'a>(tokens: &mut impl Tokens<Item = &'a u8>) -> Option<XMatrix> {
     tokens.optional(|t| {
-        if !t.tokens(b"X-Matrix ".into_tokens()) {
-            debug!("Failed to parse X-Matrix credentials, didn't start with 'X-Matrix '");
+        if !t.token(&b' ') {
+            debug!("Failed to parse X-Matrix credentials, no space after the scheme");
             return None;
         }
         let mut origin = None;
@@ -220,8 +239,14 @@ impl Credentials for XMatrix {
     const SCHEME: &'static str = "X-Matrix";
 
     fn decode(value: &HeaderValue) -> Option<Self> {
-        let value: Vec<u8> = value.as_bytes().to_vec();
-        parse_xmatrix(&mut value.into_tokens())
+        let value = value.as_bytes();
+        if !has_xmatrix_scheme(value) {
+            debug!("Failed to parse X-Matrix credentials, didn't start with 'X-Matrix '");
+            return None;
+        }
+
+        let params: Vec<u8> = value[Self::SCHEME.len()..].to_vec();
+        parse_xmatrix(&mut params.into_tokens())
     }
 
     fn encode(&self) -> HeaderValue {
@@ -238,12 +263,147 @@ impl Credentials for XMatrix {
     }
 }
 
+/// An error encountered when signing or verifying a federation request with the `X-Matrix` scheme.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum XMatrixError {
+    /// The body of the request is not a JSON object.
+    #[error("the body of the request is not a JSON object: {0}")]
+    InvalidBody(#[source] serde_json::Error),
+
+    /// The request doesn't have an `Authorization` header with the `X-Matrix` scheme.
+    #[error("missing X-Matrix Authorization header")]
+    MissingAuthorization,
+
+    /// An `Authorization` header with the `X-Matrix` scheme could not be parsed.
+    #[error("invalid X-Matrix Authorization header")]
+    InvalidAuthorization,
+
+    /// The `Authorization` headers of the request have different origins.
+    #[error("X-Matrix Authorization headers have different origins")]
+    MultipleOrigins,
+
+    /// The request is meant for another server.
+    #[error("the request is meant for {0}")]
+    WrongDestination(OwnedServerName),
+
+    /// The signature of the request could not be created or verified.
+    #[error("invalid signature: {0}")]
+    Signature(#[from] ruma_signatures::Error),
+}
+
+/// Sign an outgoing federation request with the `X-Matrix` scheme.
+///
+/// This adds an `Authorization` header to `request`, containing the signature of the request by
+/// the server `origin` with `key_pair`. The body of the request, if not empty, must be a JSON
+/// object, like the ones produced by
+/// [`OutgoingRequest::try_into_http_request`](ruma_common::api::OutgoingRequest::try_into_http_request).
+///
+/// The signature covers the method, the path and query of the URI, the origin, the destination
+/// and the content of the request, as defined in the [Matrix Server-Server API][spec].
+///
+/// [spec]: https://spec.matrix.org/v1.4/server-server-api/#request-authentication
+pub fn sign_request<T: AsRef<[u8]>, K: KeyPair>(
+    request: &mut http::Request<T>,
+    origin: &ServerName,
+    destination: &ServerName,
+    key_pair: &K,
+) -> Result<(), XMatrixError> {
+    let authorization = sign_federation_request(
+        key_pair,
+        request.method().as_str(),
+        request_uri(request),
+        origin,
+        destination,
+        request_content(request)?,
+    )?;
+    request.headers_mut().append(
+        AUTHORIZATION,
+        authorization.try_into().expect("X-Matrix header values are valid header values"),
+    );
+
+    Ok(())
+}
+
+/// Verify an incoming federation request signed with the `X-Matrix` scheme.
+///
+/// `destination` is the name of the receiving server, and `public_key_map` must contain the public
+/// keys of the origin server. Returns the name of the origin server if all the `Authorization`
+/// headers of the request with the `X-Matrix` scheme have the same origin and a valid signature.
+pub fn verify_request<T: AsRef<[u8]>>(
+    request: &http::Request<T>,
+    destination: &ServerName,
+    public_key_map: &PublicKeyMap,
+) -> Result<OwnedServerName, XMatrixError> {
+    let mut origin: Option<OwnedServerName> = None;
+
+    for header in request.headers().get_all(AUTHORIZATION) {
+        if !has_xmatrix_scheme(header.as_bytes()) {
+            continue;
+        }
+
+        let credentials = XMatrix::decode(header).ok_or(XMatrixError::InvalidAuthorization)?;
+
+        match &origin {
+            Some(origin) if *origin != credentials.origin => {
+                return Err(XMatrixError::MultipleOrigins);
+            }
+            _ => {}
+        }
+
+        if let Some(header_destination) = credentials.destination {
+            if header_destination != destination {
+                return Err(XMatrixError::WrongDestination(header_destination));
+            }
+        }
+
+        let mut object = federation_request_json(
+            request.method().as_str(),
+            request_uri(request),
+            &credentials.origin,
+            destination,
+            request_content(request)?,
+        );
+        let signatures = CanonicalJsonValue::Object(
+            [(credentials.key.to_string(), CanonicalJsonValue::String(credentials.sig))].into(),
+        );
+        object.insert(
+            "signatures".to_owned(),
+            CanonicalJsonValue::Object([(credentials.origin.to_string(), signatures)].into()),
+        );
+        verify_json(public_key_map, &object)?;
+
+        origin = Some(credentials.origin);
+    }
+
+    origin.ok_or(XMatrixError::MissingAuthorization)
+}
+
+/// The path and query of the URI of the request.
+fn request_uri<T>(request: &http::Request<T>) -> &str {
+    request.uri().path_and_query().map_or("/", |path_and_query| path_and_query.as_str())
+}
+
+/// The content of the request, if its body is not empty.
+fn request_content<T: AsRef<[u8]>>(
+    request: &http::Request<T>,
+) -> Result<Option<CanonicalJsonObject>, XMatrixError> {
+    let body = request.body().as_ref();
+    if body.is_empty() {
+        return Ok(None);
+    }
+
+    from_json_slice(body).map(Some).map_err(XMatrixError::InvalidBody)
+}
+
 #[cfg(test)]
 mod tests {
     use headers::{authorization::Credentials, HeaderValue};
-    use ruma_common::OwnedServerName;
+    use http::header::AUTHORIZATION;
+    use ruma_common::{serde::Base64, server_name, OwnedServerName};
+    use ruma_signatures::{Ed25519KeyPair, PublicKeyMap};
 
-    use super::XMatrix;
+    use super::{sign_request, verify_request, XMatrix, XMatrixError};
 
     #[test]
     fn xmatrix_auth_pre_1_3() {
@@ -281,4 +441,99 @@ mod tests {
 
         assert_eq!(credentials.encode(), header);
     }
+
+    #[test]
+    fn xmatrix_auth_case_insensitive_scheme() {
+        let header = HeaderValue::from_static(
+            "x-matrix origin=\"origin.hs.example.com\",key=\"ed25519:key1\",sig=\"ABCDEF...\"",
+        );
+        let credentials: XMatrix = Credentials::decode(&header).unwrap();
+        assert_eq!(credentials.origin, server_name!("origin.hs.example.com"));
+
+        let header = HeaderValue::from_static(
+            "X-Matrixorigin=\"origin.hs.example.com\",key=\"ed25519:key1\",sig=\"ABCDEF...\"",
+        );
+        assert!(<XMatrix as Credentials>::decode(&header).is_none());
+    }
+
+    fn key_pair() -> Ed25519KeyPair {
+        Ed25519KeyPair::from_der(&Ed25519KeyPair::generate().unwrap(), "1".to_owned()).unwrap()
+    }
+
+    fn public_key_map(key_pair: &Ed25519KeyPair) -> PublicKeyMap {
+        [(
+            "origin.hs.example.com".to_owned(),
+            [("ed25519:1".to_owned(), Base64::new(key_pair.public_key().to_owned()))].into(),
+        )]
+        .into()
+    }
+
+    fn request(body: &[u8]) -> http::Request<Vec<u8>> {
+        http::Request::put(
+            "https://destination.hs.example.com/_matrix/federation/v1/send/1?param=value",
+        )
+        .body(body.to_owned())
+        .unwrap()
+    }
+
+    #[test]
+    fn sign_and_verify_request() {
+        let key_pair = key_pair();
+        let origin = server_name!("origin.hs.example.com");
+        let destination = server_name!("destination.hs.example.com");
+
+        let mut request = request(br#"{"pdus":[],"edus":[]}"#);
+        sign_request(&mut request, origin, destination, &key_pair).unwrap();
+        assert!(request.headers()[AUTHORIZATION]
+            .to_str()
+            .unwrap()
+            .starts_with("X-Matrix origin=\"origin.hs.example.com\""));
+
+        let verified = verify_request(&request, destination, &public_key_map(&key_pair)).unwrap();
+        assert_eq!(verified, origin);
+
+        // The scheme is case-insensitive.
+        let mut lowercase = self::request(br#"{"pdus":[],"edus":[]}"#);
+        let header =
+            request.headers()[AUTHORIZATION].to_str().unwrap().replacen("X-Matrix", "x-matrix", 1);
+        lowercase.headers_mut().insert(AUTHORIZATION, header.try_into().unwrap());
+        let verified = verify_request(&lowercase, destination, &public_key_map(&key_pair)).unwrap();
+        assert_eq!(verified, origin);
+
+        // The signature doesn't match another destination.
+        assert!(matches!(
+            verify_request(
+                &request,
+                server_name!("other.hs.example.com"),
+                &public_key_map(&key_pair)
+            ),
+            Err(XMatrixError::WrongDestination(_))
+        ));
+
+        // The signature doesn't match another body.
+        let mut tampered = self::request(br#"{"pdus":[],"edus":[{}]}"#);
+        *tampered.headers_mut() = request.headers().clone();
+        assert!(matches!(
+            verify_request(&tampered, destination, &public_key_map(&key_pair)),
+            Err(XMatrixError::Signature(_))
+        ));
+    }
+
+    #[test]
+    fn sign_and_verify_request_without_body() {
+        let key_pair = key_pair();
+        let origin = server_name!("origin.hs.example.com");
+        let destination = server_name!("destination.hs.example.com");
+
+        let mut request = request(b"");
+        sign_request(&mut request, origin, destination, &key_pair).unwrap();
+
+        let verified = verify_request(&request, destination, &public_key_map(&key_pair)).unwrap();
+        assert_eq!(verified, origin);
+
+        assert!(matches!(
+            verify_request(&self::request(b""), destination, &public_key_map(&key_pair)),
+            Err(XMatrixError::MissingAuthorization)
+        ));
+    }
 }
@@ -41,6 +41,10 @@ pub enum Error {
     /// PDU was too large
     #[error("PDU is larger than maximum of 65535 bytes")]
     PduSize,
+
+    /// The ID of an event can't be computed in the given room version.
+    #[error("event IDs are not computed from the reference hash in room version {0}")]
+    EventIdNotComputable(RoomVersionId),
 }
 
 impl From<RedactionError> for Error {
@@ -153,6 +157,16 @@ pub enum VerificationError {
     /// For when [`ed25519_dalek`] cannot verify a signature.
     #[error("Could not verify signature: {0}")]
     Signature(#[source] ed25519_dalek::SignatureError),
+
+    /// For when the ID of an event doesn't match the one it is claimed to have.
+    #[error("Claimed event ID {claimed:?} does not match the event ID of the event {computed:?}")]
+    EventIdMismatch {
+        /// The event ID that the event was claimed to have.
+        claimed: OwnedEventId,
+        /// The event ID computed from the event, or its `event_id` field in room versions 1 and
+        /// 2.
+        computed: OwnedEventId,
+    },
 }
 
 impl VerificationError {
@@ -177,6 +191,10 @@ pub enum ParseError {
     #[error("Could not parse Event ID: {0}")]
     EventId(#[source] ruma_common::IdParseError),
 
+    /// For server name parsing errors.
+    #[error("Could not parse server name: {0}")]
+    ServerName(#[source] ruma_common::IdParseError),
+
     /// For when an event ID, coupled with a specific room version, doesn't have a server name
     /// embedded.
     #[error("Event Id {0:?} should have a server name for the given room version {1:?}")]
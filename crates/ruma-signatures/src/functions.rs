@@ -10,13 +10,14 @@ use base64::{encode_config, STANDARD_NO_PAD, URL_SAFE_NO_PAD};
 use ruma_common::{
     canonical_json::{redact, JsonType},
     serde::{base64::Standard, Base64},
-    CanonicalJsonObject, CanonicalJsonValue, OwnedEventId, OwnedServerName, RoomVersionId, UserId,
+    CanonicalJsonObject, CanonicalJsonValue, EventId, OwnedEventId, OwnedServerName, RoomVersionId,
+    ServerName, UserId,
 };
 use serde_json::{from_str as from_json_str, to_string as to_json_string};
 use sha2::{digest::Digest, Sha256};
 
 use crate::{
-    keys::{KeyPair, PublicKeyMap},
+    keys::{KeyPair, PublicKeyMap, PublicKeySet},
     split_id,
     verification::{Ed25519Verifier, Verified, Verifier},
     Error, JsonError, ParseError, VerificationError,
@@ -156,6 +157,65 @@ pub fn canonical_json(object: &CanonicalJsonObject) -> Result<String, Error> {
     canonical_json_with_fields_to_remove(object, CANONICAL_JSON_FIELDS_TO_REMOVE)
 }
 
+/// Creates the JSON object that is signed for a federation request.
+///
+/// `uri` is the path and query of the URI of the request, and `content` is the body of the
+/// request, if it is not empty.
+///
+/// The object is defined in the [Matrix Server-Server API][spec].
+///
+/// [spec]: https://spec.matrix.org/v1.4/server-server-api/#request-authentication
+pub fn federation_request_json(
+    method: &str,
+    uri: &str,
+    origin: &ServerName,
+    destination: &ServerName,
+    content: Option<CanonicalJsonObject>,
+) -> CanonicalJsonObject {
+    let mut object = CanonicalJsonObject::from([
+        ("method".to_owned(), CanonicalJsonValue::String(method.to_owned())),
+        ("uri".to_owned(), CanonicalJsonValue::String(uri.to_owned())),
+        ("origin".to_owned(), CanonicalJsonValue::String(origin.to_string())),
+        ("destination".to_owned(), CanonicalJsonValue::String(destination.to_string())),
+    ]);
+
+    if let Some(content) = content {
+        object.insert("content".to_owned(), CanonicalJsonValue::Object(content));
+    }
+
+    object
+}
+
+/// Signs a federation request with the `X-Matrix` scheme.
+///
+/// The parameters are the same as the ones of [`federation_request_json`]. Returns the value of
+/// the `Authorization` header of the request, containing the signature of the request by `origin`
+/// with `key_pair`.
+///
+/// # Errors
+///
+/// Returns an error if the request could not be converted to canonical JSON.
+pub fn sign_federation_request<K>(
+    key_pair: &K,
+    method: &str,
+    uri: &str,
+    origin: &ServerName,
+    destination: &ServerName,
+    content: Option<CanonicalJsonObject>,
+) -> Result<String, Error>
+where
+    K: KeyPair,
+{
+    let object = federation_request_json(method, uri, origin, destination, content);
+    let signature = key_pair.sign(canonical_json(&object)?.as_bytes());
+
+    Ok(format!(
+        "X-Matrix origin=\"{origin}\",destination=\"{destination}\",key=\"{}\",sig=\"{}\"",
+        signature.id(),
+        signature.base64(),
+    ))
+}
+
 /// Uses a set of public keys to verify a signed JSON object.
 ///
 /// Unlike `content_hash` and `reference_hash`, this function does not report an error if the
@@ -255,6 +315,62 @@ pub fn verify_json(
     Ok(())
 }
 
+/// Verifies that a JSON object is signed by the given server with at least one of the given keys.
+///
+/// Unlike [`verify_json`], the signatures of other entities, and the ones of the server made with
+/// other keys, are ignored. This is useful to check the signatures of the keys of a server, or of
+/// the notary server that returned them.
+///
+/// # Parameters
+///
+/// * server_name: The name of the server that must have signed the object.
+/// * public_keys: A map from key identifiers to public keys of the server.
+/// * object: The JSON object that was signed.
+///
+/// # Errors
+///
+/// Returns an error if the object has no signature of the server made with one of the given
+/// keys, or if verification fails.
+pub fn verify_json_signed_by(
+    server_name: &ServerName,
+    public_keys: &PublicKeySet,
+    object: &CanonicalJsonObject,
+) -> Result<(), Error> {
+    let signature_set = match object.get("signatures") {
+        Some(CanonicalJsonValue::Object(signatures)) => signatures.get(server_name.as_str()),
+        Some(_) => return Err(JsonError::not_of_type("signatures", JsonType::Object)),
+        None => None,
+    };
+    let signature_set = match signature_set {
+        Some(CanonicalJsonValue::Object(signature_set)) => signature_set,
+        Some(_) => {
+            return Err(JsonError::not_multiples_of_type("signature sets", JsonType::Object))
+        }
+        None => return Err(VerificationError::signature_not_found(server_name.to_owned())),
+    };
+
+    // Only keep the signatures of the server made with the given keys.
+    let known_signatures: CanonicalJsonObject = signature_set
+        .iter()
+        .filter(|(key_id, _)| public_keys.contains_key(*key_id))
+        .map(|(key_id, signature)| (key_id.clone(), signature.clone()))
+        .collect();
+    if known_signatures.is_empty() {
+        return Err(VerificationError::UnknownPublicKeysForSignature.into());
+    }
+
+    let mut object = object.clone();
+    object.insert(
+        "signatures".to_owned(),
+        CanonicalJsonValue::Object(
+            [(server_name.to_string(), CanonicalJsonValue::Object(known_signatures))].into(),
+        ),
+    );
+
+    let public_key_map = [(server_name.to_string(), public_keys.clone())].into();
+    verify_json(&public_key_map, &object)
+}
+
 /// Uses a public key to verify a signed JSON object.
 ///
 /// # Parameters
@@ -341,6 +457,78 @@ pub fn reference_hash(
     ))
 }
 
+/// Computes the ID of an event from its reference hash.
+///
+/// This is only possible in room versions 3 and later, where the event ID is the reference hash of
+/// the event, encoded with the base64 character set of the room version and prefixed with `$`. In
+/// room versions 1 and 2, the event ID is assigned by the server that created the event.
+///
+/// # Parameters
+///
+/// * object: The JSON object of the event.
+/// * version: Room version of the given event.
+///
+/// # Errors
+///
+/// Returns an error if the room version is 1 or 2, if the event is too large or if redaction fails.
+pub fn compute_event_id(
+    object: &CanonicalJsonObject,
+    version: &RoomVersionId,
+) -> Result<OwnedEventId, Error> {
+    if matches!(version, RoomVersionId::V1 | RoomVersionId::V2) {
+        return Err(Error::EventIdNotComputable(version.clone()));
+    }
+
+    let hash = reference_hash(object, version)?;
+
+    format!("${hash}").try_into().map_err(|e| ParseError::EventId(e).into())
+}
+
+/// Verifies that the given event ID is the ID of the event.
+///
+/// In room versions 3 and later, the event ID is computed from the event with
+/// [`compute_event_id`]. In room versions 1 and 2, it must match the `event_id` field of the
+/// event.
+///
+/// # Parameters
+///
+/// * event_id: The ID that the event is claimed to have.
+/// * object: The JSON object of the event.
+/// * version: Room version of the given event.
+///
+/// # Errors
+///
+/// Returns [`VerificationError::EventIdMismatch`] if the event ID doesn't match, or another error
+/// if the ID of the event cannot be determined.
+pub fn verify_event_id(
+    event_id: &EventId,
+    object: &CanonicalJsonObject,
+    version: &RoomVersionId,
+) -> Result<(), Error> {
+    let actual_event_id = match version {
+        RoomVersionId::V1 | RoomVersionId::V2 => match object.get("event_id") {
+            Some(CanonicalJsonValue::String(raw_event_id)) => {
+                <&EventId>::try_from(raw_event_id.as_str())
+                    .map_err(|e| Error::from(ParseError::EventId(e)))?
+                    .to_owned()
+            }
+            Some(_) => return Err(JsonError::not_of_type("event_id", JsonType::String)),
+            None => return Err(JsonError::field_missing_from_object("event_id")),
+        },
+        _ => compute_event_id(object, version)?,
+    };
+
+    if actual_event_id != event_id {
+        return Err(VerificationError::EventIdMismatch {
+            claimed: event_id.to_owned(),
+            computed: actual_event_id,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
 /// Hashes and signs an event and adds the hash and signature to objects under the keys `hashes` and
 /// `signatures`, respectively.
 ///
@@ -697,19 +885,11 @@ fn servers_to_check_signatures(
         | RoomVersionId::V7 => {}
         // TODO: And for all future versions that have join_authorised_via_users_server
         RoomVersionId::V8 | RoomVersionId::V9 | RoomVersionId::V10 => {
-            if let Some(authorized_user) = object
-                .get("content")
-                .and_then(|c| c.as_object())
-                .and_then(|c| c.get("join_authorised_via_users_server"))
-            {
-                let authorized_user = authorized_user.as_str().ok_or_else(|| {
-                    JsonError::not_of_type("join_authorised_via_users_server", JsonType::String)
-                })?;
-                let authorized_user = <&UserId>::try_from(authorized_user)
-                    .map_err(|e| Error::from(ParseError::UserId(e)))?;
-
-                servers_to_check.insert(authorized_user.server_name().to_owned());
-            }
+            servers_to_check.extend(join_authorised_via_users_server(object)?);
+        }
+        #[cfg(feature = "unstable-msc3820")]
+        RoomVersionId::V11 => {
+            servers_to_check.extend(join_authorised_via_users_server(object)?);
         }
         _ => unimplemented!(),
     }
@@ -717,6 +897,28 @@ fn servers_to_check_signatures(
     Ok(servers_to_check)
 }
 
+/// Extracts the server name of the `join_authorised_via_users_server` of the given event, if any.
+fn join_authorised_via_users_server(
+    object: &CanonicalJsonObject,
+) -> Result<Option<OwnedServerName>, Error> {
+    let authorized_user = match object
+        .get("content")
+        .and_then(|c| c.as_object())
+        .and_then(|c| c.get("join_authorised_via_users_server"))
+    {
+        Some(authorized_user) => authorized_user,
+        None => return Ok(None),
+    };
+
+    let authorized_user = authorized_user.as_str().ok_or_else(|| {
+        JsonError::not_of_type("join_authorised_via_users_server", JsonType::String)
+    })?;
+    let authorized_user =
+        <&UserId>::try_from(authorized_user).map_err(|e| Error::from(ParseError::UserId(e)))?;
+
+    Ok(Some(authorized_user.server_name().to_owned()))
+}
+
 /// Checks if `object` contains an event of type `m.room.third_party_invite`
 fn is_third_party_invite(object: &CanonicalJsonObject) -> Result<bool, Error> {
     match object.get("type") {
@@ -731,14 +933,16 @@ mod tests {
 
     use assert_matches::assert_matches;
     use ruma_common::{
-        serde::Base64, CanonicalJsonValue, RoomVersionId, ServerSigningKeyId, SigningKeyAlgorithm,
+        event_id, serde::Base64, server_name, CanonicalJsonObject, CanonicalJsonValue,
+        RoomVersionId, ServerSigningKeyId, SigningKeyAlgorithm,
     };
-    use serde_json::json;
+    use serde_json::{from_str as from_json_str, json};
 
     use super::canonical_json;
     use crate::{
-        sign_json, verify_event, Ed25519KeyPair, Error, PublicKeyMap, PublicKeySet,
-        VerificationError, Verified,
+        compute_event_id, federation_request_json, reference_hash, sign_federation_request,
+        sign_json, verify_event, verify_event_id, verify_json, verify_json_signed_by,
+        Ed25519KeyPair, Error, PublicKeyMap, PublicKeySet, VerificationError, Verified,
     };
 
     #[test]
@@ -1006,6 +1210,146 @@ mod tests {
         assert!(format!("{error:?}").contains("Some(Verification equation was not satisfied)"));
     }
 
+    fn event_for_event_id() -> CanonicalJsonObject {
+        serde_json::from_str(
+            r#"{
+                "auth_events": [],
+                "content": {},
+                "depth": 3,
+                "hashes": {
+                    "sha256": "5jM4wQpv6lnBo7CLIghJuHdW+s2CMBJPUOGOC89ncos"
+                },
+                "origin": "domain",
+                "origin_server_ts": 1000000,
+                "prev_events": [],
+                "room_id": "!x:domain",
+                "sender": "@a:domain",
+                "signatures": {},
+                "type": "X",
+                "unsigned": {
+                    "age_ts": 1000000
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn compute_event_id_from_reference_hash() {
+        let object = event_for_event_id();
+
+        for version in [RoomVersionId::V3, RoomVersionId::V4, RoomVersionId::V10] {
+            let event_id = compute_event_id(&object, &version).unwrap();
+            let hash = reference_hash(&object, &version).unwrap();
+            assert_eq!(event_id.as_str(), format!("${hash}"));
+            assert_eq!(event_id.server_name(), None);
+        }
+
+        assert_matches!(
+            compute_event_id(&object, &RoomVersionId::V1),
+            Err(Error::EventIdNotComputable(RoomVersionId::V1))
+        );
+    }
+
+    #[test]
+    fn verify_event_id_matches() {
+        let mut object = event_for_event_id();
+
+        let event_id = compute_event_id(&object, &RoomVersionId::V6).unwrap();
+        verify_event_id(&event_id, &object, &RoomVersionId::V6).unwrap();
+
+        // The event ID changes if the event is modified.
+        object.insert("depth".to_owned(), CanonicalJsonValue::Integer(4_u32.into()));
+        assert_matches!(
+            verify_event_id(&event_id, &object, &RoomVersionId::V6),
+            Err(Error::Verification(VerificationError::EventIdMismatch { .. }))
+        );
+
+        // The event ID is part of the event in room versions 1 and 2.
+        let event_id = event_id!("$abc:domain");
+        object.insert("event_id".to_owned(), CanonicalJsonValue::String(event_id.to_string()));
+        verify_event_id(event_id, &object, &RoomVersionId::V1).unwrap();
+        let (claimed, computed) = assert_matches!(
+            verify_event_id(event_id!("$def:domain"), &object, &RoomVersionId::V1),
+            Err(Error::Verification(VerificationError::EventIdMismatch { claimed, computed })) => (claimed, computed)
+        );
+        assert_eq!(claimed, "$def:domain");
+        assert_eq!(computed, event_id);
+    }
+
+    #[test]
+    fn sign_and_verify_federation_request() {
+        let key_pair = generate_key_pair();
+        let origin = server_name!("origin.hs.example.com");
+        let destination = server_name!("destination.hs.example.com");
+        let content: CanonicalJsonObject = from_json_str(r#"{ "edus": [] }"#).unwrap();
+
+        let authorization = sign_federation_request(
+            &key_pair,
+            "PUT",
+            "/_matrix/federation/v1/send/1",
+            origin,
+            destination,
+            Some(content.clone()),
+        )
+        .unwrap();
+        let prefix = r#"X-Matrix origin="origin.hs.example.com",destination="destination.hs.example.com",key="ed25519:1",sig=""#;
+        let sig = authorization.strip_prefix(prefix).unwrap().strip_suffix('"').unwrap();
+
+        let mut object = federation_request_json(
+            "PUT",
+            "/_matrix/federation/v1/send/1",
+            origin,
+            destination,
+            Some(content),
+        );
+        object.insert(
+            "signatures".to_owned(),
+            CanonicalJsonValue::Object(CanonicalJsonObject::from([(
+                origin.to_string(),
+                CanonicalJsonValue::Object(CanonicalJsonObject::from([(
+                    "ed25519:1".to_owned(),
+                    CanonicalJsonValue::String(sig.to_owned()),
+                )])),
+            )])),
+        );
+
+        let mut public_key_map = PublicKeyMap::new();
+        add_key_to_map(&mut public_key_map, origin.as_str(), &key_pair);
+        verify_json(&public_key_map, &object).unwrap();
+
+        // The signature doesn't match another method.
+        object.insert("method".to_owned(), CanonicalJsonValue::String("GET".to_owned()));
+        verify_json(&public_key_map, &object).unwrap_err();
+    }
+
+    #[test]
+    fn verify_json_signed_by_ignores_other_signatures() {
+        let server_key_pair = generate_key_pair();
+        let notary_key_pair =
+            Ed25519KeyPair::from_der(&Ed25519KeyPair::generate().unwrap(), "2".to_owned()).unwrap();
+        let mut object = CanonicalJsonObject::new();
+        object.insert("foo".to_owned(), CanonicalJsonValue::String("bar".to_owned()));
+        sign_json("domain-server", &server_key_pair, &mut object).unwrap();
+        sign_json("domain-notary", &notary_key_pair, &mut object).unwrap();
+
+        let mut public_key_map = PublicKeyMap::new();
+        add_key_to_map(&mut public_key_map, "domain-server", &server_key_pair);
+        let server_keys = &public_key_map["domain-server"];
+
+        // The signature of the notary is ignored.
+        verify_json_signed_by(server_name!("domain-server"), server_keys, &object).unwrap();
+
+        assert_matches!(
+            verify_json_signed_by(server_name!("domain-notary"), server_keys, &object),
+            Err(Error::Verification(VerificationError::UnknownPublicKeysForSignature))
+        );
+        assert_matches!(
+            verify_json_signed_by(server_name!("domain-other"), server_keys, &object),
+            Err(Error::Verification(VerificationError::SignatureNotFound(_)))
+        );
+    }
+
     fn generate_key_pair() -> Ed25519KeyPair {
         let key_content = Ed25519KeyPair::generate().unwrap();
         Ed25519KeyPair::from_der(&key_content, "1".to_owned())
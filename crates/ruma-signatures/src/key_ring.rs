@@ -0,0 +1,427 @@
+//! A collection of the signing keys of homeservers, with their validity periods.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
+
+use ruma_common::{
+    canonical_json::JsonType, serde::Base64, CanonicalJsonObject, CanonicalJsonValue,
+    MilliSecondsSinceUnixEpoch, OwnedServerName, RoomVersionId, ServerName,
+};
+use serde_json::from_value as from_json_value;
+
+use crate::{
+    verify_event, verify_json_signed_by, Error, JsonError, ParseError, PublicKeyMap, PublicKeySet,
+    Verified,
+};
+
+/// The maximum time that server keys are considered valid after they were added to a [`KeyRing`].
+const MAX_VALIDITY_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A public key of a homeserver.
+#[derive(Clone, Debug)]
+struct ServerKey {
+    /// The public key.
+    key: Base64,
+
+    /// The time until which signatures made with this key are valid.
+    valid_until_ts: MilliSecondsSinceUnixEpoch,
+
+    /// Whether the homeserver stopped using this key.
+    expired: bool,
+}
+
+/// A collection of the signing keys of homeservers.
+///
+/// Unlike a [`PublicKeyMap`], it keeps track of the validity period of the keys, so it can be used
+/// to verify events in room versions that enforce it.
+///
+/// Keys are added from the responses to the `get_server_keys` and `get_remote_server_keys`
+/// federation endpoints with [`KeyRing::add_server_keys()`].
+#[derive(Clone, Debug, Default)]
+pub struct KeyRing {
+    servers: BTreeMap<OwnedServerName, BTreeMap<String, ServerKey>>,
+}
+
+impl KeyRing {
+    /// Creates an empty `KeyRing`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the keys of a homeserver to this `KeyRing`.
+    ///
+    /// `server_keys` must be the JSON representation of the keys of a single server, as returned
+    /// by the `get_server_keys` or `get_remote_server_keys` federation endpoints. The keys are only
+    /// added if they are signed by the homeserver with one of its `verify_keys`. Signatures of
+    /// other servers, like the ones of a notary server, are ignored.
+    ///
+    /// The validity period of the `verify_keys` is capped to 7 days, as required by the
+    /// specification. Keys that were already known are updated with their latest validity period,
+    /// but a key that expired can't be used again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the JSON is not a valid server keys object or if the self-signature
+    /// verification fails.
+    pub fn add_server_keys(&mut self, server_keys: &CanonicalJsonObject) -> Result<(), Error> {
+        let server_name: OwnedServerName = match server_keys.get("server_name") {
+            Some(CanonicalJsonValue::String(server_name)) => {
+                server_name.as_str().try_into().map_err(ParseError::ServerName)?
+            }
+            Some(_) => return Err(JsonError::not_of_type("server_name", JsonType::String)),
+            None => return Err(JsonError::field_missing_from_object("server_name")),
+        };
+
+        let max_valid_until_ts =
+            MilliSecondsSinceUnixEpoch::from_system_time(SystemTime::now() + MAX_VALIDITY_PERIOD);
+        let valid_until_ts = timestamp_field(server_keys, "valid_until_ts")?;
+        let valid_until_ts = match max_valid_until_ts {
+            Some(max) if max < valid_until_ts => max,
+            _ => valid_until_ts,
+        };
+
+        let mut verify_keys = PublicKeySet::new();
+        for (key_id, verify_key) in object_field(server_keys, "verify_keys")? {
+            let verify_key = key_object(verify_key, "verify_keys")?;
+            verify_keys.insert(key_id.clone(), key_field(verify_key)?);
+        }
+
+        let mut old_verify_keys = BTreeMap::new();
+        if server_keys.contains_key("old_verify_keys") {
+            for (key_id, old_verify_key) in object_field(server_keys, "old_verify_keys")? {
+                let old_verify_key = key_object(old_verify_key, "old_verify_keys")?;
+                let expired_ts = timestamp_field(old_verify_key, "expired_ts")?;
+
+                old_verify_keys.insert(key_id.clone(), (key_field(old_verify_key)?, expired_ts));
+            }
+        }
+
+        verify_json_signed_by(&server_name, &verify_keys, server_keys)?;
+
+        let keys = self.servers.entry(server_name).or_default();
+
+        for (key_id, key) in verify_keys {
+            match keys.get_mut(&key_id) {
+                Some(known) if known.expired => {}
+                Some(known) => {
+                    known.key = key;
+                    known.valid_until_ts = known.valid_until_ts.max(valid_until_ts);
+                }
+                None => {
+                    keys.insert(key_id, ServerKey { key, valid_until_ts, expired: false });
+                }
+            }
+        }
+
+        for (key_id, (key, expired_ts)) in old_verify_keys {
+            keys.insert(key_id, ServerKey { key, valid_until_ts: expired_ts, expired: true });
+        }
+
+        Ok(())
+    }
+
+    /// The time until which the current keys of the given homeserver are valid.
+    ///
+    /// Returns `None` if no current keys are known for this homeserver. Otherwise, the keys
+    /// should be fetched again after the returned time.
+    pub fn valid_until_ts(&self, server_name: &ServerName) -> Option<MilliSecondsSinceUnixEpoch> {
+        self.servers
+            .get(server_name)?
+            .values()
+            .filter(|key| !key.expired)
+            .map(|key| key.valid_until_ts)
+            .max()
+    }
+
+    /// All the known public keys of the given homeserver, regardless of their validity period.
+    pub fn public_keys(&self, server_name: &ServerName) -> PublicKeySet {
+        self.servers
+            .get(server_name)
+            .map(|keys| {
+                keys.iter().map(|(key_id, key)| (key_id.clone(), key.key.clone())).collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The public keys of the given homeserver that were valid at the given time.
+    pub fn public_keys_at(
+        &self,
+        server_name: &ServerName,
+        ts: MilliSecondsSinceUnixEpoch,
+    ) -> PublicKeySet {
+        self.servers
+            .get(server_name)
+            .map(|keys| {
+                keys.iter()
+                    .filter(|(_, key)| key.valid_until_ts >= ts)
+                    .map(|(key_id, key)| (key_id.clone(), key.key.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// All the known public keys, regardless of their validity period.
+    pub fn public_key_map(&self) -> PublicKeyMap {
+        self.servers
+            .keys()
+            .map(|server_name| (server_name.to_string(), self.public_keys(server_name)))
+            .collect()
+    }
+
+    /// The public keys that were valid at the given time.
+    pub fn public_key_map_at(&self, ts: MilliSecondsSinceUnixEpoch) -> PublicKeyMap {
+        self.servers
+            .keys()
+            .map(|server_name| (server_name.to_string(), self.public_keys_at(server_name, ts)))
+            .collect()
+    }
+
+    /// Uses the keys in this `KeyRing` to check the signatures and hashes of an event.
+    ///
+    /// This is the same as [`verify_event()`], except that the public keys are selected according
+    /// to the room version: from room version 5 onwards, the keys must have been valid at the
+    /// `origin_server_ts` of the event.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`verify_event()`], and an error if the `origin_server_ts` of the
+    /// event is missing or invalid in a room version that enforces the validity period of keys.
+    pub fn verify_event(
+        &self,
+        object: &CanonicalJsonObject,
+        version: &RoomVersionId,
+    ) -> Result<Verified, Error> {
+        let public_key_map = match version {
+            RoomVersionId::V1 | RoomVersionId::V2 | RoomVersionId::V3 | RoomVersionId::V4 => {
+                self.public_key_map()
+            }
+            _ => self.public_key_map_at(timestamp_field(object, "origin_server_ts")?),
+        };
+
+        verify_event(&public_key_map, object, version)
+    }
+}
+
+/// Get the object in the given field of `object`.
+fn object_field<'a>(
+    object: &'a CanonicalJsonObject,
+    field: &str,
+) -> Result<&'a CanonicalJsonObject, Error> {
+    match object.get(field) {
+        Some(CanonicalJsonValue::Object(value)) => Ok(value),
+        Some(_) => Err(JsonError::not_of_type(field, JsonType::Object)),
+        None => Err(JsonError::field_missing_from_object(field)),
+    }
+}
+
+/// Get the timestamp in the given field of `object`.
+fn timestamp_field(
+    object: &CanonicalJsonObject,
+    field: &str,
+) -> Result<MilliSecondsSinceUnixEpoch, Error> {
+    match object.get(field) {
+        Some(value @ CanonicalJsonValue::Integer(_)) => from_json_value(value.clone().into())
+            .map_err(|_| JsonError::not_of_type(field, JsonType::Integer)),
+        Some(_) => Err(JsonError::not_of_type(field, JsonType::Integer)),
+        None => Err(JsonError::field_missing_from_object(field)),
+    }
+}
+
+/// Get the object of a `verify_keys` or `old_verify_keys` entry.
+fn key_object<'a>(
+    value: &'a CanonicalJsonValue,
+    target: &str,
+) -> Result<&'a CanonicalJsonObject, Error> {
+    match value {
+        CanonicalJsonValue::Object(object) => Ok(object),
+        _ => Err(JsonError::not_multiples_of_type(target, JsonType::Object)),
+    }
+}
+
+/// Get the public key of a `verify_keys` or `old_verify_keys` entry.
+fn key_field(object: &CanonicalJsonObject) -> Result<Base64, Error> {
+    let key = match object.get("key") {
+        Some(CanonicalJsonValue::String(key)) => key,
+        Some(_) => return Err(JsonError::not_of_type("key", JsonType::String)),
+        None => return Err(JsonError::field_missing_from_object("key")),
+    };
+
+    Base64::parse(key).map_err(|e| ParseError::base64("key", key, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use ruma_common::{
+        serde::Base64, server_name, CanonicalJsonObject, MilliSecondsSinceUnixEpoch, RoomVersionId,
+    };
+    use serde_json::json;
+
+    use super::KeyRing;
+    use crate::{
+        hash_and_sign_event, sign_json, Ed25519KeyPair, Error, VerificationError, Verified,
+    };
+
+    fn generate_key_pair(version: &str) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_der(&Ed25519KeyPair::generate().unwrap(), version.to_owned()).unwrap()
+    }
+
+    fn public_key(key_pair: &Ed25519KeyPair) -> String {
+        let key: Base64 = Base64::new(key_pair.public_key().to_owned());
+        key.encode()
+    }
+
+    fn ts(ms: u32) -> MilliSecondsSinceUnixEpoch {
+        MilliSecondsSinceUnixEpoch(ms.into())
+    }
+
+    fn server_keys(
+        key_pair: &Ed25519KeyPair,
+        valid_until_ts: u64,
+        old_key_pair: Option<(&Ed25519KeyPair, u32)>,
+    ) -> CanonicalJsonObject {
+        let mut server_keys: CanonicalJsonObject = serde_json::from_value(json!({
+            "server_name": "domain",
+            "verify_keys": {
+                format!("ed25519:{}", key_pair.version()): {
+                    "key": public_key(key_pair),
+                },
+            },
+            "valid_until_ts": valid_until_ts,
+        }))
+        .unwrap();
+
+        if let Some((old_key_pair, expired_ts)) = old_key_pair {
+            server_keys.insert(
+                "old_verify_keys".to_owned(),
+                serde_json::from_value(json!({
+                    format!("ed25519:{}", old_key_pair.version()): {
+                        "key": public_key(old_key_pair),
+                        "expired_ts": expired_ts,
+                    },
+                }))
+                .unwrap(),
+            );
+        }
+
+        sign_json("domain", key_pair, &mut server_keys).unwrap();
+        server_keys
+    }
+
+    fn signed_event(key_pair: &Ed25519KeyPair, origin_server_ts: u32) -> CanonicalJsonObject {
+        let mut event: CanonicalJsonObject = serde_json::from_value(json!({
+            "room_id": "!x:domain",
+            "sender": "@a:domain",
+            "origin": "domain",
+            "origin_server_ts": origin_server_ts,
+            "type": "X",
+            "content": {},
+            "prev_events": [],
+            "auth_events": [],
+            "depth": 3,
+            "unsigned": {
+                "age_ts": 1_000_000
+            }
+        }))
+        .unwrap();
+
+        hash_and_sign_event("domain", key_pair, &mut event, &RoomVersionId::V5).unwrap();
+        event
+    }
+
+    #[test]
+    fn add_server_keys_checks_self_signature() {
+        let key_pair = generate_key_pair("1");
+        let other_key_pair = generate_key_pair("2");
+        let mut key_ring = KeyRing::new();
+
+        // Signed with a key that isn't in `verify_keys`.
+        let mut keys = server_keys(&key_pair, 2000, None);
+        keys.remove("signatures");
+        sign_json("domain", &other_key_pair, &mut keys).unwrap();
+        assert_matches!(
+            key_ring.add_server_keys(&keys),
+            Err(Error::Verification(VerificationError::UnknownPublicKeysForSignature))
+        );
+
+        // Not signed by the server.
+        let mut keys = server_keys(&key_pair, 2000, None);
+        keys.remove("signatures");
+        sign_json("notary", &key_pair, &mut keys).unwrap();
+        assert_matches!(
+            key_ring.add_server_keys(&keys),
+            Err(Error::Verification(VerificationError::SignatureNotFound(_)))
+        );
+        assert!(key_ring.public_keys(server_name!("domain")).is_empty());
+
+        // Signatures of other servers are ignored.
+        let mut keys = server_keys(&key_pair, 2000, None);
+        sign_json("notary", &other_key_pair, &mut keys).unwrap();
+        key_ring.add_server_keys(&keys).unwrap();
+        assert_eq!(key_ring.public_keys(server_name!("domain")).len(), 1);
+        assert_eq!(key_ring.valid_until_ts(server_name!("domain")), Some(ts(2000)));
+    }
+
+    #[test]
+    fn public_keys_at_respects_validity() {
+        let key_pair = generate_key_pair("2");
+        let old_key_pair = generate_key_pair("1");
+        let mut key_ring = KeyRing::new();
+        key_ring
+            .add_server_keys(&server_keys(&key_pair, 2000, Some((&old_key_pair, 1000))))
+            .unwrap();
+
+        let keys = key_ring.public_keys_at(server_name!("domain"), ts(500));
+        assert_eq!(keys.len(), 2);
+
+        let keys = key_ring.public_keys_at(server_name!("domain"), ts(1500));
+        assert_eq!(keys.len(), 1);
+        assert!(keys.contains_key("ed25519:2"));
+
+        let keys = key_ring.public_keys_at(server_name!("domain"), ts(2500));
+        assert!(keys.is_empty());
+        assert_eq!(key_ring.public_keys(server_name!("domain")).len(), 2);
+
+        // A newer response extends the validity of the current key.
+        key_ring.add_server_keys(&server_keys(&key_pair, 3000, None)).unwrap();
+        let keys = key_ring.public_keys_at(server_name!("domain"), ts(2500));
+        assert!(keys.contains_key("ed25519:2"));
+
+        // An expired key can't become valid again.
+        key_ring.add_server_keys(&server_keys(&old_key_pair, 3000, None)).unwrap();
+        let keys = key_ring.public_keys_at(server_name!("domain"), ts(1500));
+        assert!(!keys.contains_key("ed25519:1"));
+    }
+
+    #[test]
+    fn verify_event_enforces_key_validity() {
+        let key_pair = generate_key_pair("1");
+        let mut key_ring = KeyRing::new();
+        key_ring.add_server_keys(&server_keys(&key_pair, 2000, None)).unwrap();
+
+        let event = signed_event(&key_pair, 1000);
+        assert_matches!(key_ring.verify_event(&event, &RoomVersionId::V5), Ok(Verified::All));
+
+        let event = signed_event(&key_pair, 3000);
+        assert_matches!(
+            key_ring.verify_event(&event, &RoomVersionId::V5),
+            Err(Error::Verification(VerificationError::UnknownPublicKeysForSignature))
+        );
+        assert_matches!(key_ring.verify_event(&event, &RoomVersionId::V4), Ok(Verified::All));
+    }
+
+    #[test]
+    fn valid_until_ts_is_capped() {
+        let key_pair = generate_key_pair("1");
+        let mut key_ring = KeyRing::new();
+        key_ring.add_server_keys(&server_keys(&key_pair, 4_000_000_000_000, None)).unwrap();
+
+        let in_eight_days = MilliSecondsSinceUnixEpoch::from_system_time(
+            std::time::SystemTime::now() + std::time::Duration::from_secs(8 * 24 * 60 * 60),
+        )
+        .unwrap();
+        assert!(key_ring.valid_until_ts(server_name!("domain")).unwrap() < in_eight_days);
+    }
+}
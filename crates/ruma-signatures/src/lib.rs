@@ -41,6 +41,10 @@
 //! To verify a signature on arbitrary JSON, use the `verify_json` function. To verify the
 //! signatures and hashes on an event, use the `verify_event` function. See the documentation for
 //! these respective functions for more details and full examples of use.
+//!
+//! The public keys of homeservers are only valid for a limited time. To take it into account when
+//! verifying events, add the keys returned by the federation API to a `KeyRing` and use
+//! `KeyRing::verify_event`.
 
 #![warn(missing_docs)]
 
@@ -48,15 +52,18 @@ use ruma_common::serde::{AsRefStr, DisplayAsRefStr};
 
 pub use error::{Error, JsonError, ParseError, VerificationError};
 pub use functions::{
-    canonical_json, content_hash, hash_and_sign_event, reference_hash, sign_json, verify_event,
-    verify_json,
+    canonical_json, compute_event_id, content_hash, federation_request_json, hash_and_sign_event,
+    reference_hash, sign_federation_request, sign_json, verify_event, verify_event_id, verify_json,
+    verify_json_signed_by,
 };
+pub use key_ring::KeyRing;
 pub use keys::{Ed25519KeyPair, KeyPair, PublicKeyMap, PublicKeySet};
 pub use signatures::Signature;
 pub use verification::Verified;
 
 mod error;
 mod functions;
+mod key_ring;
 mod keys;
 mod signatures;
 mod verification;
@@ -529,6 +529,7 @@ impl EventTypeExt for &RoomEventType {
 }
 
 mod event {
+    use js_int::UInt;
     use ruma_common::{
         events::{pdu::Pdu, RoomEventType},
         MilliSecondsSinceUnixEpoch, OwnedEventId, RoomId, UserId,
@@ -598,6 +599,15 @@ mod event {
             }
         }
 
+        fn depth(&self) -> UInt {
+            match &self.rest {
+                Pdu::RoomV1Pdu(ev) => ev.depth,
+                Pdu::RoomV3Pdu(ev) => ev.depth,
+                #[cfg(not(feature = "unstable-exhaustive-types"))]
+                _ => unreachable!("new PDU version"),
+            }
+        }
+
         fn prev_events(&self) -> Box<dyn DoubleEndedIterator<Item = &Self::Id> + '_> {
             match &self.rest {
                 Pdu::RoomV1Pdu(ev) => Box::new(ev.prev_events.iter().map(|(id, _)| id)),
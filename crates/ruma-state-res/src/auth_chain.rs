@@ -0,0 +1,290 @@
+//! Helpers for computing the auth chains of events.
+//!
+//! The auth chain of an event is the recursive set of its `auth_events`, not including the event
+//! itself. Auth chains are memoised per event in an [`AuthChainCache`], so only the part of the
+//! auth graph that hasn't been seen before needs to be walked.
+
+use std::{
+    borrow::Borrow,
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    sync::Arc,
+};
+
+use ruma_common::EventId;
+use tracing::{debug, trace};
+
+use crate::{get_auth_chain_diff, Error, Event, Result, StateMap};
+
+/// A cache for the auth chains of events.
+///
+/// Implementations can be backed by anything from a plain `HashMap` (which implements this trait)
+/// to an LRU cache or a database table.
+pub trait AuthChainCache<Id> {
+    /// Get the auth chain of the event with the given ID, if it is cached.
+    fn get_auth_chain(&self, event_id: &EventId) -> Option<Arc<HashSet<Id>>>;
+
+    /// Cache the auth chain of the event with the given ID.
+    fn insert_auth_chain(&mut self, event_id: Id, auth_chain: Arc<HashSet<Id>>);
+}
+
+impl<Id> AuthChainCache<Id> for HashMap<Id, Arc<HashSet<Id>>>
+where
+    Id: Eq + Hash + Borrow<EventId>,
+{
+    fn get_auth_chain(&self, event_id: &EventId) -> Option<Arc<HashSet<Id>>> {
+        self.get(event_id).cloned()
+    }
+
+    fn insert_auth_chain(&mut self, event_id: Id, auth_chain: Arc<HashSet<Id>>) {
+        self.insert(event_id, auth_chain);
+    }
+}
+
+/// Get the auth chain of the event with the given ID.
+///
+/// The auth chains of the event and of all the events in its auth chain are stored in `cache`.
+///
+/// ## Errors
+///
+/// Returns [`Error::NotFound`] if an event of the auth chain cannot be fetched and
+/// [`Error::InvalidPdu`] if the auth events contain a cycle.
+pub fn get_auth_chain<E: Event>(
+    event_id: &EventId,
+    cache: &mut impl AuthChainCache<E::Id>,
+    fetch_event: impl Fn(&EventId) -> Option<E>,
+) -> Result<Arc<HashSet<E::Id>>> {
+    if let Some(auth_chain) = cache.get_auth_chain(event_id) {
+        return Ok(auth_chain);
+    }
+
+    let event = fetch_event(event_id)
+        .ok_or_else(|| Error::NotFound(format!("Failed to find {event_id}")))?;
+
+    // The events whose auth events are being walked, to detect cycles.
+    let mut in_progress = HashSet::new();
+    // `true` if the auth events of the event have already been pushed on the stack.
+    let mut stack = vec![(event, false)];
+
+    while let Some((event, expanded)) = stack.pop() {
+        let id = event.event_id().clone();
+        if cache.get_auth_chain(id.borrow()).is_some() {
+            continue;
+        }
+
+        if expanded {
+            let mut auth_chain = HashSet::new();
+            for aid in event.auth_events() {
+                let aid_chain = cache
+                    .get_auth_chain(aid.borrow())
+                    .expect("auth events are cached before the events they authorize");
+                auth_chain.insert(aid.clone());
+                auth_chain.extend(aid_chain.iter().cloned());
+            }
+
+            trace!("auth chain of {id} has {} events", auth_chain.len());
+
+            in_progress.remove(&id);
+            cache.insert_auth_chain(id, Arc::new(auth_chain));
+        } else {
+            if !in_progress.insert(id.clone()) {
+                return Err(Error::InvalidPdu(format!("Auth events of {id} contain a cycle")));
+            }
+
+            let auth_events = event
+                .auth_events()
+                .filter(|aid| cache.get_auth_chain((*aid).borrow()).is_none())
+                .map(|aid| {
+                    fetch_event(aid.borrow())
+                        .ok_or_else(|| Error::NotFound(format!("Failed to find {aid}")))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            stack.push((event, true));
+            stack.extend(auth_events.into_iter().map(|ev| (ev, false)));
+        }
+    }
+
+    Ok(cache.get_auth_chain(event_id).expect("auth chain of the event was just cached"))
+}
+
+/// Get the full auth chain of each of the `state_sets`, in the format expected by
+/// [`resolve`](crate::resolve).
+///
+/// The full auth chain of a state set is the union of the auth chains of its events.
+pub fn auth_chain_sets<'a, E, SetIter>(
+    state_sets: SetIter,
+    cache: &mut impl AuthChainCache<E::Id>,
+    fetch_event: impl Fn(&EventId) -> Option<E>,
+) -> Result<Vec<HashSet<E::Id>>>
+where
+    E: Event,
+    E::Id: 'a,
+    SetIter: IntoIterator<Item = &'a StateMap<E::Id>>,
+{
+    state_sets
+        .into_iter()
+        .map(|state_set| {
+            let mut full_auth_chain = HashSet::new();
+            for event_id in state_set.values() {
+                let auth_chain = get_auth_chain(event_id.borrow(), cache, &fetch_event)?;
+                full_auth_chain.extend(auth_chain.iter().cloned());
+            }
+
+            Ok(full_auth_chain)
+        })
+        .collect()
+}
+
+/// Get the auth difference of the `state_sets`.
+///
+/// This is the set of events that appear in the full auth chain of some state sets but not
+/// others.
+pub fn auth_chain_difference<'a, E, SetIter>(
+    state_sets: SetIter,
+    cache: &mut impl AuthChainCache<E::Id>,
+    fetch_event: impl Fn(&EventId) -> Option<E>,
+) -> Result<HashSet<E::Id>>
+where
+    E: Event,
+    E::Id: 'a,
+    SetIter: IntoIterator<Item = &'a StateMap<E::Id>>,
+{
+    let auth_chain_sets = auth_chain_sets(state_sets, cache, fetch_event)?;
+    let auth_difference: HashSet<_> = get_auth_chain_diff(auth_chain_sets).collect();
+
+    debug!("auth difference: {}", auth_difference.len());
+
+    Ok(auth_difference)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::Arc,
+    };
+
+    use maplit::{hashmap, hashset};
+    use ruma_common::{events::RoomEventType, RoomVersionId};
+    use serde_json::{json, value::to_raw_value as to_raw_json_value};
+
+    use super::{auth_chain_difference, auth_chain_sets, get_auth_chain, AuthChainCache};
+    use crate::{
+        test_utils::{alice, event_id, to_pdu_event, PduEvent, TestStore, INITIAL_EVENTS},
+        Error,
+    };
+
+    #[test]
+    fn auth_chain_of_event() {
+        let events = INITIAL_EVENTS();
+        let mut cache = HashMap::new();
+
+        let auth_chain =
+            get_auth_chain(&event_id("IMB"), &mut cache, |id| events.get(id).map(Arc::clone))
+                .unwrap();
+
+        assert_eq!(
+            *auth_chain,
+            ["CREATE", "IMA", "IPOWER", "IJR"].into_iter().map(event_id).collect::<HashSet<_>>()
+        );
+
+        // The auth chains of the whole auth graph of the event are cached.
+        assert_eq!(
+            cache.keys().cloned().collect::<HashSet<_>>(),
+            ["CREATE", "IMA", "IPOWER", "IJR", "IMB"]
+                .into_iter()
+                .map(event_id)
+                .collect::<HashSet<_>>()
+        );
+        assert!(cache.get_auth_chain(&event_id("CREATE")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn auth_chain_uses_cache() {
+        let events = INITIAL_EVENTS();
+        let mut cache = hashmap! {
+            event_id("IJR") => Arc::new(hashset![event_id("CREATE")]),
+            event_id("IPOWER") => Arc::new(hashset![event_id("CREATE")]),
+        };
+
+        // `IMA` is only reachable through cached events, so it must not be fetched.
+        let auth_chain = get_auth_chain(&event_id("IMB"), &mut cache, |id| {
+            assert!(id != event_id("IMA"), "{id} was fetched");
+            events.get(id).map(Arc::clone)
+        })
+        .unwrap();
+
+        assert_eq!(
+            *auth_chain,
+            ["CREATE", "IPOWER", "IJR"].into_iter().map(event_id).collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn auth_chain_missing_event() {
+        let mut events = INITIAL_EVENTS();
+        events.remove(&event_id("IMA"));
+
+        let res = get_auth_chain(&event_id("IMB"), &mut HashMap::new(), |id| {
+            events.get(id).map(Arc::clone)
+        });
+
+        assert!(matches!(res, Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn auth_chain_cycle() {
+        let events: HashMap<_, _> = [
+            to_pdu_event(
+                "A",
+                alice(),
+                RoomEventType::RoomTopic,
+                Some(""),
+                to_raw_json_value(&json!({})).unwrap(),
+                &["B"],
+                &[],
+            ),
+            to_pdu_event(
+                "B",
+                alice(),
+                RoomEventType::RoomTopic,
+                Some(""),
+                to_raw_json_value(&json!({})).unwrap(),
+                &["A"],
+                &[],
+            ),
+        ]
+        .into_iter()
+        .map(|ev| (ev.event_id.clone(), ev))
+        .collect();
+
+        let res = get_auth_chain(&event_id("A"), &mut HashMap::new(), |id| {
+            events.get(id).map(Arc::clone)
+        });
+
+        assert!(matches!(res, Err(Error::InvalidPdu(_))));
+    }
+
+    #[test]
+    fn resolve_with_auth_chain_sets() {
+        let mut store = TestStore::<PduEvent>(hashmap! {});
+        let (state_at_bob, state_at_charlie, expected) = store.set_up();
+
+        let ev_map = store.0.clone();
+        let state_sets = [state_at_bob, state_at_charlie];
+        let fetch_event = |id: &_| ev_map.get(id).map(Arc::clone);
+
+        let mut cache = HashMap::new();
+        let auth_chain_sets = auth_chain_sets(&state_sets, &mut cache, fetch_event).unwrap();
+        let auth_difference = auth_chain_difference(&state_sets, &mut cache, fetch_event).unwrap();
+
+        // The joins of bob and charlie are not in each other's auth chains.
+        assert!(auth_difference.is_empty());
+
+        let resolved =
+            crate::resolve(&RoomVersionId::V6, &state_sets, auth_chain_sets, fetch_event).unwrap();
+
+        assert_eq!(expected, resolved);
+    }
+}
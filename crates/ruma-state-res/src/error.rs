@@ -12,6 +12,10 @@ pub enum Error {
     #[error(transparent)]
     SerdeJson(#[from] JsonError),
 
+    /// An error when hashing, signing or verifying an event.
+    #[error(transparent)]
+    Signatures(#[from] ruma_signatures::Error),
+
     /// The given option or version is unsupported.
     #[error("Unsupported room version: {0}")]
     Unsupported(String),
@@ -24,6 +28,10 @@ pub enum Error {
     #[error("Invalid PDU: {0}")]
     InvalidPdu(String),
 
+    /// An error returned when fetching an event or state.
+    #[error("Failed to fetch: {0}")]
+    Fetch(Box<dyn std::error::Error + Send + Sync>),
+
     /// A custom error.
     #[error("{0}")]
     Custom(Box<dyn std::error::Error>),
@@ -33,4 +41,8 @@ impl Error {
     pub fn custom<E: std::error::Error + 'static>(e: E) -> Self {
         Self::Custom(Box::new(e))
     }
+
+    pub(crate) fn fetch<E: std::error::Error + Send + Sync + 'static>(e: E) -> Self {
+        Self::Fetch(Box::new(e))
+    }
 }
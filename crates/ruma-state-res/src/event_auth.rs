@@ -1,10 +1,13 @@
-use std::{borrow::Borrow, collections::BTreeSet};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeSet, HashMap},
+    future::Future,
+};
 
 use js_int::{int, Int};
 use ruma_common::{
     events::{
         room::{
-            create::RoomCreateEventContent,
             join_rules::{JoinRule, RoomJoinRulesEventContent},
             member::{MembershipState, ThirdPartyInvite},
             power_levels::RoomPowerLevelsEventContent,
@@ -176,7 +179,7 @@ pub fn auth_check<E: Event>(
         }
 
         // If content has no creator field, reject
-        if content.creator.is_none() {
+        if !room_version.use_room_create_sender && content.creator.is_none() {
             warn!("no creator field found in m.room.create content");
             return Ok(false);
         }
@@ -343,9 +346,9 @@ pub fn auth_check<E: Event>(
         }
     } else {
         // If no power level event found the creator gets 100 everyone else gets 0
-        from_json_str::<RoomCreateEventContent>(room_create_event.content().get())
+        room_creator(room_version, &room_create_event)
             .ok()
-            .and_then(|create| (create.creator == *sender).then(|| int!(100)))
+            .and_then(|creator| (creator == *sender).then(|| int!(100)))
             .unwrap_or_default()
     };
 
@@ -420,6 +423,42 @@ pub fn auth_check<E: Event>(
     Ok(true)
 }
 
+/// Authenticate the incoming `event`, fetching the state asynchronously.
+///
+/// This is the same as [`auth_check`], except that `fetch_state` returns a future that can fail.
+/// The state needed to authenticate the event, as returned by [`auth_types_for_event`], is fetched
+/// before running the checks. An error returned by `fetch_state` is returned as [`Error::Fetch`],
+/// while `Ok(None)` means that there is no such state.
+pub async fn auth_check_async<E, Fut, FE>(
+    room_version: &RoomVersion,
+    incoming_event: impl Event,
+    current_third_party_invite: Option<impl Event>,
+    fetch_state: impl Fn(&StateEventType, &str) -> Fut,
+) -> Result<bool>
+where
+    E: Event,
+    Fut: Future<Output = std::result::Result<Option<E>, FE>>,
+    FE: std::error::Error + Send + Sync + 'static,
+{
+    let auth_types = auth_types_for_event(
+        incoming_event.event_type(),
+        incoming_event.sender(),
+        incoming_event.state_key(),
+        incoming_event.content(),
+    )?;
+
+    let mut auth_state = HashMap::new();
+    for (ty, state_key) in auth_types {
+        if let Some(event) = fetch_state(&ty, &state_key).await.map_err(Error::fetch)? {
+            auth_state.insert((ty, state_key), event);
+        }
+    }
+
+    auth_check(room_version, incoming_event, current_third_party_invite, |ty, state_key| {
+        auth_state.get(&(ty.clone(), state_key.to_owned()))
+    })
+}
+
 // TODO deserializing the member, power, join_rules event contents is done in conduit
 // just before this is called. Could they be passed in?
 /// Does the user who sent this member event have required power levels to do so.
@@ -527,10 +566,9 @@ fn valid_membership_change(
             let no_more_prev_events = prev_events.next().is_none();
 
             if prev_event_is_create_event && no_more_prev_events {
-                let create_content =
-                    from_json_str::<RoomCreateEventContent>(create_room.content().get())?;
+                let creator = room_creator(room_version, &create_room)?;
 
-                if create_content.creator == sender && create_content.creator == target_user {
+                if creator == sender && creator == target_user {
                     return Ok(true);
                 }
             }
@@ -925,6 +963,24 @@ fn get_send_level(
         .unwrap_or_else(|| if state_key.is_some() { int!(50) } else { int!(0) })
 }
 
+/// Get the creator of the room from its `m.room.create` event.
+///
+/// Since room version 11, the creator is the sender of the event, otherwise it is the `creator`
+/// field of the content.
+fn room_creator(room_version: &RoomVersion, room_create_event: impl Event) -> Result<OwnedUserId> {
+    #[derive(Deserialize)]
+    struct RoomCreateContentCreator {
+        creator: OwnedUserId,
+    }
+
+    if room_version.use_room_create_sender {
+        Ok(room_create_event.sender().to_owned())
+    } else {
+        let content: RoomCreateContentCreator = from_json_str(room_create_event.content().get())?;
+        Ok(content.creator)
+    }
+}
+
 fn verify_third_party_invite(
     target_user: Option<&UserId>,
     sender: &UserId,
@@ -981,8 +1037,9 @@ fn verify_third_party_invite(
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{io, sync::Arc};
 
+    use futures_util::{future, FutureExt};
     use ruma_common::events::{
         room::{
             join_rules::{
@@ -995,12 +1052,12 @@ mod tests {
     use serde_json::value::to_raw_value as to_raw_json_value;
 
     use crate::{
-        event_auth::valid_membership_change,
+        event_auth::{auth_check_async, valid_membership_change},
         test_utils::{
             alice, charlie, ella, event_id, member_content_ban, member_content_join, room_id,
             to_pdu_event, PduEvent, INITIAL_EVENTS, INITIAL_EVENTS_CREATE_ROOM,
         },
-        Event, EventTypeExt, RoomVersion, StateMap,
+        Error, Event, EventTypeExt, RoomVersion, StateMap,
     };
 
     #[test]
@@ -1296,4 +1353,108 @@ mod tests {
         )
         .unwrap());
     }
+
+    #[test]
+    fn test_auth_check_async() {
+        let _ =
+            tracing::subscriber::set_default(tracing_subscriber::fmt().with_test_writer().finish());
+        let events = INITIAL_EVENTS();
+
+        let auth_events = events
+            .values()
+            .map(|ev| (ev.event_type().with_state_key(ev.state_key().unwrap()), Arc::clone(ev)))
+            .collect::<StateMap<_>>();
+
+        let requester = to_pdu_event(
+            "HELLO",
+            alice(),
+            RoomEventType::RoomMember,
+            Some(charlie().as_str()),
+            member_content_ban(),
+            &["CREATE", "IMA", "IPOWER"],
+            &["IMC"],
+        );
+
+        let allowed =
+            auth_check_async(&RoomVersion::V6, &requester, None::<PduEvent>, |ty, key| {
+                future::ready(Ok::<_, io::Error>(
+                    auth_events.get(&ty.with_state_key(key)).map(Arc::clone),
+                ))
+            })
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(allowed);
+
+        let res = auth_check_async(&RoomVersion::V6, &requester, None::<PduEvent>, |_, _| {
+            future::ready(Err::<Option<Arc<PduEvent>>, _>(io::Error::new(
+                io::ErrorKind::Other,
+                "database unavailable",
+            )))
+        })
+        .now_or_never()
+        .unwrap();
+        assert!(matches!(res, Err(Error::Fetch(_))));
+    }
+
+    #[cfg(feature = "unstable-msc3820")]
+    #[test]
+    fn test_create_without_creator() {
+        use serde_json::json;
+
+        use crate::auth_check;
+
+        let _ =
+            tracing::subscriber::set_default(tracing_subscriber::fmt().with_test_writer().finish());
+
+        let create = to_pdu_event::<&str>(
+            "CREATE",
+            alice(),
+            RoomEventType::RoomCreate,
+            Some(""),
+            to_raw_json_value(&json!({ "room_version": "11" })).unwrap(),
+            &[],
+            &[],
+        );
+
+        // The `creator` field is required before room version 11.
+        let allowed =
+            auth_check(&RoomVersion::V10, &create, None::<PduEvent>, |_, _| None::<PduEvent>)
+                .unwrap();
+        assert!(!allowed);
+
+        let allowed =
+            auth_check(&RoomVersion::V11, &create, None::<PduEvent>, |_, _| None::<PduEvent>)
+                .unwrap();
+        assert!(allowed);
+
+        // The sender of the create event is the creator of the room.
+        let requester = to_pdu_event(
+            "HELLO",
+            alice(),
+            RoomEventType::RoomMember,
+            Some(alice().as_str()),
+            member_content_join(),
+            &["CREATE"],
+            &["CREATE"],
+        );
+        let target_user = alice();
+        let sender = alice();
+
+        assert!(valid_membership_change(
+            &RoomVersion::V11,
+            target_user,
+            None::<PduEvent>,
+            sender,
+            None::<PduEvent>,
+            &requester,
+            None::<PduEvent>,
+            None::<PduEvent>,
+            None::<PduEvent>,
+            None,
+            &MembershipState::Leave,
+            &create,
+        )
+        .unwrap());
+    }
 }
@@ -2,9 +2,12 @@ use std::{
     borrow::Borrow,
     cmp::Reverse,
     collections::{BinaryHeap, HashMap, HashSet},
+    convert::Infallible,
+    future::Future,
     hash::Hash,
 };
 
+use futures_util::{future, FutureExt, TryFutureExt};
 use itertools::Itertools;
 use js_int::{int, Int};
 use ruma_common::{
@@ -17,18 +20,28 @@ use ruma_common::{
 use serde_json::from_str as from_json_str;
 use tracing::{debug, info, trace, warn};
 
+pub mod auth_chain;
 mod error;
 pub mod event_auth;
+#[cfg(feature = "unstable-pdu")]
+pub mod pdu_builder;
+pub mod pdu_check;
 mod power_levels;
 pub mod room_version;
 mod state_event;
 #[cfg(test)]
 mod test_utils;
+mod v1;
 
+pub use auth_chain::{auth_chain_sets, AuthChainCache};
 pub use error::{Error, Result};
-pub use event_auth::{auth_check, auth_types_for_event};
+pub use event_auth::{auth_check, auth_check_async, auth_types_for_event};
+#[cfg(feature = "unstable-pdu")]
+pub use pdu_builder::{PduBuilder, ReferencedEvent};
+pub use pdu_check::{check_pdu, PduVerdict, RejectionReason};
 use power_levels::PowerLevelsContentFields;
 pub use room_version::RoomVersion;
+use room_version::StateResolutionVersion;
 pub use state_event::Event;
 
 /// A mapping of event type and state_key to some value `T`, usually an `EventId`.
@@ -45,11 +58,15 @@ pub type StateMap<T> = HashMap<(StateEventType, String), T>;
 ///   the state of a room.
 ///
 /// * `auth_chain_sets` - The full recursive set of `auth_events` for each event in the
-///   `state_sets`.
+///   `state_sets`. This is not used by the state resolution algorithm of room version 1. See
+///   [`auth_chain_sets`] for a helper to compute it.
 ///
 /// * `fetch_event` - Any event not found in the `event_map` will defer to this closure to find the
 ///   event.
 ///
+/// The state resolution algorithm is selected based on the `room_version`. See [`resolve_async`]
+/// for a version of this function that fetches events asynchronously.
+///
 /// ## Invariants
 ///
 /// The caller of `resolve` must ensure that all the events are from the same room. Although this
@@ -65,6 +82,41 @@ where
     E::Id: 'a,
     SetIter: Iterator<Item = &'a StateMap<E::Id>> + Clone,
 {
+    resolve_async(room_version, state_sets, auth_chain_sets, |id| {
+        future::ready(Ok::<_, Infallible>(fetch_event(id)))
+    })
+    .now_or_never()
+    .expect("state resolution with a synchronous `fetch_event` never has to wait")
+}
+
+/// Resolve sets of state events as they come in, fetching events asynchronously.
+///
+/// This is the same as [`resolve`], except that `fetch_event` returns a future that can fail. An
+/// error returned by `fetch_event` aborts the resolution and is returned as [`Error::Fetch`],
+/// while `Ok(None)` means that the event is missing and is handled like in [`resolve`].
+///
+/// The future returned by this function is `Send` if the events, their IDs, `fetch_event` and the
+/// futures it returns are `Send` and `Sync`.
+pub async fn resolve_async<'a, E, SetIter, Fut, FE>(
+    room_version: &RoomVersionId,
+    state_sets: impl IntoIterator<IntoIter = SetIter>,
+    auth_chain_sets: Vec<HashSet<E::Id>>,
+    fetch_event: impl Fn(&EventId) -> Fut,
+) -> Result<StateMap<E::Id>>
+where
+    E: Event + Clone,
+    E::Id: 'a,
+    SetIter: Iterator<Item = &'a StateMap<E::Id>> + Clone,
+    Fut: Future<Output = std::result::Result<Option<E>, FE>>,
+    FE: std::error::Error + Send + Sync + 'static,
+{
+    let fetch_event = |id: &EventId| fetch_event(id).map_err(Error::fetch);
+
+    let room_version = RoomVersion::new(room_version)?;
+    if let StateResolutionVersion::V1 = room_version.state_res {
+        return v1::resolve(&room_version, state_sets.into_iter(), &fetch_event).await;
+    }
+
     info!("State resolution starting");
 
     // Split non-conflicting and conflicting state
@@ -83,11 +135,13 @@ where
 
     // `all_conflicted` contains unique items
     // synapse says `full_set = {eid for eid in full_conflicted_set if eid in event_map}`
-    let all_conflicted: HashSet<_> = get_auth_chain_diff(auth_chain_sets)
-        .chain(conflicting.into_values().flatten())
+    let mut all_conflicted = HashSet::new();
+    for id in get_auth_chain_diff(auth_chain_sets).chain(conflicting.into_values().flatten()) {
         // Don't honor events we cannot "verify"
-        .filter(|id| fetch_event(id.borrow()).is_some())
-        .collect();
+        if fetch_event(id.borrow()).await?.is_some() {
+            all_conflicted.insert(id);
+        }
+    }
 
     info!("full conflicted set: {}", all_conflicted.len());
     debug!("{all_conflicted:?}");
@@ -96,23 +150,24 @@ where
     // this is now a check the caller of `resolve` must make.
 
     // Get only the control events with a state_key: "" or ban/kick event (sender != state_key)
-    let control_events = all_conflicted
-        .iter()
-        .filter(|&id| is_power_event_id(id.borrow(), &fetch_event))
-        .cloned()
-        .collect::<Vec<_>>();
+    let mut control_events = Vec::new();
+    for id in &all_conflicted {
+        if is_power_event_id(id.borrow(), &fetch_event).await? {
+            control_events.push(id.clone());
+        }
+    }
 
     // Sort the control events based on power_level/clock/event_id and outgoing/incoming edges
     let sorted_control_levels =
-        reverse_topological_power_sort(control_events, &all_conflicted, &fetch_event)?;
+        reverse_topological_power_sort(control_events, &all_conflicted, &fetch_event).await?;
 
     debug!("sorted control events: {}", sorted_control_levels.len());
     trace!("{sorted_control_levels:?}");
 
-    let room_version = RoomVersion::new(room_version)?;
     // Sequentially auth check each control event.
     let resolved_control =
-        iterative_auth_check(&room_version, &sorted_control_levels, clean.clone(), &fetch_event)?;
+        iterative_auth_check(&room_version, &sorted_control_levels, clean.clone(), &fetch_event)
+            .await?;
 
     debug!("resolved control events: {}", resolved_control.len());
     trace!("{resolved_control:?}");
@@ -137,7 +192,8 @@ where
 
     debug!("power event: {power_event:?}");
 
-    let sorted_left_events = mainline_sort(&events_to_resolve, power_event.cloned(), &fetch_event)?;
+    let sorted_left_events =
+        mainline_sort(&events_to_resolve, power_event.cloned(), &fetch_event).await?;
 
     trace!("events left, sorted: {sorted_left_events:?}");
 
@@ -146,7 +202,8 @@ where
         &sorted_left_events,
         resolved_control, // The control events are added to the final resolved state
         &fetch_event,
-    )?;
+    )
+    .await?;
 
     // Add unconflicted state to the resolved state
     // We priorities the unconflicting state
@@ -211,39 +268,41 @@ where
 ///
 /// The power level is negative because a higher power level is equated to an earlier (further back
 /// in time) origin server timestamp.
-fn reverse_topological_power_sort<E: Event>(
+async fn reverse_topological_power_sort<E, F, Fut>(
     events_to_sort: Vec<E::Id>,
     auth_diff: &HashSet<E::Id>,
-    fetch_event: impl Fn(&EventId) -> Option<E>,
-) -> Result<Vec<E::Id>> {
+    fetch_event: &F,
+) -> Result<Vec<E::Id>>
+where
+    E: Event,
+    F: Fn(&EventId) -> Fut,
+    Fut: Future<Output = Result<Option<E>>>,
+{
     debug!("reverse topological sort of power events");
 
     let mut graph = HashMap::new();
     for event_id in events_to_sort {
-        add_event_and_auth_chain_to_graph(&mut graph, event_id, auth_diff, &fetch_event);
-
-        // TODO: if these functions are ever made async here
-        // is a good place to yield every once in a while so other
-        // tasks can make progress
+        add_event_and_auth_chain_to_graph(&mut graph, event_id, auth_diff, fetch_event).await?;
     }
 
     // This is used in the `key_fn` passed to the lexico_topo_sort fn
     let mut event_to_pl = HashMap::new();
     for event_id in graph.keys() {
-        let pl = get_power_level_for_sender(event_id.borrow(), &fetch_event)?;
+        let pl = get_power_level_for_sender(event_id.borrow(), fetch_event).await?;
         info!("{event_id} power level {pl}");
 
-        event_to_pl.insert(event_id.clone(), pl);
+        let ev = fetch_event(event_id.borrow())
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("Failed to find {event_id}")))?;
 
-        // TODO: if these functions are ever made async here
-        // is a good place to yield every once in a while so other
-        // tasks can make progress
+        event_to_pl.insert(event_id.clone(), (pl, ev.origin_server_ts()));
     }
 
     lexicographical_topological_sort(&graph, |event_id| {
-        let ev = fetch_event(event_id).ok_or_else(|| Error::NotFound("".into()))?;
-        let pl = *event_to_pl.get(event_id).ok_or_else(|| Error::NotFound("".into()))?;
-        Ok((pl, ev.origin_server_ts()))
+        event_to_pl
+            .get(event_id)
+            .copied()
+            .ok_or_else(|| Error::NotFound(format!("Failed to find {event_id}")))
     })
 }
 
@@ -341,17 +400,19 @@ where
 /// Do NOT use this any where but topological sort, we find the power level for the eventId
 /// at the eventId's generation (we walk backwards to `EventId`s most recent previous power level
 /// event).
-fn get_power_level_for_sender<E: Event>(
-    event_id: &EventId,
-    fetch_event: impl Fn(&EventId) -> Option<E>,
-) -> serde_json::Result<Int> {
+async fn get_power_level_for_sender<E, F, Fut>(event_id: &EventId, fetch_event: &F) -> Result<Int>
+where
+    E: Event,
+    F: Fn(&EventId) -> Fut,
+    Fut: Future<Output = Result<Option<E>>>,
+{
     info!("fetch event ({event_id}) senders power level");
 
-    let event = fetch_event(event_id);
+    let event = fetch_event(event_id).await?;
     let mut pl = None;
 
-    for aid in event.as_ref().map(|pdu| pdu.auth_events()).into_iter().flatten() {
-        if let Some(aev) = fetch_event(aid.borrow()) {
+    for aid in event.as_ref().map(auth_event_ids).into_iter().flatten() {
+        if let Some(aev) = fetch_event(aid.borrow()).await? {
             if is_type_and_key(&aev, &RoomEventType::RoomPowerLevels, "") {
                 pl = Some(aev);
                 break;
@@ -383,12 +444,17 @@ fn get_power_level_for_sender<E: Event>(
 ///
 /// For each `events_to_check` event we gather the events needed to auth it from the the
 /// `fetch_event` closure and verify each event using the `event_auth::auth_check` function.
-fn iterative_auth_check<E: Event + Clone>(
+async fn iterative_auth_check<E, F, Fut>(
     room_version: &RoomVersion,
     events_to_check: &[E::Id],
     unconflicted_state: StateMap<E::Id>,
-    fetch_event: impl Fn(&EventId) -> Option<E>,
-) -> Result<StateMap<E::Id>> {
+    fetch_event: &F,
+) -> Result<StateMap<E::Id>>
+where
+    E: Event + Clone,
+    F: Fn(&EventId) -> Fut,
+    Fut: Future<Output = Result<Option<E>>>,
+{
     info!("starting iterative auth check");
 
     debug!("performing auth checks on {events_to_check:?}");
@@ -397,14 +463,15 @@ fn iterative_auth_check<E: Event + Clone>(
 
     for event_id in events_to_check {
         let event = fetch_event(event_id.borrow())
+            .await?
             .ok_or_else(|| Error::NotFound(format!("Failed to find {event_id}")))?;
         let state_key = event
             .state_key()
             .ok_or_else(|| Error::InvalidPdu("State event had no state key".to_owned()))?;
 
         let mut auth_events = StateMap::new();
-        for aid in event.auth_events() {
-            if let Some(ev) = fetch_event(aid.borrow()) {
+        for aid in auth_event_ids(&event) {
+            if let Some(ev) = fetch_event(aid.borrow()).await? {
                 // TODO synapse check "rejected_reason" which is most likely
                 // related to soft-failing
                 auth_events.insert(
@@ -425,7 +492,7 @@ fn iterative_auth_check<E: Event + Clone>(
             event.content(),
         )? {
             if let Some(ev_id) = resolved_state.get(&key) {
-                if let Some(event) = fetch_event(ev_id.borrow()) {
+                if let Some(event) = fetch_event(ev_id.borrow()).await? {
                     // TODO synapse checks `rejected_reason` is None here
                     auth_events.insert(key.to_owned(), event);
                 }
@@ -449,10 +516,6 @@ fn iterative_auth_check<E: Event + Clone>(
             // synapse passes here on AuthError. We do not add this event to resolved_state.
             warn!("event {event_id} failed the authentication check");
         }
-
-        // TODO: if these functions are ever made async here
-        // is a good place to yield every once in a while so other
-        // tasks can make progress
     }
     Ok(resolved_state)
 }
@@ -464,11 +527,16 @@ fn iterative_auth_check<E: Event + Clone>(
 /// power_level event. If there have been two power events the after the most recent are depth 0,
 /// the events before (with the first power level as a parent) will be marked as depth 1. depth 1 is
 /// "older" than depth 0.
-fn mainline_sort<E: Event>(
+async fn mainline_sort<E, F, Fut>(
     to_sort: &[E::Id],
     resolved_power_level: Option<E::Id>,
-    fetch_event: impl Fn(&EventId) -> Option<E>,
-) -> Result<Vec<E::Id>> {
+    fetch_event: &F,
+) -> Result<Vec<E::Id>>
+where
+    E: Event,
+    F: Fn(&EventId) -> Fut,
+    Fut: Future<Output = Result<Option<E>>>,
+{
     debug!("mainline sort of events");
 
     // There are no EventId's to sort, bail.
@@ -482,19 +550,18 @@ fn mainline_sort<E: Event>(
         mainline.push(p.clone());
 
         let event = fetch_event(p.borrow())
+            .await?
             .ok_or_else(|| Error::NotFound(format!("Failed to find {p}")))?;
         pl = None;
-        for aid in event.auth_events() {
+        for aid in auth_event_ids(&event) {
             let ev = fetch_event(aid.borrow())
+                .await?
                 .ok_or_else(|| Error::NotFound(format!("Failed to find {aid}")))?;
             if is_type_and_key(&ev, &RoomEventType::RoomPowerLevels, "") {
-                pl = Some(aid.to_owned());
+                pl = Some(aid);
                 break;
             }
         }
-        // TODO: if these functions are ever made async here
-        // is a good place to yield every once in a while so other
-        // tasks can make progress
     }
 
     let mainline_map = mainline
@@ -506,18 +573,19 @@ fn mainline_sort<E: Event>(
 
     let mut order_map = HashMap::new();
     for ev_id in to_sort.iter() {
-        if let Some(event) = fetch_event(ev_id.borrow()) {
-            if let Ok(depth) = get_mainline_depth(Some(event), &mainline_map, &fetch_event) {
-                order_map.insert(
-                    ev_id,
-                    (depth, fetch_event(ev_id.borrow()).map(|ev| ev.origin_server_ts()), ev_id),
-                );
+        // Not matched directly to avoid holding the `Result` across the `.await` below.
+        let event = fetch_event(ev_id.borrow()).await?;
+        if let Some(event) = event {
+            let origin_server_ts = event.origin_server_ts();
+            match get_mainline_depth(Some(event), &mainline_map, fetch_event).await {
+                Ok(depth) => {
+                    order_map.insert(ev_id, (depth, origin_server_ts, ev_id));
+                }
+                // Events with missing auth events are skipped.
+                Err(Error::NotFound(_)) => {}
+                Err(error) => return Err(error),
             }
         }
-
-        // TODO: if these functions are ever made async here
-        // is a good place to yield every once in a while so other
-        // tasks can make progress
     }
 
     // Sort the event_ids by their depth, timestamp and EventId
@@ -530,11 +598,16 @@ fn mainline_sort<E: Event>(
 
 /// Get the mainline depth from the `mainline_map` or finds a power_level event that has an
 /// associated mainline depth.
-fn get_mainline_depth<E: Event>(
+async fn get_mainline_depth<E, F, Fut>(
     mut event: Option<E>,
     mainline_map: &HashMap<E::Id, usize>,
-    fetch_event: impl Fn(&EventId) -> Option<E>,
-) -> Result<usize> {
+    fetch_event: &F,
+) -> Result<usize>
+where
+    E: Event,
+    F: Fn(&EventId) -> Fut,
+    Fut: Future<Output = Result<Option<E>>>,
+{
     while let Some(sort_ev) = event {
         debug!("mainline event_id {}", sort_ev.event_id());
         let id = sort_ev.event_id();
@@ -543,8 +616,9 @@ fn get_mainline_depth<E: Event>(
         }
 
         event = None;
-        for aid in sort_ev.auth_events() {
+        for aid in auth_event_ids(&sort_ev) {
             let aev = fetch_event(aid.borrow())
+                .await?
                 .ok_or_else(|| Error::NotFound(format!("Failed to find {aid}")))?;
             if is_type_and_key(&aev, &RoomEventType::RoomPowerLevels, "") {
                 event = Some(aev);
@@ -556,36 +630,56 @@ fn get_mainline_depth<E: Event>(
     Ok(0)
 }
 
-fn add_event_and_auth_chain_to_graph<E: Event>(
+async fn add_event_and_auth_chain_to_graph<E, F, Fut>(
     graph: &mut HashMap<E::Id, HashSet<E::Id>>,
     event_id: E::Id,
     auth_diff: &HashSet<E::Id>,
-    fetch_event: impl Fn(&EventId) -> Option<E>,
-) {
+    fetch_event: &F,
+) -> Result<()>
+where
+    E: Event,
+    F: Fn(&EventId) -> Fut,
+    Fut: Future<Output = Result<Option<E>>>,
+{
     let mut state = vec![event_id];
     while let Some(eid) = state.pop() {
         graph.entry(eid.clone()).or_default();
         // Prefer the store to event as the store filters dedups the events
         for aid in
-            fetch_event(eid.borrow()).as_ref().map(|ev| ev.auth_events()).into_iter().flatten()
+            fetch_event(eid.borrow()).await?.as_ref().map(auth_event_ids).into_iter().flatten()
         {
             if auth_diff.contains(aid.borrow()) {
                 if !graph.contains_key(aid.borrow()) {
-                    state.push(aid.to_owned());
+                    state.push(aid.clone());
                 }
 
                 // We just inserted this at the start of the while loop
-                graph.get_mut(eid.borrow()).unwrap().insert(aid.to_owned());
+                graph.get_mut(eid.borrow()).unwrap().insert(aid);
             }
         }
     }
+
+    Ok(())
 }
 
-fn is_power_event_id<E: Event>(event_id: &EventId, fetch: impl Fn(&EventId) -> Option<E>) -> bool {
-    match fetch(event_id).as_ref() {
+async fn is_power_event_id<E, F, Fut>(event_id: &EventId, fetch: &F) -> Result<bool>
+where
+    E: Event,
+    F: Fn(&EventId) -> Fut,
+    Fut: Future<Output = Result<Option<E>>>,
+{
+    Ok(match fetch(event_id).await?.as_ref() {
         Some(state) => is_power_event(state),
         _ => false,
-    }
+    })
+}
+
+/// Collect the IDs of the auth events of `event`.
+///
+/// This avoids holding the iterator returned by `Event::auth_events` across an `.await`, which
+/// would make the future not `Send`.
+fn auth_event_ids<E: Event>(event: &E) -> Vec<E::Id> {
+    event.auth_events().cloned().collect()
 }
 
 fn is_type_and_key(ev: impl Event, ev_type: &RoomEventType, state_key: &str) -> bool {
@@ -640,9 +734,11 @@ where
 mod tests {
     use std::{
         collections::{HashMap, HashSet},
+        io,
         sync::Arc,
     };
 
+    use futures_util::{future, FutureExt};
     use js_int::{int, uint};
     use maplit::{hashmap, hashset};
     use rand::seq::SliceRandom;
@@ -651,7 +747,7 @@ mod tests {
             room::join_rules::{JoinRule, RoomJoinRulesEventContent},
             RoomEventType, StateEventType,
         },
-        MilliSecondsSinceUnixEpoch, OwnedEventId, RoomVersionId,
+        EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, RoomVersionId,
     };
     use serde_json::{json, value::to_raw_value as to_raw_json_value};
     use tracing::debug;
@@ -663,7 +759,7 @@ mod tests {
             alice, bob, charlie, do_check, ella, event_id, member_content_ban, member_content_join,
             room_id, to_init_pdu_event, to_pdu_event, zara, PduEvent, TestStore, INITIAL_EVENTS,
         },
-        Event, EventTypeExt, StateMap,
+        Error, Event, EventTypeExt, StateMap,
     };
 
     fn test_event_sort() {
@@ -684,18 +780,22 @@ mod tests {
             .map(|pdu| pdu.event_id.clone())
             .collect::<Vec<_>>();
 
+        let fetch_event = |id: &EventId| future::ready(Ok(events.get(id).map(Arc::clone)));
+
         let sorted_power_events =
-            crate::reverse_topological_power_sort(power_events, &auth_chain, |id| {
-                events.get(id).map(Arc::clone)
-            })
-            .unwrap();
+            crate::reverse_topological_power_sort(power_events, &auth_chain, &fetch_event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
 
         let resolved_power = crate::iterative_auth_check(
             &RoomVersion::V6,
             &sorted_power_events,
             HashMap::new(), // unconflicted events
-            |id| events.get(id).map(Arc::clone),
+            &fetch_event,
         )
+        .now_or_never()
+        .unwrap()
         .expect("iterative auth check failed on resolved events");
 
         // don't remove any events so we know it sorts them all correctly
@@ -706,9 +806,10 @@ mod tests {
         let power_level =
             resolved_power.get(&(StateEventType::RoomPowerLevels, "".to_owned())).cloned();
 
-        let sorted_event_ids =
-            crate::mainline_sort(&events_to_sort, power_level, |id| events.get(id).map(Arc::clone))
-                .unwrap();
+        let sorted_event_ids = crate::mainline_sort(&events_to_sort, power_level, &fetch_event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
 
         assert_eq!(
             vec![
@@ -1069,6 +1170,73 @@ mod tests {
         assert_eq!(expected, resolved);
     }
 
+    #[test]
+    fn test_resolve_async() {
+        let mut store = TestStore::<PduEvent>(hashmap! {});
+        let (state_at_bob, state_at_charlie, expected) = store.set_up();
+
+        let ev_map = store.0.clone();
+        let state_sets = [state_at_bob, state_at_charlie];
+        let auth_chain_sets = state_sets
+            .iter()
+            .map(|map| store.auth_event_ids(room_id(), map.values().cloned().collect()).unwrap())
+            .collect::<Vec<_>>();
+
+        let resolve = crate::resolve_async(
+            &RoomVersionId::V6,
+            &state_sets,
+            auth_chain_sets.clone(),
+            |id: &EventId| future::ready(Ok::<_, io::Error>(ev_map.get(id).map(Arc::clone))),
+        );
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&resolve);
+
+        assert_eq!(resolve.now_or_never().unwrap().unwrap(), expected);
+
+        // Errors of the fetcher are not treated as missing events.
+        let res = crate::resolve_async(&RoomVersionId::V6, &state_sets, auth_chain_sets, |id| {
+            let res = if id == event_id("IMB") {
+                Err(io::Error::new(io::ErrorKind::Other, "database unavailable"))
+            } else {
+                Ok(ev_map.get(id).map(Arc::clone))
+            };
+            future::ready(res)
+        })
+        .now_or_never()
+        .unwrap();
+
+        assert!(matches!(res, Err(Error::Fetch(_))));
+    }
+
+    #[test]
+    fn mainline_sort_fetch_errors() {
+        let events = INITIAL_EVENTS();
+        let to_sort = [event_id("IPOWER")];
+
+        // Events with a missing auth event are skipped.
+        let sorted = crate::mainline_sort(&to_sort, None, &|id: &EventId| {
+            let res = if id == event_id("CREATE") { None } else { events.get(id).map(Arc::clone) };
+            future::ready(Ok(res))
+        })
+        .now_or_never()
+        .unwrap()
+        .unwrap();
+        assert!(sorted.is_empty());
+
+        // Errors of the fetcher are returned.
+        let res = crate::mainline_sort(&to_sort, None, &|id: &EventId| {
+            let res = if id == event_id("CREATE") {
+                Err(Error::fetch(io::Error::new(io::ErrorKind::Other, "database unavailable")))
+            } else {
+                Ok(events.get(id).map(Arc::clone))
+            };
+            future::ready(res)
+        })
+        .now_or_never()
+        .unwrap();
+        assert!(matches!(res, Err(Error::Fetch(_))));
+    }
+
     #[test]
     fn test_lexicographical_sort() {
         let _ =
@@ -0,0 +1,434 @@
+//! A builder for the PDUs sent by a homeserver.
+//!
+//! [`PduBuilder`] takes the content of a new event and fills in the fields that are derived from
+//! the room: `prev_events`, `auth_events`, `depth`, as well as the `hashes` and `signatures` of
+//! the originating server, using the PDU format of the room version.
+
+use std::{borrow::Borrow, collections::BTreeMap, sync::Arc};
+
+use js_int::{uint, UInt};
+use ruma_common::{
+    canonical_json::{to_canonical_value, CanonicalJsonValue},
+    events::{
+        pdu::{EventHash, Pdu, RoomV1Pdu, RoomV3Pdu},
+        MessageLikeEventContent, RoomEventType, StateEventContent, StateEventType,
+    },
+    serde::JsonObject,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, RoomId, RoomVersionId, UserId,
+};
+use ruma_signatures::{compute_event_id, hash_and_sign_event, KeyPair};
+use serde_json::{
+    from_str as from_json_str, from_value as from_json_value, to_value as to_json_value,
+    value::{to_raw_value as to_raw_json_value, RawValue as RawJsonValue},
+};
+
+use crate::{
+    auth_types_for_event, room_version::EventFormatVersion, Error, Event, Result, RoomVersion,
+};
+
+/// An event that can be referenced in the `prev_events` or `auth_events` of a new PDU.
+pub trait ReferencedEvent: Event {
+    /// The reference hash of this event.
+    ///
+    /// It is only needed for room versions that use the original event format, where events are
+    /// referenced by their ID and reference hash. It can return `None` otherwise.
+    fn reference_hash(&self) -> Option<EventHash> {
+        None
+    }
+}
+
+impl<T: ReferencedEvent> ReferencedEvent for &T {
+    fn reference_hash(&self) -> Option<EventHash> {
+        (*self).reference_hash()
+    }
+}
+
+impl<T: ReferencedEvent> ReferencedEvent for Arc<T> {
+    fn reference_hash(&self) -> Option<EventHash> {
+        (**self).reference_hash()
+    }
+}
+
+/// A builder for a new PDU.
+///
+/// It is created from the content of the event with [`PduBuilder::state()`] or
+/// [`PduBuilder::message_like()`], and turned into a signed and hashed [`Pdu`] with
+/// [`PduBuilder::build()`].
+#[derive(Clone, Debug)]
+pub struct PduBuilder {
+    event_type: RoomEventType,
+    content: Box<RawJsonValue>,
+    state_key: Option<String>,
+    redacts: Option<OwnedEventId>,
+    origin_server_ts: Option<MilliSecondsSinceUnixEpoch>,
+}
+
+impl PduBuilder {
+    /// Creates a builder for a state event with the given state key and content.
+    ///
+    /// Returns an error if the content fails to serialize.
+    pub fn state<C: StateEventContent>(
+        state_key: &C::StateKey,
+        content: &C,
+    ) -> serde_json::Result<Self> {
+        Ok(Self {
+            event_type: content.event_type().into(),
+            content: to_raw_json_value(content)?,
+            state_key: Some(state_key.as_ref().to_owned()),
+            redacts: None,
+            origin_server_ts: None,
+        })
+    }
+
+    /// Creates a builder for a message-like event with the given content.
+    ///
+    /// Returns an error if the content fails to serialize.
+    pub fn message_like<C: MessageLikeEventContent>(content: &C) -> serde_json::Result<Self> {
+        Ok(Self {
+            event_type: content.event_type().into(),
+            content: to_raw_json_value(content)?,
+            state_key: None,
+            redacts: None,
+            origin_server_ts: None,
+        })
+    }
+
+    /// Sets the ID of the event redacted by this event.
+    ///
+    /// This is only used by `m.room.redaction` events. It is put in the `redacts` field of the
+    /// content in room versions where the redacted event is part of the content, and at the top
+    /// level of the PDU otherwise.
+    pub fn redacts(self, event_id: OwnedEventId) -> Self {
+        Self { redacts: Some(event_id), ..self }
+    }
+
+    /// Sets the timestamp of the event.
+    ///
+    /// Defaults to the current time.
+    pub fn origin_server_ts(self, origin_server_ts: MilliSecondsSinceUnixEpoch) -> Self {
+        Self { origin_server_ts: Some(origin_server_ts), ..self }
+    }
+
+    /// Builds the PDU, and returns it with its event ID.
+    ///
+    /// ## Arguments
+    ///
+    /// * `room_version_id` - The version of the room, which determines the format of the PDU.
+    ///
+    /// * `room_id` - The ID of the room the event is sent in.
+    ///
+    /// * `sender` - The user sending the event. The PDU is hashed and signed by their server.
+    ///
+    /// * `prev_events` - The forward extremities of the room, which become the `prev_events` of the
+    ///   PDU.
+    ///
+    /// * `fetch_state` - Function to fetch the current state of the room by event type and state
+    ///   key. It is used to select the `auth_events` of the PDU.
+    ///
+    /// * `key_pair` - The signing key of the server of `sender`.
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`Error::Unsupported`] if the room version is not supported, and
+    /// [`Error::InvalidPdu`] if the PDU cannot be converted to canonical JSON or if a referenced
+    /// event is missing its reference hash in a room version that needs it.
+    pub fn build<E: ReferencedEvent, K: KeyPair>(
+        self,
+        room_version_id: &RoomVersionId,
+        room_id: &RoomId,
+        sender: &UserId,
+        prev_events: &[E],
+        fetch_state: impl Fn(&StateEventType, &str) -> Option<E>,
+        key_pair: &K,
+    ) -> Result<(OwnedEventId, Pdu)> {
+        let room_version = RoomVersion::new(room_version_id)?;
+
+        let auth_events: Vec<E> = auth_types_for_event(
+            &self.event_type,
+            sender,
+            self.state_key.as_deref(),
+            &self.content,
+        )?
+        .into_iter()
+        .filter_map(|(event_type, state_key)| fetch_state(&event_type, &state_key))
+        .collect();
+
+        let depth = prev_events
+            .iter()
+            .map(|ev| ev.depth())
+            .max()
+            .map_or(uint!(1), |depth| depth.checked_add(uint!(1)).unwrap_or(UInt::MAX));
+        let origin_server_ts =
+            self.origin_server_ts.unwrap_or_else(MilliSecondsSinceUnixEpoch::now);
+        let (content, redacts) = match self.redacts {
+            Some(redacts) if room_version.redacts_in_content => {
+                (content_with_redacts(&self.content, &redacts)?, None)
+            }
+            redacts => (self.content, redacts),
+        };
+
+        let pdu = match room_version.event_format {
+            EventFormatVersion::V1 => Pdu::RoomV1Pdu(RoomV1Pdu {
+                event_id: EventId::new(sender.server_name()),
+                room_id: room_id.to_owned(),
+                sender: sender.to_owned(),
+                origin_server_ts,
+                kind: self.event_type,
+                content,
+                state_key: self.state_key,
+                prev_events: prev_events.iter().map(with_reference_hash).collect::<Result<_>>()?,
+                depth,
+                auth_events: auth_events.iter().map(with_reference_hash).collect::<Result<_>>()?,
+                redacts,
+                unsigned: BTreeMap::new(),
+                hashes: EventHash::new(String::new()),
+                signatures: BTreeMap::new(),
+            }),
+            _ => Pdu::RoomV3Pdu(RoomV3Pdu {
+                room_id: room_id.to_owned(),
+                sender: sender.to_owned(),
+                origin_server_ts,
+                kind: self.event_type,
+                content,
+                state_key: self.state_key,
+                prev_events: prev_events
+                    .iter()
+                    .map(|ev| ev.event_id().borrow().to_owned())
+                    .collect(),
+                depth,
+                auth_events: auth_events
+                    .iter()
+                    .map(|ev| ev.event_id().borrow().to_owned())
+                    .collect(),
+                redacts,
+                unsigned: BTreeMap::new(),
+                hashes: EventHash::new(String::new()),
+                signatures: BTreeMap::new(),
+            }),
+        };
+
+        let mut object = match to_canonical_value(&pdu) {
+            Ok(CanonicalJsonValue::Object(object)) => object,
+            Ok(_) => return Err(Error::InvalidPdu("PDU is not a JSON object".to_owned())),
+            Err(e) => return Err(Error::InvalidPdu(format!("PDU is not canonical JSON: {e}"))),
+        };
+        hash_and_sign_event(sender.server_name().as_str(), key_pair, &mut object, room_version_id)?;
+
+        let event_id = match &pdu {
+            Pdu::RoomV1Pdu(pdu) => pdu.event_id.clone(),
+            _ => compute_event_id(&object, room_version_id)?,
+        };
+
+        Ok((event_id, from_json_value(to_json_value(&object)?)?))
+    }
+}
+
+/// Add the given `redacts` field to the given content.
+fn content_with_redacts(content: &RawJsonValue, redacts: &EventId) -> Result<Box<RawJsonValue>> {
+    let mut content: JsonObject = from_json_str(content.get())
+        .map_err(|e| Error::InvalidPdu(format!("content is not a JSON object: {e}")))?;
+    content.insert("redacts".to_owned(), redacts.as_str().into());
+
+    Ok(to_raw_json_value(&content)?)
+}
+
+/// Get the ID and reference hash of the given event, for the original event format.
+fn with_reference_hash<E: ReferencedEvent>(event: &E) -> Result<(OwnedEventId, EventHash)> {
+    let event_id = event.event_id().borrow();
+    let hash = event
+        .reference_hash()
+        .ok_or_else(|| Error::InvalidPdu(format!("missing reference hash for {event_id}")))?;
+
+    Ok((event_id.to_owned(), hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use maplit::btreemap;
+    use ruma_common::{
+        canonical_json::{to_canonical_value, CanonicalJsonValue},
+        events::{
+            pdu::Pdu,
+            room::{
+                create::RoomCreateEventContent, message::RoomMessageEventContent,
+                redaction::RoomRedactionEventContent, topic::RoomTopicEventContent,
+            },
+            EmptyStateKey, StateEventType,
+        },
+        serde::Base64,
+        CanonicalJsonObject, MilliSecondsSinceUnixEpoch, RoomVersionId,
+    };
+    use ruma_signatures::{
+        reference_hash, verify_event, verify_event_id, Ed25519KeyPair, PublicKeyMap, Verified,
+    };
+
+    use super::PduBuilder;
+    use crate::{
+        test_utils::{alice, event_id, room_id, PduEvent, INITIAL_EVENTS},
+        Event, EventTypeExt, StateMap,
+    };
+
+    fn key_pair() -> Ed25519KeyPair {
+        Ed25519KeyPair::from_der(&Ed25519KeyPair::generate().unwrap(), "1".into()).unwrap()
+    }
+
+    fn public_key_map(key_pair: &Ed25519KeyPair) -> PublicKeyMap {
+        btreemap! {
+            "foo".to_owned() => btreemap! {
+                "ed25519:1".to_owned() => Base64::new(key_pair.public_key().to_owned()),
+            },
+        }
+    }
+
+    fn to_canonical_object(pdu: &Pdu) -> CanonicalJsonObject {
+        match to_canonical_value(pdu).unwrap() {
+            CanonicalJsonValue::Object(object) => object,
+            _ => unreachable!("PDU is an object"),
+        }
+    }
+
+    #[test]
+    fn build_state_event() {
+        let key_pair = key_pair();
+        let events = INITIAL_EVENTS();
+        let state = events
+            .values()
+            .filter_map(|ev| {
+                ev.state_key().map(|key| (ev.event_type().with_state_key(key), ev.clone()))
+            })
+            .collect::<StateMap<_>>();
+
+        let (id, pdu) =
+            PduBuilder::state(&EmptyStateKey, &RoomTopicEventContent::new("Hello".to_owned()))
+                .unwrap()
+                .origin_server_ts(MilliSecondsSinceUnixEpoch(1_u32.into()))
+                .build(
+                    &RoomVersionId::V9,
+                    room_id(),
+                    alice(),
+                    &[events[&event_id("START")].clone()],
+                    |ty, key| state.get(&ty.with_state_key(key)).cloned(),
+                    &key_pair,
+                )
+                .unwrap();
+
+        let pdu_v3 = match &pdu {
+            Pdu::RoomV3Pdu(pdu) => pdu,
+            _ => panic!("expected a PDU in the room v3 format"),
+        };
+        assert_eq!(pdu_v3.prev_events, vec![event_id("START")]);
+        assert_eq!(
+            pdu_v3.auth_events,
+            vec![event_id("IPOWER"), event_id("IMA"), event_id("CREATE")]
+        );
+        assert_eq!(pdu_v3.depth, 1_u32.into());
+
+        let object = to_canonical_object(&pdu);
+        assert_eq!(
+            verify_event(&public_key_map(&key_pair), &object, &RoomVersionId::V9).unwrap(),
+            Verified::All
+        );
+        verify_event_id(&id, &object, &RoomVersionId::V9).unwrap();
+    }
+
+    #[test]
+    fn build_redaction_event() {
+        let key_pair = key_pair();
+        let events = INITIAL_EVENTS();
+        let state = events
+            .values()
+            .filter_map(|ev| {
+                ev.state_key().map(|key| (ev.event_type().with_state_key(key), ev.clone()))
+            })
+            .collect::<StateMap<_>>();
+        let build = |room_version_id: &RoomVersionId| {
+            PduBuilder::message_like(&RoomRedactionEventContent::new())
+                .unwrap()
+                .redacts(event_id("START"))
+                .build(
+                    room_version_id,
+                    room_id(),
+                    alice(),
+                    &[events[&event_id("START")].clone()],
+                    |ty, key| state.get(&ty.with_state_key(key)).cloned(),
+                    &key_pair,
+                )
+                .unwrap()
+                .1
+        };
+
+        let pdu_v3 = match build(&RoomVersionId::V10) {
+            Pdu::RoomV3Pdu(pdu) => pdu,
+            _ => panic!("expected a PDU in the room v3 format"),
+        };
+        assert_eq!(pdu_v3.redacts, Some(event_id("START")));
+        assert_eq!(pdu_v3.content.get(), "{}");
+
+        #[cfg(feature = "unstable-msc3820")]
+        {
+            let pdu_v3 = match build(&RoomVersionId::V11) {
+                Pdu::RoomV3Pdu(pdu) => pdu,
+                _ => panic!("expected a PDU in the room v3 format"),
+            };
+            assert_eq!(pdu_v3.redacts, None);
+            assert_eq!(pdu_v3.content.get(), format!(r#"{{"redacts":"{}"}}"#, event_id("START")));
+        }
+    }
+
+    #[test]
+    fn build_v1_event() {
+        let key_pair = key_pair();
+
+        let mut create_content = RoomCreateEventContent::new(alice().to_owned());
+        create_content.room_version = RoomVersionId::V1;
+        let (create_id, create) = PduBuilder::state(&EmptyStateKey, &create_content)
+            .unwrap()
+            .build::<PduEvent, _>(
+                &RoomVersionId::V1,
+                room_id(),
+                alice(),
+                &[],
+                |_, _| None,
+                &key_pair,
+            )
+            .unwrap();
+        let create = PduEvent { event_id: create_id.clone(), rest: create };
+        let create_hash =
+            reference_hash(&to_canonical_object(&create.rest), &RoomVersionId::V1).unwrap();
+
+        let (id, pdu) = PduBuilder::message_like(&RoomMessageEventContent::text_plain("Hi"))
+            .unwrap()
+            .build(
+                &RoomVersionId::V1,
+                room_id(),
+                alice(),
+                &[&create],
+                |ty, key| (*ty == StateEventType::RoomCreate && key.is_empty()).then(|| &create),
+                &key_pair,
+            )
+            .unwrap();
+
+        assert_eq!(id.server_name(), Some(alice().server_name()));
+        let pdu_v1 = match &pdu {
+            Pdu::RoomV1Pdu(pdu) => pdu,
+            _ => panic!("expected a PDU in the room v1 format"),
+        };
+        assert_eq!(pdu_v1.event_id, id);
+        assert_eq!(pdu_v1.prev_events.len(), 1);
+        assert_eq!(pdu_v1.prev_events[0].0, create_id);
+        assert_eq!(pdu_v1.prev_events[0].1.sha256, create_hash);
+        assert_eq!(pdu_v1.auth_events.len(), 1);
+        assert_eq!(pdu_v1.auth_events[0].0, create_id);
+        assert_eq!(pdu_v1.depth, 2_u32.into());
+
+        assert_eq!(
+            verify_event(
+                &public_key_map(&key_pair),
+                &to_canonical_object(&pdu),
+                &RoomVersionId::V1
+            )
+            .unwrap(),
+            Verified::All
+        );
+    }
+}
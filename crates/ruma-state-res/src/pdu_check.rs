@@ -0,0 +1,607 @@
+//! The checks performed on a PDU received over federation.
+//!
+//! [`check_pdu`] chains the checks described in the [server-server specification] for every PDU a
+//! homeserver receives: format, signatures, hashes, authorization based on the auth events, on the
+//! state before the event and on the current state of the room. It returns a [`PduVerdict`]
+//! telling the homeserver what to do with the event.
+//!
+//! [server-server specification]: https://spec.matrix.org/v1.4/server-server-api/#checks-performed-on-receipt-of-a-pdu
+
+use std::collections::hash_map::Entry;
+
+use js_int::UInt;
+use ruma_common::{
+    canonical_json::{redact, CanonicalJsonObject},
+    events::{room::member::RoomMemberEventContent, RoomEventType, StateEventType},
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId,
+    RoomVersionId, UserId,
+};
+use ruma_signatures::{compute_event_id, verify_event, PublicKeyMap, Verified};
+use serde::{de::IgnoredAny, Deserialize};
+use serde_json::{
+    from_str as from_json_str, to_string as to_json_string, value::RawValue as RawJsonValue,
+};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::{
+    auth_types_for_event, event_auth::auth_check, room_version::EventFormatVersion, Error, Event,
+    EventTypeExt, Result, RoomVersion, StateMap,
+};
+
+/// The maximum size of a PDU, in bytes.
+const MAX_PDU_BYTES: usize = 65_536;
+
+/// The outcome of the checks performed on a PDU.
+#[derive(Debug)]
+#[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
+pub enum PduVerdict {
+    /// The event passed all the checks.
+    Accept,
+
+    /// The content hash of the event didn't match, but it passed all the other checks.
+    ///
+    /// The redacted event should be stored and used instead of the received one.
+    RedactAndAccept(CanonicalJsonObject),
+
+    /// The event is authorized by the state before it, but not by the current state of the room.
+    ///
+    /// The event should be stored, but it shouldn't be sent to clients nor be referenced by new
+    /// events.
+    SoftFail {
+        /// The redacted event to store, if the content hash of the event didn't match.
+        redacted: Option<CanonicalJsonObject>,
+    },
+
+    /// The event was rejected.
+    Reject(RejectionReason),
+}
+
+/// The reason why a PDU was rejected.
+#[derive(Debug, Error)]
+#[cfg_attr(not(feature = "unstable-exhaustive-types"), non_exhaustive)]
+pub enum RejectionReason {
+    /// The event is not a valid PDU for the room version.
+    #[error("invalid PDU format: {0}")]
+    InvalidFormat(String),
+
+    /// The signatures of the event could not be verified.
+    #[error("invalid signatures: {0}")]
+    InvalidSignatures(#[source] ruma_signatures::Error),
+
+    /// The auth events of the event are not the ones that should have been selected.
+    #[error("invalid auth events: {0}")]
+    InvalidAuthEvents(String),
+
+    /// The event is not authorized by its auth events.
+    #[error("not authorized by the auth events")]
+    UnauthorizedByAuthEvents,
+
+    /// The event is not authorized by the state before it.
+    #[error("not authorized by the state before the event")]
+    UnauthorizedByStateBefore,
+}
+
+/// Check a PDU received over federation.
+///
+/// This performs the first six checks on receipt of a PDU, in order:
+///
+/// 1. The event must be a valid PDU for the room version, otherwise it is rejected. For room
+///    versions with strict canonical JSON, `pdu` must have been converted from JSON with the
+///    fallible conversions of [`CanonicalJsonValue`](ruma_common::CanonicalJsonValue).
+/// 2. The signatures of the event must be valid, otherwise it is rejected. `public_key_map` must
+///    contain the keys of all the servers that need to sign the event.
+/// 3. The content hash of the event must match, otherwise the event is redacted and the following
+///    checks are performed on the redacted event.
+/// 4. The event must be authorized by its auth events, otherwise it is rejected. The auth events
+///    are fetched with `fetch_event`, which must not return rejected events.
+/// 5. The event must be authorized by the state before it, fetched with `fetch_state_before`,
+///    otherwise it is rejected.
+/// 6. The event must be authorized by the current state of the room, fetched with
+///    `fetch_current_state`, otherwise it is soft-failed.
+///
+/// ## Errors
+///
+/// Returns [`Error::Unsupported`] if the room version is not supported and [`Error::NotFound`] if
+/// an auth event of the PDU cannot be fetched. Any other failure is reported in the returned
+/// [`PduVerdict`].
+pub fn check_pdu<E: Event>(
+    room_version_id: &RoomVersionId,
+    pdu: &CanonicalJsonObject,
+    public_key_map: &PublicKeyMap,
+    fetch_event: impl Fn(&EventId) -> Option<E>,
+    fetch_state_before: impl Fn(&StateEventType, &str) -> Option<E>,
+    fetch_current_state: impl Fn(&StateEventType, &str) -> Option<E>,
+) -> Result<PduVerdict> {
+    let room_version = RoomVersion::new(room_version_id)?;
+
+    // 1. Is a valid event, otherwise it is dropped.
+    let event = match IncomingPdu::from_canonical_object(&room_version, room_version_id, pdu) {
+        Ok(event) => event,
+        Err(reason) => return Ok(PduVerdict::Reject(reason)),
+    };
+
+    // 2. Passes signature checks, otherwise it is dropped.
+    // 3. Passes hash checks, otherwise it is redacted before being processed further.
+    let (event, redacted) = match verify_event(public_key_map, pdu, room_version_id) {
+        Ok(Verified::All) => (event, None),
+        Ok(Verified::Signatures) => {
+            debug!("content hash of {} doesn't match, redacting it", event.event_id);
+
+            let redacted = redact(pdu, room_version_id).map_err(|e| {
+                Error::InvalidPdu(format!("Failed to redact {}: {e}", event.event_id))
+            })?;
+            let event =
+                match IncomingPdu::from_canonical_object(&room_version, room_version_id, &redacted)
+                {
+                    Ok(event) => event,
+                    Err(reason) => return Ok(PduVerdict::Reject(reason)),
+                };
+
+            (event, Some(redacted))
+        }
+        Err(e) => return Ok(PduVerdict::Reject(RejectionReason::InvalidSignatures(e))),
+    };
+
+    // 4. Passes authorization rules based on the event's auth events, otherwise it is rejected.
+    let auth_events = match fetch_auth_events(&event, fetch_event)? {
+        Ok(auth_events) => auth_events,
+        Err(reason) => return Ok(PduVerdict::Reject(reason)),
+    };
+    if !is_authorized(&room_version, &event, |ty, key| auth_events.get(&ty.with_state_key(key)))? {
+        warn!("{} is not authorized by its auth events", event.event_id);
+        return Ok(PduVerdict::Reject(RejectionReason::UnauthorizedByAuthEvents));
+    }
+
+    // 5. Passes authorization rules based on the state before the event, otherwise it is rejected.
+    if !is_authorized(&room_version, &event, fetch_state_before)? {
+        warn!("{} is not authorized by the state before it", event.event_id);
+        return Ok(PduVerdict::Reject(RejectionReason::UnauthorizedByStateBefore));
+    }
+
+    // 6. Passes authorization rules based on the current state of the room, otherwise it is "soft
+    //    failed".
+    if !is_authorized(&room_version, &event, fetch_current_state)? {
+        warn!("{} is not authorized by the current state, soft-failing it", event.event_id);
+        return Ok(PduVerdict::SoftFail { redacted });
+    }
+
+    Ok(match redacted {
+        Some(redacted) => PduVerdict::RedactAndAccept(redacted),
+        None => PduVerdict::Accept,
+    })
+}
+
+/// Fetch the auth events of the given event and check that they are the ones that should have
+/// been selected for it.
+///
+/// The outer result is for fetching errors and the inner result for invalid auth events.
+fn fetch_auth_events<E: Event>(
+    event: &IncomingPdu,
+    fetch_event: impl Fn(&EventId) -> Option<E>,
+) -> Result<std::result::Result<StateMap<E>, RejectionReason>> {
+    let expected_auth_types = auth_types_for_event(
+        &event.kind,
+        &event.sender,
+        event.state_key.as_deref(),
+        &event.content,
+    )?;
+
+    let mut auth_events = StateMap::new();
+    for aid in &event.auth_events {
+        let auth_event =
+            fetch_event(aid).ok_or_else(|| Error::NotFound(format!("Failed to find {aid}")))?;
+
+        if auth_event.room_id() != event.room_id {
+            return Ok(Err(RejectionReason::InvalidAuthEvents(format!(
+                "{aid} is in a different room"
+            ))));
+        }
+
+        let key = match auth_event.state_key() {
+            Some(state_key) => auth_event.event_type().with_state_key(state_key),
+            None => {
+                return Ok(Err(RejectionReason::InvalidAuthEvents(format!(
+                    "{aid} is not a state event"
+                ))))
+            }
+        };
+
+        if !expected_auth_types.contains(&key) {
+            return Ok(Err(RejectionReason::InvalidAuthEvents(format!(
+                "{aid} is not needed to authorize the event"
+            ))));
+        }
+
+        match auth_events.entry(key) {
+            Entry::Occupied(entry) => {
+                let (ty, state_key) = entry.key();
+                return Ok(Err(RejectionReason::InvalidAuthEvents(format!(
+                    "multiple auth events for ({ty}, {state_key:?})"
+                ))));
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(auth_event);
+            }
+        }
+    }
+
+    Ok(Ok(auth_events))
+}
+
+/// Whether the given event passes the authorization rules against the state returned by
+/// `fetch_state`.
+fn is_authorized<E: Event>(
+    room_version: &RoomVersion,
+    event: &IncomingPdu,
+    fetch_state: impl Fn(&StateEventType, &str) -> Option<E>,
+) -> Result<bool> {
+    // The `m.room.third_party_invite` event is identified by the token of the invite.
+    let current_third_party_invite = if event.kind == RoomEventType::RoomMember {
+        from_json_str::<RoomMemberEventContent>(event.content.get())
+            .ok()
+            .and_then(|content| content.third_party_invite)
+            .and_then(|invite| {
+                fetch_state(&StateEventType::RoomThirdPartyInvite, &invite.signed.token)
+            })
+    } else {
+        None
+    };
+
+    auth_check(room_version, event, current_third_party_invite, fetch_state)
+}
+
+/// A PDU received over federation, in any room version.
+#[derive(Debug)]
+struct IncomingPdu {
+    event_id: OwnedEventId,
+    room_id: OwnedRoomId,
+    sender: OwnedUserId,
+    origin_server_ts: MilliSecondsSinceUnixEpoch,
+    kind: RoomEventType,
+    content: Box<RawJsonValue>,
+    state_key: Option<String>,
+    depth: UInt,
+    prev_events: Vec<OwnedEventId>,
+    auth_events: Vec<OwnedEventId>,
+    redacts: Option<OwnedEventId>,
+}
+
+impl IncomingPdu {
+    fn from_canonical_object(
+        room_version: &RoomVersion,
+        room_version_id: &RoomVersionId,
+        pdu: &CanonicalJsonObject,
+    ) -> std::result::Result<Self, RejectionReason> {
+        let invalid = |e: &dyn std::fmt::Display| RejectionReason::InvalidFormat(e.to_string());
+
+        let json = to_json_string(pdu).map_err(|e| invalid(&e))?;
+        if json.len() > MAX_PDU_BYTES {
+            return Err(invalid(&format!("PDU is larger than {MAX_PDU_BYTES} bytes")));
+        }
+
+        let raw: RawPdu = from_json_str(&json).map_err(|e| invalid(&e))?;
+
+        let event_id = match room_version.event_format {
+            EventFormatVersion::V1 => {
+                raw.event_id.ok_or_else(|| invalid(&"missing field `event_id`"))?
+            }
+            _ => compute_event_id(pdu, room_version_id).map_err(|e| invalid(&e))?,
+        };
+
+        let redacts = if room_version.redacts_in_content && raw.kind == RoomEventType::RoomRedaction
+        {
+            #[derive(Deserialize)]
+            struct RedactionContentRedacts {
+                redacts: Option<OwnedEventId>,
+            }
+
+            from_json_str::<RedactionContentRedacts>(raw.content.get())
+                .map_err(|e| invalid(&e))?
+                .redacts
+        } else {
+            raw.redacts
+        };
+
+        Ok(Self {
+            event_id,
+            room_id: raw.room_id,
+            sender: raw.sender,
+            origin_server_ts: raw.origin_server_ts,
+            kind: raw.kind,
+            content: raw.content,
+            state_key: raw.state_key,
+            depth: raw.depth,
+            prev_events: raw.prev_events.into_iter().map(EventReference::into_event_id).collect(),
+            auth_events: raw.auth_events.into_iter().map(EventReference::into_event_id).collect(),
+            redacts,
+        })
+    }
+}
+
+/// The fields of a PDU that are needed for the checks.
+#[derive(Deserialize)]
+struct RawPdu {
+    event_id: Option<OwnedEventId>,
+    room_id: OwnedRoomId,
+    sender: OwnedUserId,
+    origin_server_ts: MilliSecondsSinceUnixEpoch,
+    #[serde(rename = "type")]
+    kind: RoomEventType,
+    content: Box<RawJsonValue>,
+    state_key: Option<String>,
+    depth: UInt,
+    prev_events: Vec<EventReference>,
+    auth_events: Vec<EventReference>,
+    redacts: Option<OwnedEventId>,
+}
+
+/// A reference to another event in a PDU.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EventReference {
+    /// An event ID and its hashes, in room versions 1 and 2.
+    WithHashes((OwnedEventId, IgnoredAny)),
+
+    /// An event ID, in room versions 3 and later.
+    EventId(OwnedEventId),
+}
+
+impl EventReference {
+    fn into_event_id(self) -> OwnedEventId {
+        match self {
+            Self::WithHashes((event_id, _)) | Self::EventId(event_id) => event_id,
+        }
+    }
+}
+
+impl Event for IncomingPdu {
+    type Id = OwnedEventId;
+
+    fn event_id(&self) -> &Self::Id {
+        &self.event_id
+    }
+
+    fn room_id(&self) -> &RoomId {
+        &self.room_id
+    }
+
+    fn sender(&self) -> &UserId {
+        &self.sender
+    }
+
+    fn origin_server_ts(&self) -> MilliSecondsSinceUnixEpoch {
+        self.origin_server_ts
+    }
+
+    fn event_type(&self) -> &RoomEventType {
+        &self.kind
+    }
+
+    fn content(&self) -> &RawJsonValue {
+        &self.content
+    }
+
+    fn state_key(&self) -> Option<&str> {
+        self.state_key.as_deref()
+    }
+
+    fn depth(&self) -> UInt {
+        self.depth
+    }
+
+    fn prev_events(&self) -> Box<dyn DoubleEndedIterator<Item = &Self::Id> + '_> {
+        Box::new(self.prev_events.iter())
+    }
+
+    fn auth_events(&self) -> Box<dyn DoubleEndedIterator<Item = &Self::Id> + '_> {
+        Box::new(self.auth_events.iter())
+    }
+
+    fn redacts(&self) -> Option<&Self::Id> {
+        self.redacts.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use maplit::btreemap;
+    use ruma_common::{
+        canonical_json::CanonicalJsonValue,
+        events::{RoomEventType, StateEventType},
+        serde::Base64,
+        CanonicalJsonObject, RoomVersionId,
+    };
+    use ruma_signatures::{hash_and_sign_event, Ed25519KeyPair, PublicKeyMap};
+    use serde_json::{from_value as from_json_value, json, to_string as to_json_string};
+
+    use super::{check_pdu, IncomingPdu, PduVerdict, RejectionReason, MAX_PDU_BYTES};
+    use crate::{
+        test_utils::{alice, member_content_ban, to_pdu_event, PduEvent, INITIAL_EVENTS},
+        Event, EventTypeExt, RoomVersion, StateMap,
+    };
+
+    fn key_pair() -> Ed25519KeyPair {
+        Ed25519KeyPair::from_der(&Ed25519KeyPair::generate().unwrap(), "1".into()).unwrap()
+    }
+
+    fn public_key_map(key_pair: &Ed25519KeyPair) -> PublicKeyMap {
+        btreemap! {
+            "foo".to_owned() => btreemap! {
+                "ed25519:1".to_owned() => Base64::new(key_pair.public_key().to_owned()),
+            },
+        }
+    }
+
+    fn topic_pdu(key_pair: &Ed25519KeyPair, auth_events: &[&str]) -> CanonicalJsonObject {
+        let mut pdu: CanonicalJsonObject = from_json_value(json!({
+            "room_id": "!test:foo",
+            "sender": "@alice:foo",
+            "origin": "foo",
+            "origin_server_ts": 1,
+            "type": "m.room.topic",
+            "state_key": "",
+            "content": { "topic": "Hello" },
+            "prev_events": ["$IMC:foo"],
+            "auth_events": auth_events,
+            "depth": 7,
+        }))
+        .unwrap();
+        hash_and_sign_event("foo", key_pair, &mut pdu, &RoomVersionId::V6).unwrap();
+        pdu
+    }
+
+    fn initial_state() -> StateMap<Arc<PduEvent>> {
+        INITIAL_EVENTS()
+            .into_values()
+            .map(|ev| (ev.event_type().with_state_key(ev.state_key().unwrap()), ev))
+            .collect()
+    }
+
+    fn check(
+        pdu: &CanonicalJsonObject,
+        public_key_map: &PublicKeyMap,
+        current_state: &StateMap<Arc<PduEvent>>,
+    ) -> PduVerdict {
+        let events = INITIAL_EVENTS();
+        let state_before = initial_state();
+
+        check_pdu(
+            &RoomVersionId::V6,
+            pdu,
+            public_key_map,
+            |id| events.get(id).map(Arc::clone),
+            |ty: &StateEventType, key: &str| {
+                state_before.get(&ty.with_state_key(key)).map(Arc::clone)
+            },
+            |ty: &StateEventType, key: &str| {
+                current_state.get(&ty.with_state_key(key)).map(Arc::clone)
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn accept() {
+        let key_pair = key_pair();
+        let pdu = topic_pdu(&key_pair, &["$CREATE:foo", "$IMA:foo", "$IPOWER:foo"]);
+
+        let verdict = check(&pdu, &public_key_map(&key_pair), &initial_state());
+        assert!(matches!(verdict, PduVerdict::Accept), "{verdict:?}");
+    }
+
+    #[test]
+    fn invalid_format() {
+        let key_pair = key_pair();
+        let mut pdu = topic_pdu(&key_pair, &["$CREATE:foo", "$IMA:foo", "$IPOWER:foo"]);
+        pdu.remove("depth");
+
+        let verdict = check(&pdu, &public_key_map(&key_pair), &initial_state());
+        assert!(
+            matches!(verdict, PduVerdict::Reject(RejectionReason::InvalidFormat(_))),
+            "{verdict:?}"
+        );
+    }
+
+    #[test]
+    fn max_size() {
+        let mut pdu = topic_pdu(&key_pair(), &["$CREATE:foo", "$IMA:foo", "$IPOWER:foo"]);
+        let len = to_json_string(&pdu).unwrap().len();
+
+        // Pad the topic so the PDU has exactly the given size.
+        let mut with_size = |size: usize| {
+            let topic = "a".repeat(size - len + "Hello".len());
+            pdu.insert(
+                "content".to_owned(),
+                CanonicalJsonValue::Object(btreemap! {
+                    "topic".to_owned() => CanonicalJsonValue::String(topic),
+                }),
+            );
+            assert_eq!(to_json_string(&pdu).unwrap().len(), size);
+
+            IncomingPdu::from_canonical_object(&RoomVersion::V6, &RoomVersionId::V6, &pdu)
+        };
+
+        assert!(with_size(MAX_PDU_BYTES).is_ok());
+        assert!(matches!(with_size(MAX_PDU_BYTES + 1), Err(RejectionReason::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn invalid_signatures() {
+        let pdu = topic_pdu(&key_pair(), &["$CREATE:foo", "$IMA:foo", "$IPOWER:foo"]);
+
+        let verdict = check(&pdu, &public_key_map(&key_pair()), &initial_state());
+        assert!(
+            matches!(verdict, PduVerdict::Reject(RejectionReason::InvalidSignatures(_))),
+            "{verdict:?}"
+        );
+    }
+
+    #[test]
+    fn redact_on_hash_mismatch() {
+        let key_pair = key_pair();
+        let mut pdu = topic_pdu(&key_pair, &["$CREATE:foo", "$IMA:foo", "$IPOWER:foo"]);
+        // The topic is not covered by the signatures, only by the content hash.
+        pdu.insert(
+            "content".to_owned(),
+            CanonicalJsonValue::Object(btreemap! {
+                "topic".to_owned() => CanonicalJsonValue::String("Tampered".to_owned()),
+            }),
+        );
+
+        let verdict = check(&pdu, &public_key_map(&key_pair), &initial_state());
+        match verdict {
+            PduVerdict::RedactAndAccept(redacted) => {
+                assert_eq!(
+                    redacted.get("content"),
+                    Some(&CanonicalJsonValue::Object(btreemap! {}))
+                );
+            }
+            _ => panic!("unexpected verdict: {verdict:?}"),
+        }
+    }
+
+    #[test]
+    fn invalid_auth_events() {
+        let key_pair = key_pair();
+
+        // The membership of bob is not needed to authorize an event of alice.
+        let pdu = topic_pdu(&key_pair, &["$CREATE:foo", "$IMA:foo", "$IPOWER:foo", "$IMB:foo"]);
+        let verdict = check(&pdu, &public_key_map(&key_pair), &initial_state());
+        assert!(
+            matches!(verdict, PduVerdict::Reject(RejectionReason::InvalidAuthEvents(_))),
+            "{verdict:?}"
+        );
+
+        // Without its membership, alice is not allowed to send the event.
+        let pdu = topic_pdu(&key_pair, &["$CREATE:foo", "$IPOWER:foo"]);
+        let verdict = check(&pdu, &public_key_map(&key_pair), &initial_state());
+        assert!(
+            matches!(verdict, PduVerdict::Reject(RejectionReason::UnauthorizedByAuthEvents)),
+            "{verdict:?}"
+        );
+    }
+
+    #[test]
+    fn soft_fail() {
+        let key_pair = key_pair();
+        let pdu = topic_pdu(&key_pair, &["$CREATE:foo", "$IMA:foo", "$IPOWER:foo"]);
+
+        let ban = to_pdu_event(
+            "BAN",
+            alice(),
+            RoomEventType::RoomMember,
+            Some(alice().as_str()),
+            member_content_ban(),
+            &["CREATE", "IMA", "IPOWER"],
+            &["IMC"],
+        );
+        let mut current_state = initial_state();
+        current_state.insert(ban.event_type().with_state_key(ban.state_key().unwrap()), ban);
+
+        let verdict = check(&pdu, &public_key_map(&key_pair), &current_state);
+        assert!(matches!(verdict, PduVerdict::SoftFail { redacted: None }), "{verdict:?}");
+    }
+}
@@ -74,6 +74,16 @@ pub struct RoomVersion {
     ///
     /// See: [MSC3667](https://github.com/matrix-org/matrix-spec-proposals/pull/3667) for more information.
     pub integer_power_levels: bool,
+    /// The room creator is the sender of the `m.room.create` event, which doesn't have a `creator`
+    /// field anymore.
+    ///
+    /// See: [MSC2175](https://github.com/matrix-org/matrix-spec-proposals/pull/2175) for more information.
+    pub use_room_create_sender: bool,
+    /// The `redacts` field of `m.room.redaction` events is in the content instead of at the top
+    /// level of the event.
+    ///
+    /// See: [MSC2174](https://github.com/matrix-org/matrix-spec-proposals/pull/2174) for more information.
+    pub redacts_in_content: bool,
 }
 
 impl RoomVersion {
@@ -90,6 +100,8 @@ impl RoomVersion {
         restricted_join_rules: false,
         knock_restricted_join_rule: false,
         integer_power_levels: false,
+        use_room_create_sender: false,
+        redacts_in_content: false,
     };
 
     pub const V2: Self = Self { state_res: StateResolutionVersion::V2, ..Self::V1 };
@@ -117,6 +129,14 @@ impl RoomVersion {
     pub const V10: Self =
         Self { knock_restricted_join_rule: true, integer_power_levels: true, ..Self::V9 };
 
+    #[cfg(feature = "unstable-msc3820")]
+    pub const V11: Self = Self {
+        disposition: RoomDisposition::Unstable,
+        use_room_create_sender: true,
+        redacts_in_content: true,
+        ..Self::V10
+    };
+
     pub fn new(version: &RoomVersionId) -> Result<Self> {
         Ok(match version {
             RoomVersionId::V1 => Self::V1,
@@ -129,6 +149,8 @@ impl RoomVersion {
             RoomVersionId::V8 => Self::V8,
             RoomVersionId::V9 => Self::V9,
             RoomVersionId::V10 => Self::V10,
+            #[cfg(feature = "unstable-msc3820")]
+            RoomVersionId::V11 => Self::V11,
             ver => return Err(Error::Unsupported(format!("found version `{ver}`"))),
         })
     }
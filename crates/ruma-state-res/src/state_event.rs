@@ -5,6 +5,7 @@ use std::{
     sync::Arc,
 };
 
+use js_int::UInt;
 use ruma_common::{events::RoomEventType, EventId, MilliSecondsSinceUnixEpoch, RoomId, UserId};
 use serde_json::value::RawValue as RawJsonValue;
 
@@ -33,6 +34,11 @@ pub trait Event {
     /// The state key for this event.
     fn state_key(&self) -> Option<&str>;
 
+    /// The depth of this event in the room's event graph.
+    ///
+    /// Only used for state resolution in room version 1.
+    fn depth(&self) -> UInt;
+
     /// The events before this event.
     // Requires GATs to avoid boxing (and TAIT for making it convenient).
     fn prev_events(&self) -> Box<dyn DoubleEndedIterator<Item = &Self::Id> + '_>;
@@ -42,6 +48,11 @@ pub trait Event {
     fn auth_events(&self) -> Box<dyn DoubleEndedIterator<Item = &Self::Id> + '_>;
 
     /// If this event is a redaction event this is the event it redacts.
+    ///
+    /// In room versions where [`RoomVersion::redacts_in_content`] is `true`, this is the `redacts`
+    /// field of the content of the event.
+    ///
+    /// [`RoomVersion::redacts_in_content`]: crate::RoomVersion::redacts_in_content
     fn redacts(&self) -> Option<&Self::Id>;
 }
 
@@ -76,6 +87,10 @@ impl<T: Event> Event for &T {
         (*self).state_key()
     }
 
+    fn depth(&self) -> UInt {
+        (*self).depth()
+    }
+
     fn prev_events(&self) -> Box<dyn DoubleEndedIterator<Item = &Self::Id> + '_> {
         (*self).prev_events()
     }
@@ -120,6 +135,10 @@ impl<T: Event> Event for Arc<T> {
         (**self).state_key()
     }
 
+    fn depth(&self) -> UInt {
+        (**self).depth()
+    }
+
     fn prev_events(&self) -> Box<dyn DoubleEndedIterator<Item = &Self::Id> + '_> {
         (**self).prev_events()
     }
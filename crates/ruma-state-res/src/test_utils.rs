@@ -37,6 +37,15 @@ pub fn do_check(
     events: &[Arc<PduEvent>],
     edges: Vec<Vec<OwnedEventId>>,
     expected_state_ids: Vec<OwnedEventId>,
+) {
+    do_check_with_room_version(&RoomVersionId::V6, events, edges, expected_state_ids);
+}
+
+pub fn do_check_with_room_version(
+    room_version: &RoomVersionId,
+    events: &[Arc<PduEvent>],
+    edges: Vec<Vec<OwnedEventId>>,
+    expected_state_ids: Vec<OwnedEventId>,
 ) {
     // To activate logging use `RUST_LOG=debug cargo t`
 
@@ -119,7 +128,7 @@ pub fn do_check(
                 })
                 .collect();
 
-            let resolved = crate::resolve(&RoomVersionId::V6, state_sets, auth_chain_sets, |id| {
+            let resolved = crate::resolve(room_version, state_sets, auth_chain_sets, |id| {
                 event_map.get(id).map(Arc::clone)
             });
             match resolved {
@@ -153,7 +162,7 @@ pub fn do_check(
         // the `to_pdu_event` was split into `init` and the fn below, could be better
         let e = fake_event;
         let ev_id = e.event_id();
-        let event = to_pdu_event(
+        let mut event = to_pdu_event(
             e.event_id().as_str(),
             e.sender(),
             e.event_type().clone(),
@@ -163,6 +172,17 @@ pub fn do_check(
             &prev_events.iter().cloned().collect::<Vec<_>>(),
         );
 
+        // The depth is only needed for state resolution in room version 1
+        let depth = prev_events
+            .iter()
+            .filter_map(|id| event_map.get(id))
+            .map(|ev| ev.depth() + uint!(1))
+            .max()
+            .unwrap_or_default();
+        if let Pdu::RoomV3Pdu(pdu) = &mut Arc::make_mut(&mut event).rest {
+            pdu.depth = depth;
+        }
+
         // We have to update our store, an actual user of this lib would
         // be giving us state from a DB.
         store.0.insert(ev_id.to_owned(), event.clone());
@@ -548,10 +568,19 @@ pub fn INITIAL_EDGES() -> Vec<OwnedEventId> {
 }
 
 pub mod event {
+    use js_int::UInt;
+    #[cfg(feature = "unstable-pdu")]
+    use ruma_common::{
+        canonical_json::{to_canonical_value, CanonicalJsonValue},
+        events::pdu::EventHash,
+        RoomVersionId,
+    };
     use ruma_common::{
         events::{pdu::Pdu, RoomEventType},
         MilliSecondsSinceUnixEpoch, OwnedEventId, RoomId, UserId,
     };
+    #[cfg(feature = "unstable-pdu")]
+    use ruma_signatures::reference_hash;
     use serde::{Deserialize, Serialize};
     use serde_json::value::RawValue as RawJsonValue;
 
@@ -618,6 +647,15 @@ pub mod event {
             }
         }
 
+        fn depth(&self) -> UInt {
+            match &self.rest {
+                Pdu::RoomV1Pdu(ev) => ev.depth,
+                Pdu::RoomV3Pdu(ev) => ev.depth,
+                #[allow(unreachable_patterns)]
+                _ => unreachable!("new PDU version"),
+            }
+        }
+
         fn prev_events(&self) -> Box<dyn DoubleEndedIterator<Item = &Self::Id> + '_> {
             match &self.rest {
                 Pdu::RoomV1Pdu(ev) => Box::new(ev.prev_events.iter().map(|(id, _)| id)),
@@ -646,6 +684,22 @@ pub mod event {
         }
     }
 
+    #[cfg(feature = "unstable-pdu")]
+    impl crate::ReferencedEvent for PduEvent {
+        fn reference_hash(&self) -> Option<EventHash> {
+            let pdu = match &self.rest {
+                Pdu::RoomV1Pdu(pdu) => pdu,
+                _ => return None,
+            };
+            let object = match to_canonical_value(pdu).ok()? {
+                CanonicalJsonValue::Object(object) => object,
+                _ => return None,
+            };
+
+            reference_hash(&object, &RoomVersionId::V1).ok().map(EventHash::new)
+        }
+    }
+
     #[derive(Clone, Debug, Deserialize, Serialize)]
     #[allow(clippy::exhaustive_structs)]
     pub struct PduEvent {
@@ -0,0 +1,486 @@
+//! The state resolution algorithm used by room version 1.
+//!
+//! See the [spec](https://spec.matrix.org/v1.4/rooms/v1/#state-resolution) for more information.
+
+use std::{borrow::Borrow, cmp::Reverse, future::Future};
+
+use ruma_common::{
+    events::{RoomEventType, StateEventType},
+    EventId,
+};
+use sha1::{Digest, Sha1};
+use tracing::{debug, info, trace, warn};
+
+use crate::{
+    auth_check, auth_event_ids, auth_types_for_event, room_version::RoomVersion, Event,
+    EventTypeExt, Result, StateMap,
+};
+
+/// Resolve sets of state events using the room version 1 algorithm.
+///
+/// Unlike the newer algorithm, this does not need the auth chains of the state sets. Conflicts
+/// are resolved for `m.room.power_levels`, `m.room.join_rules` and `m.room.member` first (in that
+/// order) by iteratively auth checking the conflicted events from oldest to newest. All other
+/// conflicts are resolved by picking the newest event that passes the auth check against the
+/// resolved state.
+pub(crate) async fn resolve<'a, E, SetIter, F, Fut>(
+    room_version: &RoomVersion,
+    state_sets: SetIter,
+    fetch_event: &F,
+) -> Result<StateMap<E::Id>>
+where
+    E: Event + Clone,
+    E::Id: 'a,
+    SetIter: Iterator<Item = &'a StateMap<E::Id>>,
+    F: Fn(&EventId) -> Fut,
+    Fut: Future<Output = Result<Option<E>>>,
+{
+    info!("State resolution (v1) starting");
+
+    let (clean, conflicting) = separate(state_sets);
+
+    info!("non conflicting events: {}", clean.len());
+    trace!("{clean:?}");
+
+    if conflicting.is_empty() {
+        info!("no conflicting state found");
+        return Ok(clean);
+    }
+
+    info!("conflicting events: {}", conflicting.len());
+    debug!("{conflicting:?}");
+
+    let mut conflicting_events = StateMap::new();
+    for (key, ids) in conflicting {
+        let mut events = Vec::new();
+        for id in ids {
+            // Don't honor events we cannot "verify"
+            if let Some(event) = fetch_event(id.borrow()).await? {
+                events.push(event);
+            }
+        }
+
+        if !events.is_empty() {
+            conflicting_events.insert(key, events);
+        }
+    }
+
+    let mut resolved_state = clean;
+
+    // Resolve the conflicted auth events one event type at a time, each type being checked
+    // against the winners of the types before it.
+    for ty in
+        [StateEventType::RoomPowerLevels, StateEventType::RoomJoinRules, StateEventType::RoomMember]
+    {
+        let mut resolved_for_type = StateMap::new();
+        for (key, events) in conflicting_events.iter().filter(|((t, state_key), _)| {
+            // Only `m.room.power_levels` with an empty state key is an auth event
+            *t == ty && (ty != StateEventType::RoomPowerLevels || state_key.is_empty())
+        }) {
+            let winner =
+                resolve_auth_events(room_version, events, &resolved_state, fetch_event).await?;
+            debug!("resolved {key:?} to {}", winner.event_id());
+            resolved_for_type.insert(key.clone(), winner.event_id().clone());
+        }
+
+        resolved_state.extend(resolved_for_type);
+    }
+
+    let mut resolved_normal = StateMap::new();
+    for (key, events) in
+        conflicting_events.iter().filter(|(key, _)| !resolved_state.contains_key(*key))
+    {
+        let winner =
+            resolve_normal_events(room_version, events, &resolved_state, fetch_event).await?;
+        debug!("resolved {key:?} to {}", winner.event_id());
+        resolved_normal.insert(key.clone(), winner.event_id().clone());
+    }
+
+    resolved_state.extend(resolved_normal);
+    Ok(resolved_state)
+}
+
+/// Split the events that have no conflicts from those that are conflicting.
+///
+/// The return tuple looks like `(unconflicted, conflicted)`.
+///
+/// In contrast to the newer algorithm, a key is only conflicting if at least two state sets map it
+/// to different events. A key that is missing from some of the state sets is not a conflict.
+fn separate<'a, Id>(
+    mut state_sets_iter: impl Iterator<Item = &'a StateMap<Id>>,
+) -> (StateMap<Id>, StateMap<Vec<Id>>)
+where
+    Id: Clone + Eq + 'a,
+{
+    let mut unconflicted_state = state_sets_iter.next().cloned().unwrap_or_default();
+    let mut conflicted_state: StateMap<Vec<Id>> = StateMap::new();
+
+    for state_set in state_sets_iter {
+        for (key, id) in state_set {
+            if let Some(ids) = conflicted_state.get_mut(key) {
+                if !ids.contains(id) {
+                    ids.push(id.clone());
+                }
+            } else if let Some(unconflicted_id) = unconflicted_state.get(key) {
+                if unconflicted_id != id {
+                    let unconflicted_id = unconflicted_state.remove(key).unwrap();
+                    conflicted_state.insert(key.clone(), vec![unconflicted_id, id.clone()]);
+                }
+            } else {
+                unconflicted_state.insert(key.clone(), id.clone());
+            }
+        }
+    }
+
+    (unconflicted_state, conflicted_state)
+}
+
+/// Resolve a conflicted auth event.
+///
+/// The events are auth checked from oldest to newest, each one against the state with the
+/// previous event in place. The last event before the first failure wins.
+async fn resolve_auth_events<E, F, Fut>(
+    room_version: &RoomVersion,
+    events: &[E],
+    state: &StateMap<E::Id>,
+    fetch_event: &F,
+) -> Result<E>
+where
+    E: Event + Clone,
+    F: Fn(&EventId) -> Fut,
+    Fut: Future<Output = Result<Option<E>>>,
+{
+    let mut events = events.to_vec();
+    sort_events(&mut events);
+
+    let mut events = events.into_iter().rev();
+    let mut prev_event = events.next().expect("conflicted state contains at least one event");
+
+    for event in events {
+        if !is_authorized(room_version, &event, Some(&prev_event), state, fetch_event).await? {
+            warn!("event {} failed the authentication check", event.event_id());
+            break;
+        }
+
+        prev_event = event;
+    }
+
+    Ok(prev_event)
+}
+
+/// Resolve a conflicted non-auth event.
+///
+/// The newest event that passes the auth check against the resolved state wins. If none of them
+/// pass, the oldest event wins.
+async fn resolve_normal_events<E, F, Fut>(
+    room_version: &RoomVersion,
+    events: &[E],
+    state: &StateMap<E::Id>,
+    fetch_event: &F,
+) -> Result<E>
+where
+    E: Event + Clone,
+    F: Fn(&EventId) -> Fut,
+    Fut: Future<Output = Result<Option<E>>>,
+{
+    let mut events = events.to_vec();
+    sort_events(&mut events);
+
+    for event in &events {
+        if is_authorized(room_version, event, None, state, fetch_event).await? {
+            return Ok(event.clone());
+        }
+
+        warn!("event {} failed the authentication check", event.event_id());
+    }
+
+    Ok(events.pop().expect("conflicted state contains at least one event"))
+}
+
+/// Sort the events by descending depth, breaking ties with the SHA-1 hash of the event ID.
+fn sort_events<E: Event>(events: &mut [E]) {
+    events.sort_by_cached_key(|event| {
+        let event_id: &EventId = event.event_id().borrow();
+        (Reverse(event.depth()), Sha1::digest(event_id.as_bytes()))
+    });
+}
+
+/// Auth check `event` against `state`, with `prev_event` taking precedence over the event in
+/// `state` with the same type and state key.
+async fn is_authorized<E, F, Fut>(
+    room_version: &RoomVersion,
+    event: &E,
+    prev_event: Option<&E>,
+    state: &StateMap<E::Id>,
+    fetch_event: &F,
+) -> Result<bool>
+where
+    E: Event + Clone,
+    F: Fn(&EventId) -> Fut,
+    Fut: Future<Output = Result<Option<E>>>,
+{
+    let mut auth_state = StateMap::new();
+    for key in auth_types_for_event(
+        event.event_type(),
+        event.sender(),
+        event.state_key(),
+        event.content(),
+    )? {
+        if let Some(id) = state.get(&key) {
+            if let Some(ev) = fetch_event(id.borrow()).await? {
+                auth_state.insert(key, ev);
+            }
+        }
+    }
+
+    if let Some(prev_event) = prev_event {
+        if let Some(state_key) = prev_event.state_key() {
+            auth_state
+                .insert(prev_event.event_type().with_state_key(state_key), prev_event.clone());
+        }
+    }
+
+    let mut current_third_party = None;
+    for aid in auth_event_ids(event) {
+        if let Some(ev) = fetch_event(aid.borrow()).await? {
+            if *ev.event_type() == RoomEventType::RoomThirdPartyInvite {
+                current_third_party = Some(ev);
+                break;
+            }
+        }
+    }
+
+    auth_check(room_version, event, current_third_party, |ty, key| {
+        auth_state.get(&ty.with_state_key(key))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use maplit::hashmap;
+    use ruma_common::{
+        events::{
+            room::join_rules::{JoinRule, RoomJoinRulesEventContent},
+            RoomEventType, StateEventType,
+        },
+        RoomVersionId,
+    };
+    use serde_json::{json, value::to_raw_value as to_raw_json_value};
+
+    use crate::test_utils::{
+        alice, bob, charlie, do_check_with_room_version, ella, event_id, member_content_ban,
+        member_content_join, to_init_pdu_event,
+    };
+
+    #[test]
+    fn separate_ignores_missing_keys() {
+        let power_levels = (StateEventType::RoomPowerLevels, "".to_owned());
+        let topic = (StateEventType::RoomTopic, "".to_owned());
+        let name = (StateEventType::RoomName, "".to_owned());
+
+        let state_sets = [
+            hashmap! { power_levels.clone() => "PA", topic.clone() => "T1" },
+            hashmap! { power_levels.clone() => "PA", topic.clone() => "T2", name.clone() => "N" },
+        ];
+
+        let (unconflicted, conflicted) = super::separate(state_sets.iter());
+
+        assert_eq!(unconflicted, hashmap! { power_levels => "PA", name => "N" });
+        assert_eq!(conflicted, hashmap! { topic => vec!["T1", "T2"] });
+    }
+
+    #[test]
+    fn separate_empty() {
+        let (unconflicted, conflicted) = super::separate(std::iter::empty::<&HashMap<_, &str>>());
+
+        assert!(unconflicted.is_empty());
+        assert!(conflicted.is_empty());
+    }
+
+    #[test]
+    fn topic_basic() {
+        let _ =
+            tracing::subscriber::set_default(tracing_subscriber::fmt().with_test_writer().finish());
+
+        let events = &[
+            to_init_pdu_event(
+                "T1",
+                alice(),
+                RoomEventType::RoomTopic,
+                Some(""),
+                to_raw_json_value(&json!({})).unwrap(),
+            ),
+            to_init_pdu_event(
+                "PA1",
+                alice(),
+                RoomEventType::RoomPowerLevels,
+                Some(""),
+                to_raw_json_value(&json!({ "users": { alice(): 100, bob(): 50 } })).unwrap(),
+            ),
+            to_init_pdu_event(
+                "T2",
+                alice(),
+                RoomEventType::RoomTopic,
+                Some(""),
+                to_raw_json_value(&json!({})).unwrap(),
+            ),
+            to_init_pdu_event(
+                "PA2",
+                alice(),
+                RoomEventType::RoomPowerLevels,
+                Some(""),
+                to_raw_json_value(&json!({ "users": { alice(): 100, bob(): 0 } })).unwrap(),
+            ),
+            to_init_pdu_event(
+                "PB",
+                bob(),
+                RoomEventType::RoomPowerLevels,
+                Some(""),
+                to_raw_json_value(&json!({ "users": { alice(): 100, bob(): 50 } })).unwrap(),
+            ),
+            to_init_pdu_event(
+                "T3",
+                bob(),
+                RoomEventType::RoomTopic,
+                Some(""),
+                to_raw_json_value(&json!({})).unwrap(),
+            ),
+        ];
+
+        let edges =
+            vec![vec!["END", "PA2", "T2", "PA1", "T1", "START"], vec!["END", "T3", "PB", "PA1"]]
+                .into_iter()
+                .map(|list| list.into_iter().map(event_id).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+
+        let expected_state_ids = vec!["PA2", "T2"].into_iter().map(event_id).collect::<Vec<_>>();
+
+        do_check_with_room_version(&RoomVersionId::V1, events, edges, expected_state_ids);
+    }
+
+    #[test]
+    fn topic_reset() {
+        let _ =
+            tracing::subscriber::set_default(tracing_subscriber::fmt().with_test_writer().finish());
+
+        let events = &[
+            to_init_pdu_event(
+                "T1",
+                alice(),
+                RoomEventType::RoomTopic,
+                Some(""),
+                to_raw_json_value(&json!({})).unwrap(),
+            ),
+            to_init_pdu_event(
+                "PA",
+                alice(),
+                RoomEventType::RoomPowerLevels,
+                Some(""),
+                to_raw_json_value(&json!({ "users": { alice(): 100, bob(): 50 } })).unwrap(),
+            ),
+            to_init_pdu_event(
+                "T2",
+                bob(),
+                RoomEventType::RoomTopic,
+                Some(""),
+                to_raw_json_value(&json!({})).unwrap(),
+            ),
+            to_init_pdu_event(
+                "MB",
+                alice(),
+                RoomEventType::RoomMember,
+                Some(bob().to_string().as_str()),
+                member_content_ban(),
+            ),
+        ];
+
+        let edges = vec![vec!["END", "MB", "T2", "PA", "T1", "START"], vec!["END", "T1"]]
+            .into_iter()
+            .map(|list| list.into_iter().map(event_id).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        let expected_state_ids =
+            vec!["T1", "MB", "PA"].into_iter().map(event_id).collect::<Vec<_>>();
+
+        do_check_with_room_version(&RoomVersionId::V1, events, edges, expected_state_ids);
+    }
+
+    #[test]
+    fn offtopic_power_level() {
+        let _ =
+            tracing::subscriber::set_default(tracing_subscriber::fmt().with_test_writer().finish());
+
+        let events = &[
+            to_init_pdu_event(
+                "PA",
+                alice(),
+                RoomEventType::RoomPowerLevels,
+                Some(""),
+                to_raw_json_value(&json!({ "users": { alice(): 100, bob(): 50 } })).unwrap(),
+            ),
+            to_init_pdu_event(
+                "PB",
+                bob(),
+                RoomEventType::RoomPowerLevels,
+                Some(""),
+                to_raw_json_value(&json!({ "users": { alice(): 100, bob(): 50, charlie(): 50 } }))
+                    .unwrap(),
+            ),
+            to_init_pdu_event(
+                "PC",
+                charlie(),
+                RoomEventType::RoomPowerLevels,
+                Some(""),
+                to_raw_json_value(&json!({ "users": { alice(): 100, bob(): 50, charlie(): 0 } }))
+                    .unwrap(),
+            ),
+        ];
+
+        let edges = vec![vec!["END", "PC", "PB", "PA", "START"], vec!["END", "PA"]]
+            .into_iter()
+            .map(|list| list.into_iter().map(event_id).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        // Unlike in the newer algorithm, `PC` is only checked against `PA` which doesn't give
+        // charlie enough power to change the power levels.
+        let expected_state_ids = vec!["PA"].into_iter().map(event_id).collect::<Vec<_>>();
+
+        do_check_with_room_version(&RoomVersionId::V1, events, edges, expected_state_ids);
+    }
+
+    #[test]
+    fn join_rule_evasion() {
+        let _ =
+            tracing::subscriber::set_default(tracing_subscriber::fmt().with_test_writer().finish());
+
+        let events = &[
+            to_init_pdu_event(
+                "JR",
+                alice(),
+                RoomEventType::RoomJoinRules,
+                Some(""),
+                to_raw_json_value(&RoomJoinRulesEventContent::new(JoinRule::Private)).unwrap(),
+            ),
+            to_init_pdu_event(
+                "ME",
+                ella(),
+                RoomEventType::RoomMember,
+                Some(ella().to_string().as_str()),
+                member_content_join(),
+            ),
+        ];
+
+        let edges = vec![vec!["END", "JR", "START"], vec!["END", "ME", "START"]]
+            .into_iter()
+            .map(|list| list.into_iter().map(event_id).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        // State that only exists in one of the forks is not conflicted, so the join of ella is
+        // not checked against the new join rules.
+        let expected_state_ids = vec!["JR", "ME"].into_iter().map(event_id).collect::<Vec<_>>();
+
+        do_check_with_room_version(&RoomVersionId::V1, events, edges, expected_state_ids);
+    }
+}